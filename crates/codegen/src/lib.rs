@@ -1,22 +1,42 @@
-use std::{fmt, iter, marker::PhantomData};
+use std::{
+    collections::{HashMap, HashSet},
+    ffi::CStr,
+    fmt, fs,
+    io::{self, Write},
+    iter,
+    marker::PhantomData,
+    mem,
+    path::{Path, PathBuf},
+};
 
 use inkwell::{
+    attributes::{Attribute, AttributeLoc},
     basic_block::BasicBlock,
     builder::Builder,
+    comdat::ComdatSelectionKind,
     context::Context,
     execution_engine::ExecutionEngine,
+    intrinsics::Intrinsic,
     module::Module,
-    types::FunctionType,
+    types::{BasicTypeEnum, FunctionType, IntType},
     values::{
-        AnyValue, BasicMetadataValueEnum, BasicValue, BasicValueEnum, FunctionValue, PointerValue,
+        AnyValue, BasicMetadataValueEnum, BasicValue, BasicValueEnum, FunctionValue, GlobalValue,
+        InstructionOpcode, InstructionValue, IntValue, PointerValue, StructValue,
     },
-    AddressSpace, OptimizationLevel,
+    AddressSpace, AtomicOrdering, AtomicRMWBinOp, IntPredicate, OptimizationLevel,
+};
+use lexer::Lexer;
+use parser::{
+    ast::{Ast, BinaryOp, Root},
+    ParseStream,
+};
+use typeck::{
+    AtomicRmwOp, BlockId, FuncId, Function, GlobalId, Ordering, Statement, Static, TmpId, Type,
+    VarId,
 };
-use parser::ast::{Ast, BinaryOp, Root};
-use typeck::{BlockId, FuncId, Function, Statement, TmpId, Type, VarId};
 
-use self::types::{AsLlvm, AsLlvmConst};
-pub use self::types::{AsType, FnAsLlvm, Str};
+use self::types::{ref_type, signature_type, AsLlvm, AsLlvmConst};
+pub use self::types::{AsType, FnAsLlvm, Slice, Str};
 
 //
 
@@ -32,7 +52,31 @@ pub enum Error {
     InvalidMainFn,
     StaticRedefined(String),
     VariableNotFound(String),
+    UnsupportedOperation {
+        ty: Type,
+        op: BinaryOp,
+    },
+    ArityMismatch {
+        expected: usize,
+        got: usize,
+    },
     Type(typeck::Error),
+    /// a bug in the type checker, see [`typeck::Module::validate`]
+    InvalidIr(typeck::VerifyError),
+    /// a file from [`ModuleGen::add_file`] or `use` didn't parse
+    Parse(parser::Error),
+    ReadFile {
+        path: PathBuf,
+        err: String,
+    },
+    /// a file uses itself, directly or through other files
+    ImportCycle(PathBuf),
+    /// an error in a file from `use`, its spans are in that file
+    Import {
+        path: PathBuf,
+        err: Box<Error>,
+    },
+    Codegen(String),
 }
 
 impl fmt::Display for Error {
@@ -42,7 +86,19 @@ impl fmt::Display for Error {
             Error::InvalidMainFn => write!(f, "invalid main function signature"),
             Error::StaticRedefined(name) => write!(f, "static `{name}` already defined"),
             Error::VariableNotFound(name) => write!(f, "variable `{name}` not found"),
+            Error::UnsupportedOperation { ty, op } => {
+                write!(f, "unsupported operation: {ty:?} {op} {ty:?}")
+            }
+            Error::ArityMismatch { expected, got } => {
+                write!(f, "expected {expected} arguments, got {got}")
+            }
             Error::Type(e) => write!(f, "{e}"),
+            Error::InvalidIr(e) => write!(f, "invalid IR: {e}"),
+            Error::Parse(e) => write!(f, "{e}"),
+            Error::ReadFile { path, err } => write!(f, "can't read `{}`: {err}", path.display()),
+            Error::ImportCycle(path) => write!(f, "`{}` uses itself", path.display()),
+            Error::Import { path, err } => write!(f, "in `{}`: {err}", path.display()),
+            Error::Codegen(err) => write!(f, "codegen error: {err}"),
         }
     }
 }
@@ -55,6 +111,10 @@ impl From<typeck::Error> for Error {
 
 //
 
+/// the LLVM context of the current thread
+///
+/// LLVM contexts are not thread safe, so every thread creates its own on first use,
+/// it lives (and leaks) until the process exits
 fn context() -> &'static Context {
     thread_local! {
         static CTX: &'static Context = Box::leak(Box::new(Context::create()));
@@ -71,7 +131,7 @@ fn to_prototype(gen: &ModuleGen, code: &typeck::Module, func: &Function) -> Func
         .collect();
 
     code.get_type(func.returns)
-        .as_llvm_fn(gen, &param_types, false)
+        .as_llvm_fn(gen, &param_types, func.is_variadic)
 }
 
 //
@@ -104,6 +164,12 @@ impl IndexOf for BlockId {
     }
 }
 
+impl IndexOf for GlobalId {
+    fn index(self) -> usize {
+        self.0
+    }
+}
+
 //
 
 pub struct IdMap<K, V> {
@@ -137,6 +203,11 @@ impl<K, V: Copy> Default for IdMap<K, V> {
 }
 
 impl<K: Copy + IndexOf, V> IdMap<K, V> {
+    /// `None` if `k` was never set
+    fn try_get(&self, k: K) -> Option<&V> {
+        self.vals.get(k.index())?.as_ref()
+    }
+
     #[track_caller]
     fn get(&self, k: K) -> &V {
         // println!("get {}", k.index());
@@ -148,10 +219,27 @@ impl<K: Copy + IndexOf, V> IdMap<K, V> {
         // println!("set {}", k.index());
         self.vals[k.index()] = Some(v);
     }
+
+    /// unsets `k`, `None` if it was never set
+    fn remove(&mut self, k: K) -> Option<V> {
+        self.vals.get_mut(k.index())?.take()
+    }
+}
+
+impl<K: Copy + IndexOf, V: Copy> IdMap<K, V> {
+    /// like [`Self::set`], but grows the map first if `k` is out of bounds
+    fn set_growing(&mut self, k: K, v: V) {
+        self.reserve(k.index() + 1);
+        self.set(k, v);
+    }
 }
 
 //
 
+/// creates [`ModuleGen`]s that use the LLVM context of the current thread
+///
+/// neither `CodeGen` nor `ModuleGen` is `Send`, each thread compiles and runs
+/// its own modules, and nothing is shared between threads
 pub struct CodeGen {
     ctx: Option<&'static Context>,
 }
@@ -161,18 +249,25 @@ impl CodeGen {
         Self { ctx: None }
     }
 
-    pub fn module(&mut self) -> ModuleGen {
-        let ctx = *self.ctx.get_or_insert_with(context);
+    pub fn module(&mut self) -> Result<ModuleGen> {
+        ModuleGen::new(self.ctx.get_or_insert_with(context))
+    }
+}
 
+impl ModuleGen {
+    fn new(ctx: &'static Context) -> Result<Self> {
         let module = ctx.create_module("<run>");
         let builder = ctx.create_builder();
         let alloca_builder = ctx.create_builder();
 
         let engine = module
             .create_jit_execution_engine(OptimizationLevel::Aggressive)
-            .unwrap();
+            .map_err(|err| Error::Codegen(err.to_string()))?;
 
-        ModuleGen {
+        // pointer sized integers and struct layouts come from the target data
+        check_data_layout(engine.get_target_data().get_data_layout().as_str())?;
+
+        let mut gen = Self {
             ctx,
             module,
             builder,
@@ -182,8 +277,163 @@ impl CodeGen {
 
             types: typeck::Module::new(),
             functions: IdMap::new(),
+            globals: IdMap::new(),
+            symbols: HashMap::new(),
+            sealed: false,
+            freed: HashSet::new(),
+            stack_canaries: false,
+        };
+        gen.add_builtins();
+        Ok(gen)
+    }
+}
+
+/// variables that are set once and never referenced, they don't need an alloca
+///
+/// the one `Let` has to be in the entry block or in the same block as every `Load`,
+/// so that the value is always generated before it is used
+fn immutable_variables(func: &Function) -> Vec<bool> {
+    let mut immutable = vec![true; func.variables.len()];
+    let mut lets = vec![None; func.variables.len()];
+
+    for (block_id, block) in func.blocks() {
+        for stmt in block.stmts.iter() {
+            match stmt {
+                Statement::Let { dst, .. } => {
+                    let first = lets[dst.0].replace(block_id).is_none();
+                    immutable[dst.0] &= first;
+                }
+                Statement::Store { dst: var, .. } | Statement::Ref { src: var, .. } => {
+                    immutable[var.0] = false;
+                }
+                _ => {}
+            }
+        }
+    }
+
+    for (block_id, block) in func.blocks() {
+        for stmt in block.stmts.iter() {
+            if let Statement::Load { src, .. } = stmt {
+                if lets[src.0] != Some(BlockId(0)) && lets[src.0] != Some(block_id) {
+                    immutable[src.0] = false;
+                }
+            }
         }
     }
+
+    immutable
+}
+
+/// the integer comparison for `op`, `signed` picks between the signed and unsigned orderings
+fn int_predicate(op: BinaryOp, signed: bool) -> IntPredicate {
+    match (op, signed) {
+        (BinaryOp::Eq, _) => IntPredicate::EQ,
+        (BinaryOp::Neq, _) => IntPredicate::NE,
+        (BinaryOp::Lt, true) => IntPredicate::SLT,
+        (BinaryOp::Le, true) => IntPredicate::SLE,
+        (BinaryOp::Gt, true) => IntPredicate::SGT,
+        (BinaryOp::Ge, true) => IntPredicate::SGE,
+        (BinaryOp::Lt, false) => IntPredicate::ULT,
+        (BinaryOp::Le, false) => IntPredicate::ULE,
+        (BinaryOp::Gt, false) => IntPredicate::UGT,
+        (BinaryOp::Ge, false) => IntPredicate::UGE,
+        _ => unreachable!("{op:?} is not a comparison"),
+    }
+}
+
+fn canonicalize(path: &Path) -> Result<PathBuf> {
+    path.canonicalize().map_err(|err| Error::ReadFile {
+        path: path.to_path_buf(),
+        err: err.to_string(),
+    })
+}
+
+/// `builtin-i32-neq` for `!=` on `i32`s, so that the comparisons can be told apart in the IR
+fn int_compare_name(ty: Type, op: BinaryOp) -> String {
+    let ty = match ty {
+        Type::I32 => "i32",
+        Type::I64 => "i64",
+        Type::USize => "usize",
+        Type::Bool => "bool",
+        _ => unreachable!("{ty:?} is not an integer"),
+    };
+    let op = match op {
+        BinaryOp::Eq => "eq",
+        BinaryOp::Neq => "neq",
+        BinaryOp::Lt => "lt",
+        BinaryOp::Le => "le",
+        BinaryOp::Gt => "gt",
+        BinaryOp::Ge => "ge",
+        _ => unreachable!("{op:?} is not a comparison"),
+    };
+    format!("builtin-{ty}-{op}")
+}
+
+/// turns a plain load or store of an integer of type `ty` into an atomic one,
+/// atomic loads and stores need an explicit alignment
+fn make_atomic(inst: InstructionValue, ty: IntType, ordering: Ordering) {
+    inst.set_atomic_ordering(atomic_ordering(ordering)).unwrap();
+    inst.set_alignment(ty.get_bit_width() / 8).unwrap();
+}
+
+/// the `undef` value of `ty`
+fn undef(ty: BasicTypeEnum<'static>) -> BasicValueEnum<'static> {
+    match ty {
+        BasicTypeEnum::ArrayType(ty) => ty.get_undef().into(),
+        BasicTypeEnum::FloatType(ty) => ty.get_undef().into(),
+        BasicTypeEnum::IntType(ty) => ty.get_undef().into(),
+        BasicTypeEnum::PointerType(ty) => ty.get_undef().into(),
+        BasicTypeEnum::StructType(ty) => ty.get_undef().into(),
+        BasicTypeEnum::VectorType(ty) => ty.get_undef().into(),
+    }
+}
+
+fn atomic_ordering(ordering: Ordering) -> AtomicOrdering {
+    match ordering {
+        Ordering::Relaxed => AtomicOrdering::Monotonic,
+        Ordering::Acquire => AtomicOrdering::Acquire,
+        Ordering::Release => AtomicOrdering::Release,
+        Ordering::SeqCst => AtomicOrdering::SequentiallyConsistent,
+    }
+}
+
+fn atomic_rmw_op(op: AtomicRmwOp) -> AtomicRMWBinOp {
+    match op {
+        AtomicRmwOp::Add => AtomicRMWBinOp::Add,
+        AtomicRmwOp::Sub => AtomicRMWBinOp::Sub,
+        AtomicRmwOp::And => AtomicRMWBinOp::And,
+        AtomicRmwOp::Or => AtomicRMWBinOp::Or,
+        AtomicRmwOp::Xor => AtomicRMWBinOp::Xor,
+        AtomicRmwOp::Xchg => AtomicRMWBinOp::Xchg,
+    }
+}
+
+/// what `panic(msg)` does in the JIT code
+fn panic_with(msg: impl fmt::Display) -> ! {
+    eprintln!("{msg}");
+    std::process::exit(1);
+}
+
+/// the value of every stack canary, see [`ModuleGen::insert_stack_canaries`]
+const CANARY: u32 = 0xDEADBEEF;
+
+/// what the canary checks before each return call
+extern "C" fn check_canary(canary: u32) {
+    if canary != CANARY {
+        panic_with(format_args!(
+            "stack canary corrupted: {canary:#X}, something wrote past a variable"
+        ));
+    }
+}
+
+fn check_data_layout(layout: &CStr) -> Result<()> {
+    if layout.is_empty() {
+        return Err(Error::Codegen(
+            "the JIT target has no data layout".to_string(),
+        ));
+    }
+
+    Ok(())
 }
 
 impl Default for CodeGen {
@@ -204,31 +454,169 @@ pub struct ModuleGen {
 
     types: typeck::Module,
     functions: IdMap<FuncId, FunctionValue<'static>>,
+    globals: IdMap<GlobalId, GlobalValue<'static>>,
+
+    /// addresses for source level externs, from [`Self::define_symbol`]
+    symbols: HashMap<String, usize>,
+
+    /// `module` has already been compiled by the JIT,
+    /// so new code goes to a fresh module, see [`Self::open_module`]
+    sealed: bool,
+
+    /// functions from [`Self::free_function`] and unused redefined ones,
+    /// new code can't call them
+    freed: HashSet<FuncId>,
+
+    /// from [`Self::insert_stack_canaries`]
+    stack_canaries: bool,
 }
 
 impl ModuleGen {
+    /// type checks and generates `ast`, returns its top level code as a function
+    ///
+    /// the files from `use "file";` are added first, relative to the working directory
     pub fn add(&mut self, ast: &Ast<Root>) -> Result<FuncId> {
-        // let main = code.get_function(main);
+        self.add_in(ast, Path::new(""), &mut Vec::new())
+    }
+
+    /// reads, parses and adds the file at `path`, like [`Self::add`]
+    ///
+    /// `use "file";` paths are relative to the directory of `path`
+    pub fn add_file(&mut self, path: &Path) -> Result<FuncId> {
+        let path = canonicalize(path)?;
+        self.load_file(path, &mut Vec::new())
+    }
 
+    /// `loading` are the files that are being added, for finding import cycles
+    fn load_file(&mut self, path: PathBuf, loading: &mut Vec<PathBuf>) -> Result<FuncId> {
+        let code = fs::read_to_string(&path).map_err(|err| Error::ReadFile {
+            path: path.clone(),
+            err: err.to_string(),
+        })?;
+        let ast: Ast<Root> = ParseStream::from_lexer(Lexer::new(&code))
+            .parse()
+            .map_err(Error::Parse)?;
+
+        loading.push(path.clone());
+        let dir = path.parent().unwrap_or(Path::new(""));
+        let main = self.add_in(&ast, dir, loading);
+        loading.pop();
+
+        let main = main?;
+        self.types.add_import(path);
+        Ok(main)
+    }
+
+    /// adds the files used by `ast` and then `ast` itself
+    ///
+    /// the top level code of a used file runs once, right after it is added
+    fn add_in(
+        &mut self,
+        ast: &Ast<Root>,
+        dir: &Path,
+        loading: &mut Vec<PathBuf>,
+    ) -> Result<FuncId> {
+        for file in ast.inner.imports() {
+            let path = dir.join(file.path());
+            let wrap = |err| Error::Import {
+                path: path.clone(),
+                err: Box::new(err),
+            };
+
+            let canonical = canonicalize(&path).map_err(wrap)?;
+            if loading.contains(&canonical) {
+                return Err(Error::ImportCycle(path));
+            }
+            if self.types.is_imported(&canonical) {
+                continue;
+            }
+
+            let main = self.load_file(canonical, loading).map_err(wrap)?;
+            self.run(main).map_err(wrap)?;
+        }
+
+        let backup = self.types.snapshot();
         let main = self.types.process(ast)?;
-        self.types.dump();
 
-        // self.functions.clear();
+        // like a typeck error, a codegen error doesn't keep anything from `ast`
+        if let Err(err) = self.generate() {
+            self.discard_new(backup);
+            return Err(err);
+        }
+
+        Ok(main)
+    }
+
+    /// generates the functions and globals that typeck added since the last call
+    fn generate(&mut self) -> Result<()> {
+        self.types.validate().map_err(Error::InvalidIr)?;
+
+        self.open_module()?;
+
+        // functions and globals from the earlier `add` calls are already compiled,
+        // only the new ones get generated
         self.functions.reserve(self.types.functions().len());
 
-        // generate all function prototypes
+        // declare the externs from the source code,
+        // the JIT engine links them to the symbols that already exist
+
+        for (name, func_id) in self.types.externs() {
+            if self.functions.vals[func_id.0].is_some() {
+                continue;
+            }
+
+            let proto = to_prototype(self, &self.types, self.types.get_function(func_id));
+            let func = self.module.add_function(name, proto, None);
+            if let Some(addr) = self.symbols.get(name) {
+                self.engine.add_global_mapping(&func, *addr);
+            }
+
+            self.functions.set(func_id, func);
+        }
+
+        // generate the new function prototypes
 
+        let mut new_functions = Vec::new();
         for (i, func) in self.types.functions().iter().enumerate() {
-            if func.is_extern {
+            if func.is_extern
+                || self.functions.vals[i].is_some()
+                || self.types.is_removed(FuncId(i))
+            {
                 continue;
             }
 
+            let name = match func.name.as_deref() {
+                Some(name) if !self.is_symbol_taken(name) => name.to_string(),
+                // redefined, the old function keeps its symbol for the code that still calls it
+                Some(name) => format!("{name}<{i}>"),
+                None => format!("<anon_{i}>"),
+            };
+
             let proto = to_prototype(self, &self.types, func);
-            let func = self
-                .module
-                .add_function("fixme-keep-function-name", proto, None);
+            let func = self.module.add_function(&name, proto, None);
 
             self.functions.set(FuncId(i), func);
+            new_functions.push(FuncId(i));
+        }
+
+        // generate the new globals
+
+        self.globals.reserve(self.types.globals().len());
+        for (global_id, ty) in self.types.globals() {
+            if self.globals.vals[global_id.0].is_some() {
+                continue;
+            }
+
+            let ty = self.types.get_type(ty).as_llvm(self).unwrap();
+            let name = format!("<global_{}>", global_id.0);
+            let global = self.module.add_global(ty, None, &name);
+            // `static mut` globals start with their value, the others are set by the code
+            match self.types.get_global_init(global_id) {
+                Some(init) => global.set_initializer(&init.as_llvm_const(self).unwrap()),
+                None => global.set_initializer(&ty.const_zero()),
+            }
+
+            self.globals.set(global_id, global);
         }
 
         // compile all functions
@@ -247,26 +635,23 @@ impl ModuleGen {
                     None
                 }
             }
+        }
 
-            fn as_function_value(&self) -> Option<&FunctionValue<'static>> {
-                if let Self::FunctionValue(v) = self {
-                    Some(v)
-                } else {
-                    None
-                }
-            }
+        #[derive(Clone, Copy)]
+        enum Variable {
+            /// an alloca that the variable is loaded from and stored to
+            Ptr(PointerValue<'static>),
+            /// a variable that never changes is just its value
+            Value(BasicValueEnum<'static>),
         }
 
         let mut tmp_map: IdMap<TmpId, FuncOr<BasicValueEnum>> = IdMap::new();
-        let mut var_map: IdMap<VarId, FuncOr<PointerValue>> = IdMap::new();
+        let mut var_map: IdMap<VarId, FuncOr<Variable>> = IdMap::new();
         let mut block_map: IdMap<BlockId, BasicBlock> = IdMap::new();
 
-        for (i, func) in self.types.functions().iter().enumerate() {
-            if func.is_extern {
-                continue;
-            }
-
-            let func_val = *self.functions.get(FuncId(i));
+        for &func_id in new_functions.iter() {
+            let func = self.types.get_function(func_id);
+            let func_val = *self.functions.get(func_id);
 
             let entry = self.ctx.append_basic_block(func_val, "allocas");
             self.alloca_builder.position_at_end(entry);
@@ -278,6 +663,9 @@ impl ModuleGen {
             var_map.reserve(func.variables.len());
             block_map.reserve(func.blocks.len());
 
+            let immutable = immutable_variables(func);
+            let mut canaries = Vec::new();
+
             for (block_id, _) in func.blocks() {
                 let block = self.ctx.append_basic_block(
                     func_val,
@@ -296,14 +684,13 @@ impl ModuleGen {
                 for stmt in code_block.stmts.iter() {
                     match stmt {
                         Statement::Let { dst, src } => match *tmp_map.get(*src) {
+                            FuncOr::T(val) if immutable[dst.0] => {
+                                var_map.set(*dst, FuncOr::T(Variable::Value(val)));
+                            }
                             FuncOr::T(val) => {
-                                let ty = val.get_type();
-                                let ptr = self
-                                    .alloca_builder
-                                    .build_alloca(ty, "fixme-keep-variable-name")
-                                    .unwrap();
+                                let ptr = self.build_variable_alloca(val.get_type(), &mut canaries);
 
-                                var_map.set(*dst, FuncOr::T(ptr));
+                                var_map.set(*dst, FuncOr::T(Variable::Ptr(ptr)));
                                 self.builder.build_store(ptr, val).unwrap();
                             }
                             FuncOr::FunctionValue(val) => {
@@ -311,10 +698,9 @@ impl ModuleGen {
                             }
                         },
                         Statement::Store { dst, src } => {
-                            let ptr = *var_map
-                                .get(*dst)
-                                .as_t()
-                                .expect("cannot mutate a function value");
+                            let Some(Variable::Ptr(ptr)) = var_map.get(*dst).as_t().copied() else {
+                                panic!("cannot mutate a function value or an immutable variable");
+                            };
                             let val = *tmp_map
                                 .get(*src)
                                 .as_t()
@@ -322,7 +708,10 @@ impl ModuleGen {
                             self.builder.build_store(ptr, val).unwrap();
                         }
                         Statement::Load { dst, src } => match var_map.get(*src) {
-                            FuncOr::T(ptr) => {
+                            FuncOr::T(Variable::Value(val)) => {
+                                tmp_map.set(*dst, FuncOr::T(*val));
+                            }
+                            FuncOr::T(Variable::Ptr(ptr)) => {
                                 let val = self
                                     .builder
                                     .build_load(
@@ -338,17 +727,58 @@ impl ModuleGen {
                                 tmp_map.set(*dst, FuncOr::FunctionValue(*f));
                             }
                         },
+                        Statement::Param { dst, index } => {
+                            let val = func_val.get_nth_param(*index as u32).unwrap();
+                            tmp_map.set(*dst, FuncOr::T(val));
+                        }
+                        Statement::StoreGlobal { dst, src } => {
+                            let ptr = self.global_value(*dst)?.as_pointer_value();
+                            let val = *tmp_map
+                                .get(*src)
+                                .as_t()
+                                .expect("cannot mutate a function value");
+                            self.builder.build_store(ptr, val).unwrap();
+                        }
+                        Statement::LoadGlobal { dst, src } => {
+                            let ptr = self.global_value(*src)?.as_pointer_value();
+                            let val = self
+                                .builder
+                                .build_load(
+                                    self.types.get_type(func.tmp(*dst)).as_llvm(self).unwrap(),
+                                    ptr,
+                                    "fixme-keep-global-name",
+                                )
+                                .unwrap();
+
+                            tmp_map.set(*dst, FuncOr::T(val));
+                        }
+                        Statement::Ref { dst, src } => {
+                            let Some(Variable::Ptr(ptr)) = var_map.get(*src).as_t().copied() else {
+                                panic!(
+                                    "cannot reference a function value or an immutable variable"
+                                );
+                            };
+                            tmp_map.set(*dst, FuncOr::T(ptr.into()));
+                        }
+                        Statement::RefGlobal { dst, src } => {
+                            let ptr = self.global_value(*src)?.as_pointer_value();
+                            tmp_map.set(*dst, FuncOr::T(ptr.into()));
+                        }
                         Statement::Extern { dst, src, .. } => {
-                            let func = *self.functions.get(*src);
+                            let func = self.function_value(*src)?;
                             tmp_map.set(*dst, FuncOr::FunctionValue(func));
                         }
                         Statement::Func { dst, src } => {
-                            let func = *self.functions.get(*src);
+                            let func = self.function_value(*src)?;
                             tmp_map.set(*dst, FuncOr::FunctionValue(func));
                         }
                         Statement::Const { dst, src } => {
                             tmp_map.set(*dst, FuncOr::T(src.as_llvm_const(self).unwrap()));
                         }
+                        Statement::PoisonUndef { dst } => {
+                            let ty = self.types.get_type(func.tmp(*dst)).as_llvm(self).unwrap();
+                            tmp_map.set(*dst, FuncOr::T(undef(ty)));
+                        }
                         Statement::BinExpr { dst, lhs, op, rhs } => {
                             let lhs_val = *tmp_map
                                 .get(*lhs)
@@ -406,66 +836,122 @@ impl ModuleGen {
                                     )
                                     .unwrap()
                                     .as_basic_value_enum(),
-                                (Type::I32, BinaryOp::Ge) => self
+                                (Type::I64, BinaryOp::Add) => self
                                     .builder
-                                    .build_int_compare(
-                                        inkwell::IntPredicate::SGE,
+                                    .build_int_add(
                                         lhs_val.into_int_value(),
                                         rhs_val.into_int_value(),
-                                        "builtin-i32-ge",
+                                        "builtin-i64-add",
                                     )
                                     .unwrap()
                                     .as_basic_value_enum(),
-                                (Type::I32, BinaryOp::Le) => self
+                                (Type::I64, BinaryOp::Sub) => self
                                     .builder
-                                    .build_int_compare(
-                                        inkwell::IntPredicate::SLE,
+                                    .build_int_sub(
                                         lhs_val.into_int_value(),
                                         rhs_val.into_int_value(),
-                                        "builtin-i32-le",
+                                        "builtin-i64-sub",
                                     )
                                     .unwrap()
                                     .as_basic_value_enum(),
-                                (Type::I32, BinaryOp::Gt) => self
+                                (Type::I64, BinaryOp::Mul) => self
                                     .builder
-                                    .build_int_compare(
-                                        inkwell::IntPredicate::SLT,
+                                    .build_int_mul(
                                         lhs_val.into_int_value(),
                                         rhs_val.into_int_value(),
-                                        "builtin-i32-gt",
+                                        "builtin-i64-mul",
                                     )
                                     .unwrap()
                                     .as_basic_value_enum(),
-                                (Type::I32, BinaryOp::Lt) => self
+                                (Type::I64, BinaryOp::Div) => self
                                     .builder
-                                    .build_int_compare(
-                                        inkwell::IntPredicate::SLT,
+                                    .build_int_signed_div(
                                         lhs_val.into_int_value(),
                                         rhs_val.into_int_value(),
-                                        "builtin-i32-lt",
+                                        "builtin-i64-div",
                                     )
                                     .unwrap()
                                     .as_basic_value_enum(),
-                                (Type::I32, BinaryOp::Eq) => self
+                                (Type::I64, BinaryOp::Rem) => self
                                     .builder
-                                    .build_int_compare(
-                                        inkwell::IntPredicate::EQ,
+                                    .build_int_signed_rem(
+                                        lhs_val.into_int_value(),
+                                        rhs_val.into_int_value(),
+                                        "builtin-i64-rem",
+                                    )
+                                    .unwrap()
+                                    .as_basic_value_enum(),
+                                (Type::USize, BinaryOp::Add) => self
+                                    .builder
+                                    .build_int_add(
+                                        lhs_val.into_int_value(),
+                                        rhs_val.into_int_value(),
+                                        "builtin-usize-add",
+                                    )
+                                    .unwrap()
+                                    .as_basic_value_enum(),
+                                (Type::USize, BinaryOp::Sub) => self
+                                    .builder
+                                    .build_int_sub(
+                                        lhs_val.into_int_value(),
+                                        rhs_val.into_int_value(),
+                                        "builtin-usize-sub",
+                                    )
+                                    .unwrap()
+                                    .as_basic_value_enum(),
+                                (Type::USize, BinaryOp::Mul) => self
+                                    .builder
+                                    .build_int_mul(
+                                        lhs_val.into_int_value(),
+                                        rhs_val.into_int_value(),
+                                        "builtin-usize-mul",
+                                    )
+                                    .unwrap()
+                                    .as_basic_value_enum(),
+                                (Type::USize, BinaryOp::Div) => self
+                                    .builder
+                                    .build_int_unsigned_div(
                                         lhs_val.into_int_value(),
                                         rhs_val.into_int_value(),
-                                        "builtin-i32-eq",
+                                        "builtin-usize-div",
                                     )
                                     .unwrap()
                                     .as_basic_value_enum(),
-                                (Type::I32, BinaryOp::Neq) => self
+                                (Type::USize, BinaryOp::Rem) => self
+                                    .builder
+                                    .build_int_unsigned_rem(
+                                        lhs_val.into_int_value(),
+                                        rhs_val.into_int_value(),
+                                        "builtin-usize-rem",
+                                    )
+                                    .unwrap()
+                                    .as_basic_value_enum(),
+                                (
+                                    Type::I32 | Type::I64 | Type::USize,
+                                    BinaryOp::Lt
+                                    | BinaryOp::Le
+                                    | BinaryOp::Gt
+                                    | BinaryOp::Ge
+                                    | BinaryOp::Eq
+                                    | BinaryOp::Neq,
+                                )
+                                | (Type::Bool, BinaryOp::Eq | BinaryOp::Neq) => self
                                     .builder
                                     .build_int_compare(
-                                        inkwell::IntPredicate::NE,
+                                        int_predicate(*op, matches!(ty, Type::I32 | Type::I64)),
                                         lhs_val.into_int_value(),
                                         rhs_val.into_int_value(),
-                                        "builtin-i32-eq",
+                                        &int_compare_name(*ty, *op),
                                     )
                                     .unwrap()
                                     .as_basic_value_enum(),
+                                (Type::Str, BinaryOp::Eq | BinaryOp::Neq) => self
+                                    .build_str_eq(
+                                        lhs_val.into_struct_value(),
+                                        rhs_val.into_struct_value(),
+                                        *op == BinaryOp::Neq,
+                                    )
+                                    .as_basic_value_enum(),
                                 (Type::Bool, BinaryOp::And) => self
                                     .builder
                                     .build_and(
@@ -485,74 +971,411 @@ impl ModuleGen {
                                     .unwrap()
                                     .as_basic_value_enum(),
                                 _ => {
-                                    todo!("invalid operation: {ty:?} {}", op.as_str());
+                                    return Err(Error::UnsupportedOperation { ty: *ty, op: *op });
                                 }
                             };
 
                             tmp_map.set(*dst, FuncOr::T(res));
                         }
-                        Statement::Call { dst, func, args } => {
-                            let tmp = tmp_map
-                                .get(*func)
-                                .as_function_value()
-                                .expect("cannot call a non function");
-
-                            let args: Box<[_]> = args
-                                .iter()
-                                .map(|arg| {
-                                    tmp_map
-                                        .get(*arg)
-                                        .as_t()
-                                        .expect("cannot use functions as arguments")
-                                        .as_basic_value_enum()
-                                        .into()
-                                })
-                                .collect();
-
+                        Statement::Select {
+                            dst,
+                            cond,
+                            if_true,
+                            if_false,
+                        } => {
+                            let [cond, if_true, if_false] = [cond, if_true, if_false].map(|tmp| {
+                                *tmp_map
+                                    .get(*tmp)
+                                    .as_t()
+                                    .expect("cannot select a function value")
+                            });
                             let val = self
                                 .builder
-                                .build_direct_call(*tmp, &args, "fixme-keep-function-names")
+                                .build_select(
+                                    cond.into_int_value(),
+                                    if_true,
+                                    if_false,
+                                    "fixme-keep-variable-name",
+                                )
                                 .unwrap();
-
-                            let val = match val.try_as_basic_value().left() {
-                                Some(val) => val,
-                                None => context().struct_type(&[], false).const_zero().into(),
-                            };
-
                             tmp_map.set(*dst, FuncOr::T(val));
                         }
-                        Statement::Return { .. } => todo!(),
-                        Statement::ReturnVoid => {
-                            self.builder.build_return(None).unwrap();
+                        Statement::Tuple { dst, elems } => {
+                            let ty = self
+                                .types
+                                .get_type(func.tmp(*dst))
+                                .as_llvm(self)
+                                .unwrap()
+                                .into_struct_type();
+
+                            let mut tuple = ty.get_undef();
+                            for (i, elem) in elems.iter().enumerate() {
+                                let val = *tmp_map
+                                    .get(*elem)
+                                    .as_t()
+                                    .expect("cannot use functions as tuple fields");
+                                tuple = self
+                                    .builder
+                                    .build_insert_value(tuple, val, i as u32, "tuple")
+                                    .unwrap()
+                                    .into_struct_value();
+                            }
+
+                            tmp_map.set(*dst, FuncOr::T(tuple.as_basic_value_enum()));
                         }
-                        Statement::UnconditionalJump { id } => {
-                            let block = *block_map.get(*id);
-                            self.builder.build_unconditional_branch(block).unwrap();
+                        Statement::Field { dst, src, index } => {
+                            let tuple = tmp_map
+                                .get(*src)
+                                .as_t()
+                                .expect("cannot use functions as tuples")
+                                .into_struct_value();
+                            let val = self
+                                .builder
+                                .build_extract_value(tuple, *index as u32, "tuple-field")
+                                .unwrap();
+
+                            tmp_map.set(*dst, FuncOr::T(val));
                         }
-                        Statement::ConditionalJump {
-                            bool,
-                            then_block,
-                            else_block,
-                        } => {
-                            let bool = tmp_map
-                                .get(*bool)
+                        Statement::Cast { dst, src } => {
+                            let val = tmp_map
+                                .get(*src)
                                 .as_t()
-                                .expect("cannot use functions as values")
+                                .expect("cannot cast a function value")
                                 .into_int_value();
-                            let then_block = *block_map.get(*then_block);
-                            let else_block = *block_map.get(*else_block);
-                            self.builder
-                                .build_conditional_branch(bool, then_block, else_block)
+                            let ty = self
+                                .types
+                                .get_type(func.tmp(*dst))
+                                .as_llvm(self)
+                                .unwrap()
+                                .into_int_type();
+                            // `i32` and `i64` are sign extended, `usize` is zero extended
+                            let signed = matches!(
+                                self.types.get_type(func.tmp(*src)),
+                                Type::I32 | Type::I64
+                            );
+                            let val = self
+                                .builder
+                                .build_int_cast_sign_flag(val, ty, signed, "cast")
                                 .unwrap();
+
+                            tmp_map.set(*dst, FuncOr::T(val.into()));
                         }
-                    } // match
-                } // for
-            } // for
+                        Statement::Index { dst, src, index } => {
+                            let slice = tmp_map
+                                .get(*src)
+                                .as_t()
+                                .expect("cannot index a function value")
+                                .into_struct_value();
+                            let index = tmp_map
+                                .get(*index)
+                                .as_t()
+                                .expect("cannot index with a function value")
+                                .into_int_value();
+                            let elem_ty =
+                                self.types.get_type(func.tmp(*dst)).as_llvm(self).unwrap();
 
-            self.alloca_builder
-                .build_unconditional_branch(*block_map.get(BlockId(0)))
-                .unwrap();
-            // self.builder.build_return(None).unwrap();
+                            let len = self
+                                .builder
+                                .build_extract_value(slice, 0, "slice-len")
+                                .unwrap()
+                                .into_int_value();
+                            let ptr = self
+                                .builder
+                                .build_extract_value(slice, 1, "slice-ptr")
+                                .unwrap()
+                                .into_pointer_value();
+
+                            // out of bounds indices trap, the rest of the block continues in `ok`
+                            let in_bounds = self
+                                .builder
+                                .build_int_compare(IntPredicate::ULT, index, len, "in-bounds")
+                                .unwrap();
+                            let ok = self.ctx.append_basic_block(func_val, "index-ok");
+                            let oob = self.ctx.append_basic_block(func_val, "index-oob");
+                            self.builder
+                                .build_conditional_branch(in_bounds, ok, oob)
+                                .unwrap();
+                            self.builder.position_at_end(oob);
+                            self.build_trap();
+                            self.builder.position_at_end(ok);
+
+                            // SAFETY: the index was checked against the length above
+                            let elem_ptr = unsafe {
+                                self.builder
+                                    .build_in_bounds_gep(elem_ty, ptr, &[index], "elem-ptr")
+                                    .unwrap()
+                            };
+                            let val = self.builder.build_load(elem_ty, elem_ptr, "elem").unwrap();
+
+                            tmp_map.set(*dst, FuncOr::T(val));
+                        }
+                        Statement::LoadRef { dst, src } => {
+                            let ptr = tmp_map
+                                .get(*src)
+                                .as_t()
+                                .expect("cannot load from a function value")
+                                .into_pointer_value();
+                            let ty = self.types.get_type(func.tmp(*dst)).as_llvm(self).unwrap();
+                            let val = self.builder.build_load(ty, ptr, "deref").unwrap();
+
+                            tmp_map.set(*dst, FuncOr::T(val));
+                        }
+                        Statement::StoreRef { dst, src } => {
+                            let ptr = tmp_map
+                                .get(*dst)
+                                .as_t()
+                                .expect("cannot store to a function value")
+                                .into_pointer_value();
+                            let val = tmp_map
+                                .get(*src)
+                                .as_t()
+                                .expect("cannot store a function value through a reference");
+                            self.builder.build_store(ptr, *val).unwrap();
+                        }
+                        Statement::AtomicLoad { dst, ptr, ordering } => {
+                            let ptr = tmp_map
+                                .get(*ptr)
+                                .as_t()
+                                .expect("cannot load from a function value")
+                                .into_pointer_value();
+                            let ty = self
+                                .types
+                                .get_type(func.tmp(*dst))
+                                .as_llvm(self)
+                                .unwrap()
+                                .into_int_type();
+                            let val = self.builder.build_load(ty, ptr, "atomic-load").unwrap();
+                            make_atomic(val.as_instruction_value().unwrap(), ty, *ordering);
+
+                            tmp_map.set(*dst, FuncOr::T(val));
+                        }
+                        Statement::AtomicStore { ptr, src, ordering } => {
+                            let ptr = tmp_map
+                                .get(*ptr)
+                                .as_t()
+                                .expect("cannot store to a function value")
+                                .into_pointer_value();
+                            let val = tmp_map
+                                .get(*src)
+                                .as_t()
+                                .expect("cannot store a function value atomically")
+                                .into_int_value();
+                            let store = self.builder.build_store(ptr, val).unwrap();
+                            make_atomic(store, val.get_type(), *ordering);
+                        }
+                        Statement::AtomicRmw {
+                            dst,
+                            ptr,
+                            op,
+                            val,
+                            ordering,
+                        } => {
+                            let ptr = tmp_map
+                                .get(*ptr)
+                                .as_t()
+                                .expect("cannot modify a function value")
+                                .into_pointer_value();
+                            let val = tmp_map
+                                .get(*val)
+                                .as_t()
+                                .expect("cannot use a function value atomically")
+                                .into_int_value();
+                            let old = self
+                                .builder
+                                .build_atomicrmw(
+                                    atomic_rmw_op(*op),
+                                    ptr,
+                                    val,
+                                    atomic_ordering(*ordering),
+                                )
+                                .unwrap();
+
+                            tmp_map.set(*dst, FuncOr::T(old.into()));
+                        }
+                        Statement::CompareExchange {
+                            dst,
+                            ptr,
+                            expected,
+                            desired,
+                            success_order,
+                            fail_order,
+                        } => {
+                            let ptr = tmp_map
+                                .get(*ptr)
+                                .as_t()
+                                .expect("cannot modify a function value")
+                                .into_pointer_value();
+                            let [expected, desired] = [expected, desired].map(|tmp| {
+                                tmp_map
+                                    .get(*tmp)
+                                    .as_t()
+                                    .expect("cannot use a function value atomically")
+                                    .into_int_value()
+                            });
+                            // `{ iN, i1 }` is already the layout of the `(old, ok)` tuple
+                            let old_ok = self
+                                .builder
+                                .build_cmpxchg(
+                                    ptr,
+                                    expected,
+                                    desired,
+                                    atomic_ordering(*success_order),
+                                    atomic_ordering(*fail_order),
+                                )
+                                .unwrap();
+
+                            tmp_map.set(*dst, FuncOr::T(old_ok.into()));
+                        }
+                        Statement::Fence { ordering } => {
+                            self.builder
+                                .build_fence(atomic_ordering(*ordering), 0, "fence")
+                                .unwrap();
+                        }
+                        Statement::Call {
+                            dst,
+                            func: callee,
+                            args,
+                        } => {
+                            // C promotes the extra arguments of variadic functions,
+                            // a `bool` is passed as an `int`
+                            let fixed_params = match *self.types.get_type(func.tmp(*callee)) {
+                                Type::Func(id) if self.types.get_function(id).is_variadic => {
+                                    self.types.get_function(id).params.len()
+                                }
+                                _ => usize::MAX,
+                            };
+
+                            let args: Box<[_]> = args
+                                .iter()
+                                .enumerate()
+                                .map(|(i, arg)| match *tmp_map.get(*arg) {
+                                    FuncOr::T(BasicValueEnum::IntValue(val))
+                                        if i >= fixed_params
+                                            && val.get_type().get_bit_width() == 1 =>
+                                    {
+                                        self.builder
+                                            .build_int_z_extend(
+                                                val,
+                                                self.ctx.i32_type(),
+                                                "variadic-bool",
+                                            )
+                                            .unwrap()
+                                            .into()
+                                    }
+                                    FuncOr::T(val) => val.into(),
+                                    // functions are passed as function pointers
+                                    FuncOr::FunctionValue(f) => {
+                                        f.as_global_value().as_pointer_value().into()
+                                    }
+                                })
+                                .collect();
+
+                            let val = match *tmp_map.get(*callee) {
+                                FuncOr::FunctionValue(f) => self
+                                    .builder
+                                    .build_direct_call(f, &args, "fixme-keep-function-names")
+                                    .unwrap(),
+                                FuncOr::T(ptr) => {
+                                    let Type::FnPtr(sig) = *self.types.get_type(func.tmp(*callee))
+                                    else {
+                                        unreachable!("typeck allows calling only functions");
+                                    };
+
+                                    self.builder
+                                        .build_indirect_call(
+                                            signature_type(self, sig),
+                                            ptr.into_pointer_value(),
+                                            &args,
+                                            "fixme-keep-function-names",
+                                        )
+                                        .unwrap()
+                                }
+                            };
+
+                            let val = match val.try_as_basic_value().left() {
+                                Some(val) => val,
+                                None => context().struct_type(&[], false).const_zero().into(),
+                            };
+
+                            tmp_map.set(*dst, FuncOr::T(val));
+                        }
+                        Statement::Return { src } => {
+                            if self.types.get_type(func.returns).as_llvm(self).is_some() {
+                                let val = tmp_map
+                                    .get(*src)
+                                    .as_t()
+                                    .expect("cannot return a function value");
+                                self.builder.build_return(Some(val)).unwrap();
+                            } else {
+                                // `return f();` where `f` returns void
+                                self.builder.build_return(None).unwrap();
+                            }
+                        }
+                        Statement::Nop => {}
+                        Statement::ReturnVoid => {
+                            self.builder.build_return(None).unwrap();
+                        }
+                        Statement::Unreachable => {
+                            self.builder.build_unreachable().unwrap();
+                        }
+                        Statement::Trap => {
+                            self.build_trap();
+                        }
+                        Statement::UnconditionalJump { id } => {
+                            let block = *block_map.get(*id);
+                            self.builder.build_unconditional_branch(block).unwrap();
+                        }
+                        Statement::ConditionalJump {
+                            bool,
+                            then_block,
+                            else_block,
+                        } => {
+                            let bool = tmp_map
+                                .get(*bool)
+                                .as_t()
+                                .expect("cannot use functions as values")
+                                .into_int_value();
+                            let then_block = *block_map.get(*then_block);
+                            let else_block = *block_map.get(*else_block);
+                            self.builder
+                                .build_conditional_branch(bool, then_block, else_block)
+                                .unwrap();
+                        }
+                        Statement::SwitchInt {
+                            val,
+                            default,
+                            cases,
+                        } => {
+                            let val = tmp_map
+                                .get(*val)
+                                .as_t()
+                                .expect("cannot use functions as values")
+                                .into_int_value();
+                            let default = *block_map.get(*default);
+                            let cases: Vec<_> = cases
+                                .iter()
+                                .map(|(case, block)| {
+                                    let case = val.get_type().const_int(*case as u64, true);
+                                    (case, *block_map.get(*block))
+                                })
+                                .collect();
+                            self.builder.build_switch(val, default, &cases).unwrap();
+                        }
+                    } // match
+
+                    // anything after the terminator is dead code, LLVM blocks can't continue past it
+                    if stmt.is_terminal() {
+                        break;
+                    }
+                } // for
+            } // for
+
+            self.alloca_builder
+                .build_unconditional_branch(*block_map.get(BlockId(0)))
+                .unwrap();
+            // self.builder.build_return(None).unwrap();
+
+            self.build_canary_checks(func_val, &canaries);
 
             if !func_val.verify(true) {
                 eprintln!("LLVM IR:\n");
@@ -561,19 +1384,876 @@ impl ModuleGen {
             }
         }
 
-        Ok(main)
+        self.remove_replaced(&new_functions);
+
+        Ok(())
+    }
+
+    /// goes back to the typeck `backup` from before a failed [`Self::generate`]
+    /// and deletes the LLVM functions and globals generated for the newer ids
+    fn discard_new(&mut self, backup: typeck::Snapshot) {
+        let (new_functions, new_globals) =
+            (self.types.functions().len(), self.types.globals().len());
+        self.types.restore(backup);
+
+        let functions: Vec<_> = (self.types.functions().len()..new_functions)
+            .filter_map(|i| self.functions.remove(FuncId(i)))
+            .collect();
+        // the bodies go first, they can call each other and use the new globals
+        for func in functions.iter() {
+            for block in func.get_basic_blocks() {
+                unsafe { _ = block.delete() };
+            }
+        }
+        for func in functions {
+            unsafe { func.delete() };
+        }
+
+        for i in self.types.globals().len()..new_globals {
+            if let Some(global) = self.globals.remove(GlobalId(i)) {
+                unsafe { global.delete() };
+            }
+        }
+    }
+
+    /// `true` if a function that wasn't removed already uses the symbol `name`
+    fn is_symbol_taken(&self, name: &str) -> bool {
+        self.functions
+            .vals
+            .iter()
+            .enumerate()
+            .filter(|(i, _)| !self.freed.contains(&FuncId(*i)))
+            .filter_map(|(_, func)| func.as_ref())
+            .any(|func| func.get_name().to_bytes() == name.as_bytes())
+    }
+
+    /// deletes the new functions that were already redefined and aren't used,
+    /// like the first `f` in `f := fn() {}; f := fn() {};`
+    ///
+    /// the functions in older modules are already compiled, so those stay
+    fn remove_replaced(&mut self, new_functions: &[FuncId]) {
+        for &func_id in new_functions {
+            let Some(name) = self.types.get_function(func_id).name.as_deref() else {
+                continue;
+            };
+            if self.types.get_static(name) == Some(Static::Func(func_id)) {
+                continue;
+            }
+
+            let func = *self.functions.get(func_id);
+            if func
+                .as_global_value()
+                .as_pointer_value()
+                .get_first_use()
+                .is_none()
+            {
+                unsafe { func.delete() };
+                self.freed.insert(func_id);
+            }
+        }
+    }
+
+    /// throws away everything added so far and starts over with a fresh module,
+    /// only the [`Context`] and the symbols from [`Self::define_symbol`] are kept
+    ///
+    /// functions from the earlier modules can't be called anymore
+    pub fn reset(&mut self) -> Result<()> {
+        let symbols = mem::take(&mut self.symbols);
+        let warnings_as_errors = self.types.warnings_as_errors();
+        let stack_canaries = self.stack_canaries;
+        *self = Self::new(self.ctx)?;
+        self.symbols = symbols;
+        self.types.set_warnings_as_errors(warnings_as_errors);
+        self.stack_canaries = stack_canaries;
+        Ok(())
+    }
+
+    /// the address of the compiled `func`, for calling it from Rust
+    ///
+    /// the address is an `extern "C" fn` with the params and return type of `func`,
+    /// it stays valid until the function is freed or the module is reset
+    pub fn get_fn_ptr(&mut self, func: FuncId) -> Result<usize> {
+        if self.freed.contains(&func) {
+            return Err(Error::Codegen(format!("function {} was freed", func.0)));
+        }
+        if self.types.get_function(func).is_extern {
+            return Err(Error::Codegen(format!("function {} is an extern", func.0)));
+        }
+        let value = *self
+            .functions
+            .try_get(func)
+            .ok_or_else(|| Error::Codegen(format!("function {} was not generated", func.0)))?;
+
+        self.module.verify().unwrap();
+
+        self.sealed = true;
+        let name = value.get_name().to_str().unwrap();
+        self.engine
+            .get_function_address(name)
+            .map_err(|err| Error::Codegen(err.to_string()))
+    }
+
+    /// calls the compiled `func` that takes only `i32` params and returns an `i32`
+    ///
+    /// the signature is checked against the type checker, so a wrong argument count is an error
+    pub fn call_i32(&mut self, func: FuncId, args: &[i32]) -> Result<i32> {
+        let function = self.types.get_function(func);
+        let params = function.params.len();
+        if params != args.len() {
+            return Err(Error::ArityMismatch {
+                expected: params,
+                got: args.len(),
+            });
+        }
+
+        let is_i32 = |ty| *self.types.get_type(ty) == Type::I32;
+        if !is_i32(function.returns) || !function.params.iter().all(|&ty| is_i32(ty)) {
+            return Err(Error::Codegen(format!(
+                "function {} doesn't take and return only i32",
+                func.0
+            )));
+        }
+
+        let addr = self.get_fn_ptr(func)?;
+
+        // SAFETY: the signature was checked above
+        let res = unsafe {
+            match *args {
+                [] => mem::transmute::<usize, extern "C" fn() -> i32>(addr)(),
+                [a] => mem::transmute::<usize, extern "C" fn(i32) -> i32>(addr)(a),
+                [a, b] => mem::transmute::<usize, extern "C" fn(i32, i32) -> i32>(addr)(a, b),
+                [a, b, c] => {
+                    mem::transmute::<usize, extern "C" fn(i32, i32, i32) -> i32>(addr)(a, b, c)
+                }
+                [a, b, c, d] => mem::transmute::<usize, extern "C" fn(i32, i32, i32, i32) -> i32>(
+                    addr,
+                )(a, b, c, d),
+                _ => {
+                    return Err(Error::Codegen(format!(
+                        "calling a function with {} arguments is not supported",
+                        args.len()
+                    )))
+                }
+            }
+        };
+
+        Ok(res)
+    }
+
+    /// frees the machine code of `func`, calling it from new code is an error afterwards
+    ///
+    /// MCJIT might still keep the memory until the engine is dropped, see [`Self::reset`]
+    ///
+    /// # Safety
+    /// `func` can't be running, and nothing compiled earlier may call it anymore
+    pub unsafe fn free_function(&mut self, func: FuncId) -> Result<()> {
+        if self.freed.contains(&func) {
+            return Err(Error::Codegen(format!("function {} was freed", func.0)));
+        }
+        let value = *self
+            .functions
+            .try_get(func)
+            .ok_or_else(|| Error::Codegen(format!("function {} was not generated", func.0)))?;
+
+        self.engine.free_fn_machine_code(value);
+        self.freed.insert(func);
+        Ok(())
+    }
+
+    /// starts a new LLVM module in the same JIT engine, if the current one was already compiled
+    ///
+    /// MCJIT never compiles code added to a module after it was compiled
+    fn open_module(&mut self) -> Result<()> {
+        if !self.sealed {
+            return Ok(());
+        }
+
+        let module = self.ctx.create_module("<run>");
+        self.engine
+            .add_module(&module)
+            .map_err(|_| Error::Codegen("failed to add a module to the JIT".to_string()))?;
+
+        self.module = module;
+        self.sealed = false;
+        Ok(())
+    }
+
+    /// `func` usable in the current module,
+    /// functions from the earlier modules are declared and linked by name
+    fn function_value(&self, id: FuncId) -> Result<FunctionValue<'static>> {
+        if self.freed.contains(&id) {
+            return Err(Error::Codegen(format!("function {} was freed", id.0)));
+        }
+
+        let func = *self
+            .functions
+            .try_get(id)
+            .ok_or_else(|| Error::Codegen(format!("function {} was not generated", id.0)))?;
+        let name = func.get_name().to_str().unwrap();
+
+        Ok(self
+            .module
+            .get_function(name)
+            .unwrap_or_else(|| self.module.add_function(name, func.get_type(), None)))
+    }
+
+    /// `lhs == rhs` for strings, or `lhs != rhs` if `negate` is set
+    ///
+    /// the lengths are compared first, `memcmp` only compares the bytes of equal length strings
+    fn build_str_eq(
+        &self,
+        lhs: StructValue<'static>,
+        rhs: StructValue<'static>,
+        negate: bool,
+    ) -> IntValue<'static> {
+        // memcmp(a: ptr, b: ptr, n: usize) -> i32, linked from the process like the source externs
+        let ty_usize = Type::USize.as_llvm(self).unwrap().into_int_type();
+        let ty_ptr = self.ctx.i8_type().ptr_type(AddressSpace::default());
+        let memcmp = self.module.get_function("memcmp").unwrap_or_else(|| {
+            let ty = self
+                .ctx
+                .i32_type()
+                .fn_type(&[ty_ptr.into(), ty_ptr.into(), ty_usize.into()], false);
+            self.module.add_function("memcmp", ty, None)
+        });
+
+        let [(lhs_len, lhs_ptr), (rhs_len, rhs_ptr)] = [lhs, rhs].map(|str| {
+            let len = self.builder.build_extract_value(str, 0, "str-len").unwrap();
+            let ptr = self.builder.build_extract_value(str, 1, "str-ptr").unwrap();
+            (len.into_int_value(), ptr)
+        });
+
+        let len_eq = self
+            .builder
+            .build_int_compare(IntPredicate::EQ, lhs_len, rhs_len, "builtin-str-len-eq")
+            .unwrap();
+        let len = self
+            .builder
+            .build_select(
+                len_eq,
+                lhs_len,
+                ty_usize.const_zero(),
+                "builtin-str-cmp-len",
+            )
+            .unwrap();
+        let cmp = self
+            .builder
+            .build_call(
+                memcmp,
+                &[lhs_ptr.into(), rhs_ptr.into(), len.into()],
+                "builtin-str-memcmp",
+            )
+            .unwrap()
+            .try_as_basic_value()
+            .unwrap_left()
+            .into_int_value();
+        let bytes_eq = self
+            .builder
+            .build_int_compare(
+                IntPredicate::EQ,
+                cmp,
+                self.ctx.i32_type().const_zero(),
+                "builtin-str-bytes-eq",
+            )
+            .unwrap();
+
+        let eq = self
+            .builder
+            .build_and(len_eq, bytes_eq, "builtin-str-eq")
+            .unwrap();
+        if negate {
+            self.builder.build_not(eq, "builtin-str-neq").unwrap()
+        } else {
+            eq
+        }
+    }
+
+    /// the stack slot of a mutable variable
+    ///
+    /// with [`Self::insert_stack_canaries`] the slot is `{ i32, T, i32 }`,
+    /// the variable sits between two canaries that are added to `canaries`
+    fn build_variable_alloca(
+        &self,
+        ty: BasicTypeEnum<'static>,
+        canaries: &mut Vec<PointerValue<'static>>,
+    ) -> PointerValue<'static> {
+        if !self.stack_canaries {
+            return self
+                .alloca_builder
+                .build_alloca(ty, "fixme-keep-variable-name")
+                .unwrap();
+        }
+
+        let ty_canary = self.ctx.i32_type();
+        let guarded = self
+            .ctx
+            .struct_type(&[ty_canary.into(), ty, ty_canary.into()], false);
+        let slot = self
+            .alloca_builder
+            .build_alloca(guarded, "guarded-variable")
+            .unwrap();
+
+        for field in [0, 2] {
+            let canary = self
+                .alloca_builder
+                .build_struct_gep(guarded, slot, field, "canary")
+                .unwrap();
+            // volatile, so that LLVM can't see that the value never changes
+            self.alloca_builder
+                .build_store(canary, ty_canary.const_int(CANARY as u64, false))
+                .unwrap()
+                .set_volatile(true)
+                .unwrap();
+            canaries.push(canary);
+        }
+
+        self.alloca_builder
+            .build_struct_gep(guarded, slot, 1, "fixme-keep-variable-name")
+            .unwrap()
+    }
+
+    /// checks every canary of `func` right before each of its returns
+    fn build_canary_checks(
+        &self,
+        func: FunctionValue<'static>,
+        canaries: &[PointerValue<'static>],
+    ) {
+        if canaries.is_empty() {
+            return;
+        }
+
+        let ty_canary = self.ctx.i32_type();
+        let check = self
+            .module
+            .get_function("builtin-check-canary")
+            .unwrap_or_else(|| {
+                let ty = self.ctx.void_type().fn_type(&[ty_canary.into()], false);
+                let check = self.module.add_function("builtin-check-canary", ty, None);
+                self.engine
+                    .add_global_mapping(&check, check_canary as extern "C" fn(u32) as usize);
+                check
+            });
+
+        for block in func.get_basic_blocks() {
+            let Some(ret) = block
+                .get_terminator()
+                .filter(|term| term.get_opcode() == InstructionOpcode::Return)
+            else {
+                continue;
+            };
+
+            self.builder.position_before(&ret);
+            for canary in canaries {
+                let value = self
+                    .builder
+                    .build_load(ty_canary, *canary, "canary")
+                    .unwrap();
+                value
+                    .as_instruction_value()
+                    .unwrap()
+                    .set_volatile(true)
+                    .unwrap();
+                self.builder.build_call(check, &[value.into()], "").unwrap();
+            }
+        }
+    }
+
+    /// `global` usable in the current module, like [`Self::function_value`]
+    fn global_value(&self, id: GlobalId) -> Result<GlobalValue<'static>> {
+        let global = *self
+            .globals
+            .try_get(id)
+            .ok_or_else(|| Error::Codegen(format!("global {} was not generated", id.0)))?;
+        let name = global.get_name().to_str().unwrap();
+
+        Ok(self.module.get_global(name).unwrap_or_else(|| {
+            let ty = self
+                .types
+                .get_type(self.types.get_global(id))
+                .as_llvm(self)
+                .unwrap();
+            self.module.add_global(ty, None, name)
+        }))
+    }
+
+    pub fn types(&self) -> &typeck::Module {
+        &self.types
+    }
+
+    pub fn take_warnings(&mut self) -> Vec<typeck::Warning> {
+        self.types.take_warnings()
+    }
+
+    /// guards the mutable variables of the functions added after this with stack canaries
+    ///
+    /// each variable gets an `i32` set to `0xDEADBEEF` right before and after it,
+    /// and every return checks that both are intact, panicking if not,
+    /// this is for catching stray writes while debugging, not a security feature
+    pub fn insert_stack_canaries(&mut self) {
+        self.stack_canaries = true;
+    }
+
+    /// fails [`Self::add`] on any new warning, see [`typeck::Module::set_warnings_as_errors`]
+    pub fn set_warnings_as_errors(&mut self, enabled: bool) {
+        self.types.set_warnings_as_errors(enabled);
+    }
+
+    /// the LLVM IR of a compiled function, externs show the wrapper that calls the Rust fn
+    pub fn dump_ir(&self, func: FuncId) -> Option<String> {
+        let func = *self.functions.try_get(func)?;
+        Some(func.print_to_string().to_string())
+    }
+
+    /// puts a compiled function in the COMDAT group `name`, so the linker keeps
+    /// only one of the identical bodies when several objects define it
+    ///
+    /// does nothing if `func_id` was never compiled
+    pub fn set_comdat(&self, func_id: FuncId, name: &str) {
+        if let Some(func) = self.functions.try_get(func_id) {
+            self.set_comdat_of(func.as_global_value(), name);
+        }
+    }
+
+    /// like [`Self::set_comdat`], but for a global variable or constant
+    pub fn set_global_comdat(&self, global_id: GlobalId, name: &str) {
+        if let Some(global) = self.globals.try_get(global_id) {
+            self.set_comdat_of(*global, name);
+        }
+    }
+
+    fn set_comdat_of(&self, global: GlobalValue, name: &str) {
+        let comdat = self.module.get_or_insert_comdat(name);
+        comdat.set_selection_kind(ComdatSelectionKind::ExactMatch);
+        global.set_comdat(comdat);
+    }
+
+    /// `call void @llvm.trap()`, which ends the block
+    fn build_trap(&self) {
+        let trap = Intrinsic::find("llvm.trap")
+            .and_then(|trap| trap.get_declaration(&self.module, &[]))
+            .expect("llvm.trap should always exist");
+        self.builder.build_call(trap, &[], "trap").unwrap();
+        self.builder.build_unreachable().unwrap();
+    }
+
+    /// builtins are written directly in LLVM IR, instead of calling back into Rust
+    fn add_builtins(&mut self) {
+        // len(s: str) -> usize
+        let str_ty = Type::Str.as_llvm_meta(self).unwrap();
+        let len_ty = Type::USize.as_llvm_fn(self, &[str_ty], false);
+        let len = self.module.add_function("len", len_ty, None);
+
+        let func_id = self.types.add_extern("len", Type::USize, &[Type::Str]);
+        self.functions.set_growing(func_id, len);
+
+        let entry = self.ctx.append_basic_block(len, "entry");
+        self.builder.position_at_end(entry);
+        let str = len.get_nth_param(0).unwrap().into_struct_value();
+        let str_len = self.builder.build_extract_value(str, 0, "str-len").unwrap();
+        self.builder.build_return(Some(&str_len)).unwrap();
+
+        // unreachable() -> !, typeck turns the direct calls into Statement::Trap
+        let unreachable_ty = Type::Never.as_llvm_fn(self, &[], false);
+        let unreachable = self
+            .module
+            .add_function("unreachable", unreachable_ty, None);
+
+        let func_id = self.types.add_extern("unreachable", Type::Never, &[]);
+        self.functions.set_growing(func_id, unreachable);
+
+        let entry = self.ctx.append_basic_block(unreachable, "entry");
+        self.builder.position_at_end(entry);
+        self.build_trap();
+
+        // atomic_load(p: &i32) -> i32 and atomic_store(p: &i32, n: i32),
+        // typeck turns the direct calls into atomic statements for every integer type,
+        // the weaker orderings have a suffix, like atomic_load_acquire
+        let i32_ty = self.ctx.i32_type();
+        let ref_i32 = self.types.create_ref_type(Type::I32);
+        let params = [ref_type(self).into(), i32_ty.into()];
+
+        for ordering in [Ordering::Relaxed, Ordering::Acquire, Ordering::SeqCst] {
+            let name = format!("atomic_load{}", ordering.suffix());
+            let load_ty = Type::I32.as_llvm_fn(self, &params[..1], false);
+            let load = self.module.add_function(&name, load_ty, None);
+            let func_id = self.types.add_extern(&name, Type::I32, &[ref_i32]);
+            self.functions.set_growing(func_id, load);
+
+            let entry = self.ctx.append_basic_block(load, "entry");
+            self.builder.position_at_end(entry);
+            let ptr = load.get_nth_param(0).unwrap().into_pointer_value();
+            let val = self.builder.build_load(i32_ty, ptr, "val").unwrap();
+            make_atomic(val.as_instruction_value().unwrap(), i32_ty, ordering);
+            self.builder.build_return(Some(&val)).unwrap();
+        }
+
+        for ordering in [Ordering::Relaxed, Ordering::Release, Ordering::SeqCst] {
+            let name = format!("atomic_store{}", ordering.suffix());
+            let store_ty = Type::Void.as_llvm_fn(self, &params, false);
+            let store = self.module.add_function(&name, store_ty, None);
+            let func_id = self
+                .types
+                .add_extern(&name, Type::Void, &[ref_i32, Type::I32]);
+            self.functions.set_growing(func_id, store);
+
+            let entry = self.ctx.append_basic_block(store, "entry");
+            self.builder.position_at_end(entry);
+            let ptr = store.get_nth_param(0).unwrap().into_pointer_value();
+            let val = store.get_nth_param(1).unwrap().into_int_value();
+            let inst = self.builder.build_store(ptr, val).unwrap();
+            make_atomic(inst, i32_ty, ordering);
+            self.builder.build_return(None).unwrap();
+        }
+
+        let all_orderings = [
+            Ordering::Relaxed,
+            Ordering::Acquire,
+            Ordering::Release,
+            Ordering::SeqCst,
+        ];
+
+        // atomic_add(p: &i32, n: i32) -> i32 and friends return the old value
+        for (base, op) in [
+            ("atomic_add", AtomicRmwOp::Add),
+            ("atomic_sub", AtomicRmwOp::Sub),
+            ("atomic_and", AtomicRmwOp::And),
+            ("atomic_or", AtomicRmwOp::Or),
+            ("atomic_xor", AtomicRmwOp::Xor),
+            ("atomic_xchg", AtomicRmwOp::Xchg),
+        ] {
+            for ordering in all_orderings {
+                let name = format!("{base}{}", ordering.suffix());
+                let rmw_ty = Type::I32.as_llvm_fn(self, &params, false);
+                let rmw = self.module.add_function(&name, rmw_ty, None);
+                let func_id = self
+                    .types
+                    .add_extern(&name, Type::I32, &[ref_i32, Type::I32]);
+                self.functions.set_growing(func_id, rmw);
+
+                let entry = self.ctx.append_basic_block(rmw, "entry");
+                self.builder.position_at_end(entry);
+                let ptr = rmw.get_nth_param(0).unwrap().into_pointer_value();
+                let val = rmw.get_nth_param(1).unwrap().into_int_value();
+                let old = self
+                    .builder
+                    .build_atomicrmw(atomic_rmw_op(op), ptr, val, atomic_ordering(ordering))
+                    .unwrap();
+                self.builder.build_return(Some(&old)).unwrap();
+            }
+        }
+
+        // cmpxchg(p: &i32, expected: i32, desired: i32) -> (i32, bool)
+        let old_ok = self.types.create_tuple_type([Type::I32, Type::Bool].into());
+        for ordering in all_orderings {
+            let name = format!("cmpxchg{}", ordering.suffix());
+            let cmpxchg_ty = old_ok.as_llvm_fn(self, &[params[0], params[1], params[1]], false);
+            let cmpxchg = self.module.add_function(&name, cmpxchg_ty, None);
+            let func_id = self
+                .types
+                .add_extern(&name, old_ok, &[ref_i32, Type::I32, Type::I32]);
+            self.functions.set_growing(func_id, cmpxchg);
+
+            let entry = self.ctx.append_basic_block(cmpxchg, "entry");
+            self.builder.position_at_end(entry);
+            let ptr = cmpxchg.get_nth_param(0).unwrap().into_pointer_value();
+            let expected = cmpxchg.get_nth_param(1).unwrap().into_int_value();
+            let desired = cmpxchg.get_nth_param(2).unwrap().into_int_value();
+            let old_ok = self
+                .builder
+                .build_cmpxchg(
+                    ptr,
+                    expected,
+                    desired,
+                    atomic_ordering(ordering),
+                    atomic_ordering(ordering.failure()),
+                )
+                .unwrap();
+            self.builder.build_return(Some(&old_ok)).unwrap();
+        }
+
+        // memory_fence_acquire(), memory_fence_release() and memory_fence_seqcst()
+        for (name, ordering) in [
+            ("memory_fence_acquire", Ordering::Acquire),
+            ("memory_fence_release", Ordering::Release),
+            ("memory_fence_seqcst", Ordering::SeqCst),
+        ] {
+            let fence_ty = Type::Void.as_llvm_fn(self, &[], false);
+            let fence = self.module.add_function(name, fence_ty, None);
+            let func_id = self.types.add_extern(name, Type::Void, &[]);
+            self.functions.set_growing(func_id, fence);
+
+            let entry = self.ctx.append_basic_block(fence, "entry");
+            self.builder.position_at_end(entry);
+            self.builder
+                .build_fence(atomic_ordering(ordering), 0, "fence")
+                .unwrap();
+            self.builder.build_return(None).unwrap();
+        }
+
+        // exit(code: i32) -> !, the libc `exit`, which also flushes the C stdio buffers
+        extern "C" {
+            #[link_name = "exit"]
+            fn libc_exit(code: i32) -> !;
+        }
+        let exit_ty = Type::Never.as_llvm_fn(self, &params[1..], false);
+        let exit = self.module.add_function("exit", exit_ty, None);
+        let noreturn = Attribute::get_named_enum_kind_id("noreturn");
+        exit.add_attribute(
+            AttributeLoc::Function,
+            self.ctx.create_enum_attribute(noreturn, 0),
+        );
+        self.engine
+            .add_global_mapping(&exit, libc_exit as unsafe extern "C" fn(i32) -> ! as usize);
+
+        let func_id = self.types.add_extern("exit", Type::Never, &[Type::I32]);
+        self.functions.set_growing(func_id, exit);
+
+        // i32_to_str(n: i32) -> str
+        extern "C" fn i32_to_str(n: i32) -> Str {
+            Str::leak(n.to_string())
+        }
+        self.add_extern("i32_to_str", i32_to_str as extern "C" fn(i32) -> Str)
+            .unwrap();
+
+        // i64_to_str, usize_to_str and bool_to_str, `print` formats its arguments with these
+        extern "C" fn i64_to_str(n: i64) -> Str {
+            Str::leak(n.to_string())
+        }
+        self.add_extern("i64_to_str", i64_to_str as extern "C" fn(i64) -> Str)
+            .unwrap();
+
+        extern "C" fn usize_to_str(n: usize) -> Str {
+            Str::leak(n.to_string())
+        }
+        self.add_extern("usize_to_str", usize_to_str as extern "C" fn(usize) -> Str)
+            .unwrap();
+
+        extern "C" fn bool_to_str(b: bool) -> Str {
+            Str::leak(b.to_string())
+        }
+        self.add_extern("bool_to_str", bool_to_str as extern "C" fn(bool) -> Str)
+            .unwrap();
+
+        // print(fmt: str, ..), typeck splits the format string into one call per piece
+        extern "C" fn print(s: Str) {
+            let mut stdout = io::stdout().lock();
+            _ = stdout.write_all(s.as_bytes());
+            _ = stdout.flush();
+        }
+        self.add_extern("print", print as extern "C" fn(Str))
+            .unwrap();
+
+        // parse_int(s: str) -> i32
+        extern "C" fn parse_int(s: Str) -> i32 {
+            // there are no optional types yet, `i32::MIN` marks invalid input,
+            // byte strings that aren't UTF-8 can't be numbers either
+            str::from_utf8(s.as_bytes())
+                .ok()
+                .and_then(|s| s.trim().parse().ok())
+                .unwrap_or(i32::MIN)
+        }
+        self.add_extern("parse_int", parse_int as extern "C" fn(Str) -> i32)
+            .unwrap();
+
+        // read_line() -> str, a line from stdin without the line ending, empty at the end of input
+        extern "C" fn read_line() -> Str {
+            let mut line = String::new();
+            _ = io::stdin().read_line(&mut line);
+            let len = line.trim_end_matches(['\n', '\r']).len();
+            line.truncate(len);
+            Str::leak(line)
+        }
+        self.add_extern("read_line", read_line as extern "C" fn() -> Str)
+            .unwrap();
+
+        // concat(a: str, b: str) -> str
+        extern "C" fn concat(a: Str, b: Str) -> Str {
+            Str::leak_bytes([a.as_bytes(), b.as_bytes()].concat())
+        }
+        self.add_extern("concat", concat as extern "C" fn(Str, Str) -> Str)
+            .unwrap();
+
+        // slice(s: str, start: usize, end: usize) -> str, the bytes `start..end` without copying
+        extern "C" fn slice(s: Str, start: usize, end: usize) -> Str {
+            s.get(start..end).unwrap_or_else(|| {
+                panic_with(format_args!(
+                    "slice {start}..{end} out of bounds for a str of length {}",
+                    s.as_bytes().len()
+                ))
+            })
+        }
+        self.add_extern("slice", slice as extern "C" fn(Str, usize, usize) -> Str)
+            .unwrap();
+
+        // byte_at(s: str, n: usize) -> i32, there is no `u8` type yet
+        extern "C" fn byte_at(s: Str, n: usize) -> i32 {
+            match s.as_bytes().get(n) {
+                Some(byte) => *byte as i32,
+                None => panic_with(format_args!(
+                    "index {n} out of bounds for a str of length {}",
+                    s.as_bytes().len()
+                )),
+            }
+        }
+        self.add_extern("byte_at", byte_at as extern "C" fn(Str, usize) -> i32)
+            .unwrap();
+
+        // contains(s: str, needle: str), starts_with(s: str, prefix: str)
+        // and ends_with(s: str, suffix: str), byte strings don't have to be UTF-8
+        extern "C" fn contains(s: Str, needle: Str) -> bool {
+            let needle = needle.as_bytes();
+            needle.is_empty() || s.as_bytes().windows(needle.len()).any(|w| w == needle)
+        }
+        self.add_extern("contains", contains as extern "C" fn(Str, Str) -> bool)
+            .unwrap();
+
+        extern "C" fn starts_with(s: Str, prefix: Str) -> bool {
+            s.as_bytes().starts_with(prefix.as_bytes())
+        }
+        self.add_extern(
+            "starts_with",
+            starts_with as extern "C" fn(Str, Str) -> bool,
+        )
+        .unwrap();
+
+        extern "C" fn ends_with(s: Str, suffix: Str) -> bool {
+            s.as_bytes().ends_with(suffix.as_bytes())
+        }
+        self.add_extern("ends_with", ends_with as extern "C" fn(Str, Str) -> bool)
+            .unwrap();
+
+        // print_i32(n: i32) and print_bool(b: bool)
+        extern "C" fn print_i32(n: i32) {
+            println!("{n}");
+        }
+        self.add_extern("print_i32", print_i32 as extern "C" fn(i32))
+            .unwrap();
+
+        extern "C" fn print_bool(b: bool) {
+            println!("{b}");
+        }
+        self.add_extern("print_bool", print_bool as extern "C" fn(bool))
+            .unwrap();
+
+        // panic(msg: str) -> !
+        extern "C" fn panic(msg: Str) -> ! {
+            panic_with(msg)
+        }
+        let panic = panic as extern "C" fn(Str) -> !;
+        // SAFETY: `panic` takes a `str` and never returns
+        unsafe {
+            self.add_extern_typed("panic", panic as usize, Type::Never, &[Type::Str])
+                .unwrap();
+        }
+
+        // assert(cond: bool, msg: str)
+        extern "C" fn assert(cond: bool, msg: Str) {
+            if !cond {
+                panic_with(msg);
+            }
+        }
+        self.add_extern("assert", assert as extern "C" fn(bool, Str))
+            .unwrap();
+
+        // assert_eq(a: i32, b: i32)
+        extern "C" fn assert_eq(a: i32, b: i32) {
+            if a != b {
+                panic_with(format_args!("assertion failed: `{a} == {b}`"));
+            }
+        }
+        self.add_extern("assert_eq", assert_eq as extern "C" fn(i32, i32))
+            .unwrap();
+
+        // assert_eq(a: bool, b: bool) and assert_eq(a: str, b: str),
+        // typeck picks these helpers from the argument types
+        extern "C" fn assert_eq_bool(a: bool, b: bool) {
+            if a != b {
+                panic_with(format_args!("assertion failed: `{a} == {b}`"));
+            }
+        }
+        self.add_extern(
+            "assert_eq_bool",
+            assert_eq_bool as extern "C" fn(bool, bool),
+        )
+        .unwrap();
+
+        extern "C" fn assert_eq_str(a: Str, b: Str) {
+            if a.as_bytes() != b.as_bytes() {
+                panic_with(format_args!(
+                    "assertion failed: `{:?} == {:?}`",
+                    String::from_utf8_lossy(a.as_bytes()),
+                    String::from_utf8_lossy(b.as_bytes())
+                ));
+            }
+        }
+        self.add_extern("assert_eq_str", assert_eq_str as extern "C" fn(Str, Str))
+            .unwrap();
+
+        // typeck only lowers the calls to these, not to the externs that reuse a name later
+        let builtins: Vec<FuncId> = self.types.externs().map(|(_, func_id)| func_id).collect();
+        for func_id in builtins {
+            self.types.get_function_mut(func_id).builtin = true;
+        }
     }
 
     pub fn add_extern<F: FnAsLlvm>(&mut self, name: &str, f: F) -> Result<()> {
-        let ret = f.return_type();
-        let params = f.params();
+        let fn_ptr = f.as_extern_c_fn_ptr();
+        // SAFETY: the signature comes from the fn type itself
+        unsafe {
+            if f.variadic() {
+                self.add_variadic_extern_typed(name, fn_ptr, f.return_type(), f.params())
+            } else {
+                self.add_extern_typed(name, fn_ptr, f.return_type(), f.params())
+            }
+        }
+    }
+
+    /// like [`Self::add_extern_typed`], but for C variadic functions like `printf`
+    ///
+    /// a wrapper can't pass on the extra arguments, so the JIT code calls `fn_ptr` directly
+    ///
+    /// # Safety
+    /// the fn_ptr should be `extern "C"` signature should match `ret` and `params`,
+    /// followed by `...`
+    pub unsafe fn add_variadic_extern_typed(
+        &mut self,
+        name: &str,
+        fn_ptr: usize,
+        ret: Type,
+        params: &[Type],
+    ) -> Result<()> {
+        self.open_module()?;
+
+        let func_id = self.types.add_variadic_extern(name, ret, params);
+        let proto = to_prototype(self, &self.types, self.types.get_function(func_id));
+        let func = self.module.add_function(name, proto, None);
+        self.engine.add_global_mapping(&func, fn_ptr);
+        self.symbols.insert(name.to_string(), fn_ptr);
+        self.functions.set_growing(func_id, func);
+
+        Ok(())
+    }
+
+    /// like [`Self::add_extern`], but the signature is given at runtime,
+    /// for fn pointers that don't have a static Rust type
+    ///
+    /// # Safety
+    /// the fn_ptr should be `extern "C"` signature should match `ret` and `params`
+    pub unsafe fn add_extern_typed(
+        &mut self,
+        name: &str,
+        fn_ptr: usize,
+        ret: Type,
+        params: &[Type],
+    ) -> Result<()> {
+        self.open_module()?;
 
         let param_types: Vec<_> = params.iter().filter_map(|a| a.as_llvm_meta(self)).collect();
         let wrapper_ty = ret.as_llvm_fn(self, &param_types, false);
         let wrapper_ptr = self.module.add_function(name, wrapper_ty, None);
 
         let func_id = self.types.add_extern(name, ret, params);
-        self.functions.set(func_id, wrapper_ptr);
+        self.functions.set_growing(func_id, wrapper_ptr);
 
         let entry = self.ctx.append_basic_block(wrapper_ptr, "entry");
         self.builder.position_at_end(entry);
@@ -585,7 +2265,7 @@ impl ModuleGen {
         let fn_ptr = self
             .builder
             .build_int_to_ptr(
-                ty_usize.const_int(f.as_extern_c_fn_ptr() as _, false),
+                ty_usize.const_int(fn_ptr as _, false),
                 ty_ptr,
                 "wrapped-fn-ptr",
             )
@@ -608,8 +2288,13 @@ impl ModuleGen {
             }
             Some(BasicValueEnum::FloatValue(v)) => todo!("{v}"),
             Some(BasicValueEnum::PointerValue(v)) => todo!("{v}"),
-            Some(BasicValueEnum::StructValue(v)) => todo!("{v}"),
+            Some(BasicValueEnum::StructValue(v)) => {
+                self.builder.build_return(Some(&v)).unwrap();
+            }
             Some(BasicValueEnum::VectorValue(v)) => todo!("{v}"),
+            None if ret == Type::Never => {
+                self.builder.build_unreachable().unwrap();
+            }
             None => {
                 self.builder.build_return(None).unwrap();
             }
@@ -624,6 +2309,27 @@ impl ModuleGen {
         Ok(())
     }
 
+    /// binds a source level `extern fn name(..)` to `addr`,
+    /// instead of looking up the symbol from the process
+    ///
+    /// it can be called before or after the extern is declared
+    ///
+    /// # Safety
+    /// `addr` should be an `extern "C"` fn and its signature should match the declaration
+    pub unsafe fn define_symbol(&mut self, name: &str, addr: usize) {
+        self.symbols.insert(name.to_string(), addr);
+
+        let declared = self
+            .types
+            .get_function_by_name(name)
+            .and_then(|func_id| self.functions.try_get(func_id).copied())
+            .filter(|func| func.as_global_value().is_declaration());
+
+        if let Some(func) = declared {
+            self.engine.add_global_mapping(&func, addr);
+        }
+    }
+
     /// # Safety
     /// the fn_ptr should be `extern "C"` signature should match `ret` and `args`
     pub unsafe fn add_extern_userdata(
@@ -634,6 +2340,8 @@ impl ModuleGen {
         ret: Type,
         params: &[Type],
     ) -> Result<()> {
+        self.open_module()?;
+
         let ty_usize = self
             .ctx
             .ptr_sized_int_type(self.engine.get_target_data(), None);
@@ -648,8 +2356,7 @@ impl ModuleGen {
         let wrapper_ptr = self.module.add_function(name, wrapper_ty, None);
 
         let func_id = self.types.add_extern(name, ret, params);
-        self.functions.reserve(func_id.0 + 1);
-        self.functions.set(func_id, wrapper_ptr);
+        self.functions.set_growing(func_id, wrapper_ptr);
 
         let entry = self.ctx.append_basic_block(wrapper_ptr, "entry");
         self.builder.position_at_end(entry);
@@ -683,7 +2390,9 @@ impl ModuleGen {
             }
             Some(BasicValueEnum::FloatValue(v)) => todo!("{v}"),
             Some(BasicValueEnum::PointerValue(v)) => todo!("{v}"),
-            Some(BasicValueEnum::StructValue(v)) => todo!("{v}"),
+            Some(BasicValueEnum::StructValue(v)) => {
+                self.builder.build_return(Some(&v)).unwrap();
+            }
             Some(BasicValueEnum::VectorValue(v)) => todo!("{v}"),
             None => {
                 self.builder.build_return(None).unwrap();
@@ -699,17 +2408,368 @@ impl ModuleGen {
         Ok(())
     }
 
-    pub fn run(&mut self, main: FuncId) {
+    /// runs `main`, which has to be a generated `fn()` or `fn() -> i32`,
+    /// like the top level code from [`Self::add`]
+    pub fn run(&mut self, main: FuncId) -> Result<()> {
         // eprintln!("LLVM IR:\n");
         // self.module.print_to_stderr();
         // panic!();
 
+        // any other signature would be called with the wrong ABI
+        let func = self.types.get_function(main);
+        let returns = *self.types.get_type(func.returns);
+        if func.is_extern || !func.params.is_empty() || !matches!(returns, Type::I32 | Type::Void) {
+            return Err(Error::InvalidMainFn);
+        }
+        let main = self
+            .functions
+            .try_get(main)
+            .filter(|_| !self.freed.contains(&main))
+            .copied()
+            .ok_or(Error::NoMainFn)?;
+
         self.module.verify().unwrap();
 
-        // FIXME: validate the main function signature
+        self.sealed = true;
         unsafe {
-            self.engine.run_function(*self.functions.get(main), &[]);
+            self.engine.run_function(main, &[]);
         }
+        Ok(())
+    }
+
+    /// calls the top level `main` function with `args`
+    ///
+    /// `main` is either `fn() -> i32` or `fn(args: [str]) -> i32`,
+    /// without the `-> i32` the exit code is 0
+    pub fn run_main(&mut self, args: &[&str]) -> Result<i32> {
+        let main = self
+            .types
+            .get_function_by_name("main")
+            .ok_or(Error::NoMainFn)?;
+
+        let func = self.types.get_function(main);
+        let returns_void = match *self.types.get_type(func.returns) {
+            Type::I32 => false,
+            Type::Void => true,
+            _ => return Err(Error::InvalidMainFn),
+        };
+        if func.is_extern {
+            return Err(Error::InvalidMainFn);
+        }
+
+        let takes_args = match func.params[..] {
+            [] => false,
+            [arg] => match *self.types.get_type(arg) {
+                Type::Slice(slice) if self.types.get_slice(slice) == Type::Str => true,
+                _ => return Err(Error::InvalidMainFn),
+            },
+            _ => return Err(Error::InvalidMainFn),
+        };
+
+        self.module.verify().unwrap();
+
+        self.sealed = true;
+        let main = self
+            .functions
+            .try_get(main)
+            .filter(|_| !self.freed.contains(&main))
+            .ok_or(Error::NoMainFn)?;
+        let name = main.get_name().to_str().unwrap();
+        let addr = self
+            .engine
+            .get_function_address(name)
+            .map_err(|_| Error::NoMainFn)?;
+
+        // a `main` without a return type exits with 0
+        let args: Vec<Str> = args.iter().map(|&arg| Str::from(arg)).collect();
+        let exit_code = match (takes_args, returns_void) {
+            (true, false) => {
+                let main: extern "C" fn(Slice<Str>) -> i32 = unsafe { std::mem::transmute(addr) };
+                main(Slice::from(&args[..]))
+            }
+            (true, true) => {
+                let main: extern "C" fn(Slice<Str>) = unsafe { std::mem::transmute(addr) };
+                main(Slice::from(&args[..]));
+                0
+            }
+            (false, false) => {
+                let main: extern "C" fn() -> i32 = unsafe { std::mem::transmute(addr) };
+                main()
+            }
+            (false, true) => {
+                let main: extern "C" fn() = unsafe { std::mem::transmute(addr) };
+                main();
+                0
+            }
+        };
+
+        Ok(exit_code)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use typeck::FuncId;
+
+    use inkwell::IntPredicate;
+    use parser::ast::BinaryOp;
+    use typeck::{Statement, Type};
+
+    use lexer::Lexer;
+    use parser::ParseStream;
+
+    use crate::{
+        check_data_layout, int_compare_name, int_predicate, CodeGen, Error, IdMap, ModuleGen,
+    };
+
+    #[test]
+    fn id_map_set_growing() {
+        let mut map: IdMap<FuncId, i32> = IdMap::new();
+        map.set_growing(FuncId(10), 5);
+        map.set_growing(FuncId(2), 3);
+
+        assert_eq!(*map.get(FuncId(10)), 5);
+        assert_eq!(*map.get(FuncId(2)), 3);
+        assert_eq!(map.vals.len(), 11);
+        assert!(map.vals[0..2].iter().all(Option::is_none));
+    }
+
+    #[test]
+    fn id_map_try_get() {
+        let mut map: IdMap<FuncId, i32> = IdMap::new();
+        assert_eq!(map.try_get(FuncId(0)), None);
+
+        map.set_growing(FuncId(3), 7);
+        assert_eq!(map.try_get(FuncId(3)), Some(&7));
+        assert_eq!(map.try_get(FuncId(1)), None);
+        assert_eq!(map.try_get(FuncId(4)), None);
+    }
+
+    #[test]
+    fn int_comparisons() {
+        assert_eq!(int_predicate(BinaryOp::Eq, true), IntPredicate::EQ);
+        assert_eq!(int_predicate(BinaryOp::Neq, true), IntPredicate::NE);
+        assert_eq!(int_predicate(BinaryOp::Gt, true), IntPredicate::SGT);
+        assert_eq!(int_predicate(BinaryOp::Gt, false), IntPredicate::UGT);
+
+        assert_eq!(int_compare_name(Type::I32, BinaryOp::Eq), "builtin-i32-eq");
+        assert_eq!(
+            int_compare_name(Type::I32, BinaryOp::Neq),
+            "builtin-i32-neq"
+        );
+        assert_eq!(
+            int_compare_name(Type::USize, BinaryOp::Lt),
+            "builtin-usize-lt"
+        );
+        assert_eq!(
+            int_compare_name(Type::Bool, BinaryOp::Eq),
+            "builtin-bool-eq"
+        );
+    }
+
+    #[test]
+    fn run_validates_main() {
+        let ast = ParseStream::from_lexer(Lexer::new("f := fn(n: i32) -> i32 { n };"))
+            .parse()
+            .unwrap();
+
+        let mut gen = CodeGen::new().module().unwrap();
+        let main = gen.add(&ast).unwrap();
+        let f = gen.types().get_function_by_name("f").unwrap();
+        let print = gen.types().get_function_by_name("print_i32").unwrap();
+
+        let res = gen.run(f);
+        assert!(matches!(res, Err(Error::InvalidMainFn)), "{res:?}");
+        let res = gen.run(print);
+        assert!(matches!(res, Err(Error::InvalidMainFn)), "{res:?}");
+        let res = gen.run(main);
+        assert!(res.is_ok(), "{res:?}");
+    }
+
+    #[test]
+    fn trap() {
+        let ast = ParseStream::from_lexer(Lexer::new(
+            "parity := fn(n: usize) -> i32 { match n % 2 { 0 => 10, 1 => 20, _ => unreachable() } };",
+        ))
+        .parse()
+        .unwrap();
+
+        let mut gen = CodeGen::new().module().unwrap();
+        gen.add(&ast).unwrap();
+        let parity = gen.types().get_function_by_name("parity").unwrap();
+
+        let ir = gen.dump_ir(parity).unwrap();
+        assert!(ir.contains("call void @llvm.trap()"), "{ir}");
+        assert!(!ir.contains("@unreachable"), "{ir}");
+    }
+
+    #[test]
+    fn atomic_orderings() {
+        let ast = ParseStream::from_lexer(Lexer::new(
+            r#"
+            flag := 0;
+            get := fn() -> i32 { atomic_load_acquire(&flag) };
+            set := fn() { atomic_store_relaxed(&flag, 1); };
+            bump := fn() -> i32 { atomic_add_relaxed(&flag, 1) };
+            unlock := fn() -> bool { old, ok := cmpxchg_release(&flag, 1, 0); ok };
+            "#,
+        ))
+        .parse()
+        .unwrap();
+
+        let mut gen = CodeGen::new().module().unwrap();
+        gen.add(&ast).unwrap();
+        let ir_of = |gen: &mut ModuleGen, name: &str| {
+            let func = gen.types().get_function_by_name(name).unwrap();
+            gen.dump_ir(func).unwrap()
+        };
+
+        let ir = ir_of(&mut gen, "get");
+        assert!(
+            ir.contains("load atomic i32") && ir.contains(" acquire"),
+            "{ir}"
+        );
+        let ir = ir_of(&mut gen, "set");
+        assert!(
+            ir.contains("store atomic i32") && ir.contains(" monotonic"),
+            "{ir}"
+        );
+        let ir = ir_of(&mut gen, "bump");
+        assert!(
+            ir.contains("atomicrmw add") && ir.contains(" monotonic"),
+            "{ir}"
+        );
+        let ir = ir_of(&mut gen, "unlock");
+        assert!(ir.contains("release monotonic"), "{ir}");
+    }
+
+    #[test]
+    fn fences() {
+        let ast = ParseStream::from_lexer(Lexer::new(
+            r#"
+            flag := 0;
+            publish := fn() { memory_fence_release(); atomic_store(&flag, 1); };
+            consume := fn() -> i32 { n := atomic_load(&flag); memory_fence_acquire(); n };
+            full := fn() { memory_fence_seqcst(); };
+            "#,
+        ))
+        .parse()
+        .unwrap();
+
+        let mut gen = CodeGen::new().module().unwrap();
+        gen.add(&ast).unwrap();
+        let ir_of = |gen: &mut ModuleGen, name: &str| {
+            let func = gen.types().get_function_by_name(name).unwrap();
+            gen.dump_ir(func).unwrap()
+        };
+
+        let ir = ir_of(&mut gen, "publish");
+        assert!(ir.contains("fence release"), "{ir}");
+        assert!(!ir.contains("@memory_fence_release"), "{ir}");
+        let ir = ir_of(&mut gen, "consume");
+        assert!(ir.contains("fence acquire"), "{ir}");
+        let ir = ir_of(&mut gen, "full");
+        assert!(ir.contains("fence seq_cst"), "{ir}");
+    }
+
+    #[test]
+    fn comdat() {
+        let ast = ParseStream::from_lexer(Lexer::new(
+            r#"
+            table := 7;
+            body := fn() -> i32 { 42 };
+            "#,
+        ))
+        .parse()
+        .unwrap();
+
+        let mut gen = CodeGen::new().module().unwrap();
+        gen.add(&ast).unwrap();
+        let func = gen.types().get_function_by_name("body").unwrap();
+        let (global, _) = gen.types().globals().next().unwrap();
+        gen.set_comdat(func, "shared_body");
+        gen.set_global_comdat(global, "shared_table");
+
+        let ir = gen.dump_ir(func).unwrap();
+        let define = ir.lines().find(|line| line.starts_with("define")).unwrap();
+        assert!(define.contains("comdat($shared_body)"), "{ir}");
+
+        let ir = gen.module.print_to_string().to_string();
+        assert!(ir.contains("$shared_body = comdat exactmatch"), "{ir}");
+        assert!(ir.contains("$shared_table = comdat exactmatch"), "{ir}");
+        assert!(ir.contains("comdat($shared_table)"), "{ir}");
+    }
+
+    #[test]
+    fn unsupported_operation() {
+        let ast =
+            ParseStream::from_lexer(Lexer::new("f := fn(a: bool, b: bool) -> bool { a == b };"))
+                .parse()
+                .unwrap();
+
+        let mut gen = CodeGen::new().module().unwrap();
+        gen.types.process(&ast).unwrap();
+
+        // typeck never produces `bool * bool`, so the IR is changed by hand
+        let f = gen.types.get_function_by_name("f").unwrap();
+        for stmt in gen
+            .types
+            .get_function_mut(f)
+            .blocks
+            .iter_mut()
+            .flat_map(|block| block.stmts.iter_mut())
+        {
+            if let Statement::BinExpr { op, .. } = stmt {
+                *op = BinaryOp::Mul;
+            }
+        }
+
+        let res = gen.generate();
+        assert!(
+            matches!(
+                res,
+                Err(Error::UnsupportedOperation {
+                    ty: Type::Bool,
+                    op: BinaryOp::Mul
+                })
+            ),
+            "{res:?}"
+        );
+    }
+
+    #[test]
+    fn failed_add_is_discarded() {
+        let parse = |src| ParseStream::from_lexer(Lexer::new(src)).parse().unwrap();
+
+        let mut gen = CodeGen::new().module().unwrap();
+        let main = gen.add(&parse("f := fn() -> i32 { 1 };")).unwrap();
+        gen.run(main).unwrap();
+        let f = gen.types().get_function_by_name("f").unwrap();
+        unsafe { gen.free_function(f) }.unwrap();
+
+        // typeck accepts the call to the freed `f`, only codegen rejects it
+        let functions = gen.types().functions().len();
+        let res = gen.add(&parse("y := 5; g := fn() -> i32 { f() + y };"));
+        assert!(matches!(res, Err(Error::Codegen(_))), "{res:?}");
+        assert_eq!(gen.types().functions().len(), functions);
+        assert_eq!(gen.types().get_static("g"), None);
+        assert_eq!(gen.types().get_static("y"), None);
+
+        // the same ids and names are generated again
+        let main = gen
+            .add(&parse("y := 5; g := fn() -> i32 { y }; g();"))
+            .unwrap();
+        let res = gen.run(main);
+        assert!(res.is_ok(), "{res:?}");
+    }
+
+    #[test]
+    fn missing_data_layout() {
+        let res = check_data_layout(c"");
+        assert!(matches!(res, Err(Error::Codegen(_))), "{res:?}");
+
+        let res = check_data_layout(c"e-m:e-i64:64-n8:16:32:64-S128");
+        assert!(res.is_ok(), "{res:?}");
     }
 }
 
@@ -1,13 +1,14 @@
 use core::fmt;
-use std::{slice, str};
+use std::{marker::PhantomData, ops::Range, slice, str};
 
 use inkwell::{
     context::Context,
-    types::{BasicMetadataTypeEnum, BasicTypeEnum, FunctionType, StructType},
+    module::Linkage,
+    types::{BasicMetadataTypeEnum, BasicTypeEnum, FunctionType, IntType, PointerType, StructType},
     values::{BasicValue, BasicValueEnum, StructValue},
     AddressSpace,
 };
-use typeck::{Literal, Type};
+use typeck::{Literal, SignatureId, SliceId, TupleId, Type};
 
 use crate::ModuleGen;
 
@@ -25,6 +26,14 @@ impl AsType for i32 {
     const TYPE_ID: Type = Type::I32;
 }
 
+impl AsType for i64 {
+    const TYPE_ID: Type = Type::I64;
+}
+
+impl AsType for usize {
+    const TYPE_ID: Type = Type::USize;
+}
+
 impl AsType for () {
     const TYPE_ID: Type = Type::Void;
 }
@@ -40,18 +49,48 @@ pub struct Str {
 }
 
 impl Str {
+    /// moves the string to the heap, for returning new strings to the JIT code
+    ///
+    /// the allocation is never freed
+    pub fn leak(s: String) -> Self {
+        Self::leak_bytes(s.into_bytes())
+    }
+
+    /// like [`Self::leak`], but the bytes don't have to be UTF-8
+    pub fn leak_bytes(bytes: Vec<u8>) -> Self {
+        let bytes = bytes.leak();
+        Self {
+            len: bytes.len(),
+            ptr: bytes.as_ptr(),
+        }
+    }
+
+    /// panics if the bytes aren't UTF-8, which `b"..."` strings don't have to be,
+    /// see [`Self::as_bytes`]
     pub fn as_str(&self) -> &str {
-        let str_slice = unsafe { slice::from_raw_parts(self.ptr, self.len) };
-        str::from_utf8(str_slice).unwrap()
+        str::from_utf8(self.as_bytes()).unwrap()
+    }
+
+    /// byte strings from `b"..."` aren't always valid UTF-8, unlike [`Self::as_str`] this never panics
+    pub fn as_bytes(&self) -> &[u8] {
+        unsafe { slice::from_raw_parts(self.ptr, self.len) }
+    }
+
+    /// the bytes in `range`, pointing into the same memory instead of copying,
+    /// `None` if the range is out of bounds
+    pub fn get(&self, range: Range<usize>) -> Option<Self> {
+        let bytes = self.as_bytes().get(range)?;
+        Some(Self {
+            len: bytes.len(),
+            ptr: bytes.as_ptr(),
+        })
     }
 
     pub fn get_type(gen: &ModuleGen) -> StructType<'static> {
         get_or_init_struct(gen.ctx, "str", |s| {
             s.set_body(
                 &[
-                    gen.ctx
-                        .ptr_sized_int_type(gen.engine.get_target_data(), None)
-                        .into(),
+                    usize_type(gen).into(),
                     gen.ctx.i8_type().ptr_type(AddressSpace::default()).into(),
                 ],
                 false,
@@ -60,9 +99,7 @@ impl Str {
     }
 
     pub fn get_const(gen: &ModuleGen, str: &str) -> StructValue<'static> {
-        let str_len = gen
-            .ctx
-            .ptr_sized_int_type(gen.engine.get_target_data(), None)
+        let str_len = usize_type(gen)
             .const_int(str.len() as _, false)
             .as_basic_value_enum();
         let str_ptr = gen
@@ -73,11 +110,35 @@ impl Str {
 
         Self::get_type(gen).const_named_struct(&[str_len, str_ptr])
     }
+
+    pub fn get_const_bytes(gen: &ModuleGen, bytes: &[u8]) -> StructValue<'static> {
+        let str_len = usize_type(gen)
+            .const_int(bytes.len() as _, false)
+            .as_basic_value_enum();
+
+        let data = gen.ctx.const_string(bytes, false);
+        let global = gen.module.add_global(data.get_type(), None, "bytes");
+        global.set_initializer(&data);
+        global.set_constant(true);
+        global.set_linkage(Linkage::Private);
+        let str_ptr = global.as_pointer_value().as_basic_value_enum();
+
+        Self::get_type(gen).const_named_struct(&[str_len, str_ptr])
+    }
+}
+
+impl From<&str> for Str {
+    fn from(s: &str) -> Self {
+        Self {
+            len: s.len(),
+            ptr: s.as_ptr(),
+        }
+    }
 }
 
 impl fmt::Display for Str {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
-        write!(f, "{}", self.as_str())
+        write!(f, "{}", String::from_utf8_lossy(self.as_bytes()))
     }
 }
 
@@ -87,12 +148,37 @@ impl AsType for Str {
 
 //
 
+/// `[T]` in the JIT code, borrows the elements
+#[repr(C)]
+pub struct Slice<'a, T> {
+    len: usize,
+    ptr: *const T,
+    _p: PhantomData<&'a [T]>,
+}
+
+impl<'a, T> From<&'a [T]> for Slice<'a, T> {
+    fn from(elems: &'a [T]) -> Self {
+        Self {
+            len: elems.len(),
+            ptr: elems.as_ptr(),
+            _p: PhantomData,
+        }
+    }
+}
+
+//
+
 pub trait FnAsLlvm {
     fn return_type(&self) -> Type;
 
     fn params(&self) -> &[Type];
 
     fn as_extern_c_fn_ptr(&self) -> usize;
+
+    /// C variadic functions like `printf` take more arguments after [`Self::params`]
+    fn variadic(&self) -> bool {
+        false
+    }
 }
 
 impl<R: AsType> FnAsLlvm for extern "C" fn() -> R {
@@ -137,6 +223,56 @@ impl<A: AsType, B: AsType, R: AsType> FnAsLlvm for extern "C" fn(A, B) -> R {
     }
 }
 
+impl<A: AsType, B: AsType, C: AsType, R: AsType> FnAsLlvm for extern "C" fn(A, B, C) -> R {
+    fn return_type(&self) -> Type {
+        R::TYPE_ID
+    }
+
+    fn params(&self) -> &[Type] {
+        &[A::TYPE_ID, B::TYPE_ID, C::TYPE_ID]
+    }
+
+    fn as_extern_c_fn_ptr(&self) -> usize {
+        *self as usize
+    }
+}
+
+impl<A: AsType, R: AsType> FnAsLlvm for unsafe extern "C" fn(A, ...) -> R {
+    fn return_type(&self) -> Type {
+        R::TYPE_ID
+    }
+
+    fn params(&self) -> &[Type] {
+        &[A::TYPE_ID]
+    }
+
+    fn as_extern_c_fn_ptr(&self) -> usize {
+        *self as usize
+    }
+
+    fn variadic(&self) -> bool {
+        true
+    }
+}
+
+impl<A: AsType, B: AsType, R: AsType> FnAsLlvm for unsafe extern "C" fn(A, B, ...) -> R {
+    fn return_type(&self) -> Type {
+        R::TYPE_ID
+    }
+
+    fn params(&self) -> &[Type] {
+        &[A::TYPE_ID, B::TYPE_ID]
+    }
+
+    fn as_extern_c_fn_ptr(&self) -> usize {
+        *self as usize
+    }
+
+    fn variadic(&self) -> bool {
+        true
+    }
+}
+
 //
 
 pub trait AsLlvmConst {
@@ -148,11 +284,51 @@ impl AsLlvmConst for Literal {
         match self {
             Literal::Bool(v) => Some(gen.ctx.bool_type().const_int(*v as u64, false).into()),
             Literal::I32(v) => Some(gen.ctx.i32_type().const_int(*v as u64, false).into()),
+            Literal::I64(v) => Some(gen.ctx.i64_type().const_int(*v as u64, false).into()),
+            Literal::USize(v) => Some(usize_type(gen).const_int(*v as u64, false).into()),
             Literal::Str(v) => Some(Str::get_const(gen, v).into()),
+            Literal::ByteStr(v) => Some(Str::get_const_bytes(gen, v).into()),
+            Literal::Slice(slice, v) => Some(const_slice(gen, *slice, v)?.into()),
         }
     }
 }
 
+/// the elements go to a private constant global array, the slice points to it
+fn const_slice(
+    gen: &ModuleGen,
+    slice: SliceId,
+    values: &[Literal],
+) -> Option<StructValue<'static>> {
+    let values = values
+        .iter()
+        .map(|value| value.as_llvm_const(gen))
+        .collect::<Option<Vec<_>>>()?;
+
+    let data = match gen.types.get_slice(slice).as_llvm(gen)? {
+        BasicTypeEnum::IntType(ty) => {
+            let values: Vec<_> = values.iter().map(|v| v.into_int_value()).collect();
+            ty.const_array(&values)
+        }
+        BasicTypeEnum::StructType(ty) => {
+            let values: Vec<_> = values.iter().map(|v| v.into_struct_value()).collect();
+            ty.const_array(&values)
+        }
+        BasicTypeEnum::PointerType(ty) => {
+            let values: Vec<_> = values.iter().map(|v| v.into_pointer_value()).collect();
+            ty.const_array(&values)
+        }
+        _ => return None,
+    };
+    let global = gen.module.add_global(data.get_type(), None, "array");
+    global.set_initializer(&data);
+    global.set_constant(true);
+    global.set_linkage(Linkage::Private);
+
+    let len = usize_type(gen).const_int(values.len() as _, false);
+    let ptr = global.as_pointer_value();
+    Some(slice_type(gen, slice).const_named_struct(&[len.into(), ptr.into()]))
+}
+
 //
 
 pub trait AsLlvm {
@@ -181,7 +357,17 @@ impl AsLlvm for Type {
         match self {
             Type::Bool => ctx.bool_type().fn_type(param_types, is_var_args),
             Type::I32 => ctx.i32_type().fn_type(param_types, is_var_args),
+            Type::I64 => ctx.i64_type().fn_type(param_types, is_var_args),
+            Type::USize => usize_type(gen).fn_type(param_types, is_var_args),
             Type::Str => Str::get_type(gen).fn_type(param_types, is_var_args),
+            Type::Tuple(tuple) => tuple_type(gen, *tuple).fn_type(param_types, is_var_args),
+            Type::Slice(slice) => slice_type(gen, *slice).fn_type(param_types, is_var_args),
+            Type::Ref(_) => ref_type(gen).fn_type(param_types, is_var_args),
+            Type::Opaque(opaque) => {
+                let inner = gen.types.get_opaque(*opaque).1;
+                inner.as_llvm_fn(gen, param_types, is_var_args)
+            }
+            Type::FnPtr(_) => fn_ptr_type(gen).fn_type(param_types, is_var_args),
             Type::Void => ctx.void_type().fn_type(param_types, is_var_args),
             Type::Never => ctx.void_type().fn_type(param_types, is_var_args),
             Type::Func(_f) => ctx.void_type().fn_type(param_types, is_var_args),
@@ -194,7 +380,14 @@ impl AsLlvm for Type {
         match self {
             Type::Bool => Some(ctx.bool_type().into()),
             Type::I32 => Some(ctx.i32_type().into()),
+            Type::I64 => Some(ctx.i64_type().into()),
+            Type::USize => Some(usize_type(gen).into()),
             Type::Str => Some(Str::get_type(gen).into()),
+            Type::Tuple(tuple) => Some(tuple_type(gen, *tuple).into()),
+            Type::Slice(slice) => Some(slice_type(gen, *slice).into()),
+            Type::Ref(_) => Some(ref_type(gen).into()),
+            Type::Opaque(opaque) => gen.types.get_opaque(*opaque).1.as_llvm(gen),
+            Type::FnPtr(_) => Some(fn_ptr_type(gen).into()),
             Type::Void => None,
             Type::Never => None,
             Type::Func(_func_id) => None, // Some(get_or_init_struct(ctx, &format!("[anon_func_{}]", func_id.0)).into()),
@@ -207,7 +400,14 @@ impl AsLlvm for Type {
         match self {
             Type::Bool => Some(ctx.bool_type().into()),
             Type::I32 => Some(ctx.i32_type().into()),
+            Type::I64 => Some(ctx.i64_type().into()),
+            Type::USize => Some(usize_type(gen).into()),
             Type::Str => Some(Str::get_type(gen).into()),
+            Type::Tuple(tuple) => Some(tuple_type(gen, *tuple).into()),
+            Type::Slice(slice) => Some(slice_type(gen, *slice).into()),
+            Type::Ref(_) => Some(ref_type(gen).into()),
+            Type::Opaque(opaque) => gen.types.get_opaque(*opaque).1.as_llvm_meta(gen),
+            Type::FnPtr(_) => Some(fn_ptr_type(gen).into()),
             Type::Void => None,
             Type::Never => None,
             Type::Func(_func_id) => None, // Some(get_or_init_struct(ctx, &format!("[anon_func_{}]", func_id.0)).into()),
@@ -216,6 +416,57 @@ impl AsLlvm for Type {
     }
 }
 
+pub fn usize_type(gen: &ModuleGen) -> IntType<'static> {
+    gen.ctx
+        .ptr_sized_int_type(gen.engine.get_target_data(), None)
+}
+
+pub fn fn_ptr_type(gen: &ModuleGen) -> PointerType<'static> {
+    gen.ctx.i8_type().ptr_type(AddressSpace::default())
+}
+
+/// the function type that a function pointer with this signature points to
+pub fn signature_type(gen: &ModuleGen, sig: SignatureId) -> FunctionType<'static> {
+    let sig = gen.types.get_signature(sig);
+    let param_types: Box<[_]> = sig
+        .params
+        .iter()
+        .map(|ty| ty.as_llvm_meta(gen).unwrap())
+        .collect();
+
+    sig.returns.as_llvm_fn(gen, &param_types, false)
+}
+
+/// tuples are anonymous structs, passed around by value
+pub fn tuple_type(gen: &ModuleGen, tuple: TupleId) -> StructType<'static> {
+    let fields: Box<[_]> = gen
+        .types
+        .get_tuple(tuple)
+        .iter()
+        .map(|ty| ty.as_llvm(gen).expect("tuple fields should have a size"))
+        .collect();
+
+    gen.ctx.struct_type(&fields, false)
+}
+
+/// slices are a length and a pointer, like [`Slice`]
+pub fn slice_type(gen: &ModuleGen, _slice: SliceId) -> StructType<'static> {
+    get_or_init_struct(gen.ctx, "slice", |s| {
+        s.set_body(
+            &[
+                usize_type(gen).into(),
+                gen.ctx.i8_type().ptr_type(AddressSpace::default()).into(),
+            ],
+            false,
+        );
+    })
+}
+
+/// references are plain pointers, the pointee type doesn't matter to LLVM
+pub fn ref_type(gen: &ModuleGen) -> PointerType<'static> {
+    gen.ctx.i8_type().ptr_type(AddressSpace::default())
+}
+
 pub fn get_or_init_struct<'a>(
     ctx: &'a Context,
     name: &str,
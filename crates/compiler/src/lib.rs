@@ -1,10 +1,10 @@
 use core::fmt;
-use std::sync::Mutex;
+use std::{path::Path, sync::Mutex};
 
 use codegen::{AsType, CodeGen, FnAsLlvm, ModuleGen};
 // use codegen::{CodeGen, FnAsLlvm, ModuleGen};
-use lexer::Lexer;
-use parser::ast::{Ast, Root};
+use lexer::{Lexer, Span};
+use parser::ast::{Ast, Expr, Root};
 use typeck::Type;
 
 //
@@ -13,24 +13,66 @@ pub use codegen::Str;
 
 //
 
-pub type Result<T, E = RunError> = std::result::Result<T, E>;
+pub type Result<T, E = CompileError> = std::result::Result<T, E>;
 
+//
+
+/// the part of the compiler that produced a [`CompileError`]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Stage {
+    Lexer,
+    Parser,
+    Typeck,
+    Codegen,
+}
+
+impl fmt::Display for Stage {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Stage::Lexer => f.write_str("lexer"),
+            Stage::Parser => f.write_str("parser"),
+            Stage::Typeck => f.write_str("type check"),
+            Stage::Codegen => f.write_str("codegen"),
+        }
+    }
+}
+
+/// an error from any stage of the compiler
 #[derive(Debug)]
-pub enum RunError {
-    Parse(parser::Error),
-    Run(codegen::Error),
+pub enum StageError {
+    Lexer(lexer::Error),
+    Parser(parser::Error),
+    Typeck(typeck::Error),
+    Codegen(codegen::Error),
 }
 
-impl fmt::Display for RunError {
+impl fmt::Display for StageError {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
         match self {
-            RunError::Parse(err) => write!(f, "{err}"),
-            RunError::Run(err) => write!(f, "{err}"),
+            StageError::Lexer(err) => write!(f, "{err}"),
+            StageError::Parser(err) => write!(f, "{err}"),
+            StageError::Typeck(err) => write!(f, "{err}"),
+            StageError::Codegen(err) => write!(f, "{err}"),
         }
     }
 }
 
-impl std::error::Error for RunError {
+/// the one error type for the REPL and embedders,
+/// every stage specific error converts into it
+#[derive(Debug)]
+pub struct CompileError {
+    pub stage: Stage,
+    pub span: Option<Span>,
+    pub error: StageError,
+}
+
+impl fmt::Display for CompileError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{} error: {}", self.stage, self.error)
+    }
+}
+
+impl std::error::Error for CompileError {
     fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
         None
     }
@@ -40,15 +82,56 @@ impl std::error::Error for RunError {
     }
 }
 
-impl From<parser::Error> for RunError {
+impl From<lexer::Error> for CompileError {
+    fn from(value: lexer::Error) -> Self {
+        Self {
+            stage: Stage::Lexer,
+            span: None,
+            error: StageError::Lexer(value),
+        }
+    }
+}
+
+impl From<parser::Error> for CompileError {
     fn from(value: parser::Error) -> Self {
-        Self::Parse(value)
+        match value {
+            parser::Error::Lexer(err) => err.into(),
+            err => Self {
+                stage: Stage::Parser,
+                span: None,
+                error: StageError::Parser(err),
+            },
+        }
     }
 }
 
-impl From<codegen::Error> for RunError {
+impl From<typeck::Error> for CompileError {
+    fn from(value: typeck::Error) -> Self {
+        Self {
+            stage: Stage::Typeck,
+            span: value.span(),
+            error: StageError::Typeck(value),
+        }
+    }
+}
+
+impl From<codegen::Error> for CompileError {
     fn from(value: codegen::Error) -> Self {
-        Self::Run(value)
+        match value {
+            codegen::Error::Type(err) => err.into(),
+            codegen::Error::Parse(err) => err.into(),
+            // the span would be in the used file, not in the source that was run
+            codegen::Error::Import { ref err, .. } => Self {
+                stage: Self::from((**err).clone()).stage,
+                span: None,
+                error: StageError::Codegen(value),
+            },
+            err => Self {
+                stage: Stage::Codegen,
+                span: None,
+                error: StageError::Codegen(err),
+            },
+        }
     }
 }
 
@@ -120,14 +203,22 @@ impl Compiler {
         }
     }
 
+    /// the JIT module, created on first use
+    fn module(&mut self) -> Result<&mut ModuleGen> {
+        if self.module.is_none() {
+            self.module = Some(self.codegen.module()?);
+        }
+
+        Ok(self.module.as_mut().unwrap())
+    }
+
     pub fn add<F, A>(&mut self, name: &str, f: F) -> Result<()>
     where
         F: Func<A>,
     {
         let (wrapper, index) = f.into_wrapper_ptr_and_id();
         unsafe {
-            self.module
-                .get_or_insert_with(|| self.codegen.module())
+            self.module()?
                 .add_extern_userdata(name, wrapper, index, F::RETURN, F::ARGS)?;
         }
 
@@ -135,13 +226,55 @@ impl Compiler {
     }
 
     pub fn add_raw<F: FnAsLlvm>(&mut self, name: &str, f: F) -> Result<()> {
-        self.module
-            .get_or_insert_with(|| self.codegen.module())
-            .add_extern(name, f)?;
+        self.module()?.add_extern(name, f)?;
+
+        Ok(())
+    }
+
+    /// # Safety
+    /// see [`ModuleGen::add_extern_typed`]
+    pub unsafe fn add_extern_typed(
+        &mut self,
+        name: &str,
+        fn_ptr: usize,
+        ret: Type,
+        params: &[Type],
+    ) -> Result<()> {
+        self.module()?.add_extern_typed(name, fn_ptr, ret, params)?;
+
+        Ok(())
+    }
+
+    /// # Safety
+    /// see [`ModuleGen::define_symbol`]
+    pub unsafe fn define_symbol(&mut self, name: &str, addr: usize) -> Result<()> {
+        self.module()?.define_symbol(name, addr);
+        Ok(())
+    }
+
+    /// makes warnings fail [`Compiler::run`], see [`ModuleGen::set_warnings_as_errors`]
+    pub fn set_warnings_as_errors(&mut self, enabled: bool) -> Result<()> {
+        self.module()?.set_warnings_as_errors(enabled);
+        Ok(())
+    }
 
+    /// checks for stray writes around variables, see [`ModuleGen::insert_stack_canaries`]
+    pub fn insert_stack_canaries(&mut self) -> Result<()> {
+        self.module()?.insert_stack_canaries();
         Ok(())
     }
 
+    /// forgets all code run so far, see [`ModuleGen::reset`]
+    ///
+    /// the externs from [`Compiler::add`] have to be added again
+    pub fn reset(&mut self) -> Result<()> {
+        if let Some(module) = self.module.as_mut() {
+            module.reset()?;
+        }
+        Ok(())
+    }
+
+    /// runs the top level code of `code`, a `main` function is not called, see [`Compiler::run_main`]
     pub fn run(&mut self, code: &str) -> Result<i32> {
         // let mut parser = parser::ParseStream::from_lexer(Lexer::new(code));
         // for tok in parser.flatten() {
@@ -151,16 +284,24 @@ impl Compiler {
         let mut parser = parser::ParseStream::from_lexer(Lexer::new(code));
         let ast: Ast<Root> = parser.parse()?;
 
-        let module = self.module.get_or_insert_with(|| self.codegen.module());
+        let module = self.module()?;
 
         // TODO: type checking before code gen
 
-        let main = module.add(&ast).inspect_err(|e| {
-            if let codegen::Error::Type(typeck::Error::UnexpectedType { span, err }) = e {
-                println!("{}", lexer::SpanMessage::new(*span, code, err));
-            }
-        })?;
-        module.run(main);
+        let main = module
+            .add(&ast)
+            .map_err(CompileError::from)
+            .inspect_err(|err| {
+                if let Some(span) = err.span {
+                    println!("{}", lexer::SpanMessage::new(span, code, &err.error));
+                }
+            })?;
+
+        for warning in module.take_warnings() {
+            eprintln!("warning: {warning}");
+        }
+
+        module.run(main)?;
 
         // .expect("code generation should not fail");
 
@@ -168,6 +309,89 @@ impl Compiler {
 
         Ok(0)
     }
+
+    /// runs the file at `path` like [`Compiler::run`], see [`ModuleGen::add_file`]
+    pub fn run_file(&mut self, path: &Path) -> Result<i32> {
+        let module = self.module()?;
+        let main = module.add_file(path)?;
+
+        for warning in module.take_warnings() {
+            eprintln!("warning: {warning}");
+        }
+
+        module.run(main)?;
+        Ok(0)
+    }
+
+    /// runs `code` like [`Compiler::run`] and then calls its `main` function with `args`
+    ///
+    /// the process arguments are not passed implicitly,
+    /// a binary that runs scripts collects them from `env::args().skip(1)` itself
+    pub fn run_main(&mut self, code: &str, args: &[&str]) -> Result<i32> {
+        self.run(code)?;
+
+        let module = self.module()?;
+        Ok(module.run_main(args)?)
+    }
+
+    /// the address of the function called `name`, see [`ModuleGen::get_fn_ptr`]
+    pub fn get_fn_ptr(&mut self, name: &str) -> Result<usize> {
+        let module = self.module()?;
+
+        let func = module
+            .types()
+            .get_function_by_name(name)
+            .ok_or_else(|| codegen::Error::VariableNotFound(name.to_string()))?;
+
+        Ok(module.get_fn_ptr(func)?)
+    }
+
+    /// calls the function called `name` with `args`, see [`ModuleGen::call_i32`]
+    pub fn call_i32(&mut self, name: &str, args: &[i32]) -> Result<i32> {
+        let module = self.module()?;
+
+        let func = module
+            .types()
+            .get_function_by_name(name)
+            .ok_or_else(|| codegen::Error::VariableNotFound(name.to_string()))?;
+
+        Ok(module.call_i32(func, args)?)
+    }
+
+    /// frees the machine code of the function called `name`
+    ///
+    /// # Safety
+    /// see [`ModuleGen::free_function`]
+    pub unsafe fn free_function(&mut self, name: &str) -> Result<()> {
+        let module = self.module()?;
+
+        let func = module
+            .types()
+            .get_function_by_name(name)
+            .ok_or_else(|| codegen::Error::VariableNotFound(name.to_string()))?;
+        module.free_function(func)?;
+
+        Ok(())
+    }
+
+    /// the LLVM IR of the function called `name`
+    pub fn ir_of(&mut self, name: &str) -> Result<String> {
+        let module = self.module()?;
+
+        module
+            .types()
+            .get_function_by_name(name)
+            .and_then(|func| module.dump_ir(func))
+            .ok_or_else(|| codegen::Error::VariableNotFound(name.to_string()).into())
+    }
+
+    /// type checks `code` as a single expression without running it
+    pub fn type_of(&mut self, code: &str) -> Result<String> {
+        let mut parser = parser::ParseStream::from_lexer(Lexer::new(code));
+        let ast: Ast<Expr> = parser.parse()?;
+
+        Ok(self.module()?.types().type_name_of_expr(&ast.inner)?)
+    }
 }
 
 impl Default for Compiler {
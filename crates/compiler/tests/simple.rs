@@ -1,14 +1,21 @@
 use std::{
+    env,
+    ffi::{c_char, c_int},
     fmt::Write,
-    process::exit,
+    fs,
+    io::Write as _,
+    mem,
+    process::{exit, Command, Output, Stdio},
     sync::{
-        atomic::{AtomicUsize, Ordering},
+        atomic::{AtomicBool, AtomicI32, AtomicUsize, Ordering},
         Mutex,
     },
+    thread,
 };
 
 use codegen::Str;
-use compiler::Compiler;
+use compiler::{CompileError, Compiler, Stage, StageError};
+use typeck::Type;
 
 //
 
@@ -78,3 +85,1548 @@ fn fizz_buzz() {
 
     compiler.run(source).unwrap();
 }
+
+#[test]
+fn unsupported_operation() {
+    // typeck rejects `str + str` before codegen sees it
+    let mut compiler = Compiler::new();
+    let res = compiler.run(r#"s := "a" + "b";"#);
+
+    assert!(
+        matches!(
+            res,
+            Err(CompileError {
+                error: StageError::Typeck(typeck::Error::UnexpectedType { .. }),
+                ..
+            })
+        ),
+        "{res:?}"
+    );
+}
+
+#[test]
+fn tuple_return() {
+    let source = r#"
+        pair := fn() -> (i32, i32) {
+            return (3, 4);
+        };
+
+        a, b := pair();
+        result(a + b);
+    "#;
+
+    static RESULT: AtomicI32 = AtomicI32::new(0);
+
+    let mut compiler = Compiler::new();
+    compiler
+        .add("result", |i: i32| RESULT.store(i, Ordering::Relaxed))
+        .unwrap();
+    compiler.run(source).unwrap();
+
+    assert_eq!(RESULT.load(Ordering::Relaxed), 7);
+}
+
+#[test]
+fn str_len() {
+    static RESULT: AtomicBool = AtomicBool::new(false);
+
+    let mut compiler = Compiler::new();
+    compiler
+        .add("result", |b: bool| RESULT.store(b, Ordering::Relaxed))
+        .unwrap();
+    compiler.run(r#"result(len("abcd") == 4);"#).unwrap();
+
+    assert!(RESULT.load(Ordering::Relaxed));
+}
+
+#[test]
+fn str_eq() {
+    static RESULTS: Mutex<Vec<bool>> = Mutex::new(Vec::new());
+
+    let mut compiler = Compiler::new();
+    compiler
+        .add("result", |b: bool| RESULTS.lock().unwrap().push(b))
+        .unwrap();
+    compiler
+        .run(
+            r#"
+            result("abc" == "abc");
+            result("abc" == "abd");
+            result("ab" == "abc");
+            result("abc" != "abd");
+            result("abc" != i32_to_str(123));
+            "#,
+        )
+        .unwrap();
+
+    assert_eq!(*RESULTS.lock().unwrap(), [true, false, false, true, true]);
+
+    let res = compiler.run(r#"result("1" == 1);"#);
+    assert!(res.is_err(), "{res:?}");
+}
+
+#[test]
+fn comparisons() {
+    static RESULTS: Mutex<Vec<bool>> = Mutex::new(Vec::new());
+
+    let mut compiler = Compiler::new();
+    compiler
+        .add("result", |b: bool| RESULTS.lock().unwrap().push(b))
+        .unwrap();
+    compiler
+        .run(
+            r#"
+            result(2 > 1);
+            result(1 > 2);
+            result(0 - 1 > 1);
+            max := len("") - 1;
+            result(max > len("a"));
+            result(len("ab") < max);
+            result(max >= len("a"));
+            "#,
+        )
+        .unwrap();
+
+    // `max` is negative if the comparison is signed
+    assert_eq!(
+        *RESULTS.lock().unwrap(),
+        [true, false, false, true, true, true]
+    );
+}
+
+#[test]
+fn i32_gt() {
+    let mut compiler = Compiler::new();
+    compiler
+        .run("gt := fn(a: i32, b: i32) -> i32 { r := 0; if a > b { r = 1; }; r };")
+        .unwrap();
+
+    assert_eq!(compiler.call_i32("gt", &[5, 3]).unwrap(), 1);
+    assert_eq!(compiler.call_i32("gt", &[3, 5]).unwrap(), 0);
+    assert_eq!(compiler.call_i32("gt", &[5, 5]).unwrap(), 0);
+    assert_eq!(compiler.call_i32("gt", &[0, -1]).unwrap(), 1);
+}
+
+#[test]
+fn i32_eq_neq() {
+    let mut compiler = Compiler::new();
+    compiler
+        .run(
+            r#"
+            eq := fn(a: i32, b: i32) -> i32 { r := 0; if a == b { r = 1; }; r };
+            neq := fn(a: i32, b: i32) -> i32 { r := 0; if a != b { r = 1; }; r };
+            "#,
+        )
+        .unwrap();
+
+    for args in [[3, 3], [3, 5], [-1, 1], [0, 0]] {
+        let eq = compiler.call_i32("eq", &args).unwrap();
+        let neq = compiler.call_i32("neq", &args).unwrap();
+        assert_eq!(eq, (args[0] == args[1]) as i32, "{args:?}");
+        assert_eq!(neq, 1 - eq, "{args:?}");
+    }
+
+    let ir = compiler.ir_of("neq").unwrap();
+    assert!(ir.contains("builtin-i32-neq"), "{ir}");
+}
+
+#[test]
+fn bool_eq() {
+    static RESULTS: Mutex<Vec<bool>> = Mutex::new(Vec::new());
+
+    let mut compiler = Compiler::new();
+    compiler
+        .add("result", |b: bool| RESULTS.lock().unwrap().push(b))
+        .unwrap();
+    compiler
+        .run(
+            r#"
+            t := true;
+            f := false;
+            result(t == t);
+            result(t == f);
+            result(t != f);
+            result(f != f);
+            lt := 1 < 2;
+            gt := 3 > 4;
+            result(lt == gt);
+            "#,
+        )
+        .unwrap();
+
+    assert_eq!(*RESULTS.lock().unwrap(), [true, false, true, false, false]);
+}
+
+#[test]
+fn byte_str() {
+    static RESULT: AtomicBool = AtomicBool::new(false);
+
+    let mut compiler = Compiler::new();
+    compiler
+        .add("result", |b: bool| RESULT.store(b, Ordering::Relaxed))
+        .unwrap();
+    compiler
+        .run(r#"result(len(b"\xFF\x00a") == 3 && len("\xC3\xA4") == 2);"#)
+        .unwrap();
+    assert!(RESULT.load(Ordering::Relaxed));
+
+    let res = compiler.run(r#"result(len("\xFF") == 1);"#);
+    assert!(res.is_err(), "invalid UTF-8 without the `b` prefix");
+}
+
+#[test]
+fn recursion() {
+    let source = r#"
+        fact := fn(n: i32) -> i32 {
+            if n <= 1 {
+                return 1;
+            };
+            return n * fact(n - 1);
+        };
+
+        is_even := fn(n: i32) -> bool {
+            if n == 0 {
+                return n == 0;
+            };
+            return is_odd(n - 1);
+        };
+
+        is_odd := fn(n: i32) -> bool {
+            if n == 0 {
+                return n != 0;
+            };
+            return is_even(n - 1);
+        };
+
+        factorial(fact(5));
+        even(is_even(10));
+        odd(is_odd(10));
+    "#;
+
+    static FACTORIAL: AtomicI32 = AtomicI32::new(0);
+    static EVEN: AtomicBool = AtomicBool::new(false);
+    static ODD: AtomicBool = AtomicBool::new(true);
+
+    let mut compiler = Compiler::new();
+    compiler
+        .add("factorial", |i: i32| FACTORIAL.store(i, Ordering::Relaxed))
+        .unwrap();
+    compiler
+        .add("even", |b: bool| EVEN.store(b, Ordering::Relaxed))
+        .unwrap();
+    compiler
+        .add("odd", |b: bool| ODD.store(b, Ordering::Relaxed))
+        .unwrap();
+    compiler.run(source).unwrap();
+
+    assert_eq!(FACTORIAL.load(Ordering::Relaxed), 120);
+    assert!(EVEN.load(Ordering::Relaxed));
+    assert!(!ODD.load(Ordering::Relaxed));
+}
+
+#[test]
+fn ir_of() {
+    let mut compiler = Compiler::new();
+    compiler.add("ext", |i: i32| i).unwrap();
+    compiler
+        .run("double := fn(n: i32) -> i32 { return n + n; };")
+        .unwrap();
+
+    let ir = compiler.ir_of("double").unwrap();
+    assert!(ir.contains("define"), "{ir}");
+    assert!(ir.contains("add"), "{ir}");
+
+    let ir = compiler.ir_of("ext").unwrap();
+    assert!(ir.contains("call-fn-ptr"), "{ir}");
+
+    assert!(compiler.ir_of("missing").is_err());
+}
+
+#[test]
+fn immutable_local_without_alloca() {
+    let mut compiler = Compiler::new();
+    compiler
+        .run(
+            r#"
+            immutable := fn(n: i32) -> i32 { m := n * 2; m + 1 };
+            mutable := fn(n: i32) -> i32 { m := n * 2; m = m + 1; m };
+            "#,
+        )
+        .unwrap();
+
+    // the instructions are the indented lines of the function body
+    let instructions = |ir: &str| ir.lines().filter(|line| line.starts_with("  ")).count();
+
+    let immutable = compiler.ir_of("immutable").unwrap();
+    let mutable = compiler.ir_of("mutable").unwrap();
+    assert!(!immutable.contains("alloca"), "{immutable}");
+    assert!(mutable.contains("alloca"), "{mutable}");
+    assert!(
+        instructions(&immutable) < instructions(&mutable),
+        "{immutable}\n{mutable}"
+    );
+}
+
+#[test]
+fn select() {
+    let mut compiler = Compiler::new();
+    compiler
+        .run("clamp := fn(n: i32) -> i32 { x := 0; if n > 3 { x = 3; } else { x = n; }; x };")
+        .unwrap();
+
+    let ir = compiler.ir_of("clamp").unwrap();
+    assert!(ir.contains("select"), "{ir}");
+
+    assert_eq!(compiler.call_i32("clamp", &[5]).unwrap(), 3);
+    assert_eq!(compiler.call_i32("clamp", &[2]).unwrap(), 2);
+}
+
+#[test]
+fn match_switch() {
+    static RESULTS: Mutex<Vec<bool>> = Mutex::new(Vec::new());
+
+    let mut compiler = Compiler::new();
+    compiler
+        .add("result", |b: bool| RESULTS.lock().unwrap().push(b))
+        .unwrap();
+    compiler
+        .run(
+            r#"
+            describe := fn(n: i32) -> str { match n { 0 => "zero", 1 => "one", _ => "many" } };
+            result(describe(0) == "zero");
+            result(describe(1) == "one");
+            result(describe(7) == "many");
+            "#,
+        )
+        .unwrap();
+
+    let ir = compiler.ir_of("describe").unwrap();
+    assert_eq!(ir.matches("switch ").count(), 1, "{ir}");
+    assert_eq!(*RESULTS.lock().unwrap(), [true, true, true]);
+}
+
+#[test]
+fn usize_casts() {
+    static RESULTS: Mutex<Vec<i64>> = Mutex::new(Vec::new());
+
+    let mut compiler = Compiler::new();
+    compiler
+        .add("result", |n: i64| RESULTS.lock().unwrap().push(n))
+        .unwrap();
+    compiler
+        .run(
+            r#"
+            sum_to := fn(n: usize) -> i64 {
+                i: usize := 0;
+                total: i64 := 0;
+                for {
+                    if i == n { return total; };
+                    i = i + 1;
+                    total = total + i as i64;
+                };
+            };
+            n: usize := 10;
+            result(sum_to(n));
+
+            minus_one := 0 - 1;
+            result(minus_one as i64);
+            big: i64 := 4294967296 + 5;
+            result(big as i32 as i64);
+            result(len("four") as i64);
+            "#,
+        )
+        .unwrap();
+
+    // the same on 32 and 64 bit targets, no value here needs more than 32 bits as a `usize`
+    assert_eq!(*RESULTS.lock().unwrap(), [55, -1, 5, 4]);
+}
+
+#[test]
+fn str_bool_params() {
+    static RESULTS: Mutex<Vec<bool>> = Mutex::new(Vec::new());
+
+    let mut compiler = Compiler::new();
+    compiler
+        .add("result", |b: bool| RESULTS.lock().unwrap().push(b))
+        .unwrap();
+    compiler
+        .run(
+            r#"
+            shout := fn(s: str, loud: bool) -> str {
+                if loud { s = "HELLO"; };
+                s
+            };
+            flip := fn(b: bool, n: usize) -> bool { if n == 0 { return b; }; b == false };
+            result(shout("hello", false) == "hello");
+            result(shout("hello", true) == "HELLO");
+            result(flip(true, 0));
+            result(flip(true, 1) == false);
+            "#,
+        )
+        .unwrap();
+
+    assert_eq!(*RESULTS.lock().unwrap(), [true, true, true, true]);
+}
+
+#[test]
+fn default_params() {
+    static RESULTS: Mutex<Vec<bool>> = Mutex::new(Vec::new());
+
+    let mut compiler = Compiler::new();
+    compiler
+        .add("result", |b: bool| RESULTS.lock().unwrap().push(b))
+        .unwrap();
+    compiler
+        .run(
+            r#"
+            greet := fn(name: str, loud: bool = false) -> bool { loud };
+            result(greet("hi"));
+            result(greet("hi", true));
+            result(greet(loud: true, name: "hi"));
+            "#,
+        )
+        .unwrap();
+
+    assert_eq!(*RESULTS.lock().unwrap(), [false, true, true]);
+}
+
+#[test]
+fn static_mut() {
+    static RESULTS: Mutex<Vec<i32>> = Mutex::new(Vec::new());
+
+    let mut compiler = Compiler::new();
+    compiler
+        .add("result", |n: i32| RESULTS.lock().unwrap().push(n))
+        .unwrap();
+    compiler
+        .run(
+            r#"
+            static mut counter: i32 := 10;
+            bump := fn() { counter = counter + 1; };
+            read := fn() -> i32 { counter };
+            result(read());
+            bump();
+            bump();
+            result(read());
+            "#,
+        )
+        .unwrap();
+
+    assert_eq!(*RESULTS.lock().unwrap(), [10, 12]);
+}
+
+#[test]
+fn const_arrays() {
+    static RESULTS: Mutex<Vec<i32>> = Mutex::new(Vec::new());
+
+    let mut compiler = Compiler::new();
+    compiler
+        .add("result", |n: i32| RESULTS.lock().unwrap().push(n))
+        .unwrap();
+    compiler
+        .run(
+            r#"
+            const TABLE = [1, 2, 4, 8];
+            i: usize := 0;
+            for {
+                if i == len(TABLE) { return; };
+                result(TABLE[i]);
+                i = i + 1;
+            };
+            "#,
+        )
+        .unwrap();
+
+    assert_eq!(*RESULTS.lock().unwrap(), [1, 2, 4, 8]);
+}
+
+#[test]
+fn negative_index() {
+    let source = r#"
+        const TABLE = [1, 2, 4, 8];
+        lookup := fn(i: i32) -> i32 { TABLE[i] };
+        result(lookup(3));
+        result(lookup(0 - 1));
+        result(5);
+    "#;
+    let output = run_in_child("negative_index", source);
+    assert!(!output.status.success(), "{output:?}");
+
+    // `i32` indices convert to `usize`, negative ones fail the bounds check
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    assert!(stdout.contains("8\n"), "{stdout}");
+    assert!(!stdout.contains("5\n"), "{stdout}");
+}
+
+#[test]
+fn int_literal_hint() {
+    static RESULTS: Mutex<Vec<i64>> = Mutex::new(Vec::new());
+
+    let mut compiler = Compiler::new();
+    compiler
+        .add("result", |n: i64| RESULTS.lock().unwrap().push(n))
+        .unwrap();
+    compiler
+        .run(
+            r#"
+            big := fn(n: i64) -> i64 { n * 3000000000 };
+            x := big(2);
+            result(x);
+            result(big(1) + 1);
+            "#,
+        )
+        .unwrap();
+
+    assert_eq!(*RESULTS.lock().unwrap(), [6000000000, 3000000001]);
+}
+
+#[test]
+fn methods() {
+    static RESULTS: Mutex<Vec<i32>> = Mutex::new(Vec::new());
+
+    let mut compiler = Compiler::new();
+    compiler
+        .add("result", |n: i32| RESULTS.lock().unwrap().push(n))
+        .unwrap();
+    compiler
+        .run(
+            r#"
+            type Counter = (i32, i32);
+            impl Counter {
+                increment := fn(self: Counter) -> Counter { v, step := self; (v + step, step) };
+                value := fn(self: Counter) -> i32 { v, step := self; v };
+                bump := fn(self: &mut Counter) { v, step := *self; *self = (v + step, step); };
+                get := fn(self: &Counter) -> i32 { v, step := *self; v };
+            }
+            impl i32 {
+                double := fn(self: i32) -> i32 { self * 2 };
+            }
+
+            counter := (0, 1);
+            counter = counter.increment();
+            counter = counter.increment();
+            counter = counter.increment();
+            result(counter.value());
+            result(counter.value().double());
+
+            other := (0, 1);
+            other.bump();
+            other.bump();
+            other.bump();
+            result(other.get());
+            "#,
+        )
+        .unwrap();
+
+    assert_eq!(*RESULTS.lock().unwrap(), [3, 6, 3]);
+}
+
+#[test]
+fn fn_ptr_param() {
+    let source = r#"
+        less := fn(a: i32, b: i32) -> bool {
+            return a < b;
+        };
+
+        greater := fn(a: i32, b: i32) -> bool {
+            return b < a;
+        };
+
+        pick := fn(a: i32, b: i32, cmp: fn(i32, i32) -> bool) -> i32 {
+            if cmp(a, b) {
+                return a;
+            };
+            return b;
+        };
+
+        min(pick(3, 5, less));
+        max(pick(3, 5, greater));
+    "#;
+
+    static MIN: AtomicI32 = AtomicI32::new(0);
+    static MAX: AtomicI32 = AtomicI32::new(0);
+
+    let mut compiler = Compiler::new();
+    compiler
+        .add("min", |i: i32| MIN.store(i, Ordering::Relaxed))
+        .unwrap();
+    compiler
+        .add("max", |i: i32| MAX.store(i, Ordering::Relaxed))
+        .unwrap();
+    compiler.run(source).unwrap();
+
+    assert_eq!(MIN.load(Ordering::Relaxed), 3);
+    assert_eq!(MAX.load(Ordering::Relaxed), 5);
+}
+
+#[test]
+fn nested_call() {
+    let source = r#"
+        inner := fn(n: i32) -> i32 { n + 1 };
+        outer := fn(n: i32) -> i32 { n * 2 };
+        result(outer(inner(3)));
+    "#;
+
+    static RESULT: AtomicI32 = AtomicI32::new(0);
+
+    let mut compiler = Compiler::new();
+    compiler
+        .add("result", |i: i32| RESULT.store(i, Ordering::Relaxed))
+        .unwrap();
+    compiler.run(source).unwrap();
+
+    assert_eq!(RESULT.load(Ordering::Relaxed), 8);
+}
+
+#[test]
+fn incremental() {
+    static RESULT: AtomicI32 = AtomicI32::new(0);
+    static LATER: AtomicI32 = AtomicI32::new(0);
+
+    let mut compiler = Compiler::new();
+    compiler
+        .add("result", |i: i32| RESULT.store(i, Ordering::Relaxed))
+        .unwrap();
+    compiler
+        .run("double := fn(n: i32) -> i32 { n * 2 }; base := 20; result(double(1));")
+        .unwrap();
+    assert_eq!(RESULT.load(Ordering::Relaxed), 2);
+
+    // the second run calls into the code and reads the globals of the first one
+    compiler
+        .add("later", |i: i32| LATER.store(i, Ordering::Relaxed))
+        .unwrap();
+    compiler
+        .run("base = base + 1; result(double(base)); later(base);")
+        .unwrap();
+    assert_eq!(RESULT.load(Ordering::Relaxed), 42);
+    assert_eq!(LATER.load(Ordering::Relaxed), 21);
+}
+
+#[test]
+fn reset() {
+    static RESULT: AtomicI32 = AtomicI32::new(0);
+
+    let mut compiler = Compiler::new();
+    for i in 0..100 {
+        compiler.reset().unwrap();
+        assert!(
+            compiler.type_of("value").is_err(),
+            "the previous run should be forgotten"
+        );
+
+        compiler
+            .add("result", |i: i32| RESULT.store(i, Ordering::Relaxed))
+            .unwrap();
+        compiler
+            .run(&format!(
+                "value := {i}; get := fn() -> i32 {{ value }}; result(get());"
+            ))
+            .unwrap();
+        assert_eq!(RESULT.load(Ordering::Relaxed), i);
+    }
+}
+
+#[test]
+fn redefine_function() {
+    static RESULT: AtomicI32 = AtomicI32::new(0);
+
+    let mut compiler = Compiler::new();
+    compiler
+        .add("result", |i: i32| RESULT.store(i, Ordering::Relaxed))
+        .unwrap();
+    compiler
+        .run("f := fn() -> i32 { 1 }; g := fn() -> i32 { f() }; result(f());")
+        .unwrap();
+    assert_eq!(RESULT.load(Ordering::Relaxed), 1);
+
+    compiler
+        .run("f := fn() -> i32 { 2 }; result(f());")
+        .unwrap();
+    assert_eq!(RESULT.load(Ordering::Relaxed), 2);
+
+    // `g` was compiled with the old `f`
+    compiler.run("result(g() * 10 + f());").unwrap();
+    assert_eq!(RESULT.load(Ordering::Relaxed), 12);
+
+    // twice in the same run, the first one is never called
+    compiler
+        .run("f := fn() -> i32 { 3 }; f := fn() -> i32 { 4 }; result(f());")
+        .unwrap();
+    assert_eq!(RESULT.load(Ordering::Relaxed), 4);
+
+    let f: extern "C" fn() -> i32 =
+        unsafe { std::mem::transmute(compiler.get_fn_ptr("f").unwrap()) };
+    assert_eq!(f(), 4);
+}
+
+#[test]
+fn get_fn_ptr() {
+    let mut compiler = Compiler::new();
+    compiler
+        .run("offset := 1; scale := fn(n: i32) -> i32 { n * 3 + offset };")
+        .unwrap();
+
+    let addr = compiler.get_fn_ptr("scale").unwrap();
+    let scale: extern "C" fn(i32) -> i32 = unsafe { std::mem::transmute(addr) };
+    assert_eq!(scale(4), 13);
+    assert_eq!(scale(-1), -2);
+
+    // the function keeps working after more code is added
+    compiler.run("offset = 10;").unwrap();
+    assert_eq!(scale(4), 22);
+
+    assert!(compiler.get_fn_ptr("missing").is_err());
+}
+
+#[test]
+fn atomics() {
+    let mut compiler = Compiler::new();
+    compiler
+        .run(
+            r#"
+            flag := 0;
+            set := fn(v: i32) { atomic_store(&flag, v); };
+            wait := fn() -> i32 { for { v := atomic_load(&flag); if v != 0 { return v; }; }; };
+            "#,
+        )
+        .unwrap();
+
+    let set: extern "C" fn(i32) = unsafe { mem::transmute(compiler.get_fn_ptr("set").unwrap()) };
+    let wait: extern "C" fn() -> i32 =
+        unsafe { mem::transmute(compiler.get_fn_ptr("wait").unwrap()) };
+
+    // `wait` spins until the other thread changes the flag
+    let setter = thread::spawn(move || {
+        thread::sleep(std::time::Duration::from_millis(20));
+        set(7);
+    });
+    assert_eq!(wait(), 7);
+    setter.join().unwrap();
+}
+
+#[test]
+fn atomic_rmw() {
+    static RESULTS: Mutex<Vec<i32>> = Mutex::new(Vec::new());
+
+    let mut compiler = Compiler::new();
+    compiler
+        .add("result", |n: i32| RESULTS.lock().unwrap().push(n))
+        .unwrap();
+    compiler
+        .run(
+            r#"
+            counter := 0;
+            incr := fn() -> i32 { atomic_add(&counter, 1) };
+            result(incr());
+            result(incr());
+            result(atomic_sub(&counter, 5));
+            result(atomic_xchg(&counter, 9));
+            result(counter);
+            "#,
+        )
+        .unwrap();
+
+    // each increment sees the value the other one left behind
+    assert_eq!(*RESULTS.lock().unwrap(), [0, 1, 2, -3, 9]);
+
+    // the same counter from several threads at once doesn't lose any increments
+    compiler.run("counter = 0;").unwrap();
+    let incr: extern "C" fn() -> i32 =
+        unsafe { mem::transmute(compiler.get_fn_ptr("incr").unwrap()) };
+    let threads: Vec<_> = (0..4)
+        .map(|_| {
+            thread::spawn(move || {
+                for _ in 0..1000 {
+                    incr();
+                }
+            })
+        })
+        .collect();
+    for thread in threads {
+        thread.join().unwrap();
+    }
+    assert_eq!(incr(), 4000);
+}
+
+#[test]
+fn compare_exchange() {
+    static RESULTS: Mutex<Vec<i32>> = Mutex::new(Vec::new());
+
+    let mut compiler = Compiler::new();
+    compiler
+        .add("result", |n: i32| RESULTS.lock().unwrap().push(n))
+        .unwrap();
+    compiler
+        .run(
+            r#"
+            lock := 0;
+            count := 0;
+            acquire := fn() -> i32 {
+                spins := 0;
+                for {
+                    old, ok := cmpxchg(&lock, 0, 1);
+                    if ok { return spins; };
+                    spins = spins + 1;
+                };
+            };
+            release := fn() { atomic_store(&lock, 0); };
+            bump := fn() { acquire(); count = count + 1; release(); };
+            get := fn() -> i32 { count };
+
+            result(acquire());
+            old, ok := cmpxchg(&lock, 0, 1);
+            result(old);
+            assert_eq(ok, false);
+            release();
+            "#,
+        )
+        .unwrap();
+
+    // the free lock is taken right away, the held one isn't
+    assert_eq!(*RESULTS.lock().unwrap(), [0, 1]);
+
+    // the spin lock keeps the plain `count = count + 1` from losing updates
+    let bump: extern "C" fn() = unsafe { mem::transmute(compiler.get_fn_ptr("bump").unwrap()) };
+    let threads: Vec<_> = (0..4)
+        .map(|_| {
+            thread::spawn(move || {
+                for _ in 0..1000 {
+                    bump();
+                }
+            })
+        })
+        .collect();
+    for thread in threads {
+        thread.join().unwrap();
+    }
+    assert_eq!(compiler.call_i32("get", &[]).unwrap(), 4000);
+}
+
+#[test]
+fn if_expr() {
+    static RESULTS: Mutex<Vec<i32>> = Mutex::new(Vec::new());
+
+    let mut compiler = Compiler::new();
+    compiler
+        .add("result", |n: i32| RESULTS.lock().unwrap().push(n))
+        .unwrap();
+    compiler
+        .run(
+            r#"
+            both := fn(c: bool) -> i32 { x: i32 := if c { return 1; } else { return 2; }; x };
+            one := fn(c: bool) -> i32 { x := if c { return 10; } else { 20 }; x + 1 };
+            pick := fn(n: i32) -> i32 { s := if n < 0 { 0 - 1 } else if n == 0 { 0 } else { 1 }; s };
+            result(both(true));
+            result(both(false));
+            result(one(true));
+            result(one(false));
+            result(pick(0 - 5) + pick(0) * 10 + pick(7) * 100);
+            "#,
+        )
+        .unwrap();
+
+    assert_eq!(*RESULTS.lock().unwrap(), [1, 2, 10, 21, 99]);
+}
+
+#[test]
+fn mutual_recursion() {
+    let mut compiler = Compiler::new();
+    compiler
+        .run(
+            r#"
+            is_even := fn(n: i32) -> bool { if n == 0 { return true; }; return is_odd(n - 1); };
+            is_odd := fn(n: i32) -> bool { if n == 0 { return false; }; return is_even(n - 1); };
+            parity := fn(n: i32) -> i32 {
+                if is_even(n) { return 0; };
+                if is_odd(n) { return 1; };
+                return 2;
+            };
+            "#,
+        )
+        .unwrap();
+
+    for n in 0..6 {
+        assert_eq!(compiler.call_i32("parity", &[n]).unwrap(), n % 2);
+    }
+}
+
+#[test]
+fn call_i32() {
+    let mut compiler = Compiler::new();
+    compiler
+        .run(
+            "add := fn(a: i32, b: i32) -> i32 { a + b }; is_zero := fn(n: i32) -> bool { n == 0 };",
+        )
+        .unwrap();
+
+    assert_eq!(compiler.call_i32("add", &[2, 3]).unwrap(), 5);
+    assert_eq!(compiler.call_i32("add", &[-7, 4]).unwrap(), -3);
+
+    let res = compiler.call_i32("add", &[1]);
+    assert!(
+        matches!(
+            res,
+            Err(CompileError {
+                error: StageError::Codegen(codegen::Error::ArityMismatch {
+                    expected: 2,
+                    got: 1
+                }),
+                ..
+            })
+        ),
+        "{res:?}"
+    );
+    assert!(compiler.call_i32("is_zero", &[0]).is_err());
+}
+
+#[test]
+fn free_function() {
+    static RESULT: AtomicI32 = AtomicI32::new(0);
+
+    let mut compiler = Compiler::new();
+    compiler
+        .add("result", |i: i32| RESULT.store(i, Ordering::Relaxed))
+        .unwrap();
+    compiler
+        .run("square := fn(n: i32) -> i32 { n * n }; result(square(7));")
+        .unwrap();
+    assert_eq!(RESULT.load(Ordering::Relaxed), 49);
+
+    unsafe { compiler.free_function("square").unwrap() };
+
+    assert!(unsafe { compiler.free_function("missing") }.is_err());
+
+    // new code can't call it anymore
+    assert!(compiler.run("result(square(3));").is_err());
+    assert_eq!(RESULT.load(Ordering::Relaxed), 49);
+}
+
+#[test]
+fn bool_fn_condition() {
+    let source = r#"
+        is_small := fn(n: i32) -> bool { n < 10 };
+        classify := fn(n: i32) -> i32 {
+            if is_small(n) {
+                return 1;
+            } else if is_small(n / 10) {
+                return 2;
+            };
+            return 3;
+        };
+        result(classify(5) * 100 + classify(50) * 10 + classify(500));
+    "#;
+
+    static RESULT: AtomicI32 = AtomicI32::new(0);
+
+    let mut compiler = Compiler::new();
+    compiler
+        .add("result", |i: i32| RESULT.store(i, Ordering::Relaxed))
+        .unwrap();
+    compiler.run(source).unwrap();
+
+    assert_eq!(RESULT.load(Ordering::Relaxed), 123);
+}
+
+#[test]
+fn extern_fn() {
+    static RESULT: AtomicI32 = AtomicI32::new(0);
+
+    let mut compiler = Compiler::new();
+    compiler
+        .add("result", |i: i32| RESULT.store(i, Ordering::Relaxed))
+        .unwrap();
+    compiler
+        .run("extern fn abs(n: i32) -> i32; result(abs(3 - 8));")
+        .unwrap();
+
+    assert_eq!(RESULT.load(Ordering::Relaxed), 5);
+}
+
+#[test]
+fn bool_variable() {
+    let source = r#"
+        pick := fn(n: i32) -> i32 {
+            b := true;
+            if n == 0 {
+                b = false;
+            };
+            if b {
+                return 1;
+            };
+            return 2;
+        };
+
+        first(pick(5));
+        second(pick(0));
+    "#;
+
+    static FIRST: AtomicI32 = AtomicI32::new(0);
+    static SECOND: AtomicI32 = AtomicI32::new(0);
+
+    let mut compiler = Compiler::new();
+    compiler
+        .add("first", |i: i32| FIRST.store(i, Ordering::Relaxed))
+        .unwrap();
+    compiler
+        .add("second", |i: i32| SECOND.store(i, Ordering::Relaxed))
+        .unwrap();
+    compiler.run(source).unwrap();
+
+    assert_eq!(FIRST.load(Ordering::Relaxed), 1);
+    assert_eq!(SECOND.load(Ordering::Relaxed), 2);
+}
+
+#[test]
+fn immediately_invoked() {
+    static RESULT: AtomicI32 = AtomicI32::new(0);
+
+    let mut compiler = Compiler::new();
+    compiler
+        .add("result", |i: i32| RESULT.store(i, Ordering::Relaxed))
+        .unwrap();
+    compiler
+        .run("result((fn(x: i32) -> i32 { x * 2 })(21));")
+        .unwrap();
+
+    assert_eq!(RESULT.load(Ordering::Relaxed), 42);
+}
+
+#[test]
+fn define_symbol() {
+    extern "C" fn triple(n: i32) -> i32 {
+        n * 3
+    }
+
+    static RESULT: AtomicI32 = AtomicI32::new(0);
+
+    let mut compiler = Compiler::new();
+    compiler
+        .add("result", |i: i32| RESULT.store(i, Ordering::Relaxed))
+        .unwrap();
+    unsafe { compiler.define_symbol("triple", triple as extern "C" fn(i32) -> i32 as usize) }
+        .unwrap();
+    compiler
+        .run("extern fn triple(n: i32) -> i32; result(triple(5));")
+        .unwrap();
+
+    assert_eq!(RESULT.load(Ordering::Relaxed), 15);
+}
+
+#[test]
+fn parse_int() {
+    static RESULTS: Mutex<Vec<i32>> = Mutex::new(Vec::new());
+
+    let mut compiler = Compiler::new();
+    compiler
+        .add("result", |i: i32| RESULTS.lock().unwrap().push(i))
+        .unwrap();
+    compiler
+        .run(r#"result(parse_int("123")); result(parse_int("-7") * 2); result(parse_int("12a"));"#)
+        .unwrap();
+    compiler.run(r#"result(parse_int(b"1\xFF"));"#).unwrap();
+
+    assert_eq!(*RESULTS.lock().unwrap(), [123, -14, i32::MIN, i32::MIN]);
+}
+
+#[test]
+fn add_extern_typed() {
+    extern "C" fn sub(a: i32, b: i32) -> i32 {
+        a - b
+    }
+
+    static RESULT: AtomicI32 = AtomicI32::new(0);
+
+    // the signature only known at runtime, like from FFI discovery
+    let fn_ptr = sub as extern "C" fn(i32, i32) -> i32 as usize;
+    let (ret, params) = (Type::I32, [Type::I32, Type::I32]);
+
+    let mut compiler = Compiler::new();
+    compiler
+        .add("result", |i: i32| RESULT.store(i, Ordering::Relaxed))
+        .unwrap();
+    unsafe { compiler.add_extern_typed("sub", fn_ptr, ret, &params) }.unwrap();
+    compiler.run("result(sub(50, 8));").unwrap();
+
+    assert_eq!(RESULT.load(Ordering::Relaxed), 42);
+}
+
+#[test]
+fn i32_to_str() {
+    static BUF: Mutex<String> = Mutex::new(String::new());
+
+    let mut compiler = Compiler::new();
+    compiler
+        .add("result", |s: Str| {
+            BUF.lock()
+                .unwrap()
+                .write_fmt(format_args!("{s}\n"))
+                .unwrap();
+        })
+        .unwrap();
+    compiler
+        .run("result(i32_to_str(42)); result(i32_to_str(0 - 100));")
+        .unwrap();
+
+    assert_eq!(BUF.lock().unwrap().as_str(), "42\n-100\n");
+}
+
+#[test]
+fn main_args() {
+    let source = r#"
+        main := fn(args: [str]) -> i32 {
+            n := len(args);
+            count := 0;
+            for {
+                if n == 0 {
+                    return count;
+                };
+                n = n - 1;
+                count = count + 1;
+            };
+        };
+    "#;
+
+    let mut compiler = Compiler::new();
+    assert_eq!(compiler.run_main(source, &["a", "bc"]).unwrap(), 2);
+
+    let mut compiler = Compiler::new();
+    let res = compiler.run_main("main := fn() -> i32 { return 7; };", &["a", "bc"]);
+    assert_eq!(res.unwrap(), 7);
+
+    let mut compiler = Compiler::new();
+    let res = compiler.run_main("main := fn(n: i32) -> i32 { return n; };", &[]);
+    assert!(
+        matches!(
+            res,
+            Err(CompileError {
+                error: StageError::Codegen(codegen::Error::InvalidMainFn),
+                ..
+            })
+        ),
+        "{res:?}"
+    );
+}
+
+#[test]
+fn void_main() {
+    static RESULT: AtomicI32 = AtomicI32::new(0);
+
+    let mut compiler = Compiler::new();
+    compiler
+        .add("result", |i: i32| RESULT.store(i, Ordering::Relaxed))
+        .unwrap();
+    let res = compiler.run_main("main := fn() { result(5); };", &[]);
+    assert_eq!(res.unwrap(), 0);
+    assert_eq!(RESULT.load(Ordering::Relaxed), 5);
+
+    let mut compiler = Compiler::new();
+    let res = compiler.run_main("main := fn(args: [str]) { };", &["a"]);
+    assert_eq!(res.unwrap(), 0);
+
+    let mut compiler = Compiler::new();
+    let res = compiler.run_main("main := fn() -> bool { true };", &[]);
+    assert!(
+        matches!(
+            res,
+            Err(CompileError {
+                error: StageError::Codegen(codegen::Error::InvalidMainFn),
+                ..
+            })
+        ),
+        "{res:?}"
+    );
+}
+
+#[test]
+fn panic() {
+    let source = r#"
+        check := fn(x: i32) -> i32 {
+            if x < 0 {
+                panic("negative!");
+            };
+            return x;
+        };
+        result(check(5));
+        result(check(0 - 1));
+    "#;
+
+    let output = run_in_child("panic", source);
+
+    assert_eq!(output.status.code(), Some(1), "{output:?}");
+    assert!(String::from_utf8_lossy(&output.stdout).contains("5\n"));
+    assert!(String::from_utf8_lossy(&output.stderr).contains("negative!\n"));
+}
+
+#[test]
+fn assert() {
+    let output = run_in_child("assert", r#"assert(1 == 1, "math broke"); result(1);"#);
+    assert_eq!(output.status.code(), Some(0), "{output:?}");
+    assert!(String::from_utf8_lossy(&output.stdout).contains("1\n"));
+
+    let output = run_in_child("assert", r#"assert(1 == 2, "math broke"); result(1);"#);
+    assert_eq!(output.status.code(), Some(1), "{output:?}");
+    assert!(!String::from_utf8_lossy(&output.stdout).contains("1\n"));
+    assert!(String::from_utf8_lossy(&output.stderr).contains("math broke\n"));
+
+    let output = run_in_child("assert", "assert_eq(2 + 2, 5);");
+    assert_eq!(output.status.code(), Some(1), "{output:?}");
+    assert!(String::from_utf8_lossy(&output.stderr).contains("`4 == 5`"));
+}
+
+#[test]
+fn assert_eq() {
+    let source = r#"
+        assert_eq(2 + 2, 4);
+        assert_eq(1 < 2, true);
+        assert_eq("ab", "ab");
+        result(1);
+    "#;
+    let output = run_in_child("assert_eq", source);
+    assert_eq!(output.status.code(), Some(0), "{output:?}");
+
+    let output = run_in_child("assert_eq", "assert_eq(2 < 1, true);");
+    assert_eq!(output.status.code(), Some(1), "{output:?}");
+    assert!(String::from_utf8_lossy(&output.stderr).contains("`false == true`"));
+
+    let output = run_in_child("assert_eq", r#"assert_eq("ab", "cd");"#);
+    assert_eq!(output.status.code(), Some(1), "{output:?}");
+    assert!(String::from_utf8_lossy(&output.stderr).contains(r#"`"ab" == "cd"`"#));
+
+    // byte strings are compared by their bytes, even when they aren't UTF-8
+    let output = run_in_child("assert_eq", r#"assert_eq(b"\xFF", b"\xFE");"#);
+    assert_eq!(output.status.code(), Some(1), "{output:?}");
+    assert!(String::from_utf8_lossy(&output.stderr).contains("assertion failed"));
+}
+
+#[test]
+fn print_builtins() {
+    let output = run_in_child("print_builtins", "print_i32(42); print_bool(1 < 2);");
+    assert_eq!(output.status.code(), Some(0), "{output:?}");
+
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    assert!(stdout.contains("42\ntrue\n"), "{stdout}");
+
+    let mut compiler = Compiler::new();
+    let res = compiler.run(r#"print_i32("42");"#);
+    assert!(
+        matches!(
+            res,
+            Err(CompileError {
+                error: StageError::Typeck(_),
+                ..
+            })
+        ),
+        "{res:?}"
+    );
+}
+
+#[test]
+fn read_line() {
+    let source = r#"
+        first := read_line();
+        second := read_line();
+        print("{}|{}|{}\n", first, second, read_line());
+    "#;
+    let output = run_in_child_with_input("read_line", source, "hello\r\nworld\n", |_| {});
+    assert_eq!(output.status.code(), Some(0), "{output:?}");
+
+    // the input ends after two lines, so the third line is empty
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    assert!(stdout.contains("hello|world|\n"), "{stdout}");
+}
+
+#[test]
+fn str_ops() {
+    let source = r#"
+        s := concat("hello, ", "world");
+        assert_eq(s, "hello, world");
+        assert_eq(len(s) == 12, true);
+
+        part := s.slice(7, len(s));
+        assert_eq(part, "world");
+        assert_eq(part.starts_with("wor"), true);
+        assert_eq(s.starts_with("world"), false);
+        assert_eq(s.ends_with("world"), true);
+        assert_eq(s.contains(", w"), true);
+        assert_eq(s.contains("xyz"), false);
+
+        // the halves of a two byte character aren't UTF-8 on their own
+        e := "é";
+        assert_eq(concat(e.slice(0, 1), e.slice(1, 2)), "é");
+        result(s.byte_at(0));
+
+        s.slice(5, 20);
+        result(2);
+    "#;
+    let output = run_in_child("str_ops", source);
+    assert_eq!(output.status.code(), Some(1), "{output:?}");
+
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    assert!(stdout.contains("104\n"), "{stdout}");
+    assert!(!stdout.contains("2\n"), "{stdout}");
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert!(stderr.contains("slice 5..20 out of bounds"), "{stderr}");
+}
+
+#[test]
+fn exit_code() {
+    let output = run_in_child("exit_code", "result(1); exit(3); result(2);");
+    assert_eq!(output.status.code(), Some(3), "{output:?}");
+
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    assert!(stdout.contains("1\n"), "{stdout}");
+    assert!(!stdout.contains("2\n"), "{stdout}");
+}
+
+#[test]
+fn print_format() {
+    let source = r#"
+        x := 42;
+        name := "uni";
+        print("x = {}\n", x);
+        print("hello {}, {{{}}}\n", name, 1 < 2);
+    "#;
+    let output = run_in_child("print_format", source);
+    assert_eq!(output.status.code(), Some(0), "{output:?}");
+
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    assert!(stdout.contains("x = 42\nhello uni, {true}\n"), "{stdout}");
+
+    let mut compiler = Compiler::new();
+    let res = compiler.run(r#"print("{} + {}", 1);"#);
+    assert!(
+        matches!(
+            res,
+            Err(CompileError {
+                error: StageError::Typeck(typeck::Error::FormatArgCount { .. }),
+                ..
+            })
+        ),
+        "{res:?}"
+    );
+}
+
+#[test]
+fn threads() {
+    let run = |n: i32| {
+        thread::spawn(move || {
+            // same names, different values on each thread
+            let source = format!(
+                "value := {n}; get := fn() -> i32 {{ value }}; main := fn() -> i32 {{ get() * 2 }};"
+            );
+            Compiler::new().run_main(&source, &[]).unwrap()
+        })
+    };
+
+    let threads: Vec<_> = (1..=4).map(run).collect();
+    let results: Vec<i32> = threads.into_iter().map(|t| t.join().unwrap()).collect();
+
+    assert_eq!(results, [2, 4, 6, 8]);
+}
+
+#[test]
+fn warnings_as_errors() {
+    let source = "f := fn() -> i32 { unused := 4; 5 };";
+
+    let mut compiler = Compiler::new();
+    compiler.run(source).unwrap();
+
+    let mut compiler = Compiler::new();
+    compiler.set_warnings_as_errors(true).unwrap();
+    let res = compiler.run(source);
+    assert!(
+        matches!(
+            res,
+            Err(CompileError {
+                error: StageError::Typeck(typeck::Error::Warning(typeck::Warning::UnusedVariable(
+                    _
+                ))),
+                ..
+            })
+        ),
+        "{res:?}"
+    );
+}
+
+#[test]
+fn compile_error_stage() {
+    let mut compiler = Compiler::new();
+
+    let err: CompileError = lexer::Error::InvalidEscape.into();
+    assert_eq!(err.stage, Stage::Lexer);
+    assert_eq!(err.span, None);
+
+    let err: CompileError = parser::Error::Lexer(lexer::Error::InvalidUtf8).into();
+    assert_eq!(err.stage, Stage::Lexer);
+    assert!(matches!(err.error, StageError::Lexer(_)), "{err:?}");
+
+    let err: CompileError = compiler.run("x := ;").unwrap_err();
+    assert_eq!(err.stage, Stage::Parser);
+
+    let source = "y := 1 + true;";
+    let err: CompileError = compiler.run(source).unwrap_err();
+    assert_eq!(err.stage, Stage::Typeck);
+    assert_eq!(err.span.map(|span| span.as_str(source)), Some("true"));
+    assert!(matches!(err.error, StageError::Typeck(_)), "{err:?}");
+}
+
+#[test]
+fn variadic_printf() {
+    extern "C" {
+        fn printf(format: *const c_char, ...) -> c_int;
+    }
+
+    extern "C" fn format() -> usize {
+        c"value: %d %d\n".as_ptr() as usize
+    }
+
+    let output = run_in_child_with(
+        "variadic_printf",
+        "printf(format(), 42, true);",
+        |compiler| {
+            // the language has no C strings, the format string is passed as an address
+            let printf = printf as unsafe extern "C" fn(*const c_char, ...) -> c_int;
+            let printf: unsafe extern "C" fn(usize, ...) -> i32 = unsafe { mem::transmute(printf) };
+            compiler.add_raw("printf", printf).unwrap();
+            compiler
+                .add_raw("format", format as extern "C" fn() -> usize)
+                .unwrap();
+        },
+    );
+
+    assert_eq!(output.status.code(), Some(0), "{output:?}");
+    assert!(String::from_utf8_lossy(&output.stdout).contains("value: 42 1\n"));
+}
+
+#[test]
+fn stack_canaries() {
+    /// writes `7` to `offset` `i32`s after the variable behind `x`
+    extern "C" fn poke(x: *mut i32, offset: usize) {
+        unsafe { x.add(offset).write(7) }
+    }
+
+    let setup = |compiler: &mut Compiler| {
+        compiler.insert_stack_canaries().unwrap();
+        let poke = poke as extern "C" fn(*mut i32, usize);
+        unsafe { compiler.define_symbol("poke", poke as usize) }.unwrap();
+    };
+    let source = |offset: usize| {
+        format!(
+            r#"
+            extern fn poke(x: &i32, offset: usize);
+            f := fn() -> i32 {{ x := 1; poke(&x, {offset}); x }};
+            result(f());
+            "#
+        )
+    };
+
+    let output = run_in_child_with("stack_canaries", &source(0), setup);
+    assert_eq!(output.status.code(), Some(0), "{output:?}");
+    assert!(String::from_utf8_lossy(&output.stdout).contains("7\n"));
+
+    // one past the end of `x`
+    let output = run_in_child_with("stack_canaries", &source(1), setup);
+    assert_eq!(output.status.code(), Some(1), "{output:?}");
+    assert!(String::from_utf8_lossy(&output.stderr).contains("stack canary corrupted"));
+}
+
+#[test]
+fn use_file() {
+    let dir = env::temp_dir().join(format!("uni-use-file-{}", std::process::id()));
+    fs::create_dir_all(dir.join("lib")).unwrap();
+    fs::write(
+        dir.join("main.uni"),
+        r#"
+        use "lib/helper.uni";
+        use "lib/helper.uni";
+        main := fn() -> i32 { double(offset) };
+        "#,
+    )
+    .unwrap();
+    fs::write(
+        dir.join("lib/helper.uni"),
+        r#"
+        use "../lib/consts.uni";
+        double := fn(n: i32) -> i32 { n * 2 };
+        offset := BASE + 1;
+        "#,
+    )
+    .unwrap();
+    fs::write(dir.join("lib/consts.uni"), "const BASE: i32 = 20;").unwrap();
+    fs::write(dir.join("a.uni"), r#"use "b.uni";"#).unwrap();
+    fs::write(dir.join("b.uni"), r#"use "a.uni";"#).unwrap();
+
+    let mut compiler = Compiler::new();
+    compiler.run_file(&dir.join("main.uni")).unwrap();
+    assert_eq!(compiler.call_i32("main", &[]).unwrap(), 42);
+
+    let res = compiler.run_file(&dir.join("a.uni"));
+    assert!(
+        matches!(
+            &res,
+            Err(CompileError {
+                error: StageError::Codegen(codegen::Error::Import { err, .. }),
+                ..
+            }) if matches!(**err, codegen::Error::ImportCycle(_))
+        ),
+        "{res:?}"
+    );
+
+    let res = compiler.run_file(&dir.join("missing.uni"));
+    assert!(
+        matches!(
+            res,
+            Err(CompileError {
+                error: StageError::Codegen(codegen::Error::ReadFile { .. }),
+                ..
+            })
+        ),
+        "{res:?}"
+    );
+
+    fs::remove_dir_all(dir).unwrap();
+}
+
+/// runs `source` in a child process running only the test `test`,
+/// for code that exits the whole process like `panic`
+fn run_in_child(test: &str, source: &str) -> Output {
+    run_in_child_with(test, source, |_| {})
+}
+
+/// like [`run_in_child`], `setup` adds more externs in the child
+fn run_in_child_with(test: &str, source: &str, setup: impl FnOnce(&mut Compiler)) -> Output {
+    run_in_child_with_input(test, source, "", setup)
+}
+
+/// like [`run_in_child_with`], the child reads `input` from stdin
+fn run_in_child_with_input(
+    test: &str,
+    source: &str,
+    input: &str,
+    setup: impl FnOnce(&mut Compiler),
+) -> Output {
+    if let Some(child_source) = env::var_os("CHILD_TEST_SOURCE") {
+        let mut compiler = Compiler::new();
+        compiler.add("result", |i: i32| println!("{i}")).unwrap();
+        setup(&mut compiler);
+        compiler.run(child_source.to_str().unwrap()).unwrap();
+        exit(0);
+    }
+
+    let mut child = Command::new(env::current_exe().unwrap())
+        .args(["--exact", test, "--nocapture", "--test-threads=1"])
+        .env("CHILD_TEST_SOURCE", source)
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()
+        .unwrap();
+    child
+        .stdin
+        .take()
+        .unwrap()
+        .write_all(input.as_bytes())
+        .unwrap();
+    child.wait_with_output().unwrap()
+}
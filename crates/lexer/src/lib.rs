@@ -1,4 +1,4 @@
-#[cfg(test)]
+#[cfg(any(test, feature = "serde"))]
 use serde::Serialize;
 use std::{
     fmt::{self, Write},
@@ -40,6 +40,8 @@ pub type Result<T, E = Error> = core::result::Result<T, E>;
 pub enum Error {
     UnexpectedEoi,
     ExtraTokens,
+    InvalidEscape,
+    InvalidUtf8,
 }
 
 impl fmt::Display for Error {
@@ -47,8 +49,45 @@ impl fmt::Display for Error {
         match self {
             Error::UnexpectedEoi => f.write_str("unexpected end of input"),
             Error::ExtraTokens => f.write_str("found extra tokens"),
+            Error::InvalidEscape => f.write_str("invalid escape in a string literal"),
+            Error::InvalidUtf8 => f.write_str("string literal is not valid UTF-8"),
+        }
+    }
+}
+
+/// the bytes of a string literal without the quotes,
+/// `\n`, `\r`, `\t`, `\0`, `\\`, `\"` and `\xFF` are the only escapes
+pub fn unescape(str: &str) -> Result<Vec<u8>> {
+    let mut bytes = Vec::with_capacity(str.len());
+    let mut chars = str.chars();
+
+    while let Some(c) = chars.next() {
+        if c != '\\' {
+            let mut buf = [0; 4];
+            bytes.extend_from_slice(c.encode_utf8(&mut buf).as_bytes());
+            continue;
         }
+
+        let byte = match chars.next() {
+            Some('n') => b'\n',
+            Some('r') => b'\r',
+            Some('t') => b'\t',
+            Some('0') => b'\0',
+            Some('\\') => b'\\',
+            Some('"') => b'"',
+            Some('x') => {
+                let hex: String = chars.by_ref().take(2).collect();
+                if hex.len() != 2 || !hex.chars().all(|c| c.is_ascii_hexdigit()) {
+                    return Err(Error::InvalidEscape);
+                }
+                u8::from_str_radix(&hex, 16).unwrap()
+            }
+            _ => return Err(Error::InvalidEscape),
+        };
+        bytes.push(byte);
     }
+
+    Ok(bytes)
 }
 
 //
@@ -188,6 +227,12 @@ pub enum Token {
     /// `}`
     RBrace,
 
+    /// `[`
+    LBracket,
+
+    /// `]`
+    RBracket,
+
     /// `+`
     Plus,
 
@@ -215,6 +260,9 @@ pub enum Token {
     /// `&`
     Ampersand,
 
+    /// `.`
+    Dot,
+
     /// `<`
     Lt,
 
@@ -245,6 +293,12 @@ pub enum Token {
     /// `->`
     RArrow,
 
+    /// `=>`
+    FatArrow,
+
+    /// `::`
+    PathSep,
+
     /// `fn`
     Fn,
 
@@ -266,6 +320,45 @@ pub enum Token {
     /// `test`
     Test,
 
+    /// `extern`
+    Extern,
+
+    /// `type`
+    Type,
+
+    /// `opaque`
+    Opaque,
+
+    /// `const`
+    Const,
+
+    /// `use`
+    Use,
+
+    /// `match`
+    Match,
+
+    /// `impl`
+    Impl,
+
+    /// `mod`
+    Mod,
+
+    /// `as`
+    As,
+
+    /// `static`
+    Static,
+
+    /// `mut`
+    Mut,
+
+    /// `true`
+    True,
+
+    /// `false`
+    False,
+
     /// single line comments like `// this is a comment`
     LineComment,
 
@@ -281,6 +374,9 @@ pub enum Token {
     /// string literal like `"test"`
     LitStr,
 
+    /// byte string literal like `b"\xFF"`, not checked to be UTF-8
+    LitByteStr,
+
     /// end of input
     Eoi,
 }
@@ -294,6 +390,8 @@ impl Token {
             Token::RParen,
             Token::LBrace,
             Token::RBrace,
+            Token::LBracket,
+            Token::RBracket,
             Token::Plus,
             Token::Minus,
             Token::Asterisk,
@@ -303,6 +401,7 @@ impl Token {
             Token::Comma,
             Token::At,
             Token::Ampersand,
+            Token::Dot,
             Token::Lt,
             Token::Gt,
             Token::Le,
@@ -313,6 +412,8 @@ impl Token {
             Token::Or,
             Token::Walrus,
             Token::RArrow,
+            Token::FatArrow,
+            Token::PathSep,
             Token::Fn,
             Token::If,
             Token::Else,
@@ -320,11 +421,25 @@ impl Token {
             Token::Return,
             Token::Struct,
             Token::Test,
+            Token::Extern,
+            Token::Type,
+            Token::Opaque,
+            Token::Const,
+            Token::Use,
+            Token::Match,
+            Token::Impl,
+            Token::Mod,
+            Token::As,
+            Token::Static,
+            Token::Mut,
+            Token::True,
+            Token::False,
             Token::LineComment,
             Token::Ident,
             Token::LitFloat,
             Token::LitInt,
             Token::LitStr,
+            Token::LitByteStr,
             Token::Eoi,
         ]
     }
@@ -337,6 +452,8 @@ impl Token {
             Token::RParen => TokenType::Symbols(")"),
             Token::LBrace => TokenType::Symbols("{"),
             Token::RBrace => TokenType::Symbols("}"),
+            Token::LBracket => TokenType::Symbols("["),
+            Token::RBracket => TokenType::Symbols("]"),
             Token::Plus => TokenType::Symbols("+"),
             Token::Minus => TokenType::Symbols("-"),
             Token::Asterisk => TokenType::Symbols("*"),
@@ -346,6 +463,7 @@ impl Token {
             Token::Comma => TokenType::Symbols(","),
             Token::At => TokenType::Symbols("@"),
             Token::Ampersand => TokenType::Symbols("&"),
+            Token::Dot => TokenType::Symbols("."),
             Token::Lt => TokenType::Symbols("<"),
             Token::Gt => TokenType::Symbols(">"),
             Token::Le => TokenType::Symbols("<="),
@@ -356,6 +474,8 @@ impl Token {
             Token::Or => TokenType::Symbols("||"),
             Token::Walrus => TokenType::Symbols(":="),
             Token::RArrow => TokenType::Symbols("->"),
+            Token::FatArrow => TokenType::Symbols("=>"),
+            Token::PathSep => TokenType::Symbols("::"),
             Token::Fn => TokenType::Keyword("fn"),
             Token::If => TokenType::Keyword("if"),
             Token::Else => TokenType::Keyword("else"),
@@ -363,11 +483,25 @@ impl Token {
             Token::Return => TokenType::Keyword("return"),
             Token::Struct => TokenType::Keyword("struct"),
             Token::Test => TokenType::Keyword("test"),
+            Token::Extern => TokenType::Keyword("extern"),
+            Token::Type => TokenType::Keyword("type"),
+            Token::Opaque => TokenType::Keyword("opaque"),
+            Token::Const => TokenType::Keyword("const"),
+            Token::Use => TokenType::Keyword("use"),
+            Token::Match => TokenType::Keyword("match"),
+            Token::Impl => TokenType::Keyword("impl"),
+            Token::Mod => TokenType::Keyword("mod"),
+            Token::As => TokenType::Keyword("as"),
+            Token::Static => TokenType::Keyword("static"),
+            Token::Mut => TokenType::Keyword("mut"),
+            Token::True => TokenType::Keyword("true"),
+            Token::False => TokenType::Keyword("false"),
             Token::LineComment => TokenType::Other,
             Token::Ident => TokenType::Other,
             Token::LitFloat => TokenType::Other,
             Token::LitInt => TokenType::Other,
             Token::LitStr => TokenType::Other,
+            Token::LitByteStr => TokenType::Other,
             Token::Eoi => TokenType::Other,
         }
     }
@@ -442,7 +576,7 @@ impl SpannedToken {
 
 //
 
-#[cfg_attr(test, derive(Serialize))]
+#[cfg_attr(any(test, feature = "serde"), derive(Serialize))]
 #[derive(Debug, Clone, Copy, PartialEq, Eq)]
 pub struct Span {
     from: usize,
@@ -457,7 +591,7 @@ impl Span {
     pub fn merge(self, other: Self) -> Self {
         Self {
             from: self.from.min(other.from),
-            to: self.to.min(other.to),
+            to: self.to.max(other.to),
         }
     }
 
@@ -577,15 +711,33 @@ impl<'a> Lexer<'a> {
     }
 
     fn try_match_str(&mut self) -> Option<Result<SpannedToken>> {
-        if !self.at.starts_with('"') {
+        let (token, prefix) = if self.at.starts_with('"') {
+            (Token::LitStr, 0)
+        } else if self.at.starts_with("b\"") {
+            (Token::LitByteStr, 1)
+        } else {
             return None;
-        }
+        };
 
-        let Some(term) = self.at[1..].find('"') else {
+        // `\"` doesn't end the string
+        let mut escaped = false;
+        let Some(term) = self.at[prefix + 1..].find(|c: char| {
+            let end = c == '"' && !escaped;
+            escaped = c == '\\' && !escaped;
+            end
+        }) else {
             return self.err(Error::UnexpectedEoi);
         };
 
-        self.spanned_token_to(Token::LitStr, term + 2)
+        let bytes = match unescape(&self.at[prefix + 1..prefix + 1 + term]) {
+            Ok(bytes) => bytes,
+            Err(err) => return self.err(err),
+        };
+        if token == Token::LitStr && std::str::from_utf8(&bytes).is_err() {
+            return self.err(Error::InvalidUtf8);
+        }
+
+        self.spanned_token_to(token, prefix + term + 2)
     }
 
     fn try_match_ident(&mut self) -> Option<Result<SpannedToken>> {
@@ -706,7 +858,7 @@ impl<'a> Iterator for Lexer<'a> {
 
 #[cfg(test)]
 mod tests {
-    use crate::{Lexer, Result, SpannedToken, Token, TokenType};
+    use crate::{unescape, Error, Lexer, Result, SpannedToken, Token, TokenType};
 
     use insta::assert_yaml_snapshot;
     use serde::Serialize;
@@ -758,6 +910,29 @@ mod tests {
         assert_yaml_snapshot!(lex(&all_tokens));
     }
 
+    #[test]
+    fn lex_str_utf8() {
+        let tokens = |str| Lexer::new(str).collect::<Result<Vec<SpannedToken>>>();
+
+        let res = tokens(r#""\xFF""#);
+        assert_eq!(res, Err(Error::InvalidUtf8));
+
+        let res = tokens(r#"b"\xFF""#).unwrap();
+        assert_eq!(res[0].token(), Token::LitByteStr);
+        assert_eq!(unescape("\\xFF").unwrap(), [0xFF]);
+
+        let res = tokens(r#""\xC3\xA4 \"q\"\n""#).unwrap();
+        assert_eq!(res[0].token(), Token::LitStr);
+        assert_eq!(res[1].token(), Token::Eoi);
+        assert_eq!(
+            unescape(r#"\xC3\xA4 \"q\"\n"#).unwrap(),
+            "ä \"q\"\n".as_bytes()
+        );
+
+        assert_eq!(tokens(r#""\q""#), Err(Error::InvalidEscape));
+        assert_eq!(tokens(r#""\xG0""#), Err(Error::InvalidEscape));
+    }
+
     #[test]
     fn lex_comment() {
         assert_yaml_snapshot!(lex("// comment\ncode"));
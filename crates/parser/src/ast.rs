@@ -1,6 +1,6 @@
-use core::fmt;
+use core::{fmt, iter};
 
-use crate::{unexpected, Parse, ParseStream, Result, SingleToken, Token, TypeId};
+use crate::{unexpected, Error, Parse, ParseStream, Result, SingleToken, Token, TypeId};
 use lexer::Span;
 use macros::Parse;
 
@@ -12,6 +12,7 @@ use serde::Serialize;
 //
 
 pub mod token;
+pub mod visit;
 
 //
 
@@ -76,13 +77,64 @@ pub struct Ast<T> {
 #[cfg_attr(test, derive(Serialize))]
 #[derive(Debug, Clone, PartialEq, Eq)]
 pub struct Root {
-    pub stmts: Vec<Stmt>,
+    pub items: Vec<RootItem>,
 }
 
 impl Parse for Root {
     fn parse(tokens: &mut ParseStream) -> Result<Self> {
-        Ok(Self {
-            stmts: tokens.parse()?,
+        let mut items = Vec::new();
+        while !tokens.peek1(Token::RBrace) && !tokens.peek1(Token::Eoi) {
+            if tokens.peek1(Token::Extern) {
+                items.push(RootItem::Extern(tokens.parse()?));
+            } else if tokens.peek1(Token::Type) || tokens.peek1(Token::Opaque) {
+                items.push(RootItem::TypeAlias(tokens.parse()?));
+            } else if tokens.peek1(Token::Const) {
+                items.push(RootItem::ConstDecl(tokens.parse()?));
+            } else if tokens.peek1(Token::Static) {
+                items.push(RootItem::StaticDecl(tokens.parse()?));
+            } else if tokens.peek1(Token::Use) && tokens.peek2(Token::LitStr) {
+                items.push(RootItem::Use(tokens.parse()?));
+            } else if tokens.peek1(Token::Use) {
+                items.push(RootItem::UsePath(tokens.parse()?));
+            } else if tokens.peek1(Token::Impl) {
+                items.push(RootItem::Impl(tokens.parse()?));
+            } else if tokens.peek1(Token::Mod) {
+                items.push(RootItem::Mod(tokens.parse()?));
+            } else {
+                items.push(RootItem::Stmt(tokens.parse()?));
+            }
+
+            while tokens.peek1(Token::Semi) {
+                // skip all semicolons
+                _ = tokens.next_token()?;
+            }
+        }
+
+        Ok(Self { items })
+    }
+}
+
+impl Root {
+    pub fn stmts(&self) -> impl Iterator<Item = &Stmt> {
+        self.items.iter().filter_map(|item| match item {
+            RootItem::Stmt(stmt) => Some(&**stmt),
+            _ => None,
+        })
+    }
+
+    /// the files from `use "file";` items
+    pub fn imports(&self) -> impl Iterator<Item = &UseFile> {
+        self.items.iter().filter_map(|item| match item {
+            RootItem::Use(file) => Some(&**file),
+            _ => None,
+        })
+    }
+
+    /// the `impl Type { .. }` blocks
+    pub fn impls(&self) -> impl Iterator<Item = &Impl> {
+        self.items.iter().filter_map(|item| match item {
+            RootItem::Impl(impl_) => Some(&**impl_),
+            _ => None,
         })
     }
 }
@@ -99,6 +151,276 @@ impl Parse for Root {
 
 //
 
+/// things that can only be at the top level
+#[cfg_attr(test, derive(Serialize))]
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum RootItem {
+    Extern(Box<ExternFn>),
+    TypeAlias(Box<TypeAlias>),
+    ConstDecl(Box<ConstDecl>),
+    StaticDecl(Box<StaticDecl>),
+    Use(Box<UseFile>),
+    UsePath(Box<UsePath>),
+    Impl(Box<Impl>),
+    Mod(Box<ModDecl>),
+    Stmt(Box<Stmt>),
+}
+
+//
+
+/// `type Meters = i32;` is just another name for `i32`,
+/// `opaque type Meters = i32;` is a new type that only looks like an `i32`
+#[cfg_attr(test, derive(Serialize))]
+#[derive(Debug, Clone, PartialEq, Eq, Parse)]
+pub struct TypeAlias {
+    pub opaque_kw: Option<token::Opaque>,
+    pub type_kw: token::Type,
+    pub name: Ident,
+    pub assign: token::Assign,
+    pub ty: Ty,
+    pub semi: token::Semi,
+}
+
+//
+
+/// `const MAX: i32 = 100;` is a value known at compile time,
+/// it is copied into every place that uses it
+#[cfg_attr(test, derive(Serialize))]
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ConstDecl {
+    pub const_kw: token::Const,
+    pub name: Ident,
+    pub ty: Option<(token::Colon, Ty)>,
+    pub assign: token::Assign,
+    pub value: Expr,
+    pub semi: token::Semi,
+}
+
+impl Parse for ConstDecl {
+    fn parse(tokens: &mut ParseStream) -> Result<Self> {
+        let const_kw = tokens.parse()?;
+        let name = tokens.parse()?;
+
+        let mut look = tokens.look1();
+        let ty = if look.peek(Token::Colon) {
+            Some((tokens.parse()?, tokens.parse()?))
+        } else if look.peek(Token::Assign) {
+            None
+        } else {
+            return Err(look.err());
+        };
+
+        Ok(Self {
+            const_kw,
+            name,
+            ty,
+            assign: tokens.parse()?,
+            value: tokens.parse()?,
+            semi: tokens.parse()?,
+        })
+    }
+}
+
+//
+
+/// `static mut counter: i32 := 0;` is a module level variable,
+/// the initial value is known at compile time and every function can load and store it
+#[cfg_attr(test, derive(Serialize))]
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct StaticDecl {
+    pub static_kw: token::Static,
+    pub mut_kw: token::Mut,
+    pub name: Ident,
+    pub ty: Option<(token::Colon, Ty)>,
+    pub walrus: token::Walrus,
+    pub value: Expr,
+    pub semi: token::Semi,
+}
+
+impl Parse for StaticDecl {
+    fn parse(tokens: &mut ParseStream) -> Result<Self> {
+        let static_kw = tokens.parse()?;
+        let mut_kw = tokens.parse()?;
+        let name = tokens.parse()?;
+
+        let mut look = tokens.look1();
+        let ty = if look.peek(Token::Colon) {
+            Some((tokens.parse()?, tokens.parse()?))
+        } else if look.peek(Token::Walrus) {
+            None
+        } else {
+            return Err(look.err());
+        };
+
+        Ok(Self {
+            static_kw,
+            mut_kw,
+            name,
+            ty,
+            walrus: tokens.parse()?,
+            value: tokens.parse()?,
+            semi: tokens.parse()?,
+        })
+    }
+}
+
+//
+
+/// `use "other.uni";` loads another file before the file that uses it,
+/// the path is relative to the directory of the using file
+#[cfg_attr(test, derive(Serialize))]
+#[derive(Debug, Clone, PartialEq, Eq, Parse)]
+pub struct UseFile {
+    pub use_kw: token::Use,
+    pub path: LitStr,
+    pub semi: token::Semi,
+}
+
+impl UseFile {
+    pub fn path(&self) -> &std::path::Path {
+        std::path::Path::new(&self.path.value)
+    }
+}
+
+/// `use math::add;` makes `add` another name for `math::add`
+#[cfg_attr(test, derive(Serialize))]
+#[derive(Debug, Clone, PartialEq, Eq, Parse)]
+pub struct UsePath {
+    pub use_kw: token::Use,
+    pub path: ModPath,
+    pub semi: token::Semi,
+}
+
+//
+
+/// `impl Counter { inc := fn(self: Counter) -> Counter { .. }; }`,
+/// the methods are called like `counter.inc()`
+#[cfg_attr(test, derive(Serialize))]
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Impl {
+    pub impl_kw: token::Impl,
+    pub ty: Ident,
+    pub open: LBrace,
+    pub methods: Vec<Method>,
+    pub close: RBrace,
+}
+
+impl Parse for Impl {
+    fn parse(tokens: &mut ParseStream) -> Result<Self> {
+        let impl_kw = tokens.parse()?;
+        let ty = tokens.parse()?;
+        let open = tokens.parse()?;
+
+        let mut methods = Vec::new();
+        while !tokens.peek1(Token::RBrace) && !tokens.peek1(Token::Eoi) {
+            methods.push(tokens.parse()?);
+
+            while tokens.peek1(Token::Semi) {
+                // skip all semicolons
+                _ = tokens.next_token()?;
+            }
+        }
+
+        Ok(Self {
+            impl_kw,
+            ty,
+            open,
+            methods,
+            close: tokens.parse()?,
+        })
+    }
+}
+
+/// `inc := fn(self: Counter) -> Counter { .. }`, the first argument is the receiver
+#[cfg_attr(test, derive(Serialize))]
+#[derive(Debug, Clone, PartialEq, Eq, Parse)]
+pub struct Method {
+    pub name: Ident,
+    pub walrus: Walrus,
+    pub func: Func,
+}
+
+//
+
+/// `mod math { add := fn(a: i32, b: i32) -> i32 { a + b }; }`,
+/// the items inside are named like `math::add` from the outside
+#[cfg_attr(test, derive(Serialize))]
+#[derive(Debug, Clone, PartialEq, Eq, Parse)]
+pub struct ModDecl {
+    pub mod_kw: token::Mod,
+    pub name: Ident,
+    pub open: LBrace,
+    pub root: Root,
+    pub close: RBrace,
+}
+
+//
+
+/// `extern fn puts(s: str) -> i32;`,
+/// a function that is linked from the symbols already in the JIT engine
+#[cfg_attr(test, derive(Serialize))]
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ExternFn {
+    pub extern_kw: token::Extern,
+    pub fn_kw: token::Fn,
+    pub name: Ident,
+    pub args_beg: token::LParen,
+    pub args: Option<CommaSeparated<Argument>>,
+    pub args_end: token::RParen,
+    pub return_ty: Option<(token::RArrow, Ty)>,
+    pub semi: token::Semi,
+}
+
+impl ExternFn {
+    pub fn args(&self) -> impl ExactSizeIterator<Item = &Argument> + Clone {
+        OptionInner {
+            inner: self.args.as_ref().map(|s| s.iter()),
+        }
+    }
+}
+
+impl Parse for ExternFn {
+    fn parse(tokens: &mut ParseStream) -> Result<Self> {
+        let extern_kw = tokens.parse()?;
+        let fn_kw = tokens.parse()?;
+        let name = tokens.parse()?;
+        let args_beg = tokens.parse()?;
+
+        let mut look = tokens.look1();
+        let args = if look.peek(Token::Ident) {
+            Some(tokens.parse()?)
+        } else if look.peek(Token::RParen) {
+            None
+        } else {
+            return Err(look.err());
+        };
+
+        let args_end = tokens.parse()?;
+
+        let mut look = tokens.look1();
+        let return_ty = if look.peek(Token::RArrow) {
+            Some((tokens.parse()?, tokens.parse()?))
+        } else if look.peek(Token::Semi) {
+            None
+        } else {
+            return Err(look.err());
+        };
+
+        Ok(Self {
+            extern_kw,
+            fn_kw,
+            name,
+            args_beg,
+            args,
+            args_end,
+            return_ty,
+            semi: tokens.parse()?,
+        })
+    }
+}
+
+//
+
 #[cfg_attr(test, derive(Serialize))]
 #[derive(Debug, Clone, PartialEq, Eq, Parse)]
 pub struct Target {
@@ -113,6 +435,48 @@ pub struct Path {
     pub ident: Ident,
 }
 
+/// `math::add`, an item inside one or more `mod` blocks
+#[cfg_attr(test, derive(Serialize))]
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ModPath {
+    pub first: Ident,
+    pub rest: Vec<(token::PathSep, Ident)>,
+}
+
+impl Parse for ModPath {
+    fn parse(tokens: &mut ParseStream) -> Result<Self> {
+        let first = tokens.parse()?;
+
+        let mut rest = Vec::new();
+        while tokens.peek1(Token::PathSep) {
+            rest.push((tokens.parse()?, tokens.parse()?));
+        }
+
+        Ok(Self { first, rest })
+    }
+}
+
+impl ModPath {
+    pub fn segments(&self) -> impl Iterator<Item = &Ident> {
+        iter::once(&self.first).chain(self.rest.iter().map(|(_, ident)| ident))
+    }
+
+    /// the segments joined with `::`, like `math::add`
+    pub fn mangled(&self) -> String {
+        let mut name = self.first.value.clone();
+        for (_, ident) in self.rest.iter() {
+            name.push_str("::");
+            name.push_str(&ident.value);
+        }
+        name
+    }
+
+    pub fn span(&self) -> Span {
+        let last = self.rest.last().map_or(&self.first, |(_, ident)| ident);
+        self.first.span().merge(last.span())
+    }
+}
+
 //
 
 #[cfg_attr(test, derive(Serialize))]
@@ -238,48 +602,30 @@ impl Block {
 
 impl Parse for Block {
     fn parse(tokens: &mut ParseStream) -> Result<Self> {
-        Ok(Self {
-            ty: TypeId::Unknown,
-            open: tokens.parse()?,
-            stmts: tokens.parse()?,
-            auto_return: false,
-            close: tokens.parse()?,
-        })
-    }
-}
+        let open = tokens.parse()?;
 
-impl Parse for Vec<Stmt> {
-    fn parse(tokens: &mut ParseStream) -> Result<Self> {
         let mut stmts = Vec::new();
+        let mut auto_return = false;
         while !tokens.peek1(Token::RBrace) && !tokens.peek1(Token::Eoi) {
-            stmts.push(tokens.parse()?);
+            let stmt = tokens.parse()?;
+
+            // no semicolon after the last expression == it is the value of the block
+            auto_return = matches!(stmt, Stmt::Expr(_)) && !tokens.peek1(Token::Semi);
+            stmts.push(stmt);
 
-            // let mut has_semi = false;
             while tokens.peek1(Token::Semi) {
                 // skip all semicolons
-                // has_semi = true;
                 _ = tokens.next_token()?;
             }
-
-            /* if !has_semi {
-                // no semi == it is the last statement and an implicit return
-
-                let r_brace = tokens.next_token()?;
-                if r_brace.token() != Token::RBrace {
-                    return Err(unexpected(r_brace, &[Token::Semi, Token::RBrace], false));
-                }
-
-                return Ok(Self {
-                    ty: TypeId::Unknown,
-                    open,
-                    stmts,
-                    auto_return: true,
-                    close: RBrace,
-                });
-            } */
         }
 
-        Ok(stmts)
+        Ok(Self {
+            ty: TypeId::Unknown,
+            open,
+            stmts,
+            auto_return,
+            close: tokens.parse()?,
+        })
     }
 }
 
@@ -289,7 +635,9 @@ impl Parse for Vec<Stmt> {
 #[derive(Debug, Clone, PartialEq, Eq)]
 pub enum Stmt {
     Init(Init),
+    Decl(Decl),
     Set(Set),
+    SetRef(SetRef),
     Cond(Cond),
     Loop(Loop),
     Expr(StmtExpr),
@@ -302,11 +650,32 @@ impl Parse for Stmt {
             tokens.top1().map(|t| t.token()),
             tokens.top2().map(|t| t.token()),
         ) {
+            (Some(Token::Ident), Some(Token::Colon)) => {
+                let name: Ident = tokens.parse()?;
+                let colon = tokens.parse()?;
+                let ty = tokens.parse()?;
+                if !tokens.peek1(Token::Walrus) {
+                    return Ok(Self::Decl(Decl { name, colon, ty }));
+                }
+
+                Ok(Self::Init(Init {
+                    targets: CommaSeparated {
+                        first: Target {
+                            path: Path { ident: name },
+                        },
+                        inner: Vec::new(),
+                    },
+                    ty: Some((colon, ty)),
+                    walrus: tokens.parse()?,
+                    exprs: tokens.parse()?,
+                }))
+            }
             (Some(Token::Ident), Some(Token::Walrus | Token::Assign | Token::Comma)) => {
                 let targets = tokens.parse()?;
                 if tokens.peek1(Token::Walrus) {
                     Ok(Self::Init(Init {
                         targets,
+                        ty: None,
                         walrus: tokens.parse()?,
                         exprs: tokens.parse()?,
                     }))
@@ -318,6 +687,7 @@ impl Parse for Stmt {
                     }))
                 }
             }
+            (Some(Token::Asterisk), Some(Token::Ident)) => Ok(Self::SetRef(tokens.parse()?)),
             (Some(Token::If), _) => Ok(Self::Cond(tokens.parse()?)),
             (Some(Token::For), _) => Ok(Self::Loop(tokens.parse()?)),
             (Some(Token::Return), _) => Ok(Self::Return(tokens.parse()?)),
@@ -334,6 +704,17 @@ pub struct Cond {
     pub else_last: Option<Else>,
 }
 
+impl Cond {
+    pub fn span(&self) -> Span {
+        let last = match (&self.else_last, self.else_ifs.last()) {
+            (Some(else_last), _) => &else_last.block,
+            (None, Some(else_if)) => &else_if.inner.block,
+            (None, None) => &self.if_first.block,
+        };
+        self.if_first.if_token.span().merge(last.span())
+    }
+}
+
 impl Parse for Cond {
     fn parse(tokens: &mut ParseStream) -> Result<Self> {
         let if_first = tokens.parse()?;
@@ -405,14 +786,25 @@ pub struct Loop {
 
 //
 
+/// `x := 5;` or `x: i64 := 5;`, the type annotation guides and checks the value
 #[cfg_attr(test, derive(Serialize))]
-#[derive(Debug, Clone, PartialEq, Eq, Parse)]
+#[derive(Debug, Clone, PartialEq, Eq)]
 pub struct Init {
     pub targets: CommaSeparated<Target>,
+    pub ty: Option<(token::Colon, Ty)>,
     pub walrus: Walrus,
     pub exprs: CommaSeparated<Expr>,
 }
 
+/// `x: i32;`, a variable without a value, it has to be assigned before it is read
+#[cfg_attr(test, derive(Serialize))]
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Decl {
+    pub name: Ident,
+    pub colon: token::Colon,
+    pub ty: Ty,
+}
+
 //
 
 #[cfg_attr(test, derive(Serialize))]
@@ -423,6 +815,16 @@ pub struct Set {
     pub exprs: CommaSeparated<Expr>,
 }
 
+/// `*r = value;` stores the value in the variable that the reference `r` points to
+#[cfg_attr(test, derive(Serialize))]
+#[derive(Debug, Clone, PartialEq, Eq, Parse)]
+pub struct SetRef {
+    pub star: token::Asterisk,
+    pub ident: Ident,
+    pub assign: token::Assign,
+    pub expr: Expr,
+}
+
 //
 
 #[cfg_attr(test, derive(Serialize))]
@@ -551,11 +953,25 @@ pub enum AnyExpr {
     Block(Box<Block>),
     LitInt(LitInt),
     LitStr(LitStr),
+    LitByteStr(LitByteStr),
+    LitBool(LitBool),
     Load(Ident),
+    Path(Box<ModPath>),
+    Ref(Box<RefExpr>),
+    Deref(Box<DerefExpr>),
 
     Func(Func),
 
     Call(Box<Call>),
+    MethodCall(Box<MethodCall>),
+    Cast(Box<Cast>),
+    Index(Box<Index>),
+
+    Tuple(Box<Tuple>),
+    Array(Box<Array>),
+
+    Match(Box<Match>),
+    If(Box<Cond>),
 
     Binary {
         op: BinaryOp,
@@ -569,9 +985,21 @@ impl AnyExpr {
             AnyExpr::Block(v) => v.span(),
             AnyExpr::LitInt(v) => v.span(),
             AnyExpr::LitStr(v) => v.span(),
+            AnyExpr::LitByteStr(v) => v.span(),
+            AnyExpr::LitBool(v) => v.span(),
             AnyExpr::Load(v) => v.span(),
+            AnyExpr::Path(v) => v.span(),
+            AnyExpr::Ref(v) => v.amp.span().merge(v.ident.span()),
+            AnyExpr::Deref(v) => v.star.span().merge(v.ident.span()),
             AnyExpr::Func(v) => v.span(),
             AnyExpr::Call(v) => v.span(),
+            AnyExpr::MethodCall(v) => v.span(),
+            AnyExpr::Cast(v) => v.span(),
+            AnyExpr::Index(v) => v.span(),
+            AnyExpr::Tuple(v) => v.span(),
+            AnyExpr::Array(v) => v.span(),
+            AnyExpr::Match(v) => v.span(),
+            AnyExpr::If(v) => v.span(),
             AnyExpr::Binary { sides, .. } => sides.0.span().merge(sides.1.span()),
         }
     }
@@ -679,7 +1107,7 @@ impl Expr {
     }
 
     fn parse_math_term(tokens: &mut ParseStream) -> Result<Self> {
-        let mut lhs: Self = Self::parse_math_call(tokens)?;
+        let mut lhs: Self = Self::parse_math_cast(tokens)?;
 
         while tokens.peek1(Token::Asterisk)
             | tokens.peek1(Token::Slash)
@@ -691,7 +1119,7 @@ impl Expr {
                 Token::Percent => BinaryOp::Rem,
                 _ => unreachable!(),
             };
-            let sides = Box::new((lhs, Self::parse_math_call(tokens)?));
+            let sides = Box::new((lhs, Self::parse_math_cast(tokens)?));
 
             lhs = Self::from(AnyExpr::Binary { op, sides });
         }
@@ -699,10 +1127,64 @@ impl Expr {
         Ok(lhs)
     }
 
+    fn parse_math_cast(tokens: &mut ParseStream) -> Result<Self> {
+        let mut lhs: Self = Self::parse_math_call(tokens)?;
+
+        while tokens.peek1(Token::As) {
+            lhs = AnyExpr::Cast(Box::new(Cast {
+                value: lhs,
+                as_kw: tokens.parse()?,
+                ty: tokens.parse()?,
+            }))
+            .into();
+        }
+
+        Ok(lhs)
+    }
+
     fn parse_math_call(tokens: &mut ParseStream) -> Result<Self> {
         let mut lhs: Self = Self::parse_math_atom(tokens)?;
 
-        while tokens.peek1(Token::LParen) {
+        while tokens.peek1(Token::LParen)
+            || tokens.peek1(Token::Dot)
+            || tokens.peek1(Token::LBracket)
+        {
+            if tokens.peek1(Token::LBracket) {
+                // `x[i]`
+                lhs = AnyExpr::Index(Box::new(Index {
+                    value: lhs,
+                    open: tokens.parse()?,
+                    index: tokens.parse()?,
+                    close: tokens.parse()?,
+                }))
+                .into();
+                continue;
+            }
+
+            if tokens.peek1(Token::Dot) {
+                // `x.f(y)`
+                let receiver = lhs;
+                let dot = tokens.parse()?;
+                let method = tokens.parse()?;
+                let args_beg = tokens.parse()?;
+                let args = if !tokens.peek1(Token::RParen) {
+                    Some(tokens.parse()?)
+                } else {
+                    None
+                };
+
+                lhs = AnyExpr::MethodCall(Box::new(MethodCall {
+                    receiver,
+                    dot,
+                    method,
+                    args_beg,
+                    args,
+                    args_end: tokens.parse()?,
+                }))
+                .into();
+                continue;
+            }
+
             let func = lhs;
             let args_beg: token::LParen = tokens.parse()?;
 
@@ -745,29 +1227,95 @@ impl Expr {
             Ok(AnyExpr::LitInt(tokens.parse()?).into())
         } else if look.peek(Token::LitStr) {
             Ok(AnyExpr::LitStr(tokens.parse()?).into())
+        } else if look.peek(Token::LitByteStr) {
+            Ok(AnyExpr::LitByteStr(tokens.parse()?).into())
+        } else if look.peek(Token::True) || look.peek(Token::False) {
+            Ok(AnyExpr::LitBool(tokens.parse()?).into())
         } else if look.peek(Token::Ident) {
+            if tokens.peek2(Token::PathSep) {
+                return Ok(AnyExpr::Path(tokens.parse()?).into());
+            }
             Ok(AnyExpr::Load(tokens.parse()?).into())
+        } else if look.peek(Token::Ampersand) {
+            Ok(AnyExpr::Ref(tokens.parse()?).into())
+        } else if look.peek(Token::Asterisk) {
+            Ok(AnyExpr::Deref(tokens.parse()?).into())
         } else if look.peek(Token::LParen) {
-            let _: token::LParen = tokens.parse()?;
+            let open: token::LParen = tokens.parse()?;
             let expr: Expr = tokens.parse()?;
-            let _: token::RParen = tokens.parse()?;
-            Ok(expr)
-        } else if look.peek(Token::Fn) {
-            Ok(AnyExpr::Func(tokens.parse()?).into())
-        } else {
-            Err(look.err())
-        }
-    }
-}
 
-//
+            // `(a)` is just a parenthesized expr, `(a, b)` is a tuple
+            if !tokens.peek1(Token::Comma) {
+                let _: token::RParen = tokens.parse()?;
+                return Ok(expr);
+            }
+
+            let mut inner = Vec::new();
+            while tokens.peek1(Token::Comma) {
+                inner.push(CommaSeparatedItem {
+                    comma: tokens.parse()?,
+                    item: tokens.parse()?,
+                });
+            }
+
+            Ok(AnyExpr::Tuple(Box::new(Tuple {
+                open,
+                elems: CommaSeparated { first: expr, inner },
+                close: tokens.parse()?,
+            }))
+            .into())
+        } else if look.peek(Token::LBracket) {
+            let open = tokens.parse()?;
+            let elems = if !tokens.peek1(Token::RBracket) {
+                Some(tokens.parse()?)
+            } else {
+                None
+            };
+
+            Ok(AnyExpr::Array(Box::new(Array {
+                open,
+                elems,
+                close: tokens.parse()?,
+            }))
+            .into())
+        } else if look.peek(Token::Fn) {
+            Ok(AnyExpr::Func(tokens.parse()?).into())
+        } else if look.peek(Token::Match) {
+            Ok(AnyExpr::Match(tokens.parse()?).into())
+        } else if look.peek(Token::If) {
+            Ok(AnyExpr::If(tokens.parse()?).into())
+        } else {
+            Err(look.err())
+        }
+    }
+}
+
+//
+
+/// `&x`, a reference to a variable
+#[cfg_attr(test, derive(Serialize))]
+#[derive(Debug, Clone, PartialEq, Eq, Parse)]
+pub struct RefExpr {
+    pub amp: token::Ampersand,
+    pub ident: Ident,
+}
+
+/// `*r`, the value of the variable that the reference `r` points to
+#[cfg_attr(test, derive(Serialize))]
+#[derive(Debug, Clone, PartialEq, Eq, Parse)]
+pub struct DerefExpr {
+    pub star: token::Asterisk,
+    pub ident: Ident,
+}
+
+//
 
 #[cfg_attr(test, derive(Serialize))]
 #[derive(Debug, Clone, PartialEq, Eq)]
 pub struct Call {
     pub func: Expr,
     pub args_beg: token::LParen,
-    pub args: Option<CommaSeparated<Expr>>,
+    pub args: Option<CommaSeparated<CallArg>>,
     pub args_end: token::RParen,
 }
 
@@ -776,15 +1324,266 @@ impl Call {
         self.func.span().merge(self.args_end.span())
     }
 
+    /// the values of the arguments, in the order they are written
     pub fn args(&self) -> impl ExactSizeIterator<Item = &Expr> {
+        self.call_args().map(|arg| &arg.value)
+    }
+
+    pub fn args_mut(&mut self) -> impl ExactSizeIterator<Item = &mut Expr> {
+        OptionInner {
+            inner: self.args.as_mut().map(|s| s.iter_mut()),
+        }
+        .map(|arg| &mut arg.value)
+    }
+
+    /// the arguments with their names, if they are named
+    pub fn call_args(&self) -> impl ExactSizeIterator<Item = &CallArg> {
         OptionInner {
             inner: self.args.as_ref().map(|s| s.iter()),
         }
     }
+}
 
-    pub fn args_mut(&mut self) -> impl ExactSizeIterator<Item = &mut Expr> {
+/// `20` or `y: 20` in `draw(x: 10, y: 20)`, named arguments are matched to the parameters by name
+#[cfg_attr(test, derive(Serialize))]
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct CallArg {
+    pub name: Option<(Ident, token::Colon)>,
+    pub value: Expr,
+}
+
+impl CallArg {
+    pub fn span(&self) -> Span {
+        match &self.name {
+            Some((name, _)) => name.span().merge(self.value.span()),
+            None => self.value.span(),
+        }
+    }
+}
+
+impl Parse for CallArg {
+    fn parse(tokens: &mut ParseStream) -> Result<Self> {
+        let named = tokens.top1().map(|t| t.token()) == Some(Token::Ident)
+            && tokens.top2().map(|t| t.token()) == Some(Token::Colon);
+        let name = if named {
+            Some((tokens.parse()?, tokens.parse()?))
+        } else {
+            None
+        };
+
+        Ok(Self {
+            name,
+            value: tokens.parse()?,
+        })
+    }
+}
+
+//
+
+/// `x.f(y)`, calls the method `f` of the type of `x`, or the function `f`, like `f(x, y)`
+#[cfg_attr(test, derive(Serialize))]
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct MethodCall {
+    pub receiver: Expr,
+    pub dot: token::Dot,
+    pub method: Ident,
+    pub args_beg: token::LParen,
+    pub args: Option<CommaSeparated<CallArg>>,
+    pub args_end: token::RParen,
+}
+
+impl MethodCall {
+    pub fn span(&self) -> Span {
+        self.receiver.span().merge(self.args_end.span())
+    }
+
+    /// the values of the arguments after the receiver
+    pub fn args(&self) -> impl ExactSizeIterator<Item = &Expr> {
+        self.call_args().map(|arg| &arg.value)
+    }
+
+    /// the arguments after the receiver, with their names if they are named
+    pub fn call_args(&self) -> impl ExactSizeIterator<Item = &CallArg> {
         OptionInner {
-            inner: self.args.as_mut().map(|s| s.iter_mut()),
+            inner: self.args.as_ref().map(|s| s.iter()),
+        }
+    }
+}
+
+//
+
+/// `n as usize`, converts between the integer types
+#[cfg_attr(test, derive(Serialize))]
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Cast {
+    pub value: Expr,
+    pub as_kw: token::As,
+    pub ty: Ty,
+}
+
+impl Cast {
+    pub fn span(&self) -> Span {
+        self.value.span().merge(self.ty.span())
+    }
+}
+
+//
+
+/// `table[i]`, the element at index `i`, which has to be less than the length
+#[cfg_attr(test, derive(Serialize))]
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Index {
+    pub value: Expr,
+    pub open: token::LBracket,
+    pub index: Expr,
+    pub close: token::RBracket,
+}
+
+impl Index {
+    pub fn span(&self) -> Span {
+        self.value.span().merge(self.close.span())
+    }
+}
+
+//
+
+#[cfg_attr(test, derive(Serialize))]
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Tuple {
+    pub open: token::LParen,
+    pub elems: CommaSeparated<Expr>,
+    pub close: token::RParen,
+}
+
+impl Tuple {
+    pub fn span(&self) -> Span {
+        self.open.span().merge(self.close.span())
+    }
+}
+
+//
+
+/// `[1, 2, 4, 8]`, a slice of values known at compile time
+#[cfg_attr(test, derive(Serialize))]
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Array {
+    pub open: token::LBracket,
+    pub elems: Option<CommaSeparated<Expr>>,
+    pub close: token::RBracket,
+}
+
+impl Array {
+    pub fn span(&self) -> Span {
+        self.open.span().merge(self.close.span())
+    }
+
+    pub fn elems(&self) -> impl Iterator<Item = &Expr> {
+        self.elems.iter().flat_map(|elems| elems.iter())
+    }
+}
+
+//
+
+/// `match n { 0 => a, 1 => b, _ => c }`
+#[cfg_attr(test, derive(Serialize))]
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Match {
+    pub match_kw: token::Match,
+    pub value: Expr,
+    pub open: LBrace,
+    pub arms: Vec<MatchArm>,
+    pub close: RBrace,
+}
+
+impl Match {
+    pub fn span(&self) -> Span {
+        self.match_kw.span().merge(self.close.span())
+    }
+}
+
+impl Parse for Match {
+    fn parse(tokens: &mut ParseStream) -> Result<Self> {
+        let match_kw = tokens.parse()?;
+        let value = tokens.parse()?;
+        let open = tokens.parse()?;
+
+        let mut arms = Vec::new();
+        while !tokens.peek1(Token::RBrace) && !tokens.peek1(Token::Eoi) {
+            let arm = MatchArm {
+                pattern: tokens.parse()?,
+                arrow: tokens.parse()?,
+                expr: tokens.parse()?,
+            };
+            arms.push(arm);
+
+            // the comma after the last arm is optional
+            if !tokens.peek1(Token::Comma) {
+                break;
+            }
+            _ = tokens.next_token()?;
+        }
+
+        Ok(Self {
+            match_kw,
+            value,
+            open,
+            arms,
+            close: tokens.parse()?,
+        })
+    }
+}
+
+#[cfg_attr(test, derive(Serialize))]
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct MatchArm {
+    pub pattern: Pattern,
+    pub arrow: token::FatArrow,
+    pub expr: Expr,
+}
+
+/// what a [`MatchArm`] compares the value to
+#[cfg_attr(test, derive(Serialize))]
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Pattern {
+    LitInt(LitInt),
+    LitBool(LitBool),
+    /// `_` matches anything
+    Wildcard(Ident),
+}
+
+impl Pattern {
+    pub fn span(&self) -> Span {
+        match self {
+            Pattern::LitInt(v) => v.span(),
+            Pattern::LitBool(v) => v.span(),
+            Pattern::Wildcard(v) => v.span(),
+        }
+    }
+}
+
+impl Parse for Pattern {
+    fn parse(tokens: &mut ParseStream) -> Result<Self> {
+        let mut look = tokens.look1();
+        if look.peek(Token::LitInt) {
+            Ok(Pattern::LitInt(tokens.parse()?))
+        } else if look.peek(Token::True) || look.peek(Token::False) {
+            Ok(Pattern::LitBool(tokens.parse()?))
+        } else if look.peek(Token::Ident) {
+            let tok = tokens.next_token()?;
+            // other names would be bindings, which aren't supported
+            if tok.as_str(tokens.source()) != "_" {
+                return Err(unexpected(
+                    tok,
+                    &[Token::LitInt, Token::True, Token::False],
+                    false,
+                ));
+            }
+            Ok(Pattern::Wildcard(Ident {
+                value: "_".to_string(),
+                span: tok.span(),
+            }))
+        } else {
+            Err(look.err())
         }
     }
 }
@@ -799,7 +1598,7 @@ pub struct Proto {
     pub args_beg: token::LParen,
     pub args: Option<CommaSeparated<Argument>>,
     pub args_end: token::RParen,
-    pub return_ty: Option<(token::RArrow, Ident)>,
+    pub return_ty: Option<(token::RArrow, Ty)>,
 }
 
 impl Proto {
@@ -869,12 +1668,109 @@ impl Func {
 
 //
 
+/// `x: i32` or `x: i32 = 4`, only the trailing parameters can have default values
 #[cfg_attr(test, derive(Serialize))]
-#[derive(Debug, Clone, PartialEq, Eq, Parse)]
+#[derive(Debug, Clone, PartialEq, Eq)]
 pub struct Argument {
     pub id: Ident,
     pub colon: token::Colon,
-    pub ty: Ident,
+    pub ty: Ty,
+    pub default: Option<(token::Assign, Box<Expr>)>,
+}
+
+impl Parse for Argument {
+    fn parse(tokens: &mut ParseStream) -> Result<Self> {
+        let id = tokens.parse()?;
+        let colon = tokens.parse()?;
+        let ty = tokens.parse()?;
+        let default = if tokens.top1().map(|t| t.token()) == Some(Token::Assign) {
+            Some((tokens.parse()?, tokens.parse()?))
+        } else {
+            None
+        };
+
+        Ok(Self {
+            id,
+            colon,
+            ty,
+            default,
+        })
+    }
+}
+
+//
+
+/// a type written in the source code, like `i32`, `(i32, str)`, `[str]` or `&i32`
+#[cfg_attr(test, derive(Serialize))]
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Ty {
+    Name(Ident),
+    Tuple(Box<TupleTy>),
+    Slice(Box<SliceTy>),
+    Ref(Box<RefTy>),
+    Fn(Box<FnTy>),
+}
+
+impl Ty {
+    pub fn span(&self) -> Span {
+        match self {
+            Ty::Name(v) => v.span(),
+            Ty::Tuple(v) => v.open.span().merge(v.close.span()),
+            Ty::Slice(v) => v.open.span().merge(v.close.span()),
+            Ty::Ref(v) => v.amp.span().merge(v.elem.span()),
+            Ty::Fn(v) => v.fn_kw.span().merge(v.args_end.span()),
+        }
+    }
+}
+
+impl Parse for Ty {
+    fn parse(tokens: &mut ParseStream) -> Result<Self> {
+        let mut look = tokens.look1();
+        if look.peek(Token::Ident) {
+            Ok(Ty::Name(tokens.parse()?))
+        } else if look.peek(Token::LParen) {
+            Ok(Ty::Tuple(tokens.parse()?))
+        } else if look.peek(Token::LBracket) {
+            Ok(Ty::Slice(tokens.parse()?))
+        } else if look.peek(Token::Ampersand) {
+            Ok(Ty::Ref(tokens.parse()?))
+        } else if look.peek(Token::Fn) {
+            Ok(Ty::Fn(tokens.parse()?))
+        } else {
+            Err(look.err())
+        }
+    }
+}
+
+//
+
+#[cfg_attr(test, derive(Serialize))]
+#[derive(Debug, Clone, PartialEq, Eq, Parse)]
+pub struct TupleTy {
+    pub open: token::LParen,
+    pub elems: CommaSeparated<Ty>,
+    pub close: token::RParen,
+}
+
+//
+
+#[cfg_attr(test, derive(Serialize))]
+#[derive(Debug, Clone, PartialEq, Eq, Parse)]
+pub struct SliceTy {
+    pub open: token::LBracket,
+    pub elem: Ty,
+    pub close: token::RBracket,
+}
+
+//
+
+#[cfg_attr(test, derive(Serialize))]
+#[derive(Debug, Clone, PartialEq, Eq, Parse)]
+pub struct RefTy {
+    pub amp: token::Ampersand,
+    /// `&mut T` is the same type as `&T`, every reference can be written through
+    pub mut_kw: Option<token::Mut>,
+    pub elem: Ty,
 }
 
 //
@@ -922,7 +1818,9 @@ impl Parse for LitStr {
         let tok = tokens.expect_next(Token::LitStr)?;
         let span = tok.span();
         let tok = tok.as_str(tokens.source());
-        let value = tok[1..tok.len() - 1].to_string();
+        let value = lexer::unescape(&tok[1..tok.len() - 1]).map_err(Error::Lexer)?;
+        let value =
+            String::from_utf8(value).map_err(|_| Error::Lexer(lexer::Error::InvalidUtf8))?;
 
         Ok(LitStr { value, span })
     }
@@ -930,6 +1828,33 @@ impl Parse for LitStr {
 
 //
 
+/// `b"..."`, the bytes don't have to be UTF-8
+#[cfg_attr(test, derive(Serialize))]
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct LitByteStr {
+    pub value: Vec<u8>,
+    span: Span,
+}
+
+impl LitByteStr {
+    pub fn span(&self) -> Span {
+        self.span
+    }
+}
+
+impl Parse for LitByteStr {
+    fn parse(tokens: &mut ParseStream) -> Result<Self> {
+        let tok = tokens.expect_next(Token::LitByteStr)?;
+        let span = tok.span();
+        let tok = tok.as_str(tokens.source());
+        let value = lexer::unescape(&tok[2..tok.len() - 1]).map_err(Error::Lexer)?;
+
+        Ok(LitByteStr { value, span })
+    }
+}
+
+//
+
 #[cfg_attr(test, derive(Serialize))]
 #[derive(Debug, Clone, PartialEq, Eq)]
 pub struct LitInt {
@@ -955,3 +1880,84 @@ impl Parse for LitInt {
         Ok(LitInt { value, span })
     }
 }
+
+//
+
+#[cfg_attr(test, derive(Serialize))]
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct LitBool {
+    pub value: bool,
+    span: Span,
+}
+
+impl LitBool {
+    pub fn span(&self) -> Span {
+        self.span
+    }
+}
+
+impl Parse for LitBool {
+    fn parse(tokens: &mut ParseStream) -> Result<Self> {
+        let mut look = tokens.look1();
+        let value = if look.peek(Token::True) {
+            true
+        } else if look.peek(Token::False) {
+            false
+        } else {
+            return Err(look.err());
+        };
+
+        let span = tokens.next_token()?.span();
+        Ok(LitBool { value, span })
+    }
+}
+
+//
+
+/// a function pointer type, like `fn(i32, i32) -> bool`
+#[cfg_attr(test, derive(Serialize))]
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct FnTy {
+    pub fn_kw: token::Fn,
+    pub args_beg: token::LParen,
+    pub params: Option<CommaSeparated<Ty>>,
+    pub args_end: token::RParen,
+    pub return_ty: Option<(token::RArrow, Ty)>,
+}
+
+impl FnTy {
+    pub fn params(&self) -> impl ExactSizeIterator<Item = &Ty> + Clone {
+        OptionInner {
+            inner: self.params.as_ref().map(|s| s.iter()),
+        }
+    }
+}
+
+impl Parse for FnTy {
+    fn parse(tokens: &mut ParseStream) -> Result<Self> {
+        let fn_kw = tokens.parse()?;
+        let args_beg = tokens.parse()?;
+
+        let params = if !tokens.peek1(Token::RParen) {
+            Some(tokens.parse()?)
+        } else {
+            None
+        };
+
+        let args_end = tokens.parse()?;
+
+        let return_ty = if tokens.peek1(Token::RArrow) {
+            Some((tokens.parse()?, tokens.parse()?))
+        } else {
+            None
+        };
+
+        Ok(FnTy {
+            fn_kw,
+            args_beg,
+            params,
+            args_end,
+            return_ty,
+        })
+    }
+}
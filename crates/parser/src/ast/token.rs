@@ -39,6 +39,12 @@ simple_tokens! {
     /// `}`
     RBrace,
 
+    /// `[`
+    LBracket,
+
+    /// `]`
+    RBracket,
+
     /// `+`
     Plus,
 
@@ -63,6 +69,9 @@ simple_tokens! {
     /// `&`
     Ampersand,
 
+    /// `.`
+    Dot,
+
     /// `<`
     Lt,
 
@@ -93,6 +102,12 @@ simple_tokens! {
     /// `->`
     RArrow,
 
+    /// `=>`
+    FatArrow,
+
+    /// `::`
+    PathSep,
+
     /// `fn`
     Fn,
 
@@ -114,6 +129,45 @@ simple_tokens! {
     /// `test`
     Test,
 
+    /// `extern`
+    Extern,
+
+    /// `type`
+    Type,
+
+    /// `opaque`
+    Opaque,
+
+    /// `const`
+    Const,
+
+    /// `use`
+    Use,
+
+    /// `match`
+    Match,
+
+    /// `impl`
+    Impl,
+
+    /// `mod`
+    Mod,
+
+    /// `as`
+    As,
+
+    /// `static`
+    Static,
+
+    /// `mut`
+    Mut,
+
+    /// `true`
+    True,
+
+    /// `false`
+    False,
+
     /// end of input
     Eoi,
 }
@@ -0,0 +1,475 @@
+use super::{
+    AnyExpr, Argument, Array, Block, Call, CallArg, Cast, Cond, ConstDecl, Decl, Expr, ExternFn,
+    Func, Ident, If, Impl, Index, Init, LitBool, LitByteStr, LitInt, LitStr, Loop, Match,
+    MethodCall, ModDecl, ModPath, Pattern, Return, Root, RootItem, Set, SetRef, StaticDecl, Stmt,
+    Target, Tuple, Ty, TypeAlias, UseFile, UsePath,
+};
+
+//
+
+/// read-only traversal of the syntax tree
+///
+/// every method visits the children by default,
+/// override the ones you care about and call the matching `walk_*` fn to keep going deeper
+pub trait Visit<'ast> {
+    fn visit_root(&mut self, root: &'ast Root) {
+        walk_root(self, root)
+    }
+
+    fn visit_extern_fn(&mut self, ext: &'ast ExternFn) {
+        walk_extern_fn(self, ext)
+    }
+
+    fn visit_type_alias(&mut self, alias: &'ast TypeAlias) {
+        walk_type_alias(self, alias)
+    }
+
+    fn visit_const_decl(&mut self, decl: &'ast ConstDecl) {
+        walk_const_decl(self, decl)
+    }
+
+    fn visit_static_decl(&mut self, decl: &'ast StaticDecl) {
+        walk_static_decl(self, decl)
+    }
+
+    fn visit_use_file(&mut self, file: &'ast UseFile) {
+        walk_use_file(self, file)
+    }
+
+    fn visit_use_path(&mut self, use_: &'ast UsePath) {
+        walk_use_path(self, use_)
+    }
+
+    fn visit_impl(&mut self, impl_: &'ast Impl) {
+        walk_impl(self, impl_)
+    }
+
+    fn visit_mod_decl(&mut self, decl: &'ast ModDecl) {
+        walk_mod_decl(self, decl)
+    }
+
+    fn visit_stmt(&mut self, stmt: &'ast Stmt) {
+        walk_stmt(self, stmt)
+    }
+
+    fn visit_block(&mut self, block: &'ast Block) {
+        walk_block(self, block)
+    }
+
+    fn visit_init(&mut self, init: &'ast Init) {
+        walk_init(self, init)
+    }
+
+    fn visit_decl(&mut self, decl: &'ast Decl) {
+        walk_decl(self, decl)
+    }
+
+    fn visit_set(&mut self, set: &'ast Set) {
+        walk_set(self, set)
+    }
+
+    fn visit_target(&mut self, target: &'ast Target) {
+        walk_target(self, target)
+    }
+
+    fn visit_set_ref(&mut self, set: &'ast SetRef) {
+        walk_set_ref(self, set)
+    }
+
+    fn visit_cond(&mut self, cond: &'ast Cond) {
+        walk_cond(self, cond)
+    }
+
+    fn visit_if(&mut self, if_: &'ast If) {
+        walk_if(self, if_)
+    }
+
+    fn visit_loop(&mut self, loop_: &'ast Loop) {
+        walk_loop(self, loop_)
+    }
+
+    fn visit_return(&mut self, ret: &'ast Return) {
+        walk_return(self, ret)
+    }
+
+    fn visit_expr(&mut self, expr: &'ast Expr) {
+        walk_expr(self, expr)
+    }
+
+    fn visit_mod_path(&mut self, path: &'ast ModPath) {
+        walk_mod_path(self, path)
+    }
+
+    fn visit_call(&mut self, call: &'ast Call) {
+        walk_call(self, call)
+    }
+
+    fn visit_method_call(&mut self, call: &'ast MethodCall) {
+        walk_method_call(self, call)
+    }
+
+    fn visit_cast(&mut self, cast: &'ast Cast) {
+        walk_cast(self, cast)
+    }
+
+    fn visit_index(&mut self, index: &'ast Index) {
+        walk_index(self, index)
+    }
+
+    fn visit_tuple(&mut self, tuple: &'ast Tuple) {
+        walk_tuple(self, tuple)
+    }
+
+    fn visit_array(&mut self, array: &'ast Array) {
+        walk_array(self, array)
+    }
+
+    fn visit_match(&mut self, match_: &'ast Match) {
+        walk_match(self, match_)
+    }
+
+    fn visit_func(&mut self, func: &'ast Func) {
+        walk_func(self, func)
+    }
+
+    fn visit_argument(&mut self, arg: &'ast Argument) {
+        walk_argument(self, arg)
+    }
+
+    fn visit_ty(&mut self, ty: &'ast Ty) {
+        walk_ty(self, ty)
+    }
+
+    fn visit_ident(&mut self, _ident: &'ast Ident) {}
+
+    fn visit_lit_int(&mut self, _int: &'ast LitInt) {}
+
+    fn visit_lit_str(&mut self, _str: &'ast LitStr) {}
+
+    fn visit_lit_byte_str(&mut self, _str: &'ast LitByteStr) {}
+
+    fn visit_lit_bool(&mut self, _bool: &'ast LitBool) {}
+}
+
+//
+
+pub fn walk_root<'ast, V: Visit<'ast> + ?Sized>(v: &mut V, root: &'ast Root) {
+    for item in root.items.iter() {
+        match item {
+            RootItem::Extern(ext) => v.visit_extern_fn(ext),
+            RootItem::TypeAlias(alias) => v.visit_type_alias(alias),
+            RootItem::ConstDecl(decl) => v.visit_const_decl(decl),
+            RootItem::StaticDecl(decl) => v.visit_static_decl(decl),
+            RootItem::Use(file) => v.visit_use_file(file),
+            RootItem::UsePath(use_) => v.visit_use_path(use_),
+            RootItem::Impl(impl_) => v.visit_impl(impl_),
+            RootItem::Mod(decl) => v.visit_mod_decl(decl),
+            RootItem::Stmt(stmt) => v.visit_stmt(stmt),
+        }
+    }
+}
+
+pub fn walk_extern_fn<'ast, V: Visit<'ast> + ?Sized>(v: &mut V, ext: &'ast ExternFn) {
+    v.visit_ident(&ext.name);
+    for arg in ext.args() {
+        v.visit_argument(arg);
+    }
+    if let Some((_, ty)) = ext.return_ty.as_ref() {
+        v.visit_ty(ty);
+    }
+}
+
+pub fn walk_type_alias<'ast, V: Visit<'ast> + ?Sized>(v: &mut V, alias: &'ast TypeAlias) {
+    v.visit_ident(&alias.name);
+    v.visit_ty(&alias.ty);
+}
+
+pub fn walk_const_decl<'ast, V: Visit<'ast> + ?Sized>(v: &mut V, decl: &'ast ConstDecl) {
+    v.visit_ident(&decl.name);
+    if let Some((_, ty)) = decl.ty.as_ref() {
+        v.visit_ty(ty);
+    }
+    v.visit_expr(&decl.value);
+}
+
+pub fn walk_static_decl<'ast, V: Visit<'ast> + ?Sized>(v: &mut V, decl: &'ast StaticDecl) {
+    v.visit_ident(&decl.name);
+    if let Some((_, ty)) = decl.ty.as_ref() {
+        v.visit_ty(ty);
+    }
+    v.visit_expr(&decl.value);
+}
+
+pub fn walk_use_file<'ast, V: Visit<'ast> + ?Sized>(v: &mut V, file: &'ast UseFile) {
+    v.visit_lit_str(&file.path);
+}
+
+pub fn walk_use_path<'ast, V: Visit<'ast> + ?Sized>(v: &mut V, use_: &'ast UsePath) {
+    v.visit_mod_path(&use_.path);
+}
+
+pub fn walk_impl<'ast, V: Visit<'ast> + ?Sized>(v: &mut V, impl_: &'ast Impl) {
+    v.visit_ident(&impl_.ty);
+    for method in impl_.methods.iter() {
+        v.visit_ident(&method.name);
+        v.visit_func(&method.func);
+    }
+}
+
+pub fn walk_mod_decl<'ast, V: Visit<'ast> + ?Sized>(v: &mut V, decl: &'ast ModDecl) {
+    v.visit_ident(&decl.name);
+    v.visit_root(&decl.root);
+}
+
+pub fn walk_stmt<'ast, V: Visit<'ast> + ?Sized>(v: &mut V, stmt: &'ast Stmt) {
+    match stmt {
+        Stmt::Init(init) => v.visit_init(init),
+        Stmt::Decl(decl) => v.visit_decl(decl),
+        Stmt::Set(set) => v.visit_set(set),
+        Stmt::SetRef(set) => v.visit_set_ref(set),
+        Stmt::Cond(cond) => v.visit_cond(cond),
+        Stmt::Loop(loop_) => v.visit_loop(loop_),
+        Stmt::Expr(expr) => v.visit_expr(&expr.expr),
+        Stmt::Return(ret) => v.visit_return(ret),
+    }
+}
+
+pub fn walk_block<'ast, V: Visit<'ast> + ?Sized>(v: &mut V, block: &'ast Block) {
+    for stmt in block.stmts.iter() {
+        v.visit_stmt(stmt);
+    }
+}
+
+pub fn walk_init<'ast, V: Visit<'ast> + ?Sized>(v: &mut V, init: &'ast Init) {
+    for target in init.targets.iter() {
+        v.visit_target(target);
+    }
+    if let Some((_, ty)) = init.ty.as_ref() {
+        v.visit_ty(ty);
+    }
+    for expr in init.exprs.iter() {
+        v.visit_expr(expr);
+    }
+}
+
+pub fn walk_decl<'ast, V: Visit<'ast> + ?Sized>(v: &mut V, decl: &'ast Decl) {
+    v.visit_ident(&decl.name);
+    v.visit_ty(&decl.ty);
+}
+
+pub fn walk_set<'ast, V: Visit<'ast> + ?Sized>(v: &mut V, set: &'ast Set) {
+    for target in set.targets.iter() {
+        v.visit_target(target);
+    }
+    for expr in set.exprs.iter() {
+        v.visit_expr(expr);
+    }
+}
+
+pub fn walk_target<'ast, V: Visit<'ast> + ?Sized>(v: &mut V, target: &'ast Target) {
+    v.visit_ident(&target.path.ident);
+}
+
+pub fn walk_set_ref<'ast, V: Visit<'ast> + ?Sized>(v: &mut V, set: &'ast SetRef) {
+    v.visit_ident(&set.ident);
+    v.visit_expr(&set.expr);
+}
+
+pub fn walk_cond<'ast, V: Visit<'ast> + ?Sized>(v: &mut V, cond: &'ast Cond) {
+    v.visit_if(&cond.if_first);
+    for else_if in cond.else_ifs.iter() {
+        v.visit_if(&else_if.inner);
+    }
+    if let Some(else_last) = cond.else_last.as_ref() {
+        v.visit_block(&else_last.block);
+    }
+}
+
+pub fn walk_if<'ast, V: Visit<'ast> + ?Sized>(v: &mut V, if_: &'ast If) {
+    v.visit_expr(&if_.check);
+    v.visit_block(&if_.block);
+}
+
+pub fn walk_loop<'ast, V: Visit<'ast> + ?Sized>(v: &mut V, loop_: &'ast Loop) {
+    v.visit_block(&loop_.block);
+}
+
+pub fn walk_return<'ast, V: Visit<'ast> + ?Sized>(v: &mut V, ret: &'ast Return) {
+    if let Some(expr) = ret.expr.as_ref() {
+        v.visit_expr(expr);
+    }
+}
+
+pub fn walk_expr<'ast, V: Visit<'ast> + ?Sized>(v: &mut V, expr: &'ast Expr) {
+    match &expr.expr {
+        AnyExpr::Block(block) => v.visit_block(block),
+        AnyExpr::LitInt(int) => v.visit_lit_int(int),
+        AnyExpr::LitStr(str) => v.visit_lit_str(str),
+        AnyExpr::LitByteStr(str) => v.visit_lit_byte_str(str),
+        AnyExpr::LitBool(bool) => v.visit_lit_bool(bool),
+        AnyExpr::Load(ident) => v.visit_ident(ident),
+        AnyExpr::Path(path) => v.visit_mod_path(path),
+        AnyExpr::Ref(ref_) => v.visit_ident(&ref_.ident),
+        AnyExpr::Deref(deref) => v.visit_ident(&deref.ident),
+        AnyExpr::Func(func) => v.visit_func(func),
+        AnyExpr::Call(call) => v.visit_call(call),
+        AnyExpr::MethodCall(call) => v.visit_method_call(call),
+        AnyExpr::Cast(cast) => v.visit_cast(cast),
+        AnyExpr::Index(index) => v.visit_index(index),
+        AnyExpr::Tuple(tuple) => v.visit_tuple(tuple),
+        AnyExpr::Array(array) => v.visit_array(array),
+        AnyExpr::Match(match_) => v.visit_match(match_),
+        AnyExpr::If(cond) => v.visit_cond(cond),
+        AnyExpr::Binary { sides, .. } => {
+            v.visit_expr(&sides.0);
+            v.visit_expr(&sides.1);
+        }
+    }
+}
+
+pub fn walk_mod_path<'ast, V: Visit<'ast> + ?Sized>(v: &mut V, path: &'ast ModPath) {
+    for ident in path.segments() {
+        v.visit_ident(ident);
+    }
+}
+
+pub fn walk_call<'ast, V: Visit<'ast> + ?Sized>(v: &mut V, call: &'ast Call) {
+    v.visit_expr(&call.func);
+    for arg in call.call_args() {
+        walk_call_arg(v, arg);
+    }
+}
+
+pub fn walk_method_call<'ast, V: Visit<'ast> + ?Sized>(v: &mut V, call: &'ast MethodCall) {
+    v.visit_expr(&call.receiver);
+    v.visit_ident(&call.method);
+    for arg in call.call_args() {
+        walk_call_arg(v, arg);
+    }
+}
+
+fn walk_call_arg<'ast, V: Visit<'ast> + ?Sized>(v: &mut V, arg: &'ast CallArg) {
+    if let Some((name, _)) = &arg.name {
+        v.visit_ident(name);
+    }
+    v.visit_expr(&arg.value);
+}
+
+pub fn walk_cast<'ast, V: Visit<'ast> + ?Sized>(v: &mut V, cast: &'ast Cast) {
+    v.visit_expr(&cast.value);
+    v.visit_ty(&cast.ty);
+}
+
+pub fn walk_index<'ast, V: Visit<'ast> + ?Sized>(v: &mut V, index: &'ast Index) {
+    v.visit_expr(&index.value);
+    v.visit_expr(&index.index);
+}
+
+pub fn walk_tuple<'ast, V: Visit<'ast> + ?Sized>(v: &mut V, tuple: &'ast Tuple) {
+    for elem in tuple.elems.iter() {
+        v.visit_expr(elem);
+    }
+}
+
+pub fn walk_array<'ast, V: Visit<'ast> + ?Sized>(v: &mut V, array: &'ast Array) {
+    for elem in array.elems() {
+        v.visit_expr(elem);
+    }
+}
+
+pub fn walk_match<'ast, V: Visit<'ast> + ?Sized>(v: &mut V, match_: &'ast Match) {
+    v.visit_expr(&match_.value);
+    for arm in match_.arms.iter() {
+        match &arm.pattern {
+            Pattern::LitInt(int) => v.visit_lit_int(int),
+            Pattern::LitBool(bool) => v.visit_lit_bool(bool),
+            Pattern::Wildcard(_) => {}
+        }
+        v.visit_expr(&arm.expr);
+    }
+}
+
+pub fn walk_func<'ast, V: Visit<'ast> + ?Sized>(v: &mut V, func: &'ast Func) {
+    for arg in func.proto.args() {
+        v.visit_argument(arg);
+    }
+    if let Some((_, ty)) = func.proto.return_ty.as_ref() {
+        v.visit_ty(ty);
+    }
+    v.visit_block(&func.block);
+}
+
+pub fn walk_argument<'ast, V: Visit<'ast> + ?Sized>(v: &mut V, arg: &'ast Argument) {
+    v.visit_ident(&arg.id);
+    v.visit_ty(&arg.ty);
+    if let Some((_, default)) = arg.default.as_ref() {
+        v.visit_expr(default);
+    }
+}
+
+pub fn walk_ty<'ast, V: Visit<'ast> + ?Sized>(v: &mut V, ty: &'ast Ty) {
+    match ty {
+        Ty::Name(name) => v.visit_ident(name),
+        Ty::Tuple(tuple) => {
+            for elem in tuple.elems.iter() {
+                v.visit_ty(elem);
+            }
+        }
+        Ty::Slice(slice) => v.visit_ty(&slice.elem),
+        Ty::Ref(ref_) => v.visit_ty(&ref_.elem),
+        Ty::Fn(func) => {
+            for param in func.params() {
+                v.visit_ty(param);
+            }
+            if let Some((_, ty)) = func.return_ty.as_ref() {
+                v.visit_ty(ty);
+            }
+        }
+    }
+}
+
+//
+
+#[cfg(test)]
+mod tests {
+    use lexer::Lexer;
+
+    use super::{walk_call, Visit};
+    use crate::{
+        ast::{Ast, Call, Root},
+        ParseStream,
+    };
+
+    //
+
+    #[derive(Default)]
+    struct CountCalls(usize);
+
+    impl<'ast> Visit<'ast> for CountCalls {
+        fn visit_call(&mut self, call: &'ast Call) {
+            self.0 += 1;
+            walk_call(self, call);
+        }
+    }
+
+    #[test]
+    fn count_calls() {
+        let source = r#"
+            extern fn abs(n: i32) -> i32;
+            f := fn(n: i32) -> i32 {
+                if abs(n) == 1 {
+                    return f(n - 1);
+                };
+                return (fn() -> i32 { 4 })();
+            };
+            x, y := f(abs(3)), (1, f(2));
+        "#;
+
+        let mut stream = ParseStream::from_lexer(Lexer::new(source));
+        let ast: Ast<Root> = stream.parse().unwrap();
+
+        let mut counter = CountCalls::default();
+        counter.visit_root(&ast.inner);
+        assert_eq!(counter.0, 6);
+    }
+}
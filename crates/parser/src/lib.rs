@@ -195,7 +195,7 @@ impl<'a> ParseStream<'a> {
     }
 
     pub fn peek2(&mut self, token: Token) -> bool {
-        Some(token) == self.top1().map(|tok| tok.token())
+        Some(token) == self.top2().map(|tok| tok.token())
     }
 
     pub fn next_token(&mut self) -> Result<SpannedToken> {
@@ -396,7 +396,7 @@ mod tests {
         let extra = stream
             .next()
             .transpose()
-            .map(|t| t.map(|t| t.as_str().to_string()))
+            .map(|t| t.map(|t| t.as_str(str).to_string()))
             .map_err(|err| err.to_string());
 
         (str, ast, extra)
@@ -407,4 +407,127 @@ mod tests {
         assert_yaml_snapshot!(parse::<ast::token::Comma>(","));
         assert_yaml_snapshot!(parse::<ast::Root>("val := "));
     }
+
+    #[test]
+    fn parse_init_annotation() {
+        let mut stream = ParseStream::from_lexer(Lexer::new("x: i64 := 5; y := 1;"));
+        let ast: ast::Ast<ast::Root> = stream.parse().unwrap();
+
+        let tys: Vec<_> = ast
+            .inner
+            .stmts()
+            .map(|stmt| match stmt {
+                ast::Stmt::Init(init) => init.ty.as_ref().map(|(_, ty)| match ty {
+                    ast::Ty::Name(name) => name.value.as_str(),
+                    _ => unreachable!(),
+                }),
+                _ => unreachable!(),
+            })
+            .collect();
+        assert_eq!(tys, [Some("i64"), None]);
+
+        let mut stream = ParseStream::from_lexer(Lexer::new("x: := 5;"));
+        assert!(stream.parse::<ast::Ast<ast::Root>>().is_err());
+    }
+
+    #[test]
+    fn parse_named_args() {
+        let mut stream = ParseStream::from_lexer(Lexer::new("draw(x: 10, y: 20, z)"));
+        let ast: ast::Ast<ast::Expr> = stream.parse().unwrap();
+
+        let ast::AnyExpr::Call(call) = &ast.inner.expr else {
+            panic!("{ast:?}");
+        };
+        let names: Vec<_> = call
+            .call_args()
+            .map(|arg| arg.name.as_ref().map(|(name, _)| name.value.as_str()))
+            .collect();
+        assert_eq!(names, [Some("x"), Some("y"), None]);
+        assert_eq!(call.args().len(), 3);
+    }
+
+    #[test]
+    fn parse_default_params() {
+        let mut stream = ParseStream::from_lexer(Lexer::new(
+            "fn(name: str, loud: bool = false) -> bool { loud }",
+        ));
+        let ast: ast::Ast<ast::Expr> = stream.parse().unwrap();
+
+        let ast::AnyExpr::Func(func) = &ast.inner.expr else {
+            panic!("{ast:?}");
+        };
+        let defaults: Vec<_> = func
+            .proto
+            .args()
+            .map(|arg| arg.default.as_ref().map(|(_, value)| &value.expr))
+            .collect();
+        assert!(matches!(
+            defaults[..],
+            [
+                None,
+                Some(ast::AnyExpr::LitBool(ast::LitBool { value: false, .. }))
+            ]
+        ));
+    }
+
+    #[test]
+    fn parse_array_index() {
+        let mut stream = ParseStream::from_lexer(Lexer::new("[1, 2, 4][i + 1]"));
+        let ast: ast::Ast<ast::Expr> = stream.parse().unwrap();
+
+        let ast::AnyExpr::Index(index) = &ast.inner.expr else {
+            panic!("{ast:?}");
+        };
+        let ast::AnyExpr::Array(array) = &index.value.expr else {
+            panic!("{ast:?}");
+        };
+        assert_eq!(array.elems().count(), 3);
+        assert!(matches!(index.index.expr, ast::AnyExpr::Binary { .. }));
+
+        let mut stream = ParseStream::from_lexer(Lexer::new("[]"));
+        let ast: ast::Ast<ast::Expr> = stream.parse().unwrap();
+        assert!(matches!(&ast.inner.expr, ast::AnyExpr::Array(array) if array.elems.is_none()));
+    }
+
+    #[test]
+    fn parse_if_expr() {
+        let mut stream = ParseStream::from_lexer(Lexer::new(
+            "x := if a { 1 } else if b { return 2; } else { 3 } + 4",
+        ));
+        let ast: ast::Ast<ast::Stmt> = stream.parse().unwrap();
+
+        let ast::Stmt::Init(init) = &ast.inner else {
+            panic!("{ast:?}");
+        };
+        let ast::AnyExpr::Binary { sides, .. } = &init.exprs.first.expr else {
+            panic!("{ast:?}");
+        };
+        let ast::AnyExpr::If(cond) = &sides.0.expr else {
+            panic!("{ast:?}");
+        };
+        assert_eq!(cond.else_ifs.len(), 1);
+        assert!(cond.else_last.is_some());
+    }
+
+    #[test]
+    fn parse_mod() {
+        let mut stream = ParseStream::from_lexer(Lexer::new(
+            "mod math { add := fn() {}; mod inner { x := 1; } } y := math::inner::x;",
+        ));
+        let ast: ast::Ast<ast::Root> = stream.parse().unwrap();
+
+        let [ast::RootItem::Mod(decl), ast::RootItem::Stmt(stmt)] = &ast.inner.items[..] else {
+            panic!("{ast:?}");
+        };
+        assert_eq!(decl.name.value, "math");
+        assert!(matches!(decl.root.items[1], ast::RootItem::Mod(_)));
+
+        let ast::Stmt::Init(init) = &**stmt else {
+            panic!("{ast:?}");
+        };
+        let ast::AnyExpr::Path(path) = &init.exprs.first.expr else {
+            panic!("{ast:?}");
+        };
+        assert_eq!(path.mangled(), "math::inner::x");
+    }
 }
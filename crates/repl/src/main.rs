@@ -1,184 +1,104 @@
-use std::{
-    error::Error,
-    thread::{self},
-    time::{Duration, Instant},
-};
+use std::error::Error;
 
-use compiler::{Compiler, Str};
+use lexer::{Lexer, Token};
+use rustyline::{error::ReadlineError, DefaultEditor};
+
+use self::session::ReplSession;
+
+//
+
+mod session;
 
 //
 
 fn main() -> Result<(), Box<dyn Error>> {
-    // let mut compiler = Interpreter::new();
-    let mut compiler = Compiler::new();
-    // let mut compiler = Runtime::new();
-
-    let mut rng = BadRng::init();
-
-    // compiler.add_extern("printi", Type::Void, &[Type::Str]);
-    compiler.add("rand", || rng.next()).unwrap();
-    compiler.add("printi", |i: i32| println!("{i}")).unwrap();
-    compiler.add("prints", |s: Str| println!("{s}")).unwrap();
-    compiler
-        .add("wait", || thread::sleep(Duration::from_millis(200)))
-        .unwrap();
-    compiler.run(
-        r#"
-            prints("Fibonacci sequence:");
-            a := 0;
-            b := 1;
-            for {
-                printi(a);
-                a, b = b, a + b;
-                wait();
-
-                if a >= 100 {
-                    return;
-                }
-            }
-        "#,
-    )?;
-    compiler
-        .run(
-            r#"            
-            fib := fn() {
-                print("Fibonacci sequence:");
-                a := 0;
-                b := 1;
-                for {
-                    print(a);
-                    a, b = b, a + b;
-                    wait();
-
-                    if a >= 100 {
-                        return;
-                    }
-                }
-            }
+    let mut session = ReplSession::new();
 
-            fizzbuzz := fn() {
-                print("FizzBuzz:");
-                i := 1;
-                for {
-                    if ((i % 3) == 0) && ((i % 5) == 0) {
-                        print("FizzBuzz");
-                    } else if (i % 3) == 0 {
-                        print("Fizz");
-                    } else if (i % 5) == 0 {
-                        print("Buzz");
-                    } else {
-                        print(i);
-                    }
-
-                    wait();
-                    i = i + 1;
-                }
-            }
+    let mut editor = DefaultEditor::new()?;
+    let mut buf = String::new();
 
-            print_randoms := fn() {
-                print("Random numbers:");
-                i := 20;
-                for {
-                    if i == 0 {
-                        return;
-                    }
-                    print(rand());
-                    i = i - 1;
-                }
-            }
+    loop {
+        let prompt = if buf.is_empty() { ">> " } else { ".. " };
 
-            v := rand() % 3;
-            if v == 0 {
-                fizzbuzz();
-            } else if v == 1 {
-                fib();
-            } else {
-                print_randoms();
+        let line = match editor.readline(prompt) {
+            Ok(line) => line,
+            Err(ReadlineError::Interrupted) => {
+                // ctrl+c discards the current (possibly multi-line) input
+                buf.clear();
+                continue;
             }
-        "#,
-        )
-        .unwrap();
-
-    // println!("{module:#?}");
+            Err(ReadlineError::Eof) => break,
+            Err(err) => return Err(err.into()),
+        };
+
+        if buf.is_empty() {
+            if let Some(cmd) = line.trim().strip_prefix(':') {
+                _ = editor.add_history_entry(line.trim());
+                command(&mut session, cmd);
+                continue;
+            }
+        }
 
-    Ok(())
+        buf.push_str(&line);
+        buf.push('\n');
 
-    /* compiler
-        .run(
-            r"
-        main := fn() {
-            x := 4;
-            printi(x);
+        if is_incomplete(&buf) {
+            continue;
         }
-    ",
-        )
-        .unwrap();
-
-    // compiler.add("sum", |a: i32, b: i32| a + b).unwrap();
-    // compiler.add("printb", |a: bool| println!("{a}")).unwrap();
-    // compiler.add("printi", |a: i32| println!("{a}")).unwrap();
-    // compiler.add("prints", |a: Str| println!("{a}")).unwrap();
-    // compiler
-    //     .add("wait", || thread::sleep(Duration::from_millis(200)))
-    //     .unwrap();
-
-    let res = if let Some(path_arg) = args().nth(1) {
-        let src = read_to_string(path_arg)?;
-        compiler.run(src.as_str())?
-    } else {
-        let stdin = stdin();
-        let mut buf = String::new();
-        loop {
-            print!(">>> ");
-            stdout().flush()?;
-
-            buf.clear();
-            let line_len = stdin.read_line(&mut buf)?;
-            if line_len == 0 {
-                break;
-            }
-            let line = &buf[..line_len];
 
-            if let Err(e) = compiler.run(line) {
-                eprintln!("{e}");
-            }
+        _ = editor.add_history_entry(buf.trim_end());
 
-            println!();
+        if let Err(err) = session.run(&buf) {
+            eprintln!("{err}");
         }
-    };
 
-    // if res != 0 {
-    //     println!("main returned: `{res:?}`");
-    // }
+        buf.clear();
+    }
 
-    Ok(()) */
+    Ok(())
 }
 
-//
-
-/// xor shift rng seeded with subsec nanos elapsed from init to first use
-struct BadRng {
-    init: Instant,
-    state: Option<i32>,
+/// `:type <expr>` prints the type of `expr` without running it
+///
+/// `:ir <name>` prints the LLVM IR of the function `name`
+///
+/// `:reset` forgets everything defined in the session
+///
+/// `:load <path>` runs a source file in the session
+fn command(session: &mut ReplSession, cmd: &str) {
+    let (cmd, args) = cmd.split_once(' ').unwrap_or((cmd, ""));
+
+    match cmd {
+        "type" => match session.compiler().type_of(args) {
+            Ok(ty) => println!("{ty}"),
+            Err(err) => eprintln!("{err}"),
+        },
+        "ir" => match session.compiler().ir_of(args.trim()) {
+            Ok(ir) => println!("{ir}"),
+            Err(err) => eprintln!("{err}"),
+        },
+        "reset" => session.reset(),
+        "load" => {
+            if let Err(err) = session.load(args.trim()) {
+                eprintln!("{err}");
+            }
+        }
+        _ => eprintln!("unknown command `:{cmd}`"),
+    }
 }
 
-impl BadRng {
-    fn init() -> Self {
-        Self {
-            init: Instant::now(),
-            state: None,
+/// input with unbalanced `{` or an unterminated string continues on the next line
+fn is_incomplete(code: &str) -> bool {
+    let mut depth = 0isize;
+
+    for token in Lexer::new(code) {
+        match token.map(|token| token.token()) {
+            Ok(Token::LBrace) => depth += 1,
+            Ok(Token::RBrace) => depth -= 1,
+            Err(lexer::Error::UnexpectedEoi) => return true,
+            _ => {}
         }
     }
 
-    fn next(&mut self) -> i32 {
-        let state = self
-            .state
-            .get_or_insert_with(|| self.init.elapsed().subsec_nanos() as i32);
-        let mut x = *state;
-        x ^= x << 13;
-        x ^= x >> 17;
-        x ^= x << 5;
-        *state = x;
-        x
-    }
+    depth > 0
 }
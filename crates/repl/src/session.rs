@@ -0,0 +1,170 @@
+use std::{
+    fmt, fs, io,
+    path::Path,
+    thread,
+    time::{Duration, Instant},
+};
+
+use compiler::{CompileError, Compiler, Str};
+
+//
+
+#[derive(Debug)]
+pub enum LoadError {
+    Io(io::Error),
+    Run(CompileError),
+}
+
+impl fmt::Display for LoadError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            LoadError::Io(err) => write!(f, "{err}"),
+            LoadError::Run(err) => write!(f, "{err}"),
+        }
+    }
+}
+
+//
+
+/// everything defined in the REPL, the same compiler is used for every line,
+/// so that the variables and functions from earlier lines stay in scope
+pub struct ReplSession {
+    compiler: Compiler,
+}
+
+impl ReplSession {
+    pub fn new() -> Self {
+        let mut compiler = Compiler::new();
+
+        let mut rng = BadRng::init();
+
+        compiler.add("rand", move || rng.next()).unwrap();
+        compiler.add("printi", |i: i32| println!("{i}")).unwrap();
+        compiler.add("prints", |s: Str| println!("{s}")).unwrap();
+        compiler
+            .add("wait", || thread::sleep(Duration::from_millis(200)))
+            .unwrap();
+
+        Self { compiler }
+    }
+
+    pub fn compiler(&mut self) -> &mut Compiler {
+        &mut self.compiler
+    }
+
+    pub fn run(&mut self, code: &str) -> Result<i32, CompileError> {
+        self.compiler.run(code)
+    }
+
+    /// throws away all variables and functions
+    pub fn reset(&mut self) {
+        *self = Self::new();
+    }
+
+    /// runs the file as if it was typed in,
+    /// nothing is defined if it fails to parse or type check
+    pub fn load(&mut self, path: impl AsRef<Path>) -> Result<(), LoadError> {
+        let code = fs::read_to_string(path).map_err(LoadError::Io)?;
+        self.compiler.run(&code).map_err(LoadError::Run)?;
+        Ok(())
+    }
+}
+
+impl Default for ReplSession {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+//
+
+/// xor shift rng seeded with subsec nanos elapsed from init to first use
+struct BadRng {
+    init: Instant,
+    state: Option<i32>,
+}
+
+impl BadRng {
+    fn init() -> Self {
+        Self {
+            init: Instant::now(),
+            state: None,
+        }
+    }
+
+    fn next(&mut self) -> i32 {
+        let state = self
+            .state
+            .get_or_insert_with(|| self.init.elapsed().subsec_nanos() as i32);
+        let mut x = *state;
+        x ^= x << 13;
+        x ^= x >> 17;
+        x ^= x << 5;
+        *state = x;
+        x
+    }
+}
+
+//
+
+#[cfg(test)]
+mod tests {
+    use std::{env, fs, path::PathBuf, process};
+
+    use super::{LoadError, ReplSession};
+
+    //
+
+    /// a file in the temp dir that is deleted when dropped,
+    /// the process id keeps test runs at the same time apart
+    struct SourceFile(PathBuf);
+
+    impl SourceFile {
+        fn new(name: &str, code: &str) -> Self {
+            let path = env::temp_dir().join(format!("{}-{name}", process::id()));
+            fs::write(&path, code).unwrap();
+            Self(path)
+        }
+    }
+
+    impl Drop for SourceFile {
+        fn drop(&mut self) {
+            _ = fs::remove_file(&self.0);
+        }
+    }
+
+    #[test]
+    fn load_and_reset() {
+        let mut session = ReplSession::new();
+
+        let file = SourceFile::new(
+            "repl-load-ok.uni",
+            "x := 5; f := fn() -> i32 { return x; };",
+        );
+        session.load(&file.0).unwrap();
+        assert_eq!(session.compiler().type_of("f() + x").unwrap(), "i32");
+
+        session.reset();
+        assert!(session.compiler().type_of("x").is_err());
+        assert!(matches!(
+            session.load("does-not-exist.uni"),
+            Err(LoadError::Io(_))
+        ));
+    }
+
+    #[test]
+    fn failed_load_changes_nothing() {
+        let mut session = ReplSession::new();
+        session.run("x := 5;").unwrap();
+
+        // `g` is declared before `f` fails to type check
+        let file = SourceFile::new(
+            "repl-load-err.uni",
+            "g := fn() -> i32 { return 1; }; f := fn() -> i32 { return y; };",
+        );
+        assert!(matches!(session.load(&file.0), Err(LoadError::Run(_))));
+
+        assert_eq!(session.compiler().type_of("x").unwrap(), "i32");
+        assert!(session.compiler().type_of("g").is_err());
+    }
+}
@@ -1,13 +1,20 @@
 use std::{
-    collections::{hash_map::Entry, HashMap},
-    fmt,
+    collections::{hash_map::Entry, HashMap, HashSet},
+    fmt, iter, mem,
+    path::{Path, PathBuf},
     rc::Rc,
 };
 
 use lexer::{Span, Unexpected};
-use parser::ast::{
-    self, AnyExpr, Ast, BinaryOp, Call, Cond, Expr, Func, Init, Loop, Return, Root, Set, Stmt, Test,
+use parser::{
+    ast::{
+        self, AnyExpr, Argument, Array, Ast, BinaryOp, Call, CallArg, Cast, Cond, ConstDecl, Decl,
+        Expr, ExternFn, Func, Impl, Index, Init, Loop, Match, MethodCall, Pattern, RefExpr, Return,
+        Root, RootItem, Set, SetRef, StaticDecl, Stmt, Test, TypeAlias, UsePath,
+    },
+    SingleToken,
 };
+use serde::{Deserialize, Serialize};
 
 //
 
@@ -20,6 +27,78 @@ pub enum Error {
         span: Span,
         err: Unexpected<'static, String>,
     },
+    MissingReturn {
+        span: Span,
+    },
+    ReturnsLocalReference {
+        span: Span,
+    },
+    NonConstExpr {
+        span: Span,
+    },
+    /// some values of the `match` don't have an arm
+    NonExhaustiveMatch {
+        span: Span,
+    },
+    /// the result of a function that returns nothing is used as a value
+    VoidValue {
+        span: Span,
+    },
+    /// the last expression of a function body doesn't have the return type of the function
+    TypeMismatch {
+        span: Span,
+        expected: Type,
+        got: Type,
+    },
+    /// a call has both named and positional arguments
+    MixedArguments {
+        span: Span,
+    },
+    /// a named argument that isn't a parameter of the called function
+    UnknownArgument {
+        span: Span,
+        name: String,
+    },
+    /// the same parameter is named twice in a call
+    DuplicateArgument {
+        span: Span,
+        name: String,
+    },
+    /// a call with named arguments doesn't name this parameter
+    MissingArgument {
+        span: Span,
+        name: String,
+    },
+    /// a parameter without a default value comes after one with a default value
+    DefaultNotTrailing {
+        span: Span,
+    },
+    /// [`Module::remove_function`] on a function that another function still uses
+    FunctionStillReferenced {
+        func: FuncId,
+        by: FuncId,
+    },
+    /// the function was already removed with [`Module::remove_function`]
+    FunctionRemoved(FuncId),
+    /// a `{` or `}` in a `print` format string that isn't part of `{}`, `{{` or `}}`
+    InvalidFormat {
+        span: Span,
+    },
+    /// a `print` format string has a different number of `{}` than there are arguments after it
+    FormatArgCount {
+        span: Span,
+        placeholders: usize,
+        args: usize,
+    },
+    /// `use math::add;` where `add` already means a different item
+    AmbiguousImport {
+        span: Span,
+        name: String,
+    },
+    /// the name is already used by another top level item
+    StaticRedefined(String),
+    /// a warning with [`Module::set_warnings_as_errors`]
+    Warning(Warning),
 }
 
 impl fmt::Display for Error {
@@ -31,6 +110,80 @@ impl fmt::Display for Error {
             Error::UnexpectedType { .. } => {
                 write!(f, "unexpected type")
             }
+            Error::MissingReturn { .. } => {
+                write!(f, "missing return value on some path")
+            }
+            Error::ReturnsLocalReference { .. } => {
+                write!(f, "returns a reference to a local variable")
+            }
+            Error::NonConstExpr { .. } => {
+                write!(f, "expression can't be evaluated at compile time")
+            }
+            Error::NonExhaustiveMatch { .. } => {
+                write!(f, "match doesn't cover every value, add a `_` arm")
+            }
+            Error::VoidValue { .. } => {
+                write!(f, "expression has no value, it can't be used here")
+            }
+            Error::TypeMismatch { expected, got, .. } => {
+                write!(f, "expected {expected:?}, found {got:?}")
+            }
+            Error::MixedArguments { .. } => {
+                write!(f, "can't mix named and positional arguments")
+            }
+            Error::UnknownArgument { name, .. } => write!(f, "no parameter named `{name}`"),
+            Error::DuplicateArgument { name, .. } => {
+                write!(f, "argument `{name}` is given more than once")
+            }
+            Error::MissingArgument { name, .. } => write!(f, "missing argument `{name}`"),
+            Error::DefaultNotTrailing { .. } => {
+                write!(f, "only the trailing parameters can have default values")
+            }
+            Error::FunctionStillReferenced { func, by } => {
+                write!(f, "function {} is still used by function {}", func.0, by.0)
+            }
+            Error::FunctionRemoved(func) => write!(f, "function {} was removed", func.0),
+            Error::InvalidFormat { .. } => {
+                write!(
+                    f,
+                    "invalid format string, use `{{{{` and `}}}}` for literal braces"
+                )
+            }
+            Error::FormatArgCount {
+                placeholders, args, ..
+            } => write!(
+                f,
+                "format string has {placeholders} placeholders but {args} arguments were given"
+            ),
+            Error::AmbiguousImport { name, .. } => {
+                write!(f, "`{name}` already refers to a different item")
+            }
+            Error::StaticRedefined(name) => write!(f, "`{name}` is already defined"),
+            Error::Warning(warning) => write!(f, "{warning}"),
+        }
+    }
+}
+
+impl Error {
+    /// where in the source the error happened, if it is known
+    pub fn span(&self) -> Option<Span> {
+        match self {
+            Error::UnexpectedType { span, .. }
+            | Error::MissingReturn { span }
+            | Error::ReturnsLocalReference { span }
+            | Error::NonConstExpr { span }
+            | Error::NonExhaustiveMatch { span }
+            | Error::VoidValue { span }
+            | Error::TypeMismatch { span, .. }
+            | Error::MixedArguments { span }
+            | Error::UnknownArgument { span, .. }
+            | Error::DuplicateArgument { span, .. }
+            | Error::MissingArgument { span, .. }
+            | Error::DefaultNotTrailing { span }
+            | Error::InvalidFormat { span }
+            | Error::FormatArgCount { span, .. }
+            | Error::AmbiguousImport { span, .. } => Some(*span),
+            _ => None,
         }
     }
 }
@@ -39,7 +192,143 @@ pub type Result<T, E = Error> = std::result::Result<T, E>;
 
 //
 
-#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+/// problems that don't stop the code from compiling
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Warning {
+    PossibleInfiniteRecursion(String),
+    UnusedVariable(String),
+    /// a variable from `x: i32;` is read before anything is assigned to it, the value is `undef`
+    UninitializedVariable(String),
+}
+
+impl fmt::Display for Warning {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Warning::PossibleInfiniteRecursion(name) => {
+                write!(f, "function `{name}` calls itself on every path")
+            }
+            Warning::UnusedVariable(name) => write!(f, "unused variable `{name}`"),
+            Warning::UninitializedVariable(name) => {
+                write!(f, "variable `{name}` is read before it is assigned")
+            }
+        }
+    }
+}
+
+/// broken IR invariants found by [`Module::verify`]
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum VerifyError {
+    /// used on some path before any statement defines it
+    UndefinedTmp {
+        func: FuncId,
+        tmp: TmpId,
+    },
+    /// used without a [`Statement::Let`] declaring it
+    UndeclaredVar {
+        func: FuncId,
+        var: VarId,
+    },
+    MissingBlock {
+        func: FuncId,
+        block: BlockId,
+    },
+    MissingFunction {
+        func: FuncId,
+        callee: FuncId,
+    },
+    MissingGlobal {
+        func: FuncId,
+        global: GlobalId,
+    },
+    ArityMismatch {
+        func: FuncId,
+        expected: usize,
+        got: usize,
+    },
+    /// the last statement of the block is not a jump, return or unreachable
+    MissingTerminator {
+        func: FuncId,
+        block: BlockId,
+    },
+    /// a reachable return doesn't match the return type of the function
+    ReturnTypeMismatch {
+        func: FuncId,
+        expected: Type,
+        got: Type,
+    },
+    InvalidType(LinkedType),
+}
+
+impl fmt::Display for VerifyError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            VerifyError::UndefinedTmp { func, tmp } => {
+                write!(
+                    f,
+                    "function {}: tmp {} is used before it is defined",
+                    func.0, tmp.0
+                )
+            }
+            VerifyError::UndeclaredVar { func, var } => {
+                write!(f, "function {}: variable {} is not declared", func.0, var.0)
+            }
+            VerifyError::MissingBlock { func, block } => {
+                write!(f, "function {}: block {} doesn't exist", func.0, block.0)
+            }
+            VerifyError::MissingFunction { func, callee } => {
+                write!(
+                    f,
+                    "function {}: function {} doesn't exist",
+                    func.0, callee.0
+                )
+            }
+            VerifyError::MissingGlobal { func, global } => {
+                write!(f, "function {}: global {} doesn't exist", func.0, global.0)
+            }
+            VerifyError::ArityMismatch {
+                func,
+                expected,
+                got,
+            } => {
+                write!(
+                    f,
+                    "function {}: call with {got} arguments, expected {expected}",
+                    func.0
+                )
+            }
+            VerifyError::MissingTerminator { func, block } => {
+                write!(
+                    f,
+                    "function {}: block {} doesn't end with a terminator",
+                    func.0, block.0
+                )
+            }
+            VerifyError::ReturnTypeMismatch {
+                func,
+                expected,
+                got,
+            } => {
+                write!(
+                    f,
+                    "function {}: returns {got:?}, expected {expected:?}",
+                    func.0
+                )
+            }
+            VerifyError::InvalidType(ty) => write!(f, "type {} is invalid", ty.0),
+        }
+    }
+}
+
+/// the same problem is only reported once
+fn push_error(errors: &mut Vec<VerifyError>, error: VerifyError) {
+    if !errors.contains(&error) {
+        errors.push(error);
+    }
+}
+
+//
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash, Serialize, Deserialize)]
 pub struct LinkedType(pub usize);
 
 #[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
@@ -48,7 +337,7 @@ pub struct VarId(pub usize);
 #[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
 pub struct TmpId(pub usize);
 
-#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash, Serialize, Deserialize)]
 pub struct FuncId(pub usize);
 
 #[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
@@ -57,13 +346,61 @@ pub struct LabelId(pub usize);
 #[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
 pub struct BlockId(pub usize);
 
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash, Serialize, Deserialize)]
+pub struct GlobalId(pub usize);
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash, Serialize, Deserialize)]
+pub struct TupleId(pub usize);
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash, Serialize, Deserialize)]
+pub struct SignatureId(pub usize);
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash, Serialize, Deserialize)]
+pub struct SliceId(pub usize);
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash, Serialize, Deserialize)]
+pub struct RefId(pub usize);
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash, Serialize, Deserialize)]
+pub struct OpaqueId(pub usize);
+
+//
+
+/// a module level name, visible to every function and to later [`Module::process`] calls
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub enum Static {
+    Func(FuncId),
+    Global(GlobalId),
+}
+
 //
 
-#[derive(Debug)]
+/// only the [`ModuleInterface`] is serialized, the functions come back as externs
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(into = "ModuleInterface", from = "ModuleInterface")]
 pub struct Module {
     types: Types,
     functions: Vec<Function>,
+    globals: Vec<LinkedType>,
+    /// the initial values of `static mut` globals, the other globals start zeroed
+    global_inits: Option<HashMap<GlobalId, Literal>>,
+    statics: Option<HashMap<Rc<str>, Static>>,
     externs: Option<HashMap<Rc<str>, FuncId>>,
+    /// `type` and `opaque type` names
+    aliases: Option<HashMap<Rc<str>, Type>>,
+    /// `const` values, inlined where they are used
+    consts: Option<HashMap<Rc<str>, Literal>>,
+    /// the methods from `impl` blocks of each type
+    methods: Option<HashMap<Type, HashMap<Rc<str>, FuncId>>>,
+    /// files already added, a `use` of one of them doesn't add it again
+    imports: Vec<PathBuf>,
+    /// the `mod` blocks around the items being processed, like `math::`,
+    /// always empty between [`Self::process`] calls
+    namespace: String,
+    /// functions from [`Self::remove_function`], their ids are never reused
+    removed: Option<HashSet<FuncId>>,
+    warnings: Vec<Warning>,
+    warnings_as_errors: bool,
 }
 
 impl Module {
@@ -72,9 +409,29 @@ impl Module {
             types: Types {
                 type_links: Vec::new(),
                 known_type_links: None,
+                tuples: Vec::new(),
+                known_tuples: None,
+                signatures: Vec::new(),
+                known_signatures: None,
+                slices: Vec::new(),
+                known_slices: None,
+                refs: Vec::new(),
+                known_refs: None,
+                opaques: Vec::new(),
             },
             functions: Vec::new(),
+            globals: Vec::new(),
+            global_inits: None,
+            statics: None,
             externs: None,
+            aliases: None,
+            consts: None,
+            methods: None,
+            imports: Vec::new(),
+            namespace: String::new(),
+            removed: None,
+            warnings: Vec::new(),
+            warnings_as_errors: false,
         }
     }
 
@@ -82,747 +439,7660 @@ impl Module {
         &self.types.type_links[id.0]
     }
 
+    pub fn get_tuple(&self, id: TupleId) -> &[Type] {
+        &self.types.tuples[id.0]
+    }
+
+    pub fn get_signature(&self, id: SignatureId) -> &Signature {
+        &self.types.signatures[id.0]
+    }
+
+    /// the element type of a slice
+    pub fn get_slice(&self, id: SliceId) -> Type {
+        self.types.slices[id.0]
+    }
+
+    /// the type a reference points to
+    pub fn get_ref(&self, id: RefId) -> Type {
+        self.types.refs[id.0]
+    }
+
+    /// the name and the underlying type of an `opaque type`
+    pub fn get_opaque(&self, id: OpaqueId) -> (&str, Type) {
+        let (name, ty) = &self.types.opaques[id.0];
+        (name, *ty)
+    }
+
+    /// the type `&elem`
+    pub fn create_ref_type(&mut self, elem: Type) -> Type {
+        Type::Ref(self.types.create_ref(elem))
+    }
+
+    /// the type `(elems..)`
+    pub fn create_tuple_type(&mut self, elems: Box<[Type]>) -> Type {
+        Type::Tuple(self.types.create_tuple(elems))
+    }
+
+    /// the type that a `type` or `opaque type` declaration named `name` stands for
+    pub fn get_alias(&self, name: &str) -> Option<Type> {
+        self.aliases.as_ref()?.get(name).copied()
+    }
+
+    /// the value of the `const` named `name`
+    pub fn get_const(&self, name: &str) -> Option<&Literal> {
+        self.consts.as_ref()?.get(name)
+    }
+
+    /// the method `name` from an `impl` block of `ty`
+    pub fn get_method(&self, ty: Type, name: &str) -> Option<FuncId> {
+        self.methods.as_ref()?.get(&ty)?.get(name).copied()
+    }
+
+    /// `true` for methods that take `self: &Type` instead of `self: Type`
+    fn takes_self_by_ref(&self, method: FuncId) -> bool {
+        self.functions[method.0]
+            .params
+            .first()
+            .is_some_and(|param| matches!(self.get_type(*param), Type::Ref(_)))
+    }
+
+    /// records that the file at `path` was added,
+    /// `false` if it already was
+    pub fn add_import(&mut self, path: PathBuf) -> bool {
+        if self.is_imported(&path) {
+            return false;
+        }
+        self.imports.push(path);
+        true
+    }
+
+    pub fn is_imported(&self, path: &Path) -> bool {
+        self.imports.iter().any(|import| import == path)
+    }
+
+    /// the files added so far, in the order they were added
+    pub fn imports(&self) -> &[PathBuf] {
+        &self.imports[..]
+    }
+
+    /// the signature that a function pointer to `id` would have
+    pub fn signature_of(&self, id: FuncId) -> Signature {
+        let func = self.get_function(id);
+        Signature {
+            returns: *self.get_type(func.returns),
+            params: func
+                .params
+                .iter()
+                .map(|param| *self.get_type(*param))
+                .collect(),
+        }
+    }
+
+    /// `true` if a value of type `from` can be used where `to` is expected
+    pub fn coerces_to(&self, from: Type, to: Type) -> bool {
+        match (from, to) {
+            (Type::Func(func), Type::FnPtr(sig)) => {
+                // function pointers can't express the extra arguments
+                !self.get_function(func).is_variadic
+                    && self.signature_of(func) == *self.get_signature(sig)
+            }
+            // the code that would use a value that never exists is never reached
+            (Type::Never, _) => true,
+            _ => from == to,
+        }
+    }
+
     pub fn get_function(&self, id: FuncId) -> &Function {
         &self.functions[id.0]
     }
 
+    /// for building or rewriting IR by hand, [`Self::verify`] checks the result
+    pub fn get_function_mut(&mut self, id: FuncId) -> &mut Function {
+        &mut self.functions[id.0]
+    }
+
+    /// looks up a top level function or an extern by its name
+    pub fn get_function_by_name(&self, name: &str) -> Option<FuncId> {
+        if let Some(Static::Func(func_id)) = self.get_static(name) {
+            return Some(func_id);
+        }
+
+        self.externs.as_ref()?.get(name).copied()
+    }
+
+    /// every function, in ascending [`FuncId`] order, `functions()[id.0]` is the function `id`
+    ///
+    /// removed functions are still there, but without a body, see [`Self::is_removed`]
     pub fn functions(&self) -> &[Function] {
         &self.functions[..]
     }
 
+    pub fn get_global(&self, id: GlobalId) -> LinkedType {
+        self.globals[id.0]
+    }
+
+    pub fn globals(&self) -> impl ExactSizeIterator<Item = (GlobalId, LinkedType)> + '_ {
+        self.globals
+            .iter()
+            .enumerate()
+            .map(|(i, ty)| (GlobalId(i), *ty))
+    }
+
+    /// all externs, added from Rust or declared in the source code
+    pub fn externs(&self) -> impl Iterator<Item = (&str, FuncId)> + '_ {
+        self.externs
+            .iter()
+            .flatten()
+            .map(|(name, func_id)| (name.as_ref(), *func_id))
+    }
+
+    pub fn get_static(&self, name: &str) -> Option<Static> {
+        self.statics.as_ref()?.get(name).copied()
+    }
+
+    /// looks up `name` in the current `mod` first and then in each `mod` around it,
+    /// `name` can be a path like `math::add`
+    fn resolve_static(&self, name: &str) -> Option<Static> {
+        let mut namespace = self.namespace.as_str();
+        loop {
+            if let Some(item) = self.get_static(&format!("{namespace}{name}")) {
+                return Some(item);
+            }
+            if namespace.is_empty() {
+                return None;
+            }
+
+            // `a::b::` -> `a::`
+            let outer = namespace[..namespace.len() - 2].rfind("::");
+            namespace = &namespace[..outer.map_or(0, |i| i + 2)];
+        }
+    }
+
+    /// the full name of an item declared in the current `mod`
+    fn qualified(&self, name: &str) -> Rc<str> {
+        format!("{}{name}", self.namespace).into()
+    }
+
+    /// the compile time initial value of a `static mut` global
+    pub fn get_global_init(&self, id: GlobalId) -> Option<&Literal> {
+        self.global_inits.as_ref()?.get(&id)
+    }
+
+    fn new_global(&mut self, ty: LinkedType) -> GlobalId {
+        let id = GlobalId(self.globals.len());
+        self.globals.push(ty);
+        id
+    }
+
     pub fn add_extern(&mut self, name: &str, ret: Type, params: &[Type]) -> FuncId {
         let fn_id = FuncId(self.functions.len());
         self.externs
             .get_or_insert_with(Default::default)
             .insert(name.into(), fn_id);
-        self.functions.push(Function::new_extern(
+        let mut func = Function::new_extern(
             self.types.create_known(ret),
             params
                 .iter()
                 .map(|ty| self.types.create_known(*ty))
                 .collect(),
             true,
-        ));
+        );
+        func.name = Some(name.into());
+        self.functions.push(func);
         fn_id
     }
 
-    pub fn process(&mut self, ast: &Ast<Root>) -> Result<FuncId> {
-        let mut func = Function::new(self.types.create_known(Type::Void), [].into());
+    /// like [`Self::add_extern`], but the direct calls to it can be turned into statements,
+    /// like `len` and `print`, an extern with the same name from the source stays a call
+    pub fn add_builtin(&mut self, name: &str, ret: Type, params: &[Type]) -> FuncId {
+        let fn_id = self.add_extern(name, ret, params);
+        self.functions[fn_id.0].builtin = true;
+        fn_id
+    }
 
-        ast.inner.process(self, &mut func)?;
+    /// gives the top level function or extern `id` a new name,
+    /// the old name stops referring to it
+    pub fn rename_function(&mut self, id: FuncId, new_name: String) -> Result<()> {
+        let taken = match (self.get_static(&new_name), self.externs.as_ref()) {
+            (Some(item), _) => item != Static::Func(id),
+            (None, Some(externs)) => externs.get(new_name.as_str()).is_some_and(|f| *f != id),
+            (None, None) => false,
+        };
+        if taken {
+            return Err(Error::StaticRedefined(new_name));
+        }
 
-        func.terminate();
+        let new_name: Rc<str> = new_name.into();
+        if let Some(statics) = self.statics.as_mut() {
+            statics.retain(|_, item| *item != Static::Func(id));
+        }
+        let registered = if let Some(externs) = self.externs.as_mut() {
+            let len = externs.len();
+            externs.retain(|_, func_id| *func_id != id);
+            len != externs.len()
+        } else {
+            false
+        };
 
-        let main = FuncId(self.functions.len());
-        self.functions.push(func);
-        Ok(main)
+        if registered {
+            self.externs
+                .get_or_insert_with(Default::default)
+                .insert(new_name.clone(), id);
+        } else {
+            self.insert_static(new_name.clone(), Static::Func(id));
+        }
+        self.functions[id.0].name = Some(new_name);
+        Ok(())
     }
 
-    pub fn dump(&self) {
-        for (i, func) in self.functions.iter().enumerate() {
-            println!("Function{i}");
-            print!(" - return: ");
-            self.print_linked_type(func.returns);
-            println!();
-
-            println!(" - params: ");
-            for (i, param) in func.params.iter().enumerate() {
-                print!("   - {i}: ");
-                self.print_linked_type(*param);
-                println!();
-            }
+    /// copies the function `id` into a new top level function named `new_name`
+    ///
+    /// temporaries, variables and blocks are numbered per function, so the copy keeps the ids,
+    /// but every type gets a new link, so the types of the copy can be changed separately,
+    /// calls of the function to itself call the copy instead
+    pub fn clone_function(&mut self, id: FuncId, new_name: &str) -> FuncId {
+        let new_id = FuncId(self.functions.len());
+        let mut func = self.functions[id.0].clone();
+
+        let mut relink = |ty: LinkedType| {
+            let ty = match self.types.type_links[ty.0] {
+                Type::Func(func_id) if func_id == id => Type::Func(new_id),
+                ty => ty,
+            };
+            self.types.create_new(ty)
+        };
+        func.returns = relink(func.returns);
+        for ty in func
+            .params
+            .iter_mut()
+            .chain(func.variables.iter_mut())
+            .chain(func.temporaries.iter_mut())
+        {
+            *ty = relink(*ty);
+        }
 
-            if func.is_extern {
-                println!(" - is_extern");
-                continue;
+        for stmt in func
+            .blocks
+            .iter_mut()
+            .flat_map(|block| block.stmts.iter_mut())
+        {
+            if let Statement::Func { src, .. } = stmt {
+                if *src == id {
+                    *src = new_id;
+                }
             }
+        }
 
-            println!(" - blocks: ");
-            for (block_id, block) in func.blocks() {
-                println!("   - Block{}: ", block_id.0);
-                println!("   - stmts: ");
-                for stmt in block.stmts.iter() {
-                    match stmt {
-                        Statement::Let { dst, src } => {
-                            print!("     - let {}: ", dst.0);
-                            self.print_linked_type(func.variables[dst.0]);
-                            print!(" = %{}", src.0);
-                        }
-                        Statement::Store { dst, src } => {
-                            print!("     - {}: ", dst.0);
-                            self.print_linked_type(func.variables[dst.0]);
-                            print!(" = %{}", src.0);
-                        }
-                        Statement::Load { dst, src } => {
-                            print!("     - %{}: ", dst.0);
-                            self.print_linked_type(func.temporaries[dst.0]);
-                            print!(" = {}", src.0);
-                        }
-                        Statement::Extern { dst, src: _, name } => {
-                            print!("     - %{}: ", dst.0);
-                            self.print_linked_type(func.temporaries[dst.0]);
-                            print!(" = {name}");
-                        }
-                        Statement::Func { dst, src } => {
-                            print!("     - %{}: ", dst.0);
-                            self.print_linked_type(func.temporaries[dst.0]);
-                            print!(" = {:?}", src);
-                        }
-                        Statement::Const { dst, src } => {
-                            print!("     - %{}: ", dst.0);
-                            self.print_linked_type(func.temporaries[dst.0]);
-                            print!(" = {:?}", src);
-                        }
-                        Statement::BinExpr { dst, lhs, op, rhs } => {
-                            print!("     - %{}: ", dst.0);
-                            self.print_linked_type(func.temporaries[dst.0]);
-                            print!(" = %{} {op} %{}", lhs.0, rhs.0);
-                        }
-                        Statement::Call { dst, func: f, args } => {
-                            print!("     - %{}: ", dst.0);
-                            self.print_linked_type(func.temporaries[dst.0]);
-                            print!(" = call %{} %({:?})", f.0, args);
-                        }
-                        Statement::Return { src } => {
-                            print!("     - return %{}: ", src.0);
-                            self.print_linked_type(func.temporaries[src.0]);
-                        }
-                        Statement::ReturnVoid => {
-                            print!("     - return void");
-                        }
-                        Statement::UnconditionalJump { id } => {
-                            print!("     - jump {}", id.0);
-                        }
-                        Statement::ConditionalJump {
-                            bool,
-                            then_block,
-                            else_block,
-                        } => {
-                            print!(
-                                "     - if %{} then jump {} else jump {}",
-                                bool.0, then_block.0, else_block.0
-                            );
-                        }
-                    }
+        let new_name: Rc<str> = new_name.into();
+        func.name = Some(new_name.clone());
+        func.toplevel = false;
+        self.functions.push(func);
+        self.insert_static(new_name, Static::Func(new_id));
+        new_id
+    }
 
-                    println!();
-                }
+    /// replaces the type `from` with `to` everywhere in the function `func`,
+    /// like a type parameter in a copy from [`Self::clone_function`]
+    ///
+    /// only the types of `func` change, even if other functions share the same type links
+    pub fn substitute_type(&mut self, func: FuncId, from: Type, to: Type) {
+        let func = &mut self.functions[func.0];
+        for ty in iter::once(&mut func.returns)
+            .chain(func.params.iter_mut())
+            .chain(func.variables.iter_mut())
+            .chain(func.temporaries.iter_mut())
+        {
+            let old = self.types.type_links[ty.0];
+            let new = self.types.substitute(old, from, to);
+            if new != old {
+                *ty = self.types.create_known(new);
             }
         }
     }
 
-    fn print_linked_type(&self, ty: LinkedType) {
-        print!("{:?}", self.types.type_links[ty.0]);
+    /// makes `new_name` refer to the local variable `var` of the function `func`,
+    /// instead of the names it had before
+    pub fn rename_variable(&mut self, func: FuncId, var: VarId, new_name: String) {
+        let variables = &mut self.functions[func.0].variables_raw;
+        variables.retain(|_, id| *id != var);
+        variables.insert(new_name.into(), var);
     }
-}
 
-impl Default for Module {
-    fn default() -> Self {
-        Self::new()
+    /// like [`Self::add_extern`], but calls can pass any number of arguments after `params`
+    pub fn add_variadic_extern(&mut self, name: &str, ret: Type, params: &[Type]) -> FuncId {
+        let func_id = self.add_extern(name, ret, params);
+        self.functions[func_id.0].is_variadic = true;
+        func_id
     }
-}
 
-//
+    /// type checks `ast` into a new top level function,
+    /// the module is left untouched if it fails
+    ///
+    /// the prototypes of all top level functions are collected before any body is checked,
+    /// so they can call each other in any order
+    pub fn process(&mut self, ast: &Ast<Root>) -> Result<FuncId> {
+        let first_new = self.functions.len();
+        let first_warning = self.warnings.len();
+        let backup = self.snapshot();
 
-trait Process {
-    type Return;
+        let mut func = Function::new(self.types.create_known(Type::Void), [].into());
+        func.toplevel = true;
 
-    fn process(&self, module: &mut Module, function: &mut Function) -> Result<Self::Return>;
-}
+        if let Err(err) = ast.inner.process(self, &mut func) {
+            self.restore(backup);
+            return Err(err);
+        }
 
-impl Process for Root {
-    type Return = ();
+        func.terminate();
 
-    fn process(&self, module: &mut Module, function: &mut Function) -> Result<Self::Return> {
-        for item in self.stmts.iter() {
-            item.process(module, function)?;
-        }
-        Ok(())
-    }
-}
+        let main = FuncId(self.functions.len());
+        self.functions.push(func);
 
-impl Process for Init {
-    type Return = ();
+        self.check_recursion(first_new);
 
-    fn process(&self, module: &mut Module, function: &mut Function) -> Result<Self::Return> {
-        let mut expr_results = Vec::with_capacity(self.exprs.iter().len());
-        for expr in self.exprs.iter() {
-            expr_results.push(expr.process(module, function)?);
+        if self.warnings_as_errors && self.warnings.len() > first_warning {
+            let warning = self.warnings.swap_remove(first_warning);
+            self.restore(backup);
+            return Err(Error::Warning(warning));
         }
 
-        for (target, src) in self.targets.iter().zip(expr_results) {
-            let dst = function.new_varid(function.temporaries[src.0]);
-
-            // shadow old variables
-            function
-                .variables_raw
-                .insert(target.path.ident.value.as_str().into(), dst);
+        Ok(main)
+    }
 
-            function.push_stmt(Statement::Let { dst, src });
+    /// the state to go back to with [`Self::restore`] if [`Self::process`] or a later step fails
+    ///
+    /// the tables that only grow just remember their length, the rest are cloned,
+    /// the existing functions are never changed by [`Self::process`]
+    pub fn snapshot(&self) -> Snapshot {
+        Snapshot {
+            types: self.types.len(),
+            functions: self.functions.len(),
+            globals: self.globals.len(),
+            imports: self.imports.len(),
+            warnings: self.warnings.len(),
+            global_inits: self.global_inits.clone(),
+            statics: self.statics.clone(),
+            externs: self.externs.clone(),
+            aliases: self.aliases.clone(),
+            consts: self.consts.clone(),
+            methods: self.methods.clone(),
         }
+    }
 
-        Ok(())
+    /// undoes everything since [`Self::snapshot`]
+    pub fn restore(&mut self, snapshot: Snapshot) {
+        self.types.truncate(snapshot.types);
+        self.functions.truncate(snapshot.functions);
+        self.globals.truncate(snapshot.globals);
+        self.imports.truncate(snapshot.imports);
+        self.warnings.truncate(snapshot.warnings);
+        self.global_inits = snapshot.global_inits;
+        self.statics = snapshot.statics;
+        self.externs = snapshot.externs;
+        self.aliases = snapshot.aliases;
+        self.consts = snapshot.consts;
+        self.methods = snapshot.methods;
     }
-}
 
-impl Process for Test {
-    type Return = ();
+    /// makes [`Self::process`] fail on the first new warning, nothing from that call is kept
+    pub fn set_warnings_as_errors(&mut self, enabled: bool) {
+        self.warnings_as_errors = enabled;
+    }
 
-    fn process(&self, _module: &mut Module, _function: &mut Function) -> Result<Self::Return> {
-        todo!()
+    pub fn warnings_as_errors(&self) -> bool {
+        self.warnings_as_errors
     }
-}
 
-impl Process for Expr {
-    type Return = TmpId;
+    /// warnings from all [`Self::process`] calls since the last `take_warnings`
+    pub fn take_warnings(&mut self) -> Vec<Warning> {
+        mem::take(&mut self.warnings)
+    }
 
-    fn process(&self, module: &mut Module, function: &mut Function) -> Result<Self::Return> {
-        match &self.expr {
-            AnyExpr::Block(block) => block.process(module, function),
-            AnyExpr::LitInt(int) => {
-                let dst = function.new_tmpid(module.types.create_known(Type::I32));
+    fn check_recursion(&mut self, first_new: usize) {
+        // in `FuncId` order, so the warnings don't depend on the `HashMap` order
+        let graph = self.call_graph();
+        for func_id in (first_new..self.functions.len()).map(FuncId) {
+            if !graph[&func_id].contains(&func_id) || !self.always_calls_itself(func_id) {
+                continue;
+            }
 
-                function.push_stmt(Statement::Const {
-                    dst,
-                    src: Literal::I32(int.value as _),
-                });
+            let name = self
+                .statics
+                .iter()
+                .flatten()
+                .find(|(_, item)| **item == Static::Func(func_id))
+                .map_or_else(|| format!("{func_id:?}"), |(name, _)| name.to_string());
 
-                Ok(dst)
+            self.warnings.push(Warning::PossibleInfiniteRecursion(name));
+        }
+    }
+
+    /// `true` if every path from the entry block calls the function itself before returning
+    fn always_calls_itself(&self, func_id: FuncId) -> bool {
+        /// `results[i]` is `None` for blocks not visited yet, blocks being visited are `false`
+        /// until their result is known
+        fn visit(
+            module: &Module,
+            func_id: FuncId,
+            block_id: BlockId,
+            results: &mut Vec<Option<bool>>,
+        ) -> bool {
+            // a loop back to a block still being visited doesn't call anything new,
+            // but a block reached again on another path, like the end of an if-else,
+            // has the same result on both paths
+            if let Some(result) = results[block_id.0] {
+                return result;
             }
-            AnyExpr::LitStr(str) => {
-                let dst = function.new_tmpid(module.types.create_known(Type::Str));
+            results[block_id.0] = Some(false);
 
-                function.push_stmt(Statement::Const {
-                    dst,
-                    src: Literal::Str(str.value.as_str().into()),
-                });
+            let result = visit_stmts(module, func_id, block_id, results);
+            results[block_id.0] = Some(result);
+            result
+        }
 
-                Ok(dst)
+        fn visit_stmts(
+            module: &Module,
+            func_id: FuncId,
+            block_id: BlockId,
+            results: &mut Vec<Option<bool>>,
+        ) -> bool {
+            let func = module.get_function(func_id);
+            for stmt in func.blocks[block_id.0].stmts.iter() {
+                match stmt {
+                    Statement::Call { func: callee, .. }
+                        if *module.get_type(func.tmp(*callee)) == Type::Func(func_id) =>
+                    {
+                        return true;
+                    }
+                    Statement::UnconditionalJump { id } => {
+                        return visit(module, func_id, *id, results);
+                    }
+                    Statement::ConditionalJump {
+                        then_block,
+                        else_block,
+                        ..
+                    } => {
+                        return visit(module, func_id, *then_block, results)
+                            && visit(module, func_id, *else_block, results);
+                    }
+                    Statement::SwitchInt { default, cases, .. } => {
+                        return visit(module, func_id, *default, results)
+                            && cases
+                                .iter()
+                                .all(|(_, block)| visit(module, func_id, *block, results));
+                    }
+                    stmt if stmt.is_terminal() => return false,
+                    _ => {}
+                }
             }
-            AnyExpr::Load(var) => {
-                if let Some(src) = function.variables_raw.get(var.value.as_str()).copied() {
-                    let dst = function.new_tmpid(function.variables[src.0]);
-                    function.push_stmt(Statement::Load { dst, src });
-                    return Ok(dst);
+
+            false
+        }
+
+        let func = self.get_function(func_id);
+        if func.is_extern || func.blocks.is_empty() {
+            return false;
+        }
+
+        visit(
+            self,
+            func_id,
+            BlockId(0),
+            &mut vec![None; func.blocks.len()],
+        )
+    }
+
+    /// every function and the functions it calls directly, in the order of the first call
+    pub fn call_graph(&self) -> HashMap<FuncId, Vec<FuncId>> {
+        self.functions
+            .iter()
+            .enumerate()
+            .map(|(i, func)| {
+                let mut callees = Vec::new();
+                for (_, block) in func.blocks() {
+                    for stmt in block.stmts.iter() {
+                        let Statement::Call { func: callee, .. } = stmt else {
+                            continue;
+                        };
+                        let Type::Func(callee) = *self.get_type(func.tmp(*callee)) else {
+                            continue;
+                        };
+                        if !callees.contains(&callee) {
+                            callees.push(callee);
+                        }
+                    }
                 }
 
-                let src = module
-                    .externs
-                    .as_ref()
-                    .and_then(|map| map.get_key_value(var.value.as_str()))
-                    .ok_or_else(|| Error::VariableNotFound(var.value.clone()))?;
-                let name = src.0.clone();
-                let src = *src.1;
+                (FuncId(i), callees)
+            })
+            .collect()
+    }
 
-                let dst = function.new_tmpid(module.types.create_known(Type::Func(src)));
-                // let dst = function.new_tmpid(module.functions[src.0].returns);
+    /// `true` if the function can call itself, directly or through other functions
+    pub fn is_recursive(&self, id: FuncId) -> bool {
+        calls_itself(&self.call_graph(), id)
+    }
 
-                function.push_stmt(Statement::Extern { dst, name, src });
-                Ok(dst)
-            }
-            AnyExpr::Func(func) => func.process(module, function),
-            AnyExpr::Call(call) => call.process(module, function),
-            AnyExpr::Binary { op, sides } => {
-                let lhs = sides.0.process(module, function)?;
-                let rhs = sides.1.process(module, function)?;
-
-                let ty = match op {
-                    BinaryOp::Lt
-                    | BinaryOp::Le
-                    | BinaryOp::Gt
-                    | BinaryOp::Ge
-                    | BinaryOp::Eq
-                    | BinaryOp::Neq
-                    | BinaryOp::And
-                    | BinaryOp::Or => module.types.create_known(Type::Bool),
-                    _ => function.temporaries[lhs.0],
-                };
+    /// `true` if the function was removed with [`Self::remove_function`]
+    pub fn is_removed(&self, id: FuncId) -> bool {
+        self.removed
+            .as_ref()
+            .is_some_and(|removed| removed.contains(&id))
+    }
 
-                let dst = function.new_tmpid(ty);
-                let op = *op;
+    /// removes a function that no other function uses anymore,
+    /// its [`FuncId`] stays invalid and its name can't be looked up anymore
+    ///
+    /// panics if `id` is not from this module, like [`Self::get_function`]
+    pub fn remove_function(&mut self, id: FuncId) -> Result<()> {
+        assert!(id.0 < self.functions.len(), "no function {}", id.0);
+        if self.is_removed(id) {
+            return Err(Error::FunctionRemoved(id));
+        }
 
-                function.push_stmt(Statement::BinExpr { dst, lhs, op, rhs });
+        // the call graph misses the functions that are only used as values
+        let graph = self.call_graph();
+        let by = (0..self.functions.len())
+            .map(FuncId)
+            .filter(|by| *by != id && !self.is_removed(*by))
+            .find(|by| graph[by].contains(&id) || self.uses_function(*by, id));
+        if let Some(by) = by {
+            return Err(Error::FunctionStillReferenced { func: id, by });
+        }
 
-                Ok(dst)
+        self.unlink_function(id);
+        Ok(())
+    }
+
+    /// removes every function that `entry` can't reach through calls or function values,
+    /// including the ones that only reach each other
+    pub fn eliminate_dead_functions(&mut self, entry: FuncId) {
+        let graph = self.call_graph();
+        let mut reachable = HashSet::from([entry]);
+        let mut stack = vec![entry];
+        while let Some(func_id) = stack.pop() {
+            let used = graph[&func_id]
+                .iter()
+                .copied()
+                .chain(
+                    (0..self.functions.len())
+                        .map(FuncId)
+                        .filter(|used| self.uses_function(func_id, *used)),
+                )
+                .collect::<Vec<_>>();
+            for used in used {
+                if reachable.insert(used) {
+                    stack.push(used);
+                }
+            }
+        }
+
+        for i in 0..self.functions.len() {
+            if !reachable.contains(&FuncId(i)) && !self.is_removed(FuncId(i)) {
+                self.unlink_function(FuncId(i));
             }
         }
     }
-}
 
-impl Process for ast::Block {
-    type Return = TmpId;
+    /// `true` if a statement of `func` uses a value of the function `used`,
+    /// to call it or to take a pointer to it
+    fn uses_function(&self, func: FuncId, used: FuncId) -> bool {
+        let func = self.get_function(func);
+        func.statements().any(|stmt| {
+            let defined = stmt.defined_tmp();
+            stmt.tmps()
+                .into_iter()
+                .filter(|tmp| Some(*tmp) != defined)
+                .any(|tmp| *self.get_type(func.tmp(tmp)) == Type::Func(used))
+        })
+    }
 
-    fn process(&self, module: &mut Module, function: &mut Function) -> Result<Self::Return> {
-        let mut last = None;
+    /// replaces the function with an empty one and forgets every name that refers to it,
+    /// the function values that are left in other functions are unused, so those go too
+    fn unlink_function(&mut self, id: FuncId) {
+        let func = &self.functions[id.0];
+        self.functions[id.0] = Function::new_extern(func.returns, func.params.clone(), false);
+        self.removed.get_or_insert_with(Default::default).insert(id);
+
+        for func in self.functions.iter_mut() {
+            for stmt in func
+                .blocks
+                .iter_mut()
+                .flat_map(|block| block.stmts.iter_mut())
+            {
+                if let Statement::Func { src, .. } | Statement::Extern { src, .. } = stmt {
+                    if *src == id {
+                        *stmt = Statement::Nop;
+                    }
+                }
+            }
+            func.compact();
+        }
 
-        for stmt in self.stmts.iter() {
-            last = stmt.process(module, function)?;
+        if let Some(statics) = self.statics.as_mut() {
+            statics.retain(|_, item| *item != Static::Func(id));
         }
+        if let Some(externs) = self.externs.as_mut() {
+            externs.retain(|_, func_id| *func_id != id);
+        }
+        for methods in self
+            .methods
+            .iter_mut()
+            .flat_map(|methods| methods.values_mut())
+        {
+            methods.retain(|_, func_id| *func_id != id);
+        }
+    }
 
-        if self.auto_return {
-            if let Some(last) = last {
-                return Ok(last);
+    /// copies the bodies of small functions into the places that call them directly
+    ///
+    /// only functions with a single block of at most `max_stmts` statements are inlined,
+    /// recursive functions and functions that never return never are
+    pub fn inline_calls(&mut self, max_stmts: usize) {
+        let graph = self.call_graph();
+        let is_recursive = |func_id: FuncId| calls_itself(&graph, func_id);
+
+        let inlinable: HashMap<FuncId, Function> = self
+            .functions
+            .iter()
+            .enumerate()
+            .filter(|(i, func)| {
+                !func.is_extern
+                    && func.blocks.len() == 1
+                    && func.blocks[0].stmts.len() <= max_stmts
+                    // the caller's block would continue after an `unreachable`
+                    && matches!(
+                        func.blocks[0].stmts.last(),
+                        Some(Statement::Return { .. } | Statement::ReturnVoid)
+                    )
+                    && !is_recursive(FuncId(*i))
+            })
+            .map(|(i, func)| (FuncId(i), func.clone()))
+            .collect();
+
+        for (caller_id, caller) in self.functions.iter_mut().enumerate() {
+            if caller.is_extern {
+                continue;
             }
-        };
 
-        Ok(function.new_tmpid(module.types.create_known(Type::Void)))
-    }
-}
+            let mut uses = HashMap::<TmpId, usize>::new();
+            for stmt in caller.statements() {
+                for tmp in stmt.tmps() {
+                    *uses.entry(tmp).or_default() += 1;
+                }
+            }
 
-impl Process for Func {
-    type Return = TmpId;
+            let mut inlined_any = false;
+            for block in 0..caller.blocks.len() {
+                let stmts = mem::take(&mut caller.blocks[block].stmts);
+                let mut inlined = Vec::with_capacity(stmts.len());
+
+                for stmt in stmts {
+                    let callee = match &stmt {
+                        Statement::Call { dst, func, args } => {
+                            match self.types.type_links[caller.temporaries[func.0].0] {
+                                Type::Func(callee) if callee.0 != caller_id => inlinable
+                                    .get(&callee)
+                                    // a void result can't be copied, unless nothing uses it
+                                    .filter(|callee| {
+                                        !matches!(
+                                            callee.blocks[0].stmts.last(),
+                                            Some(Statement::ReturnVoid)
+                                        ) || uses[dst] == 1
+                                    })
+                                    .map(|callee| (callee, *dst, args.clone())),
+                                _ => None,
+                            }
+                        }
+                        _ => None,
+                    };
 
-    fn process(&self, module: &mut Module, function: &mut Function) -> Result<Self::Return> {
-        let ret_ty =
-            type_hint(self.proto.return_ty.as_ref().map(|(_, i)| i.value.as_str()))?.unwrap();
+                    let Some((callee, dst, args)) = callee else {
+                        inlined.push(stmt);
+                        continue;
+                    };
 
-        let mut func = Function::new(
-            module.types.create_known(ret_ty),
-            (0..self.proto.args.iter().len())
-                .map(|_| module.types.create())
-                .collect(),
-        );
+                    inlined_any = true;
+                    caller.inline(callee, dst, &args, &mut inlined);
+                }
 
-        self.block.process(module, &mut func)?;
+                caller.blocks[block].stmts = inlined;
+            }
 
-        func.terminate();
+            if inlined_any {
+                caller.remove_unused_funcs();
+                caller.compact();
+            }
+        }
+    }
 
-        let func_id = FuncId(module.functions.len());
-        module.functions.push(func);
+    /// removes the blocks that can't be reached from the entry block of each function
+    ///
+    /// jumps on constant bools only follow the taken branch,
+    /// and blocks entered by a single unconditional jump are merged into the jumping block
+    pub fn eliminate_dead_blocks(&mut self) {
+        for func in self.functions.iter_mut() {
+            if !func.is_extern && !func.blocks.is_empty() {
+                func.eliminate_dead_blocks();
+            }
+        }
+    }
 
-        let dst = function.new_tmpid(module.types.create_known(Type::Func(func_id)));
-        function.push_stmt(Statement::Func { dst, src: func_id });
+    /// checks that the IR is consistent, passes like [`Self::inline_calls`] could break it
+    ///
+    /// an empty list means everything is fine
+    pub fn verify(&self) -> Vec<VerifyError> {
+        let mut errors = Vec::new();
+
+        let mut types: Vec<LinkedType> = self.globals.clone();
+        for func in self.functions.iter() {
+            types.push(func.returns);
+            types.extend(func.params.iter().copied());
+            types.extend(func.variables.iter().copied());
+            types.extend(func.temporaries.iter().copied());
+        }
+        types.sort();
+        types.dedup();
+        for ty in types {
+            let valid = self
+                .types
+                .type_links
+                .get(ty.0)
+                .is_some_and(|known| self.is_valid_type(*known));
+            if !valid {
+                errors.push(VerifyError::InvalidType(ty));
+            }
+        }
 
-        Ok(dst)
+        for (i, func) in self.functions.iter().enumerate() {
+            if !func.is_extern && !self.is_removed(FuncId(i)) {
+                self.verify_function(FuncId(i), func, &mut errors);
+            }
+        }
+
+        errors
     }
-}
 
-impl Process for Call {
-    type Return = TmpId;
+    /// the first problem [`Self::verify`] finds, checked before generating code from the IR
+    pub fn validate(&self) -> std::result::Result<(), VerifyError> {
+        match self.verify().into_iter().next() {
+            Some(err) => Err(err),
+            None => Ok(()),
+        }
+    }
 
-    fn process(&self, module: &mut Module, function: &mut Function) -> Result<Self::Return> {
-        let func = self.func.process(module, function)?;
-        let Type::Func(func_id) = module.types.type_links[function.temporaries[func.0].0] else {
-            return Err(Error::NotCallable);
-        };
+    fn is_valid_type(&self, ty: Type) -> bool {
+        match ty {
+            Type::Func(id) => id.0 < self.functions.len(),
+            Type::FnPtr(id) => self.types.signatures.get(id.0).is_some_and(|sig| {
+                self.is_valid_type(sig.returns)
+                    && sig.params.iter().all(|param| self.is_valid_type(*param))
+            }),
+            Type::Tuple(id) => self
+                .types
+                .tuples
+                .get(id.0)
+                .is_some_and(|elems| elems.iter().all(|elem| self.is_valid_type(*elem))),
+            Type::Slice(id) => self
+                .types
+                .slices
+                .get(id.0)
+                .is_some_and(|elem| self.is_valid_type(*elem)),
+            Type::Ref(id) => self
+                .types
+                .refs
+                .get(id.0)
+                .is_some_and(|elem| self.is_valid_type(*elem)),
+            Type::Opaque(id) => self
+                .types
+                .opaques
+                .get(id.0)
+                .is_some_and(|(_, ty)| self.is_valid_type(*ty)),
+            _ => true,
+        }
+    }
 
-        let args = self
-            .args()
-            .map(|expr| expr.process(module, function))
-            .collect::<Result<Box<[_]>>>()?;
+    fn verify_function(&self, func_id: FuncId, func: &Function, errors: &mut Vec<VerifyError>) {
+        let declared: HashSet<VarId> = func
+            .statements()
+            .filter_map(|stmt| match stmt {
+                Statement::Let { dst, .. } => Some(*dst),
+                _ => None,
+            })
+            .collect();
+
+        let mut successors = vec![Vec::new(); func.blocks.len()];
+        for (block_id, block) in func.blocks() {
+            if !block.stmts.last().is_some_and(Statement::is_terminal) {
+                push_error(
+                    errors,
+                    VerifyError::MissingTerminator {
+                        func: func_id,
+                        block: block_id,
+                    },
+                );
+            }
 
-        let func_ref = &module.functions[func_id.0];
-        let dst = function.new_tmpid(func_ref.returns);
+            for stmt in block.stmts.iter() {
+                let targets = match stmt {
+                    Statement::UnconditionalJump { id } => vec![*id],
+                    Statement::ConditionalJump {
+                        then_block,
+                        else_block,
+                        ..
+                    } => vec![*then_block, *else_block],
+                    Statement::SwitchInt { default, cases, .. } => iter::once(*default)
+                        .chain(cases.iter().map(|(_, block)| *block))
+                        .collect(),
+                    _ => vec![],
+                };
+                for target in targets {
+                    if target.0 < func.blocks.len() {
+                        successors[block_id.0].push(target);
+                    } else {
+                        push_error(
+                            errors,
+                            VerifyError::MissingBlock {
+                                func: func_id,
+                                block: target,
+                            },
+                        );
+                    }
+                }
 
-        for ((arg, span), param_lty) in args
-            .iter()
-            .zip(self.args().map(|e| e.span()))
-            .zip(func_ref.params.iter().copied())
-        {
-            let arg_lty = function.temporaries[arg.0];
-            let arg_ty = module.types.type_links[arg_lty.0];
-            let param_ty = module.types.type_links[param_lty.0];
+                self.verify_statement(func_id, func, stmt, &declared, errors);
+            }
+        }
 
-            if arg_ty != param_ty {
-                let got = format!("{arg_ty:?}");
-                let expected = [format!("{param_ty:?}")].into();
-                return Err(Error::UnexpectedType {
-                    span,
-                    err: Unexpected::new("type", got, expected, false),
-                });
+        // tmps defined on every path into each block, `None` is every tmp until a path reaches it
+        let mut defined_in: Vec<Option<HashSet<TmpId>>> = vec![None; func.blocks.len()];
+        if func.blocks.is_empty() {
+            return;
+        }
+        defined_in[0] = Some(HashSet::new());
+        let mut changed = true;
+        while changed {
+            changed = false;
+            for block_id in 0..func.blocks.len() {
+                let Some(mut defined) = defined_in[block_id].clone() else {
+                    continue;
+                };
+                defined.extend(
+                    func.blocks[block_id]
+                        .stmts
+                        .iter()
+                        .filter_map(Statement::defined_tmp),
+                );
+
+                for succ in successors[block_id].iter() {
+                    let next = match &defined_in[succ.0] {
+                        Some(old) => old.intersection(&defined).copied().collect(),
+                        None => defined.clone(),
+                    };
+                    if defined_in[succ.0].as_ref() != Some(&next) {
+                        defined_in[succ.0] = Some(next);
+                        changed = true;
+                    }
+                }
             }
         }
 
-        function.push_stmt(Statement::Call { dst, func, args });
+        let type_of = |ty: LinkedType| self.types.type_links.get(ty.0).copied();
+        let returns = type_of(func.returns).unwrap_or(Type::Unknown);
+        let unchecked = |ty: Type| matches!(ty, Type::Unknown | Type::Never);
 
-        Ok(dst)
-    }
-}
+        // unreachable blocks are never executed, so they can't use anything too early
+        for (block, defined) in func.blocks.iter().zip(defined_in) {
+            let Some(mut defined) = defined else {
+                continue;
+            };
+            for stmt in block.stmts.iter() {
+                let got = match stmt {
+                    Statement::Return { src } => func
+                        .temporaries
+                        .get(src.0)
+                        .and_then(|ty| type_of(*ty))
+                        .unwrap_or(Type::Unknown),
+                    Statement::ReturnVoid => Type::Void,
+                    _ => Type::Unknown,
+                };
+                if !unchecked(returns) && !unchecked(got) && got != returns {
+                    push_error(
+                        errors,
+                        VerifyError::ReturnTypeMismatch {
+                            func: func_id,
+                            expected: returns,
+                            got,
+                        },
+                    );
+                }
 
-impl Process for Stmt {
-    type Return = Option<TmpId>;
+                let mut tmps = stmt.tmps();
+                if let Some(dst) = stmt.defined_tmp() {
+                    tmps.remove(0);
+                    defined.insert(dst);
+                }
+                for tmp in tmps {
+                    if !defined.contains(&tmp) {
+                        push_error(errors, VerifyError::UndefinedTmp { func: func_id, tmp });
+                    }
+                }
+            }
+        }
+    }
 
-    fn process(
+    fn verify_statement(
         &self,
-        module: &mut Module,
-        function: &mut Function,
-    ) -> Result<<Self as Process>::Return> {
-        match self {
-            Stmt::Init(v) => {
-                v.process(module, function)?;
-                Ok(None)
+        func_id: FuncId,
+        func: &Function,
+        stmt: &Statement,
+        declared: &HashSet<VarId>,
+        errors: &mut Vec<VerifyError>,
+    ) {
+        let mut stmt_errors = Vec::new();
+
+        let (mut tmps, mut vars) = (Vec::new(), Vec::new());
+        let mut stmt = stmt.clone();
+        stmt.map_ids(
+            |tmp| {
+                tmps.push(tmp);
+                tmp
+            },
+            |var| {
+                vars.push(var);
+                var
+            },
+        );
+        for tmp in tmps {
+            if tmp.0 >= func.temporaries.len() {
+                stmt_errors.push(VerifyError::UndefinedTmp { func: func_id, tmp });
             }
-            Stmt::Set(v) => {
-                v.process(module, function)?;
-                Ok(None)
+        }
+        for var in vars {
+            if !declared.contains(&var) || var.0 >= func.variables.len() {
+                stmt_errors.push(VerifyError::UndeclaredVar { func: func_id, var });
             }
-            Stmt::Cond(v) => {
-                v.process(module, function)?;
-                Ok(None)
+        }
+
+        match &stmt {
+            Statement::StoreGlobal { dst: global, .. }
+            | Statement::LoadGlobal { src: global, .. }
+            | Statement::RefGlobal { src: global, .. }
+                if global.0 >= self.globals.len() =>
+            {
+                stmt_errors.push(VerifyError::MissingGlobal {
+                    func: func_id,
+                    global: *global,
+                });
             }
-            Stmt::Loop(v) => {
-                v.process(module, function)?;
-                Ok(None)
+            Statement::Extern { src: callee, .. } | Statement::Func { src: callee, .. }
+                if callee.0 >= self.functions.len() || self.is_removed(*callee) =>
+            {
+                stmt_errors.push(VerifyError::MissingFunction {
+                    func: func_id,
+                    callee: *callee,
+                });
             }
-            Stmt::Expr(v) => Ok(Some(v.expr.process(module, function)?)),
-            Stmt::Return(v) => {
-                v.process(module, function)?;
-                Ok(None)
+            Statement::Call {
+                func: callee, args, ..
+            } => {
+                let expected = func
+                    .temporaries
+                    .get(callee.0)
+                    .and_then(|ty| self.types.type_links.get(ty.0))
+                    .and_then(|ty| match ty {
+                        Type::Func(id) => self.functions.get(id.0).map(|f| f.params.len()),
+                        Type::FnPtr(id) => self.types.signatures.get(id.0).map(|s| s.params.len()),
+                        _ => None,
+                    });
+                if let Some(expected) = expected.filter(|expected| *expected != args.len()) {
+                    stmt_errors.push(VerifyError::ArityMismatch {
+                        func: func_id,
+                        expected,
+                        got: args.len(),
+                    });
+                }
             }
+            _ => {}
         }
-    }
-}
-
-impl Process for Set {
-    type Return = ();
 
-    fn process(&self, module: &mut Module, function: &mut Function) -> Result<Self::Return> {
-        let mut expr_results = Vec::with_capacity(self.exprs.iter().len());
-        for expr in self.exprs.iter() {
-            expr_results.push(expr.process(module, function)?);
+        for error in stmt_errors {
+            push_error(errors, error);
         }
+    }
 
-        for (target, src) in self.targets.iter().zip(expr_results) {
-            let dst = *function
-                .variables_raw
-                .get(target.path.ident.value.as_str())
-                .ok_or_else(|| Error::VariableNotFound(target.path.ident.value.clone()))?;
+    /// type checks a single expression as if it was on the top level,
+    /// without adding anything to this module
+    pub fn type_of_expr(&self, ast: &Expr) -> Result<Type> {
+        Ok(self.check_expr(ast)?.1)
+    }
+
+    /// same as [`Self::type_of_expr`] + [`Self::type_name`],
+    /// but function types can refer to functions that `ast` itself creates
+    pub fn type_name_of_expr(&self, ast: &Expr) -> Result<String> {
+        let (module, ty) = self.check_expr(ast)?;
+        Ok(module.type_name(ty))
+    }
 
-            // let src_ty_link = function.tmp(src);
-            // let dst_ty_link = function.var(dst);
-            // let src_ty = module.get_type(src_ty_link);
-            // let dst_ty = module.get_type(dst_ty_link);
+    fn check_expr(&self, ast: &Expr) -> Result<(Module, Type)> {
+        let mut module = self.clone();
+        let mut func = Function::new(module.types.create_known(Type::Void), [].into());
 
-            // match (src_ty, dst_ty) {
-            //     (Type::Unknown, Type::Unknown) => {}
-            //     // (Type::Unknown, _) => {
-            //     //     module.types.type_links[];
-            //     // }
-            //     _ => {}
-            // }
+        let tmp = ast.process(&mut module, &mut func)?;
+        let ty = *module.get_type(func.tmp(tmp));
+        Ok((module, ty))
+    }
 
-            function.push_stmt(Statement::Store { dst, src });
+    /// formats the type like it would be written in the source code
+    pub fn type_name(&self, ty: Type) -> String {
+        match ty {
+            Type::Func(func) => {
+                let func = self.get_function(func);
+                let params = func
+                    .params
+                    .iter()
+                    .map(|param| self.type_name(*self.get_type(*param)))
+                    .collect::<Vec<_>>()
+                    .join(", ");
+                let returns = self.type_name(*self.get_type(func.returns));
+                format!("fn({params}) -> {returns}")
+            }
+            Type::FnPtr(sig) => {
+                let sig = self.get_signature(sig);
+                let params = sig
+                    .params
+                    .iter()
+                    .map(|param| self.type_name(*param))
+                    .collect::<Vec<_>>()
+                    .join(", ");
+                let returns = self.type_name(sig.returns);
+                format!("fn({params}) -> {returns}")
+            }
+            Type::Tuple(tuple) => {
+                let elems = self
+                    .get_tuple(tuple)
+                    .iter()
+                    .map(|elem| self.type_name(*elem))
+                    .collect::<Vec<_>>()
+                    .join(", ");
+                format!("({elems})")
+            }
+            Type::Slice(slice) => format!("[{}]", self.type_name(self.get_slice(slice))),
+            Type::Ref(ref_) => format!("&{}", self.type_name(self.get_ref(ref_))),
+            Type::Opaque(opaque) => self.get_opaque(opaque).0.to_string(),
+            Type::Bool => "bool".to_string(),
+            Type::I32 => "i32".to_string(),
+            Type::I64 => "i64".to_string(),
+            Type::USize => "usize".to_string(),
+            Type::Str => "Str".to_string(),
+            Type::Never => "!".to_string(),
+            Type::Void => "void".to_string(),
+            Type::Unknown => "unknown".to_string(),
         }
-
-        Ok(())
     }
-}
 
-impl Process for Cond {
-    type Return = ();
+    pub fn dump(&self) {
+        print!("{self}");
+    }
 
-    fn process(&self, module: &mut Module, function: &mut Function) -> Result<Self::Return> {
-        // if a {} else if b {} else if c {} else {}
-        // becomes
-        // if a {} else { if b {} else { if c {} else {} } }
+    /// every function with its blocks and statements as text, see [`Self::fmt_ir`]
+    pub fn dump_to_string(&self) -> String {
+        self.to_string()
+    }
 
-        let continue_block = function.push_block();
+    /// everything other modules need to be compiled against this one,
+    /// without the function bodies
+    pub fn interface(&self) -> ModuleInterface {
+        let functions = self
+            .functions
+            .iter()
+            .map(|func| FunctionSignature {
+                name: func.name.clone(),
+                returns: func.returns,
+                params: func.params.clone(),
+                param_names: func.param_names.clone(),
+                param_defaults: func.param_defaults.clone(),
+                is_variadic: func.is_variadic,
+            })
+            .collect();
+
+        ModuleInterface {
+            types: self.types.clone(),
+            functions,
+            globals: self.globals.clone().into(),
+            global_inits: self
+                .global_inits
+                .clone()
+                .unwrap_or_default()
+                .into_iter()
+                .collect(),
+            statics: self.statics.clone().unwrap_or_default(),
+            externs: self.externs.clone().unwrap_or_default(),
+            aliases: self.aliases.clone().unwrap_or_default(),
+            consts: self.consts.clone().unwrap_or_default(),
+            methods: self
+                .methods
+                .iter()
+                .flatten()
+                .flat_map(|(ty, methods)| {
+                    methods
+                        .iter()
+                        .map(|(name, func_id)| (*ty, name.clone(), *func_id))
+                })
+                .collect(),
+            removed: self
+                .removed
+                .clone()
+                .unwrap_or_default()
+                .into_iter()
+                .collect(),
+        }
+    }
 
-        // if/else if chain
-        for i in [&self.if_first]
-            .into_iter()
-            .chain(self.else_ifs.iter().map(|s| &s.inner))
-        {
-            let then_block = function.push_block();
-            let else_block = function.push_block();
+    /// prints the IR in a form similar to LLVM IR,
+    /// `%n` are temporaries, `$n` are variables and `@n` are globals
+    pub fn fmt_ir(&self, f: &mut impl fmt::Write) -> fmt::Result {
+        for (i, func) in self.functions.iter().enumerate() {
+            if self.is_removed(FuncId(i)) {
+                continue;
+            }
 
-            // TODO: check the type and narrow down the LinkedType
-            let bool = i.check.process(module, function)?;
+            if i != 0 {
+                writeln!(f)?;
+            }
 
-            function.push_stmt(Statement::ConditionalJump {
-                bool,
-                then_block,
-                else_block,
-            });
+            if func.is_extern {
+                write!(f, "extern ")?;
+            }
+            match func.name.as_deref() {
+                Some(name) => write!(f, "fn {name}(")?,
+                None => write!(f, "fn #{i}(")?,
+            }
+            for (i, param) in func.params.iter().enumerate() {
+                if i != 0 {
+                    write!(f, ", ")?;
+                }
+                if let Some(name) = func.param_names.get(i) {
+                    write!(f, "{name}: ")?;
+                }
+                write!(f, "{}", self.type_name(*self.get_type(*param)))?;
+            }
+            if func.is_variadic {
+                write!(f, ", ...")?;
+            }
+            write!(f, ") -> {}", self.type_name(*self.get_type(func.returns)))?;
 
-            // if true block
-            function.move_to_block(then_block);
-            i.block.process(module, function)?;
-            function.terminate_with(Statement::UnconditionalJump { id: continue_block });
+            if func.is_extern {
+                writeln!(f, ";")?;
+                continue;
+            }
 
-            // if false block
-            function.move_to_block(else_block);
+            writeln!(f, " {{")?;
+            for (block_id, block) in func.blocks() {
+                writeln!(f, "block{}:", block_id.0)?;
+                for stmt in block.stmts.iter() {
+                    write!(f, "    ")?;
+                    self.fmt_stmt(f, func, stmt)?;
+                    writeln!(f, ";")?;
+                }
+            }
+            writeln!(f, "}}")?;
         }
 
-        // final else block
-        if let Some(else_last) = self.else_last.as_ref() {
-            else_last.block.process(module, function)?;
+        Ok(())
+    }
+
+    fn fmt_dst(&self, f: &mut impl fmt::Write, func: &Function, dst: TmpId) -> fmt::Result {
+        let ty = self.type_name(*self.get_type(func.tmp(dst)));
+        write!(f, "%{}: {ty} = ", dst.0)
+    }
+
+    fn fmt_stmt(&self, f: &mut impl fmt::Write, func: &Function, stmt: &Statement) -> fmt::Result {
+        match stmt {
+            Statement::Let { dst, src } => {
+                let ty = self.type_name(*self.get_type(func.variables[dst.0]));
+                write!(f, "let ${}: {ty} = %{}", dst.0, src.0)
+            }
+            Statement::Store { dst, src } => write!(f, "${} = %{}", dst.0, src.0),
+            Statement::Load { dst, src } => {
+                self.fmt_dst(f, func, *dst)?;
+                write!(f, "${}", src.0)
+            }
+            Statement::Param { dst, index } => {
+                self.fmt_dst(f, func, *dst)?;
+                write!(f, "param {index}")
+            }
+            Statement::StoreGlobal { dst, src } => write!(f, "@{} = %{}", dst.0, src.0),
+            Statement::LoadGlobal { dst, src } => {
+                self.fmt_dst(f, func, *dst)?;
+                write!(f, "@{}", src.0)
+            }
+            Statement::Ref { dst, src } => {
+                self.fmt_dst(f, func, *dst)?;
+                write!(f, "&${}", src.0)
+            }
+            Statement::RefGlobal { dst, src } => {
+                self.fmt_dst(f, func, *dst)?;
+                write!(f, "&@{}", src.0)
+            }
+            Statement::Extern { dst, src: _, name } => {
+                self.fmt_dst(f, func, *dst)?;
+                write!(f, "extern {name}")
+            }
+            Statement::Func { dst, src } => {
+                self.fmt_dst(f, func, *dst)?;
+                match self.functions[src.0].name.as_deref() {
+                    Some(name) => write!(f, "fn {name}"),
+                    None => write!(f, "fn #{}", src.0),
+                }
+            }
+            Statement::Const { dst, src } => {
+                self.fmt_dst(f, func, *dst)?;
+                write!(f, "const ")?;
+                fmt_literal(f, src)
+            }
+            Statement::PoisonUndef { dst } => {
+                self.fmt_dst(f, func, *dst)?;
+                write!(f, "undef")
+            }
+            Statement::BinExpr { dst, lhs, op, rhs } => {
+                self.fmt_dst(f, func, *dst)?;
+                write!(f, "%{} {op} %{}", lhs.0, rhs.0)
+            }
+            Statement::Select {
+                dst,
+                cond,
+                if_true,
+                if_false,
+            } => {
+                self.fmt_dst(f, func, *dst)?;
+                write!(f, "select %{}, %{}, %{}", cond.0, if_true.0, if_false.0)
+            }
+            Statement::Tuple { dst, elems } => {
+                self.fmt_dst(f, func, *dst)?;
+                write!(f, "(")?;
+                for (i, elem) in elems.iter().enumerate() {
+                    if i != 0 {
+                        write!(f, ", ")?;
+                    }
+                    write!(f, "%{}", elem.0)?;
+                }
+                write!(f, ")")
+            }
+            Statement::Field { dst, src, index } => {
+                self.fmt_dst(f, func, *dst)?;
+                write!(f, "%{}.{index}", src.0)
+            }
+            Statement::Cast { dst, src } => {
+                self.fmt_dst(f, func, *dst)?;
+                let ty = self.type_name(*self.get_type(func.tmp(*dst)));
+                write!(f, "%{} as {ty}", src.0)
+            }
+            Statement::Index { dst, src, index } => {
+                self.fmt_dst(f, func, *dst)?;
+                write!(f, "%{}[%{}]", src.0, index.0)
+            }
+            Statement::LoadRef { dst, src } => {
+                self.fmt_dst(f, func, *dst)?;
+                write!(f, "*%{}", src.0)
+            }
+            Statement::StoreRef { dst, src } => write!(f, "*%{} = %{}", dst.0, src.0),
+            Statement::AtomicLoad { dst, ptr, ordering } => {
+                self.fmt_dst(f, func, *dst)?;
+                write!(f, "atomic load {ordering:?} %{}", ptr.0)
+            }
+            Statement::AtomicStore { ptr, src, ordering } => {
+                write!(f, "atomic store {ordering:?} %{}, %{}", ptr.0, src.0)
+            }
+            Statement::AtomicRmw {
+                dst,
+                ptr,
+                op,
+                val,
+                ordering,
+            } => {
+                self.fmt_dst(f, func, *dst)?;
+                write!(f, "atomic {op:?} {ordering:?} %{}, %{}", ptr.0, val.0)
+            }
+            Statement::CompareExchange {
+                dst,
+                ptr,
+                expected,
+                desired,
+                success_order,
+                fail_order,
+            } => {
+                self.fmt_dst(f, func, *dst)?;
+                write!(
+                    f,
+                    "cmpxchg {success_order:?} {fail_order:?} %{}, %{}, %{}",
+                    ptr.0, expected.0, desired.0
+                )
+            }
+            Statement::Fence { ordering } => write!(f, "fence {ordering:?}"),
+            Statement::Call {
+                dst,
+                func: callee,
+                args,
+            } => {
+                self.fmt_dst(f, func, *dst)?;
+                write!(f, "call %{}(", callee.0)?;
+                for (i, arg) in args.iter().enumerate() {
+                    if i != 0 {
+                        write!(f, ", ")?;
+                    }
+                    write!(f, "%{}", arg.0)?;
+                }
+                write!(f, ")")
+            }
+            Statement::Return { src } => write!(f, "ret %{}", src.0),
+            Statement::Nop => write!(f, "nop"),
+            Statement::ReturnVoid => write!(f, "ret void"),
+            Statement::Unreachable => write!(f, "unreachable"),
+            Statement::Trap => write!(f, "trap"),
+            Statement::UnconditionalJump { id } => write!(f, "jump block{}", id.0),
+            Statement::ConditionalJump {
+                bool,
+                then_block,
+                else_block,
+            } => write!(
+                f,
+                "br %{}, block{}, block{}",
+                bool.0, then_block.0, else_block.0
+            ),
+            Statement::SwitchInt {
+                val,
+                default,
+                cases,
+            } => {
+                write!(f, "switch %{} [", val.0)?;
+                for (i, (case, block)) in cases.iter().enumerate() {
+                    if i != 0 {
+                        write!(f, ", ")?;
+                    }
+                    write!(f, "{case} => block{}", block.0)?;
+                }
+                write!(f, "] else block{}", default.0)
+            }
         }
-        function.terminate_with(Statement::UnconditionalJump { id: continue_block });
+    }
 
-        // continue block
-        function.move_to_block(continue_block);
+    fn insert_static(&mut self, name: Rc<str>, item: Static) {
+        self.statics
+            .get_or_insert_with(Default::default)
+            .insert(name, item);
+    }
+}
 
-        Ok(())
+impl Default for Module {
+    fn default() -> Self {
+        Self::new()
     }
 }
 
-impl Process for Loop {
-    type Return = ();
+impl From<Module> for ModuleInterface {
+    fn from(module: Module) -> Self {
+        module.interface()
+    }
+}
 
-    fn process(&self, module: &mut Module, function: &mut Function) -> Result<Self::Return> {
-        let id = function.push_block();
-        function.push_stmt(Statement::UnconditionalJump { id });
-        function.move_to_block(id);
+impl From<ModuleInterface> for Module {
+    fn from(interface: ModuleInterface) -> Self {
+        let mut types = interface.types;
+        types.reindex();
 
-        for stmt in self.block.stmts.iter() {
-            stmt.process(module, function)?;
+        let functions = interface
+            .functions
+            .into_vec()
+            .into_iter()
+            .map(|sig| {
+                let mut func = Function::new_extern(sig.returns, sig.params, true);
+                func.name = sig.name;
+                func.param_names = sig.param_names;
+                func.param_defaults = sig.param_defaults;
+                func.is_variadic = sig.is_variadic;
+                func
+            })
+            .collect();
+
+        let mut methods: HashMap<Type, HashMap<Rc<str>, FuncId>> = HashMap::new();
+        for (ty, name, func_id) in interface.methods.into_vec() {
+            methods.entry(ty).or_default().insert(name, func_id);
         }
 
-        function.push_stmt(Statement::UnconditionalJump { id });
-
-        Ok(())
+        Self {
+            types,
+            functions,
+            globals: interface.globals.into_vec(),
+            global_inits: non_empty(interface.global_inits.into_vec().into_iter().collect()),
+            statics: non_empty(interface.statics),
+            externs: non_empty(interface.externs),
+            aliases: non_empty(interface.aliases),
+            consts: non_empty(interface.consts),
+            methods: non_empty(methods),
+            removed: non_empty(interface.removed.into_vec().into_iter().collect()),
+            ..Self::new()
+        }
     }
 }
 
-impl Process for Return {
-    type Return = ();
-
-    fn process(&self, module: &mut Module, function: &mut Function) -> Result<Self::Return> {
-        if let Some(expr) = self.expr.as_ref() {
-            let src = expr.process(module, function)?;
-            function.push_stmt(Statement::Return { src });
-        } else {
-            function.push_stmt(Statement::ReturnVoid);
-        };
+fn non_empty<C: IntoIterator + Default + PartialEq>(items: C) -> Option<C> {
+    (items != C::default()).then_some(items)
+}
 
-        Ok(())
+impl fmt::Display for Module {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        self.fmt_ir(f)
     }
 }
 
-//
+fn fmt_literal(f: &mut impl fmt::Write, lit: &Literal) -> fmt::Result {
+    match lit {
+        Literal::Bool(v) => write!(f, "{v}"),
+        Literal::I32(v) => write!(f, "{v}"),
+        Literal::I64(v) => write!(f, "{v}i64"),
+        Literal::USize(v) => write!(f, "{v}usize"),
+        Literal::Str(v) => write!(f, "{v:?}"),
+        Literal::ByteStr(v) => write!(f, "b\"{}\"", v.escape_ascii()),
+        Literal::Slice(_, elems) => {
+            write!(f, "[")?;
+            for (i, elem) in elems.iter().enumerate() {
+                if i != 0 {
+                    write!(f, ", ")?;
+                }
+                fmt_literal(f, elem)?;
+            }
+            write!(f, "]")
+        }
+    }
+}
 
-fn type_hint(v: Option<&str>) -> Result<Option<Type>> {
-    Ok(match v {
-        Some("i32") => Some(Type::I32),
-        Some("void") => Some(Type::Void),
-        Some(_) => return Err(Error::InvalidType),
-        None => Some(Type::Void),
-    })
+/// what [`Module::process`] needs to undo a failed call, see [`Module::snapshot`]
+pub struct Snapshot {
+    types: TypesLen,
+    functions: usize,
+    globals: usize,
+    imports: usize,
+    warnings: usize,
+    global_inits: Option<HashMap<GlobalId, Literal>>,
+    statics: Option<HashMap<Rc<str>, Static>>,
+    externs: Option<HashMap<Rc<str>, FuncId>>,
+    aliases: Option<HashMap<Rc<str>, Type>>,
+    consts: Option<HashMap<Rc<str>, Literal>>,
+    methods: Option<HashMap<Type, HashMap<Rc<str>, FuncId>>>,
 }
 
 //
 
-#[derive(Debug)]
-pub struct Block {
-    pub stmts: Vec<Statement>,
+/// the serialized form of a [`Module`], its types, function signatures and module level names,
+/// the function bodies are linked in from the already compiled module
+///
+/// the maps with non-string keys are stored as lists of entries
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ModuleInterface {
+    types: Types,
+    /// every function, indexed by [`FuncId`]
+    functions: Box<[FunctionSignature]>,
+    globals: Box<[LinkedType]>,
+    global_inits: Box<[(GlobalId, Literal)]>,
+    statics: HashMap<Rc<str>, Static>,
+    externs: HashMap<Rc<str>, FuncId>,
+    aliases: HashMap<Rc<str>, Type>,
+    consts: HashMap<Rc<str>, Literal>,
+    /// `(self type, method name, method)`
+    methods: Box<[(Type, Rc<str>, FuncId)]>,
+    removed: Box<[FuncId]>,
 }
 
-//
-
-#[derive(Debug)]
-pub struct Function {
+/// the part of a [`Function`] that callers need
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct FunctionSignature {
+    pub name: Option<Rc<str>>,
     pub returns: LinkedType,
     pub params: Box<[LinkedType]>,
-    pub blocks: Vec<Block>,
-    pub is_extern: bool,
+    pub param_names: Box<[Rc<str>]>,
+    pub param_defaults: Box<[Option<Literal>]>,
+    pub is_variadic: bool,
+}
 
-    pub current_block: BlockId,
+//
 
-    pub variables: Vec<LinkedType>,
-    variables_raw: HashMap<Rc<str>, VarId>,
-    pub temporaries: Vec<LinkedType>,
+trait Process {
+    type Return;
+
+    fn process(&self, module: &mut Module, function: &mut Function) -> Result<Self::Return>;
 }
 
-impl Function {
-    pub fn new(returns: LinkedType, params: Box<[LinkedType]>) -> Self {
-        Self::new_extern(returns, params, false)
-    }
+impl Process for Root {
+    type Return = ();
 
-    pub fn new_extern(returns: LinkedType, params: Box<[LinkedType]>, is_extern: bool) -> Self {
-        Self {
-            returns,
-            params,
-            blocks: Vec::new(),
-            is_extern,
+    fn process(&self, module: &mut Module, function: &mut Function) -> Result<Self::Return> {
+        // type names are needed for the function signatures
+        for item in self.items.iter() {
+            if let RootItem::TypeAlias(alias) = item {
+                declare_alias(module, alias)?;
+            }
+        }
 
-            current_block: BlockId(0),
+        for item in self.items.iter() {
+            if let RootItem::ConstDecl(decl) = item {
+                declare_const(module, decl)?;
+            }
+        }
 
-            variables: Vec::new(),
-            variables_raw: HashMap::new(),
-            temporaries: Vec::new(),
+        for item in self.items.iter() {
+            if let RootItem::StaticDecl(decl) = item {
+                declare_static(module, function, decl)?;
+            }
         }
-    }
 
-    pub fn current(&self) -> Option<&Statement> {
-        self.blocks
-            .get(self.current_block.0)
-            .and_then(|b| b.stmts.last())
-    }
+        // declare all externs and top level functions first,
+        // so that they can call themselves and each other in any order
+        for item in self.items.iter() {
+            let init = match item {
+                RootItem::Extern(ext) => {
+                    declare_extern(module, ext)?;
+                    continue;
+                }
+                RootItem::Impl(impl_) => {
+                    declare_methods(module, function, impl_)?;
+                    continue;
+                }
+                // the used files are added before this one, see `codegen::ModuleGen::add`
+                RootItem::TypeAlias(_)
+                | RootItem::ConstDecl(_)
+                | RootItem::StaticDecl(_)
+                | RootItem::Use(_)
+                | RootItem::UsePath(_)
+                | RootItem::Mod(_) => continue,
+                RootItem::Stmt(stmt) => match &**stmt {
+                    Stmt::Init(init) => init,
+                    _ => continue,
+                },
+            };
+            if init.targets.iter().len() != init.exprs.iter().len() {
+                continue;
+            }
 
-    pub fn push_stmt(&mut self, stmt: Statement) {
-        if self.blocks.is_empty() {
-            self.push_block();
+            for (target, expr) in init.targets.iter().zip(init.exprs.iter()) {
+                let AnyExpr::Func(func) = &expr.expr else {
+                    continue;
+                };
+
+                let name = module.qualified(target.path.ident.value.as_str());
+                let func_id = FuncId(module.functions.len());
+                let mut proto = prototype(module, func)?;
+                proto.name = Some(name.clone());
+                module.functions.push(proto);
+                module.insert_static(name, Static::Func(func_id));
+                function
+                    .predeclared
+                    .push((func.proto.fn_kw.span(), func_id));
+            }
         }
 
-        self.blocks[self.current_block.0].stmts.push(stmt);
-    }
+        // the `mod` blocks go first, so that the other items can use them in any order
+        for item in self.items.iter() {
+            if let RootItem::Mod(decl) = item {
+                let outer = module.namespace.len();
+                module.namespace.push_str(decl.name.value.as_str());
+                module.namespace.push_str("::");
+                let res = decl.root.process(module, function);
+                module.namespace.truncate(outer);
+                res?;
+            }
+        }
 
-    pub fn push_block(&mut self) -> BlockId {
-        let id = BlockId(self.blocks.len());
-        self.blocks.push(Block { stmts: Vec::new() });
-        id
-    }
+        for item in self.items.iter() {
+            if let RootItem::UsePath(use_) = item {
+                import(module, use_)?;
+            }
+        }
 
-    pub fn move_to_block(&mut self, id: BlockId) {
-        self.current_block = id;
+        for item in self.items.iter() {
+            match item {
+                RootItem::Stmt(stmt) => {
+                    stmt.process(module, function)?;
+                }
+                RootItem::Impl(impl_) => {
+                    for method in impl_.methods.iter() {
+                        method.func.process(module, function)?;
+                    }
+                }
+                _ => {}
+            }
+        }
+        Ok(())
     }
+}
 
-    pub fn terminate(&mut self) {
-        self.terminate_with(Statement::ReturnVoid);
+/// `use math::add;` adds `add` as another name of `math::add` in the current `mod`
+fn import(module: &mut Module, use_: &UsePath) -> Result<()> {
+    let path = use_.path.mangled();
+    let item = module
+        .resolve_static(&path)
+        .ok_or(Error::VariableNotFound(path))?;
+
+    let last = use_.path.segments().last().unwrap();
+    let name = module.qualified(last.value.as_str());
+    match module.get_static(&name) {
+        // importing the same item again changes nothing
+        Some(old) if old == item => Ok(()),
+        Some(_) => Err(Error::AmbiguousImport {
+            span: use_.path.span(),
+            name: name.to_string(),
+        }),
+        None => {
+            module.insert_static(name, item);
+            Ok(())
+        }
     }
+}
 
-    pub fn terminate_with(&mut self, terminal_stmt: Statement) {
-        // println!("current: {:?}", self.current());
-        if self.current().map_or(false, |stmt| stmt.is_terminal()) {
-            return;
+/// adds the prototypes of the methods in `impl Type { .. }` to the method table of the type,
+/// the first parameter of each method has to be the type itself or a reference to it
+fn declare_methods(module: &mut Module, function: &mut Function, impl_: &Impl) -> Result<()> {
+    let ty = type_hint(module, Some(&ast::Ty::Name(impl_.ty.clone())))?.unwrap();
+
+    for method in impl_.methods.iter() {
+        let func = &method.func;
+        let mut proto = prototype(module, func)?;
+
+        let receiver = proto.params.first().map(|param| *module.get_type(*param));
+        let by_ref = matches!(receiver, Some(Type::Ref(elem)) if module.get_ref(elem) == ty);
+        if receiver != Some(ty) && !by_ref {
+            let (got, span) = match (receiver, func.proto.args().next()) {
+                (Some(got), Some(arg)) => (format!("{got:?}"), arg.ty.span()),
+                _ => ("no receiver".to_string(), func.proto.args_end.span()),
+            };
+            return Err(Error::UnexpectedType {
+                span,
+                err: Unexpected::new("type", got, [format!("{ty:?}")].into(), false),
+            });
         }
 
-        self.push_stmt(terminal_stmt);
+        let name: Rc<str> = method.name.value.as_str().into();
+        let func_id = FuncId(module.functions.len());
+        proto.name = Some(format!("{}::{name}", impl_.ty.value).into());
+        module.functions.push(proto);
+        module
+            .methods
+            .get_or_insert_with(Default::default)
+            .entry(ty)
+            .or_default()
+            .insert(name, func_id);
+        function
+            .predeclared
+            .push((func.proto.fn_kw.span(), func_id));
     }
 
-    pub fn blocks(&self) -> impl DoubleEndedIterator<Item = (BlockId, &Block)> + ExactSizeIterator {
-        self.blocks.iter().enumerate().map(|(i, b)| (BlockId(i), b))
-    }
+    Ok(())
+}
 
-    pub fn var(&self, var: VarId) -> LinkedType {
-        self.variables[var.0]
-    }
+impl Process for Init {
+    type Return = ();
 
-    pub fn tmp(&self, tmp: TmpId) -> LinkedType {
-        self.temporaries[tmp.0]
-    }
+    fn process(&self, module: &mut Module, function: &mut Function) -> Result<Self::Return> {
+        // `x: i64 := 5` guides the literal inference and checks the value
+        let annotation = match &self.ty {
+            Some((_, ty)) => type_hint(module, Some(ty))?,
+            None => None,
+        };
 
-    pub fn new_varid(&mut self, ty: LinkedType) -> VarId {
-        let id = VarId(self.variables.len());
-        self.variables.push(ty);
-        id
+        let mut expr_results = Vec::with_capacity(self.exprs.iter().len());
+        for expr in self.exprs.iter() {
+            let hint = annotation.unwrap_or(Type::Unknown);
+            expr_results.push(process_hinted(module, function, expr, hint)?);
+        }
+        let expr_results = destructure(module, function, self.targets.iter().len(), expr_results)?;
+
+        for (target, src) in self.targets.iter().zip(expr_results) {
+            let name: Rc<str> = target.path.ident.value.as_str().into();
+            let ty = function.temporaries[src.0];
+
+            if let Some(expected) = annotation {
+                let got = *module.get_type(ty);
+                if !module.coerces_to(got, expected) {
+                    return Err(Error::UnexpectedType {
+                        span: self.exprs.first.span(),
+                        err: Unexpected::new(
+                            "type",
+                            format!("{got:?}"),
+                            [format!("{expected:?}")].into(),
+                            false,
+                        ),
+                    });
+                }
+            }
+
+            if function.toplevel {
+                // top level variables outlive the function that initializes them,
+                // so that the later `process` calls can still use them
+                match *module.get_type(ty) {
+                    Type::Func(func_id) => {
+                        module.insert_static(module.qualified(&name), Static::Func(func_id));
+                        continue;
+                    }
+                    Type::Void | Type::Never => {}
+                    _ => {
+                        let dst = module.new_global(ty);
+                        module.insert_static(module.qualified(&name), Static::Global(dst));
+                        // the global shadows any older local with the same name
+                        function.variables_raw.remove(&name);
+                        function.push_stmt(Statement::StoreGlobal { dst, src });
+                        continue;
+                    }
+                }
+            }
+
+            let dst = function.new_varid(ty);
+
+            // shadow old variables
+            function.variables_raw.insert(name, dst);
+
+            function.push_stmt(Statement::Let { dst, src });
+        }
+
+        Ok(())
     }
+}
 
-    pub fn new_tmpid(&mut self, ty: LinkedType) -> TmpId {
-        let id = TmpId(self.temporaries.len());
-        self.temporaries.push(ty);
-        id
+impl Process for Decl {
+    type Return = ();
+
+    fn process(&self, module: &mut Module, function: &mut Function) -> Result<Self::Return> {
+        let ty = type_hint(module, Some(&self.ty))?.unwrap();
+        let ty = module.types.create_known(ty);
+
+        // the variable exists right away, it just doesn't have a meaningful value
+        let src = function.new_tmpid(ty);
+        function.push_stmt(Statement::PoisonUndef { dst: src });
+        let dst = function.new_varid(ty);
+        function.push_stmt(Statement::Let { dst, src });
+
+        function
+            .variables_raw
+            .insert(self.name.value.as_str().into(), dst);
+        function.uninitialized.insert(dst);
+        Ok(())
     }
 }
 
-//
+impl Process for Test {
+    type Return = ();
 
-#[derive(Debug)]
-pub enum Statement {
-    Let {
-        dst: VarId,
-        src: TmpId,
-    },
-    Store {
-        dst: VarId,
-        src: TmpId,
-    },
-    Load {
-        dst: TmpId,
-        src: VarId,
-    },
-    Extern {
-        dst: TmpId,
+    fn process(&self, _module: &mut Module, _function: &mut Function) -> Result<Self::Return> {
+        todo!()
+    }
+}
 
-        src: FuncId,
-        name: Rc<str>,
-    },
-    Func {
-        dst: TmpId,
-        src: FuncId,
-    },
-    Const {
-        dst: TmpId,
-        src: Literal,
-    },
-    BinExpr {
-        dst: TmpId,
-        lhs: TmpId,
-        op: BinaryOp,
-        rhs: TmpId,
-    },
-    Call {
-        dst: TmpId,
-        func: TmpId,
-        args: Box<[TmpId]>,
-    },
-    Return {
-        src: TmpId,
-    },
-    ReturnVoid,
-    UnconditionalJump {
-        id: BlockId,
-    },
-    ConditionalJump {
-        bool: TmpId,
-        then_block: BlockId,
-        else_block: BlockId,
-    },
+impl Process for Expr {
+    type Return = TmpId;
+
+    fn process(&self, module: &mut Module, function: &mut Function) -> Result<Self::Return> {
+        match &self.expr {
+            AnyExpr::Block(block) => block.process(module, function),
+            AnyExpr::LitInt(int) => Ok(lit_int(module, function, int, Type::I32)),
+            AnyExpr::LitStr(str) => {
+                let dst = function.new_tmpid(module.types.create_known(Type::Str));
+
+                function.push_stmt(Statement::Const {
+                    dst,
+                    src: Literal::Str(str.value.as_str().into()),
+                });
+
+                Ok(dst)
+            }
+            AnyExpr::LitByteStr(str) => {
+                let dst = function.new_tmpid(module.types.create_known(Type::Str));
+
+                function.push_stmt(Statement::Const {
+                    dst,
+                    src: Literal::ByteStr(str.value.as_slice().into()),
+                });
+
+                Ok(dst)
+            }
+            AnyExpr::LitBool(bool) => {
+                let dst = function.new_tmpid(module.types.create_known(Type::Bool));
+
+                function.push_stmt(Statement::Const {
+                    dst,
+                    src: Literal::Bool(bool.value),
+                });
+
+                Ok(dst)
+            }
+            AnyExpr::Load(var) => {
+                if let Some(src) = function.variables_raw.get(var.value.as_str()).copied() {
+                    // warned only once, the later reads would just repeat it
+                    if function.uninitialized.remove(&src) {
+                        module
+                            .warnings
+                            .push(Warning::UninitializedVariable(var.value.clone()));
+                    }
+
+                    let dst = function.new_tmpid(function.variables[src.0]);
+                    function.push_stmt(Statement::Load { dst, src });
+                    return Ok(dst);
+                }
+
+                load_item(module, function, var.value.as_str())
+            }
+            AnyExpr::Path(path) => load_item(module, function, &path.mangled()),
+            AnyExpr::Ref(ref_) => {
+                let name = ref_.ident.value.as_str();
+                if let Some(src) = function.variables_raw.get(name).copied() {
+                    // the variable might be assigned through the reference
+                    function.uninitialized.remove(&src);
+
+                    let elem = *module.get_type(function.var(src));
+                    let ty = Type::Ref(module.types.create_ref(elem));
+                    let dst = function.new_tmpid(module.types.create_known(ty));
+                    function.push_stmt(Statement::Ref { dst, src });
+                    return Ok(dst);
+                }
+
+                match module.resolve_static(name) {
+                    Some(Static::Global(src)) => {
+                        let elem = *module.get_type(module.globals[src.0]);
+                        let ty = Type::Ref(module.types.create_ref(elem));
+                        let dst = function.new_tmpid(module.types.create_known(ty));
+                        function.push_stmt(Statement::RefGlobal { dst, src });
+                        Ok(dst)
+                    }
+                    // functions are not variables
+                    Some(Static::Func(_)) => Err(Error::InvalidType),
+                    None => Err(Error::VariableNotFound(name.to_string())),
+                }
+            }
+            AnyExpr::Deref(deref) => {
+                let src =
+                    Expr::from(AnyExpr::Load(deref.ident.clone())).process(module, function)?;
+                let elem = deref_type(module, function, src, deref.ident.span())?;
+                let dst = function.new_tmpid(module.types.create_known(elem));
+                function.push_stmt(Statement::LoadRef { dst, src });
+                Ok(dst)
+            }
+            AnyExpr::Func(func) => func.process(module, function),
+            AnyExpr::Call(call) => call.process(module, function),
+            AnyExpr::MethodCall(call) => call.process(module, function),
+            AnyExpr::Cast(cast) => cast.process(module, function),
+            AnyExpr::Index(index) => index.process(module, function),
+            AnyExpr::Array(array) => lit_array(module, function, array, Type::Unknown),
+            AnyExpr::Match(match_) => match_.process(module, function),
+            AnyExpr::If(cond) => if_expr(module, function, cond, Type::Unknown),
+            AnyExpr::Tuple(tuple) => {
+                let elems = tuple
+                    .elems
+                    .iter()
+                    .map(|expr| {
+                        let elem = expr.process(module, function)?;
+                        non_void(module, function, elem, expr.span())
+                    })
+                    .collect::<Result<Box<[_]>>>()?;
+
+                let elem_types = elems
+                    .iter()
+                    .map(|elem| *module.get_type(function.tmp(*elem)))
+                    .collect();
+                let tuple = module.types.create_tuple(elem_types);
+                let dst = function.new_tmpid(module.types.create_known(Type::Tuple(tuple)));
+
+                function.push_stmt(Statement::Tuple { dst, elems });
+
+                Ok(dst)
+            }
+            AnyExpr::Binary { op, sides } => binary(module, function, *op, sides, Type::Unknown),
+        }
+    }
 }
 
-impl Statement {
-    pub const fn is_terminal(&self) -> bool {
-        matches!(
-            self,
-            Statement::Return { .. }
-                | Statement::ReturnVoid
-                | Statement::UnconditionalJump { .. }
-                | Statement::ConditionalJump { .. }
-        )
+/// `value` unless it is the missing result of a void function, `span` is where it came from
+///
+/// operands, arguments and tuple fields need an actual value
+fn non_void(module: &Module, function: &Function, value: TmpId, span: Span) -> Result<TmpId> {
+    if *module.get_type(function.tmp(value)) == Type::Void {
+        return Err(Error::VoidValue { span });
     }
+    Ok(value)
 }
 
-//
+/// like [`Process::process`], but int literals get the type `hint` from the context,
+/// like the type of the parameter or the variable the value goes to
+fn process_hinted(
+    module: &mut Module,
+    function: &mut Function,
+    expr: &Expr,
+    hint: Type,
+) -> Result<TmpId> {
+    match &expr.expr {
+        AnyExpr::LitInt(int) => Ok(lit_int(module, function, int, hint)),
+        AnyExpr::Array(array) => lit_array(module, function, array, hint),
+        AnyExpr::Binary { op, sides } => binary(module, function, *op, sides, hint),
+        AnyExpr::If(cond) => if_expr(module, function, cond, hint),
+        _ => expr.process(module, function),
+    }
+}
 
-#[derive(Debug)]
-pub struct Types {
-    type_links: Vec<Type>,
-    known_type_links: Option<HashMap<Type, LinkedType>>,
+/// `lhs op rhs`, the `hint` goes to the operands of arithmetic ops
+fn binary(
+    module: &mut Module,
+    function: &mut Function,
+    op: BinaryOp,
+    sides: &(Expr, Expr),
+    hint: Type,
+) -> Result<TmpId> {
+    let hint = match op {
+        BinaryOp::Mul | BinaryOp::Div | BinaryOp::Rem | BinaryOp::Add | BinaryOp::Sub => hint,
+        _ => Type::Unknown,
+    };
+
+    // an int literal takes the type of the other side, so that `len(s) == 4` works
+    let (lhs, rhs) = if let AnyExpr::LitInt(int) = &sides.0.expr {
+        let rhs = process_hinted(module, function, &sides.1, hint)?;
+        let hint = *module.get_type(function.tmp(rhs));
+        (lit_int(module, function, int, hint), rhs)
+    } else {
+        let lhs = process_hinted(module, function, &sides.0, hint)?;
+        let hint = *module.get_type(function.tmp(lhs));
+        (lhs, process_hinted(module, function, &sides.1, hint)?)
+    };
+    non_void(module, function, lhs, sides.0.span())?;
+    non_void(module, function, rhs, sides.1.span())?;
+
+    let ty = binop_result_type(
+        op,
+        (*module.get_type(function.tmp(lhs)), sides.0.span()),
+        (*module.get_type(function.tmp(rhs)), sides.1.span()),
+    )?;
+    let ty = match ty {
+        Type::Bool => module.types.create_known(Type::Bool),
+        _ => function.temporaries[lhs.0],
+    };
+
+    let dst = function.new_tmpid(ty);
+    function.push_stmt(Statement::BinExpr { dst, lhs, op, rhs });
+
+    Ok(dst)
 }
 
-impl Types {
-    pub fn create(&mut self) -> LinkedType {
-        self.create_new(Type::Unknown)
+impl Process for ast::Block {
+    type Return = TmpId;
+
+    fn process(&self, module: &mut Module, function: &mut Function) -> Result<Self::Return> {
+        block_hinted(module, function, self, Type::Unknown)
     }
+}
 
-    pub fn create_known(&mut self, ty: Type) -> LinkedType {
-        if let Some(known) = self.known_type_links.as_ref().and_then(|map| map.get(&ty)) {
-            return *known;
+/// like [`Process::process`], the value of the block gets the `hint` like in [`process_hinted`]
+fn block_hinted(
+    module: &mut Module,
+    function: &mut Function,
+    block: &ast::Block,
+    hint: Type,
+) -> Result<TmpId> {
+    let mut last = None;
+
+    for (i, stmt) in block.stmts.iter().enumerate() {
+        last = match stmt {
+            Stmt::Expr(tail) if block.auto_return && i + 1 == block.stmts.len() => {
+                Some(process_hinted(module, function, &tail.expr, hint)?)
+            }
+            stmt => stmt.process(module, function)?,
+        };
+    }
+
+    if block.auto_return {
+        if let Some(last) = last {
+            return Ok(last);
         }
+    };
 
-        self.create_new(ty)
+    // the end of the block is never reached after a `return` or an endless loop
+    if function.current().is_some_and(Statement::is_terminal) {
+        return Ok(function.new_tmpid(module.types.create_known(Type::Never)));
     }
 
-    fn create_new(&mut self, ty: Type) -> LinkedType {
-        let id = LinkedType(self.type_links.len());
-        self.type_links.push(ty);
-        match self
-            .known_type_links
-            .get_or_insert_with(Default::default)
-            .entry(ty)
+    Ok(function.new_tmpid(module.types.create_known(Type::Void)))
+}
+
+/// a `const`, a top level function or global or an extern called `name`,
+/// the local variables are checked before this
+fn load_item(module: &mut Module, function: &mut Function, name: &str) -> Result<TmpId> {
+    if let Some(src) = module.get_const(name).cloned() {
+        let dst = function.new_tmpid(module.types.create_known(src.ty()));
+        function.push_stmt(Statement::Const { dst, src });
+        return Ok(dst);
+    }
+
+    match module.resolve_static(name) {
+        Some(Static::Func(src)) => {
+            let dst = function.new_tmpid(module.types.create_known(Type::Func(src)));
+            function.push_stmt(Statement::Func { dst, src });
+            return Ok(dst);
+        }
+        Some(Static::Global(src)) => {
+            let dst = function.new_tmpid(module.globals[src.0]);
+            function.push_stmt(Statement::LoadGlobal { dst, src });
+            return Ok(dst);
+        }
+        None => {}
+    }
+
+    let src = module
+        .externs
+        .as_ref()
+        .and_then(|map| map.get_key_value(name))
+        .ok_or_else(|| Error::VariableNotFound(name.to_string()))?;
+    let name = src.0.clone();
+    let src = *src.1;
+
+    let dst = function.new_tmpid(module.types.create_known(Type::Func(src)));
+    function.push_stmt(Statement::Extern { dst, name, src });
+    Ok(dst)
+}
+
+impl Process for Func {
+    type Return = TmpId;
+
+    fn process(&self, module: &mut Module, function: &mut Function) -> Result<Self::Return> {
+        let predeclared = function
+            .predeclared
+            .iter()
+            .find(|(span, _)| *span == self.proto.fn_kw.span())
+            .map(|(_, func_id)| *func_id);
+
+        let mut func = match predeclared {
+            Some(func_id) => {
+                let proto = &module.functions[func_id.0];
+                let mut func = Function::new(proto.returns, proto.params.clone());
+                func.name.clone_from(&proto.name);
+                func.param_names.clone_from(&proto.param_names);
+                func.param_defaults.clone_from(&proto.param_defaults);
+                func
+            }
+            None => prototype(module, self)?,
+        };
+
+        for (index, arg) in self.proto.args().enumerate() {
+            let ty = func.params[index];
+            let src = func.new_tmpid(ty);
+            func.push_stmt(Statement::Param { dst: src, index });
+
+            let dst = func.new_varid(ty);
+            func.variables_raw.insert(arg.id.value.as_str().into(), dst);
+            func.push_stmt(Statement::Let { dst, src });
+        }
+
+        let returns = *module.get_type(func.returns);
+        let value = block_hinted(module, &mut func, &self.block, returns)?;
+        if self.block.auto_return {
+            implicit_return(module, &mut func, &self.block, value)?;
+        }
+
+        func.terminate();
+
+        if !matches!(module.get_type(func.returns), Type::Void | Type::Never)
+            && func.may_return_void()
         {
-            Entry::Vacant(entry) => _ = entry.insert(id),
-            Entry::Occupied(_) => {}
+            return Err(Error::MissingReturn {
+                span: self.block.close.span(),
+            });
         }
-        id
+
+        warn_unused_variables(module, &func, self.proto.args().len());
+        func.propagate_consts();
+
+        let func_id = if let Some(func_id) = predeclared {
+            module.functions[func_id.0] = func;
+            func_id
+        } else {
+            let func_id = FuncId(module.functions.len());
+            module.functions.push(func);
+            func_id
+        };
+
+        let dst = function.new_tmpid(module.types.create_known(Type::Func(func_id)));
+        function.push_stmt(Statement::Func { dst, src: func_id });
+
+        Ok(dst)
     }
 }
 
-#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
-pub enum Type {
-    Func(FuncId),
-    Bool,
-    I32,
-    Str,
-    Never,
-    Void,
-    Unknown,
+impl Process for Call {
+    type Return = TmpId;
+
+    fn process(&self, module: &mut Module, function: &mut Function) -> Result<Self::Return> {
+        // `Meters(5)` makes an `opaque type Meters = i32;` from an `i32`
+        if let AnyExpr::Load(name) = &self.func.expr {
+            if let Some(Type::Opaque(opaque)) = module.get_alias(name.value.as_str()) {
+                return opaque_from(module, function, self, opaque);
+            }
+        }
+
+        let func = self.func.process(module, function)?;
+        call(
+            module,
+            function,
+            func,
+            None,
+            &self.call_args().collect::<Vec<_>>(),
+            self.span(),
+        )
+    }
 }
 
-#[derive(Debug, Clone)]
-pub enum Literal {
-    Bool(bool),
-    I32(i32),
-    Str(Box<str>),
+impl Process for MethodCall {
+    type Return = TmpId;
+
+    fn process(&self, module: &mut Module, function: &mut Function) -> Result<Self::Return> {
+        let name = self.method.value.as_str();
+
+        // `c.increment()` passes `&c` to a method that takes `self: &Counter`,
+        // so that the method can change `c`
+        let by_ref = match &self.receiver.expr {
+            AnyExpr::Load(var) => variable_type(module, function, &var.value)
+                .and_then(|ty| module.get_method(ty, name))
+                .filter(|method| module.takes_self_by_ref(*method))
+                .map(|_| var),
+            _ => None,
+        };
+        let mut receiver = match by_ref {
+            Some(var) => Expr::from(AnyExpr::Ref(Box::new(RefExpr {
+                amp: ast::token::Ampersand(var.span()),
+                ident: var.clone(),
+            })))
+            .process(module, function)?,
+            None => self.receiver.process(module, function)?,
+        };
+        let receiver_ty = *module.get_type(function.tmp(receiver));
+        let (value_ty, is_ref) = match receiver_ty {
+            Type::Ref(elem) => (module.get_ref(elem), true),
+            ty => (ty, false),
+        };
+
+        // `obj.method(arg)` is `Type::method(obj, arg)` if the type has that method,
+        // otherwise it is the free function `method(obj, arg)`
+        let func = match module.get_method(value_ty, name) {
+            Some(src) => {
+                match (module.takes_self_by_ref(src), is_ref) {
+                    // a temporary isn't in any variable, the method gets a reference to a copy
+                    (true, false) => {
+                        let var = function.new_varid(function.tmp(receiver));
+                        function.push_stmt(Statement::Let {
+                            dst: var,
+                            src: receiver,
+                        });
+                        let ty = Type::Ref(module.types.create_ref(value_ty));
+                        receiver = function.new_tmpid(module.types.create_known(ty));
+                        function.push_stmt(Statement::Ref {
+                            dst: receiver,
+                            src: var,
+                        });
+                    }
+                    (false, true) => {
+                        let dst = function.new_tmpid(module.types.create_known(value_ty));
+                        function.push_stmt(Statement::LoadRef { dst, src: receiver });
+                        receiver = dst;
+                    }
+                    _ => {}
+                }
+
+                let dst = function.new_tmpid(module.types.create_known(Type::Func(src)));
+                function.push_stmt(Statement::Func { dst, src });
+                dst
+            }
+            None => Expr::from(AnyExpr::Load(self.method.clone())).process(module, function)?,
+        };
+
+        call(
+            module,
+            function,
+            func,
+            Some((receiver, self.receiver.span())),
+            &self.call_args().collect::<Vec<_>>(),
+            self.span(),
+        )
+    }
+}
+
+impl Process for Cast {
+    type Return = TmpId;
+
+    fn process(&self, module: &mut Module, function: &mut Function) -> Result<Self::Return> {
+        let is_int = |ty| matches!(ty, Type::I32 | Type::I64 | Type::USize);
+        let unexpected = |ty: Type, span| Error::UnexpectedType {
+            span,
+            err: Unexpected::new(
+                "type",
+                format!("{ty:?}"),
+                ["I32".to_string(), "I64".to_string(), "USize".to_string()].into(),
+                false,
+            ),
+        };
+
+        let ty = type_hint(module, Some(&self.ty))?.unwrap();
+        if !is_int(ty) {
+            return Err(unexpected(ty, self.ty.span()));
+        }
+
+        // `5 as usize` is just a `usize` literal
+        let src = process_hinted(module, function, &self.value, ty)?;
+        let src_ty = *module.get_type(function.tmp(src));
+        if !is_int(src_ty) {
+            return Err(unexpected(src_ty, self.value.span()));
+        }
+        if src_ty == ty {
+            return Ok(src);
+        }
+
+        let dst = function.new_tmpid(module.types.create_known(ty));
+        function.push_stmt(Statement::Cast { dst, src });
+        Ok(dst)
+    }
+}
+
+impl Process for Index {
+    type Return = TmpId;
+
+    fn process(&self, module: &mut Module, function: &mut Function) -> Result<Self::Return> {
+        let src = self.value.process(module, function)?;
+        let ty = *module.get_type(function.tmp(src));
+        let Type::Slice(slice) = ty else {
+            return Err(Error::UnexpectedType {
+                span: self.value.span(),
+                err: Unexpected::new("type", format!("{ty:?}"), ["Slice".into()].into(), false),
+            });
+        };
+
+        let mut index = process_hinted(module, function, &self.index, Type::USize)?;
+        let mut index_ty = *module.get_type(function.tmp(index));
+        // `i32` indices are converted implicitly, the cast sign extends negative indices
+        // to huge ones, so the bounds check catches them too
+        if index_ty == Type::I32 {
+            let src = index;
+            index = function.new_tmpid(module.types.create_known(Type::USize));
+            function.push_stmt(Statement::Cast { dst: index, src });
+            index_ty = Type::USize;
+        }
+        if index_ty != Type::USize {
+            return Err(Error::UnexpectedType {
+                span: self.index.span(),
+                err: Unexpected::new(
+                    "type",
+                    format!("{index_ty:?}"),
+                    ["USize".into()].into(),
+                    false,
+                ),
+            });
+        }
+
+        let elem = module.get_slice(slice);
+        let dst = function.new_tmpid(module.types.create_known(elem));
+        function.push_stmt(Statement::Index { dst, src, index });
+        Ok(dst)
+    }
+}
+
+/// calls `func` with the arguments, `receiver` is the first argument of a method call
+fn call(
+    module: &mut Module,
+    function: &mut Function,
+    mut func: TmpId,
+    receiver: Option<(TmpId, Span)>,
+    call_args: &[&CallArg],
+    span: Span,
+) -> Result<TmpId> {
+    let func_ty = module.types.type_links[function.temporaries[func.0].0];
+    let mut sig = match func_ty {
+        Type::Func(func_id) => module.signature_of(func_id),
+        // an indirect call through a function pointer
+        Type::FnPtr(sig) => module.get_signature(sig).clone(),
+        _ => return Err(Error::NotCallable),
+    };
+    let arg_exprs = positional_args(
+        module,
+        func_ty,
+        receiver.is_some() as usize,
+        call_args,
+        span,
+    )?;
+
+    // the builtins `atomic_load`, `cmpxchg` and friends become atomic statements, instead of calls
+    if let (Type::Func(func_id), None) = (func_ty, receiver) {
+        let callee = module.get_function(func_id);
+        let builtin = callee.name.as_deref().and_then(AtomicBuiltin::from_name);
+        if let (true, Some((builtin, ordering))) = (callee.builtin, builtin) {
+            if let Some(atomic) = atomic(module, function, builtin, ordering, &arg_exprs)? {
+                return Ok(atomic);
+            }
+        }
+    }
+
+    // the builtin `print` formats the arguments after a constant format string
+    if let (Type::Func(func_id), None, [ArgValue::Expr(fmt), rest @ ..]) =
+        (func_ty, receiver, &arg_exprs[..])
+    {
+        let callee = module.get_function(func_id);
+        if callee.builtin && callee.name.as_deref() == Some("print") {
+            if let Some(print) = print_format(module, function, func, fmt, rest, span)? {
+                return Ok(print);
+            }
+        }
+    }
+
+    if let Some((receiver, span)) = receiver {
+        non_void(module, function, receiver, span)?;
+    }
+
+    let skip = receiver.is_some() as usize;
+    let args = receiver
+        .map(|(receiver, _)| Ok(receiver))
+        .into_iter()
+        .chain(arg_exprs.iter().enumerate().map(|(index, arg)| {
+            let expr = match arg {
+                ArgValue::Expr(expr) => expr,
+                ArgValue::Default(src) => {
+                    let dst = function.new_tmpid(module.types.create_known(src.ty()));
+                    function.push_stmt(Statement::Const {
+                        dst,
+                        src: src.clone(),
+                    });
+                    return Ok(dst);
+                }
+            };
+            let hint = sig
+                .params
+                .get(index + skip)
+                .copied()
+                .unwrap_or(Type::Unknown);
+            let arg = process_hinted(module, function, expr, hint)?;
+            non_void(module, function, arg, expr.span())
+        }))
+        .collect::<Result<Box<[_]>>>()?;
+    let spans = receiver
+        .map(|(_, span)| span)
+        .into_iter()
+        .chain(arg_exprs.iter().map(|arg| match arg {
+            ArgValue::Expr(expr) => expr.span(),
+            ArgValue::Default(_) => span,
+        }));
+
+    // the builtin `len` works on every slice, the length is the first field
+    if let (Type::Func(func_id), [arg]) = (func_ty, &args[..]) {
+        let callee = module.get_function(func_id);
+        if callee.builtin
+            && callee.name.as_deref() == Some("len")
+            && matches!(module.get_type(function.tmp(*arg)), Type::Slice(_))
+        {
+            let dst = function.new_tmpid(module.types.create_known(Type::USize));
+            function.push_stmt(Statement::Field {
+                dst,
+                src: *arg,
+                index: 0,
+            });
+            return Ok(dst);
+        }
+    }
+
+    // the builtin `unreachable` traps right there, instead of calling anything
+    if let (Type::Func(func_id), []) = (func_ty, &args[..]) {
+        let callee = module.get_function(func_id);
+        if callee.builtin && callee.name.as_deref() == Some("unreachable") {
+            function.push_stmt(Statement::Trap);
+            return Ok(function.new_tmpid(module.types.create_known(Type::Never)));
+        }
+    }
+
+    // the builtin `assert_eq` has a separate helper for each type it compares
+    if let (Type::Func(func_id), [lhs, _]) = (func_ty, &args[..]) {
+        let callee = module.get_function(func_id);
+        let helper = match module.get_type(function.tmp(*lhs)) {
+            Type::Bool => "assert_eq_bool",
+            Type::Str => "assert_eq_str",
+            _ => "assert_eq",
+        };
+
+        let helper = (callee.builtin && callee.name.as_deref() == Some("assert_eq"))
+            .then(|| module.get_function_by_name(helper))
+            .flatten()
+            .filter(|helper| *helper != func_id);
+
+        if let Some(src) = helper {
+            let dst = function.new_tmpid(module.types.create_known(Type::Func(src)));
+            let name = module.get_function(src).name.clone().unwrap();
+            function.push_stmt(Statement::Extern { dst, src, name });
+            func = dst;
+            sig = module.signature_of(src);
+        }
+    }
+
+    let dst = function.new_tmpid(module.types.create_known(sig.returns));
+
+    for ((arg, span), param_ty) in args.iter().zip(spans).zip(sig.params.iter().copied()) {
+        let arg_lty = function.temporaries[arg.0];
+        let arg_ty = module.types.type_links[arg_lty.0];
+
+        if !module.coerces_to(arg_ty, param_ty) {
+            let got = format!("{arg_ty:?}");
+            let expected = [format!("{param_ty:?}")].into();
+            return Err(Error::UnexpectedType {
+                span,
+                err: Unexpected::new("type", got, expected, false),
+            });
+        }
+    }
+
+    function.push_stmt(Statement::Call { dst, func, args });
+
+    // `panic("..")` and friends never return, so the block ends here
+    if sig.returns == Type::Never {
+        function.push_stmt(Statement::Unreachable);
+    }
+
+    Ok(dst)
+}
+
+impl Process for Stmt {
+    type Return = Option<TmpId>;
+
+    fn process(
+        &self,
+        module: &mut Module,
+        function: &mut Function,
+    ) -> Result<<Self as Process>::Return> {
+        match self {
+            Stmt::Init(v) => {
+                v.process(module, function)?;
+                Ok(None)
+            }
+            Stmt::Decl(v) => {
+                v.process(module, function)?;
+                Ok(None)
+            }
+            Stmt::Set(v) => {
+                v.process(module, function)?;
+                Ok(None)
+            }
+            Stmt::SetRef(v) => {
+                v.process(module, function)?;
+                Ok(None)
+            }
+            Stmt::Cond(v) => {
+                v.process(module, function)?;
+                Ok(None)
+            }
+            Stmt::Loop(v) => {
+                v.process(module, function)?;
+                Ok(None)
+            }
+            Stmt::Expr(v) => Ok(Some(v.expr.process(module, function)?)),
+            Stmt::Return(v) => {
+                v.process(module, function)?;
+                Ok(None)
+            }
+        }
+    }
+}
+
+impl Process for Set {
+    type Return = ();
+
+    fn process(&self, module: &mut Module, function: &mut Function) -> Result<Self::Return> {
+        let mut expr_results = Vec::with_capacity(self.exprs.iter().len());
+        for (index, expr) in self.exprs.iter().enumerate() {
+            let hint = self
+                .targets
+                .iter()
+                .nth(index)
+                .and_then(|target| variable_type(module, function, &target.path.ident.value))
+                .unwrap_or(Type::Unknown);
+            expr_results.push(process_hinted(module, function, expr, hint)?);
+        }
+        let expr_results = destructure(module, function, self.targets.iter().len(), expr_results)?;
+        let spans: Vec<Span> = self.exprs.iter().map(|expr| expr.span()).collect();
+
+        for (i, (target, src)) in self.targets.iter().zip(expr_results).enumerate() {
+            let span = spans[i.min(spans.len() - 1)];
+            assign(
+                module,
+                function,
+                target.path.ident.value.as_str(),
+                src,
+                span,
+            )?;
+        }
+
+        Ok(())
+    }
+}
+
+impl Process for SetRef {
+    type Return = ();
+
+    fn process(&self, module: &mut Module, function: &mut Function) -> Result<Self::Return> {
+        let dst = Expr::from(AnyExpr::Load(self.ident.clone())).process(module, function)?;
+        let elem = deref_type(module, function, dst, self.ident.span())?;
+
+        let src = process_hinted(module, function, &self.expr, elem)?;
+        let src_ty = *module.get_type(function.tmp(src));
+        if src_ty != elem {
+            return Err(Error::UnexpectedType {
+                span: self.expr.span(),
+                err: Unexpected::new(
+                    "type",
+                    format!("{src_ty:?}"),
+                    [format!("{elem:?}")].into(),
+                    false,
+                ),
+            });
+        }
+
+        function.push_stmt(Statement::StoreRef { dst, src });
+        Ok(())
+    }
+}
+
+/// the type that the reference `ptr` points to, for `*r` and `*r = value`
+fn deref_type(module: &Module, function: &Function, ptr: TmpId, span: Span) -> Result<Type> {
+    match *module.get_type(function.tmp(ptr)) {
+        Type::Ref(elem) => Ok(module.get_ref(elem)),
+        ty => Err(Error::UnexpectedType {
+            span,
+            err: Unexpected::new(
+                "type",
+                format!("{ty:?}"),
+                ["reference".to_string()].into(),
+                false,
+            ),
+        }),
+    }
+}
+
+/// the type of the local or global variable `name`
+fn variable_type(module: &Module, function: &Function, name: &str) -> Option<Type> {
+    let ty = match function.variables_raw.get(name) {
+        Some(var) => function.var(*var),
+        None => match module.resolve_static(name)? {
+            Static::Global(global) => module.globals[global.0],
+            Static::Func(_) => return None,
+        },
+    };
+    Some(*module.get_type(ty))
+}
+
+/// stores `src` in the local or global variable `name`,
+/// the value has to match the type the variable was initialized with
+fn assign(
+    module: &mut Module,
+    function: &mut Function,
+    name: &str,
+    src: TmpId,
+    span: Span,
+) -> Result<()> {
+    let src_ty = *module.get_type(function.temporaries[src.0]);
+
+    let check = |dst_ty: LinkedType| {
+        let dst_ty = *module.get_type(dst_ty);
+        if src_ty == dst_ty {
+            return Ok(());
+        }
+
+        Err(Error::UnexpectedType {
+            span,
+            err: Unexpected::new(
+                "type",
+                format!("{src_ty:?}"),
+                [format!("{dst_ty:?}")].into(),
+                false,
+            ),
+        })
+    };
+
+    let Some(dst) = function.variables_raw.get(name).copied() else {
+        match module.resolve_static(name) {
+            Some(Static::Global(dst)) => {
+                check(module.globals[dst.0])?;
+                function.push_stmt(Statement::StoreGlobal { dst, src });
+                return Ok(());
+            }
+            Some(Static::Func(_)) => return Err(Error::InvalidType),
+            None => return Err(Error::VariableNotFound(name.to_string())),
+        }
+    };
+    check(function.var(dst))?;
+
+    function.uninitialized.remove(&dst);
+    function.push_stmt(Statement::Store { dst, src });
+    Ok(())
+}
+
+impl Process for Cond {
+    type Return = ();
+
+    fn process(&self, module: &mut Module, function: &mut Function) -> Result<Self::Return> {
+        // if a {} else if b {} else if c {} else {}
+        // becomes
+        // if a {} else { if b {} else { if c {} else {} } }
+
+        // `if c { x = a; } else { x = b; }` with plain values doesn't need any branches
+        if let Some((name, if_true, if_false)) = select_assign(module, function, self) {
+            return select(
+                module,
+                function,
+                &self.if_first.check,
+                name,
+                if_true,
+                if_false,
+            );
+        }
+
+        let continue_block = function.push_block();
+
+        // a variable stays uninitialized after the `if`
+        // if any branch that reaches the end leaves it uninitialized
+        let mut uninitialized = HashSet::new();
+
+        // if/else if chain
+        for i in [&self.if_first]
+            .into_iter()
+            .chain(self.else_ifs.iter().map(|s| &s.inner))
+        {
+            let then_block = function.push_block();
+            let else_block = function.push_block();
+
+            let bool = condition(module, function, &i.check)?;
+
+            function.push_stmt(Statement::ConditionalJump {
+                bool,
+                then_block,
+                else_block,
+            });
+
+            // if true block
+            let before = function.uninitialized.clone();
+            function.move_to_block(then_block);
+            i.block.process(module, function)?;
+            if !function.current().is_some_and(|stmt| stmt.is_terminal()) {
+                uninitialized.extend(function.uninitialized.iter().copied());
+            }
+            function.uninitialized = before;
+            function.terminate_with(Statement::UnconditionalJump { id: continue_block });
+
+            // if false block
+            function.move_to_block(else_block);
+        }
+
+        // final else block
+        if let Some(else_last) = self.else_last.as_ref() {
+            else_last.block.process(module, function)?;
+        }
+        if !function.current().is_some_and(|stmt| stmt.is_terminal()) {
+            uninitialized.extend(function.uninitialized.iter().copied());
+        }
+        function.uninitialized = uninitialized;
+        function.terminate_with(Statement::UnconditionalJump { id: continue_block });
+
+        // continue block
+        function.move_to_block(continue_block);
+
+        Ok(())
+    }
+}
+
+/// the condition of an `if`, it has to be a `bool`
+fn condition(module: &mut Module, function: &mut Function, check: &Expr) -> Result<TmpId> {
+    let bool = check.process(module, function)?;
+    let ty = *module.get_type(function.tmp(bool));
+    if ty != Type::Bool {
+        return Err(Error::UnexpectedType {
+            span: check.span(),
+            err: Unexpected::new(
+                "type",
+                format!("{ty:?}"),
+                ["Bool".to_string()].into(),
+                false,
+            ),
+        });
+    }
+    Ok(bool)
+}
+
+impl Process for Match {
+    type Return = TmpId;
+
+    fn process(&self, module: &mut Module, function: &mut Function) -> Result<Self::Return> {
+        let value = self.value.process(module, function)?;
+        let ty = *module.get_type(function.tmp(value));
+        if !matches!(ty, Type::I32 | Type::I64 | Type::USize | Type::Bool) {
+            return Err(Error::UnexpectedType {
+                span: self.value.span(),
+                err: Unexpected::new(
+                    "type",
+                    format!("{ty:?}"),
+                    ["I32", "I64", "USize", "Bool"]
+                        .map(|ty| ty.to_string())
+                        .into(),
+                    false,
+                ),
+            });
+        }
+
+        // only the first arm of each value can be reached
+        let mut default = None;
+        let mut cases: Vec<(i64, BlockId)> = Vec::new();
+        let mut arm_blocks = Vec::with_capacity(self.arms.len());
+        for arm in self.arms.iter() {
+            let block = function.push_block();
+            arm_blocks.push(block);
+
+            let case = match (&arm.pattern, ty) {
+                (Pattern::Wildcard(_), _) => {
+                    default.get_or_insert(block);
+                    continue;
+                }
+                (Pattern::LitInt(int), Type::I32 | Type::I64 | Type::USize) => int.value as i64,
+                (Pattern::LitBool(bool), Type::Bool) => bool.value as i64,
+                (pattern, _) => {
+                    let got = match pattern {
+                        Pattern::LitBool(_) => "Bool",
+                        _ => "I32",
+                    };
+                    return Err(Error::UnexpectedType {
+                        span: pattern.span(),
+                        err: Unexpected::new(
+                            "type",
+                            got.to_string(),
+                            [format!("{ty:?}")].into(),
+                            false,
+                        ),
+                    });
+                }
+            };
+            if default.is_none() && !cases.iter().any(|(other, _)| *other == case) {
+                cases.push((case, block));
+            }
+        }
+
+        let non_exhaustive = || Error::NonExhaustiveMatch { span: self.span() };
+        if ty == Type::Bool {
+            let case = |value: bool| {
+                cases
+                    .iter()
+                    .find(|(case, _)| *case == value as i64)
+                    .map(|(_, block)| *block)
+                    .or(default)
+            };
+            let (Some(then_block), Some(else_block)) = (case(true), case(false)) else {
+                return Err(non_exhaustive());
+            };
+            function.push_stmt(Statement::ConditionalJump {
+                bool: value,
+                then_block,
+                else_block,
+            });
+        } else {
+            let default = default.ok_or_else(non_exhaustive)?;
+            function.push_stmt(Statement::SwitchInt {
+                val: value,
+                default,
+                cases,
+            });
+        }
+
+        let mut result_ty = None;
+        let mut arm_values = Vec::with_capacity(self.arms.len());
+        for (arm, block) in self.arms.iter().zip(arm_blocks) {
+            function.move_to_block(block);
+
+            // int literals take the type of the other arms
+            let hint = result_ty.unwrap_or(Type::Unknown);
+            let src = process_hinted(module, function, &arm.expr, hint)?;
+            let src_ty = *module.get_type(function.tmp(src));
+
+            match result_ty {
+                _ if src_ty == Type::Never => continue,
+                None => result_ty = Some(src_ty),
+                Some(ty) if ty == src_ty => {}
+                Some(ty) => {
+                    return Err(Error::UnexpectedType {
+                        span: arm.expr.span(),
+                        err: Unexpected::new(
+                            "type",
+                            format!("{src_ty:?}"),
+                            [format!("{ty:?}")].into(),
+                            false,
+                        ),
+                    });
+                }
+            }
+            arm_values.push((function.current_block, src));
+        }
+
+        join_branches(module, function, result_ty, arm_values)
+    }
+}
+
+/// `if a { x } else if b { y } else { z }` as a value, like a `match`,
+/// the branches that never reach their end don't have to match the type of the others
+fn if_expr(module: &mut Module, function: &mut Function, cond: &Cond, hint: Type) -> Result<TmpId> {
+    let checked = iter::once(&cond.if_first)
+        .chain(cond.else_ifs.iter().map(|else_if| &else_if.inner))
+        .map(|if_| (Some(&if_.check), &if_.block));
+    let last = cond.else_last.as_ref().map(|else_| (None, &else_.block));
+
+    let mut result_ty = None;
+    let mut branch_values = Vec::new();
+    let mut unify = |module: &Module, src_ty: Type, span: Span| match result_ty {
+        _ if src_ty == Type::Never => Ok(false),
+        None => {
+            result_ty = Some(src_ty);
+            Ok(true)
+        }
+        Some(ty) if module.coerces_to(src_ty, ty) => Ok(true),
+        Some(ty) => Err(Error::UnexpectedType {
+            span,
+            err: Unexpected::new(
+                "type",
+                format!("{src_ty:?}"),
+                [format!("{ty:?}")].into(),
+                false,
+            ),
+        }),
+    };
+
+    for (check, block) in checked.chain(last) {
+        let else_block = match check {
+            Some(check) => {
+                let bool = condition(module, function, check)?;
+                let then_block = function.push_block();
+                let else_block = function.push_block();
+                function.push_stmt(Statement::ConditionalJump {
+                    bool,
+                    then_block,
+                    else_block,
+                });
+                function.move_to_block(then_block);
+                Some(else_block)
+            }
+            None => None,
+        };
+
+        let src = block_hinted(module, function, block, hint)?;
+        let src_ty = *module.get_type(function.tmp(src));
+        if unify(module, src_ty, block.span())? {
+            branch_values.push((function.current_block, src));
+        }
+
+        if let Some(else_block) = else_block {
+            function.move_to_block(else_block);
+        }
+    }
+
+    // without an `else`, nothing happens if none of the conditions hold
+    if cond.else_last.is_none() {
+        let src = function.new_tmpid(module.types.create_known(Type::Void));
+        unify(module, Type::Void, cond.span())?;
+        branch_values.push((function.current_block, src));
+    }
+
+    join_branches(module, function, result_ty, branch_values)
+}
+
+/// the value of a `match` or an `if` expression,
+/// `values` are the blocks that reach the end of a branch and the value of the branch
+fn join_branches(
+    module: &mut Module,
+    function: &mut Function,
+    result_ty: Option<Type>,
+    mut values: Vec<(BlockId, TmpId)>,
+) -> Result<TmpId> {
+    // every branch stores its value to the same variable,
+    // the block generated first declares it and the continue block is generated last
+    values.sort_by_key(|(block, _)| block.0);
+    let continue_block = function.push_block();
+    let mut result = None;
+    for (block, src) in values {
+        function.move_to_block(block);
+        if result_ty != Some(Type::Void) {
+            match result {
+                Some(dst) => function.push_stmt(Statement::Store { dst, src }),
+                None => {
+                    let dst = function.new_varid(function.tmp(src));
+                    function.push_stmt(Statement::Let { dst, src });
+                    result = Some(dst);
+                }
+            }
+        }
+        function.terminate_with(Statement::UnconditionalJump { id: continue_block });
+    }
+    function.move_to_block(continue_block);
+
+    match (result_ty, result) {
+        (_, Some(src)) => {
+            let dst = function.new_tmpid(function.var(src));
+            function.push_stmt(Statement::Load { dst, src });
+            Ok(dst)
+        }
+        (Some(ty), None) => Ok(function.new_tmpid(module.types.create_known(ty))),
+        // no branch reaches its end
+        (None, None) => {
+            function.push_stmt(Statement::Unreachable);
+            Ok(function.new_tmpid(module.types.create_known(Type::Never)))
+        }
+    }
+}
+
+/// `name = if cond { if_true } else { if_false }` as a [`Statement::Select`]
+fn select(
+    module: &mut Module,
+    function: &mut Function,
+    cond: &Expr,
+    name: &str,
+    if_true: &Expr,
+    if_false: &Expr,
+) -> Result<()> {
+    let cond = condition(module, function, cond)?;
+    let hint = variable_type(module, function, name).unwrap_or(Type::Unknown);
+    let true_span = if_true.span();
+    let if_true = process_hinted(module, function, if_true, hint)?;
+    let false_span = if_false.span();
+    let if_false = process_hinted(module, function, if_false, hint)?;
+
+    let ty = function.temporaries[if_true.0];
+    let (true_ty, false_ty) = (
+        *module.get_type(ty),
+        *module.get_type(function.tmp(if_false)),
+    );
+    if true_ty != false_ty {
+        return Err(Error::UnexpectedType {
+            span: false_span,
+            err: Unexpected::new(
+                "type",
+                format!("{false_ty:?}"),
+                [format!("{true_ty:?}")].into(),
+                false,
+            ),
+        });
+    }
+
+    let dst = function.new_tmpid(ty);
+    function.push_stmt(Statement::Select {
+        dst,
+        cond,
+        if_true,
+        if_false,
+    });
+    assign(module, function, name, dst, true_span)
+}
+
+/// `if c { x = a; } else { x = b; }` where `a` and `b` are literals or variables,
+/// returns `x`, `a` and `b`
+fn select_assign<'a>(
+    module: &Module,
+    function: &Function,
+    cond: &'a Cond,
+) -> Option<(&'a str, &'a Expr, &'a Expr)> {
+    if !cond.else_ifs.is_empty() {
+        return None;
+    }
+
+    let single_set = |block: &'a ast::Block| {
+        let [Stmt::Set(set)] = &block.stmts[..] else {
+            return None;
+        };
+        let mut targets = set.targets.iter();
+        let mut exprs = set.exprs.iter();
+        match (targets.next(), targets.next(), exprs.next(), exprs.next()) {
+            (Some(target), None, Some(expr), None) => {
+                Some((target.path.ident.value.as_str(), expr))
+            }
+            _ => None,
+        }
+    };
+    // evaluating both sides has no side effects and function values can't be selected
+    let is_plain_value = |expr: &Expr| match &expr.expr {
+        AnyExpr::LitInt(_) | AnyExpr::LitBool(_) => true,
+        AnyExpr::Load(name) => {
+            let name = name.value.as_str();
+            match function.variables_raw.get(name) {
+                Some(var) => !matches!(module.get_type(function.var(*var)), Type::Func(_)),
+                None => {
+                    module.get_const(name).is_some()
+                        || matches!(module.resolve_static(name), Some(Static::Global(_)))
+                }
+            }
+        }
+        _ => false,
+    };
+
+    let (name, if_true) = single_set(&cond.if_first.block)?;
+    let (else_name, if_false) = single_set(&cond.else_last.as_ref()?.block)?;
+    (name == else_name && is_plain_value(if_true) && is_plain_value(if_false))
+        .then_some((name, if_true, if_false))
+}
+
+impl Process for Loop {
+    type Return = ();
+
+    fn process(&self, module: &mut Module, function: &mut Function) -> Result<Self::Return> {
+        let id = function.push_block();
+        function.push_stmt(Statement::UnconditionalJump { id });
+        function.move_to_block(id);
+
+        for stmt in self.block.stmts.iter() {
+            stmt.process(module, function)?;
+        }
+
+        function.push_stmt(Statement::UnconditionalJump { id });
+
+        Ok(())
+    }
+}
+
+impl Process for Return {
+    type Return = ();
+
+    fn process(&self, module: &mut Module, function: &mut Function) -> Result<Self::Return> {
+        if let Some(expr) = self.expr.as_ref() {
+            let returns = *module.get_type(function.returns);
+            let src = process_hinted(module, function, expr, returns)?;
+            if function.points_to_local(src) {
+                return Err(Error::ReturnsLocalReference { span: expr.span() });
+            }
+            function.push_stmt(Statement::Return { src });
+        } else {
+            function.push_stmt(Statement::ReturnVoid);
+        };
+
+        Ok(())
+    }
+}
+
+//
+
+fn type_hint(module: &mut Module, v: Option<&ast::Ty>) -> Result<Option<Type>> {
+    Ok(match v {
+        Some(ast::Ty::Name(name)) => match name.value.as_str() {
+            "i32" => Some(Type::I32),
+            "i64" => Some(Type::I64),
+            "usize" => Some(Type::USize),
+            "bool" => Some(Type::Bool),
+            "str" => Some(Type::Str),
+            "void" => Some(Type::Void),
+            name => Some(module.get_alias(name).ok_or(Error::InvalidType)?),
+        },
+        Some(ast::Ty::Tuple(tuple)) => {
+            let elems = tuple
+                .elems
+                .iter()
+                .map(|ty| Ok(type_hint(module, Some(ty))?.unwrap()))
+                .collect::<Result<_>>()?;
+            Some(Type::Tuple(module.types.create_tuple(elems)))
+        }
+        Some(ast::Ty::Slice(slice)) => {
+            let elem = type_hint(module, Some(&slice.elem))?.unwrap();
+            Some(Type::Slice(module.types.create_slice(elem)))
+        }
+        Some(ast::Ty::Ref(ref_)) => {
+            let elem = type_hint(module, Some(&ref_.elem))?.unwrap();
+            Some(Type::Ref(module.types.create_ref(elem)))
+        }
+        Some(ast::Ty::Fn(func)) => {
+            let returns = type_hint(module, func.return_ty.as_ref().map(|(_, ty)| ty))?.unwrap();
+            let params = func
+                .params()
+                .map(|ty| Ok(type_hint(module, Some(ty))?.unwrap()))
+                .collect::<Result<_>>()?;
+            Some(Type::FnPtr(
+                module.types.create_signature(Signature { returns, params }),
+            ))
+        }
+        None => Some(Type::Void),
+    })
+}
+
+/// `true` if `func_id` can reach itself in the call graph
+fn calls_itself(graph: &HashMap<FuncId, Vec<FuncId>>, func_id: FuncId) -> bool {
+    let mut visited = HashSet::new();
+    let mut stack = graph[&func_id].clone();
+    while let Some(callee) = stack.pop() {
+        if callee == func_id {
+            return true;
+        }
+        if visited.insert(callee) {
+            stack.extend(graph[&callee].iter().copied());
+        }
+    }
+    false
+}
+
+/// a function without a body, only the return and param types
+fn prototype(module: &mut Module, func: &Func) -> Result<Function> {
+    let ret_ty = type_hint(module, func.proto.return_ty.as_ref().map(|(_, ty)| ty))?.unwrap();
+
+    let params = func
+        .proto
+        .args()
+        .map(|arg| {
+            let ty = type_hint(module, Some(&arg.ty))?.unwrap();
+            Ok(module.types.create_known(ty))
+        })
+        .collect::<Result<_>>()?;
+
+    let mut proto = Function::new(module.types.create_known(ret_ty), params);
+    proto.param_names = param_names(func);
+    let param_tys = proto
+        .params
+        .iter()
+        .map(|param| module.types.type_links[param.0])
+        .collect::<Vec<_>>();
+    proto.param_defaults = param_defaults(module, func.proto.args(), &param_tys)?;
+    Ok(proto)
+}
+
+/// const evaluates the default values of the parameters,
+/// empty if none of them has a default value
+fn param_defaults<'a>(
+    module: &mut Module,
+    args: impl Iterator<Item = &'a Argument>,
+    params: &[Type],
+) -> Result<Box<[Option<Literal>]>> {
+    let mut defaults = Vec::new();
+    for (arg, ty) in args.zip(params.iter().copied()) {
+        let Some((_, default)) = arg.default.as_ref() else {
+            if defaults.iter().any(Option::is_some) {
+                return Err(Error::DefaultNotTrailing {
+                    span: arg.id.span().merge(arg.ty.span()),
+                });
+            }
+            defaults.push(None);
+            continue;
+        };
+
+        let value = const_eval(module, default, ty)?;
+        if value.ty() != ty {
+            return Err(Error::UnexpectedType {
+                span: default.span(),
+                err: Unexpected::new(
+                    "type",
+                    format!("{:?}", value.ty()),
+                    [format!("{ty:?}")].into(),
+                    false,
+                ),
+            });
+        }
+        defaults.push(Some(value));
+    }
+
+    if defaults.iter().all(Option::is_none) {
+        return Ok(Box::new([]));
+    }
+    Ok(defaults.into())
+}
+
+fn param_names(func: &Func) -> Box<[Rc<str>]> {
+    func.proto
+        .args()
+        .map(|arg| arg.id.value.as_str().into())
+        .collect()
+}
+
+/// `fn() -> i32 { x * 2 }` returns the trailing expression of its block
+fn implicit_return(
+    module: &mut Module,
+    function: &mut Function,
+    block: &ast::Block,
+    value: TmpId,
+) -> Result<()> {
+    let returns = *module.get_type(function.returns);
+    let ty = *module.get_type(function.temporaries[value.0]);
+
+    if returns == Type::Void || ty == Type::Never {
+        return Ok(());
+    }
+
+    if ty != returns {
+        let span = match block.stmts.last() {
+            Some(Stmt::Expr(tail)) => tail.expr.span(),
+            _ => block.close.span(),
+        };
+        return Err(Error::TypeMismatch {
+            span,
+            expected: returns,
+            got: ty,
+        });
+    }
+
+    if function.points_to_local(value) {
+        let span = match block.stmts.last() {
+            Some(Stmt::Expr(tail)) => tail.expr.span(),
+            _ => block.close.span(),
+        };
+        return Err(Error::ReturnsLocalReference { span });
+    }
+
+    function.terminate_with(Statement::Return { src: value });
+    Ok(())
+}
+
+/// registers a source level `extern fn`,
+/// declaring the same extern again is allowed if the signature stays the same
+fn declare_extern(module: &mut Module, ext: &ExternFn) -> Result<FuncId> {
+    let returns = type_hint(module, ext.return_ty.as_ref().map(|(_, ty)| ty))?.unwrap();
+    let params = ext
+        .args()
+        .map(|arg| Ok(type_hint(module, Some(&arg.ty))?.unwrap()))
+        .collect::<Result<Vec<_>>>()?;
+
+    let name = ext.name.value.as_str();
+    let old = module
+        .externs
+        .as_ref()
+        .and_then(|map| map.get(name))
+        .copied();
+    if let Some(func_id) = old {
+        let old = module.signature_of(func_id);
+        if old.returns != returns || old.params[..] != params[..] {
+            return Err(Error::InvalidType);
+        }
+        return Ok(func_id);
+    }
+
+    let defaults = param_defaults(module, ext.args(), &params)?;
+    let func_id = module.add_extern(name, returns, &params);
+    module.functions[func_id.0].param_names =
+        ext.args().map(|arg| arg.id.value.as_str().into()).collect();
+    module.functions[func_id.0].param_defaults = defaults;
+    Ok(func_id)
+}
+
+/// the type of `lhs op rhs`, the error points at the operand with the wrong type
+fn binop_result_type(op: BinaryOp, lhs: (Type, Span), rhs: (Type, Span)) -> Result<Type> {
+    let unexpected = |(ty, span): (Type, Span), expected: &[Type]| Error::UnexpectedType {
+        span,
+        err: Unexpected::new(
+            "type",
+            format!("{ty:?}"),
+            expected.iter().map(|ty| format!("{ty:?}")).collect(),
+            false,
+        ),
+    };
+    // unresolved and diverging operands were already reported or never produce a value
+    let unchecked = |ty: Type| matches!(ty, Type::Unknown | Type::Never);
+
+    let (operands, result): (&[Type], _) = match op {
+        BinaryOp::Mul | BinaryOp::Div | BinaryOp::Rem | BinaryOp::Add | BinaryOp::Sub => {
+            (&[Type::I32, Type::I64, Type::USize], lhs.0)
+        }
+        BinaryOp::Lt | BinaryOp::Le | BinaryOp::Gt | BinaryOp::Ge => {
+            (&[Type::I32, Type::I64, Type::USize], Type::Bool)
+        }
+        BinaryOp::Eq | BinaryOp::Neq => (&[], Type::Bool),
+        BinaryOp::And | BinaryOp::Or => (&[Type::Bool], Type::Bool),
+    };
+
+    if !operands.is_empty() && !unchecked(lhs.0) && !operands.contains(&lhs.0) {
+        return Err(unexpected(lhs, operands));
+    }
+    if !unchecked(lhs.0) && !unchecked(rhs.0) && rhs.0 != lhs.0 {
+        return Err(unexpected(rhs, &[lhs.0]));
+    }
+
+    Ok(result)
+}
+
+/// an argument of a call, either written in the call or the default value of the parameter
+enum ArgValue<'a> {
+    Expr(&'a Expr),
+    Default(Literal),
+}
+
+#[derive(Debug, Clone, Copy)]
+enum AtomicBuiltin {
+    Load,
+    Store,
+    Rmw(AtomicRmwOp),
+    CmpXchg,
+    Fence,
+}
+
+impl AtomicBuiltin {
+    /// `atomic_load` is `SeqCst`, `atomic_load_acquire` and the other suffixes pick the ordering
+    fn from_name(name: &str) -> Option<(Self, Ordering)> {
+        let (base, ordering) = [Ordering::Relaxed, Ordering::Acquire, Ordering::Release]
+            .into_iter()
+            .find_map(|ordering| Some((name.strip_suffix(ordering.suffix())?, ordering)))
+            .unwrap_or((name, Ordering::SeqCst));
+
+        let builtin = match base {
+            "atomic_load" => Self::Load,
+            "atomic_store" => Self::Store,
+            "atomic_add" => Self::Rmw(AtomicRmwOp::Add),
+            "atomic_sub" => Self::Rmw(AtomicRmwOp::Sub),
+            "atomic_and" => Self::Rmw(AtomicRmwOp::And),
+            "atomic_or" => Self::Rmw(AtomicRmwOp::Or),
+            "atomic_xor" => Self::Rmw(AtomicRmwOp::Xor),
+            "atomic_xchg" => Self::Rmw(AtomicRmwOp::Xchg),
+            "cmpxchg" => Self::CmpXchg,
+            // a fence always names its ordering
+            "memory_fence" if ordering != Ordering::SeqCst => Self::Fence,
+            "memory_fence_seqcst" => Self::Fence,
+            _ => return None,
+        };
+
+        builtin
+            .orderings()
+            .contains(&ordering)
+            .then_some((builtin, ordering))
+    }
+
+    /// the orderings the builtin has a variant for
+    fn orderings(self) -> &'static [Ordering] {
+        match self {
+            Self::Load => &[Ordering::Relaxed, Ordering::Acquire, Ordering::SeqCst],
+            Self::Store => &[Ordering::Relaxed, Ordering::Release, Ordering::SeqCst],
+            Self::Rmw(_) | Self::CmpXchg => &[
+                Ordering::Relaxed,
+                Ordering::Acquire,
+                Ordering::Release,
+                Ordering::SeqCst,
+            ],
+            Self::Fence => &[Ordering::Acquire, Ordering::Release, Ordering::SeqCst],
+        }
+    }
+
+    /// the number of integer values after the reference
+    fn values(self) -> usize {
+        match self {
+            Self::Load | Self::Fence => 0,
+            Self::Store | Self::Rmw(_) => 1,
+            Self::CmpXchg => 2,
+        }
+    }
+}
+
+/// the atomic builtins like `atomic_load(&x)`, `atomic_store(&x, value)`,
+/// `atomic_add(&x, value)` and `cmpxchg(&x, expected, desired)` work on references
+/// to every integer type, not just the `&i32` of their signatures
+///
+/// they are `SeqCst`, the variants like `atomic_load_acquire(&x)` and `atomic_add_relaxed(&x, 1)`
+/// use a weaker ordering, the fences like `memory_fence_acquire()` don't take a reference
+///
+/// `None` with the wrong number of arguments, the call is then checked like any other call
+fn atomic(
+    module: &mut Module,
+    function: &mut Function,
+    builtin: AtomicBuiltin,
+    ordering: Ordering,
+    args: &[ArgValue],
+) -> Result<Option<TmpId>> {
+    let Some(args) = args
+        .iter()
+        .map(|arg| match arg {
+            ArgValue::Expr(expr) => Some(*expr),
+            ArgValue::Default(_) => None,
+        })
+        .collect::<Option<Vec<_>>>()
+    else {
+        return Ok(None);
+    };
+    if let AtomicBuiltin::Fence = builtin {
+        if !args.is_empty() {
+            return Ok(None);
+        }
+        function.push_stmt(Statement::Fence { ordering });
+        return Ok(Some(
+            function.new_tmpid(module.types.create_known(Type::Void)),
+        ));
+    }
+    let [ptr, values @ ..] = &args[..] else {
+        return Ok(None);
+    };
+    if values.len() != builtin.values() {
+        return Ok(None);
+    }
+
+    let ptr_tmp = ptr.process(module, function)?;
+    let ptr_ty = *module.get_type(function.tmp(ptr_tmp));
+    let elem = match ptr_ty {
+        Type::Ref(elem) => module.get_ref(elem),
+        _ => Type::Unknown,
+    };
+    if !matches!(elem, Type::I32 | Type::I64 | Type::USize) {
+        return Err(Error::UnexpectedType {
+            span: ptr.span(),
+            err: Unexpected::new(
+                "type",
+                format!("{ptr_ty:?}"),
+                ["&I32", "&I64", "&USize"].map(|ty| ty.to_string()).into(),
+                false,
+            ),
+        });
+    }
+
+    let values = values
+        .iter()
+        .map(|value| {
+            let src = process_hinted(module, function, value, elem)?;
+            let src_ty = *module.get_type(function.tmp(src));
+            if src_ty != elem {
+                return Err(Error::UnexpectedType {
+                    span: value.span(),
+                    err: Unexpected::new(
+                        "type",
+                        format!("{src_ty:?}"),
+                        [format!("{elem:?}")].into(),
+                        false,
+                    ),
+                });
+            }
+            Ok(src)
+        })
+        .collect::<Result<Vec<_>>>()?;
+
+    let ptr = ptr_tmp;
+    let (dst, stmt) = match (builtin, &values[..]) {
+        (AtomicBuiltin::Load, []) => {
+            let dst = function.new_tmpid(module.types.create_known(elem));
+            (dst, Statement::AtomicLoad { dst, ptr, ordering })
+        }
+        (AtomicBuiltin::Store, [src]) => {
+            let dst = function.new_tmpid(module.types.create_known(Type::Void));
+            let src = *src;
+            (dst, Statement::AtomicStore { ptr, src, ordering })
+        }
+        (AtomicBuiltin::Rmw(op), [val]) => {
+            let dst = function.new_tmpid(module.types.create_known(elem));
+            let val = *val;
+            let stmt = Statement::AtomicRmw {
+                dst,
+                ptr,
+                op,
+                val,
+                ordering,
+            };
+            (dst, stmt)
+        }
+        (AtomicBuiltin::CmpXchg, [expected, desired]) => {
+            let tuple = module.types.create_tuple([elem, Type::Bool].into());
+            let dst = function.new_tmpid(module.types.create_known(Type::Tuple(tuple)));
+            let stmt = Statement::CompareExchange {
+                dst,
+                ptr,
+                expected: *expected,
+                desired: *desired,
+                success_order: ordering,
+                fail_order: ordering.failure(),
+            };
+            (dst, stmt)
+        }
+        _ => unreachable!("the argument count was checked above"),
+    };
+    function.push_stmt(stmt);
+    Ok(Some(dst))
+}
+
+/// `print("x = {}\n", x)` prints each piece of the format string and each argument in turn,
+/// the arguments are turned into strings with the `i32_to_str` style builtins
+///
+/// `None` if the only argument isn't a constant string, it is then printed with a normal call
+fn print_format(
+    module: &mut Module,
+    function: &mut Function,
+    print: TmpId,
+    fmt: &Expr,
+    args: &[ArgValue],
+    span: Span,
+) -> Result<Option<TmpId>> {
+    let pieces = match const_eval(module, fmt, Type::Str) {
+        Ok(Literal::Str(fmt_str)) => {
+            format_pieces(&fmt_str).ok_or(Error::InvalidFormat { span: fmt.span() })?
+        }
+        _ if args.is_empty() => return Ok(None),
+        Ok(lit) => {
+            return Err(Error::UnexpectedType {
+                span: fmt.span(),
+                err: Unexpected::new(
+                    "type",
+                    format!("{:?}", lit.ty()),
+                    ["Str".into()].into(),
+                    false,
+                ),
+            })
+        }
+        Err(err) => return Err(err),
+    };
+    if pieces.len() - 1 != args.len() {
+        return Err(Error::FormatArgCount {
+            span,
+            placeholders: pieces.len() - 1,
+            args: args.len(),
+        });
+    }
+
+    // every argument is evaluated before anything is printed, like in a normal call
+    let mut strs = Vec::with_capacity(args.len());
+    for arg in args {
+        let ArgValue::Expr(arg) = arg else {
+            unreachable!("`print` has no default values")
+        };
+        let tmp = arg.process(module, function)?;
+        let tmp = non_void(module, function, tmp, arg.span())?;
+        let ty = *module.get_type(function.tmp(tmp));
+        let helper = match ty {
+            Type::Str => {
+                strs.push(tmp);
+                continue;
+            }
+            Type::I32 => "i32_to_str",
+            Type::I64 => "i64_to_str",
+            Type::USize => "usize_to_str",
+            Type::Bool => "bool_to_str",
+            _ => "",
+        };
+        let Some(helper) = module.get_function_by_name(helper) else {
+            return Err(Error::UnexpectedType {
+                span: arg.span(),
+                err: Unexpected::new(
+                    "type",
+                    format!("{ty:?}"),
+                    ["I32", "I64", "USize", "Bool", "Str"]
+                        .map(|ty| ty.to_string())
+                        .into(),
+                    false,
+                ),
+            });
+        };
+        strs.push(call_function(module, function, helper, [tmp].into()));
+    }
+
+    let mut strs = strs.into_iter();
+    for piece in pieces {
+        let piece = (!piece.is_empty()).then(|| {
+            let dst = function.new_tmpid(module.types.create_known(Type::Str));
+            function.push_stmt(Statement::Const {
+                dst,
+                src: Literal::Str(piece.into()),
+            });
+            dst
+        });
+        for str in piece.into_iter().chain(strs.next()) {
+            let dst = function.new_tmpid(module.types.create_known(Type::Void));
+            function.push_stmt(Statement::Call {
+                dst,
+                func: print,
+                args: [str].into(),
+            });
+        }
+    }
+
+    Ok(Some(
+        function.new_tmpid(module.types.create_known(Type::Void)),
+    ))
+}
+
+/// the text around the `{}` placeholders of a format string, `{{` and `}}` are literal braces
+///
+/// `None` if a brace is not part of any of those
+fn format_pieces(fmt: &str) -> Option<Vec<String>> {
+    let mut pieces = vec![String::new()];
+    let mut chars = fmt.chars().peekable();
+    while let Some(c) = chars.next() {
+        match (c, chars.peek()) {
+            ('{', Some('{')) | ('}', Some('}')) => {
+                chars.next();
+                pieces.last_mut().unwrap().push(c);
+            }
+            ('{', Some('}')) => {
+                chars.next();
+                pieces.push(String::new());
+            }
+            ('{' | '}', _) => return None,
+            _ => pieces.last_mut().unwrap().push(c),
+        }
+    }
+    Some(pieces)
+}
+
+/// calls the function `func` directly by its id
+fn call_function(
+    module: &mut Module,
+    function: &mut Function,
+    func: FuncId,
+    args: Box<[TmpId]>,
+) -> TmpId {
+    let src = func;
+    let name = module.get_function(src).name.clone().unwrap();
+    let func = function.new_tmpid(module.types.create_known(Type::Func(src)));
+    function.push_stmt(Statement::Extern {
+        dst: func,
+        src,
+        name,
+    });
+
+    let returns = module.signature_of(src).returns;
+    let dst = function.new_tmpid(module.types.create_known(returns));
+    function.push_stmt(Statement::Call { dst, func, args });
+    dst
+}
+
+/// the argument values in parameter order, named arguments go to the parameter with the same name,
+/// so they are also evaluated in parameter order,
+/// missing trailing arguments are filled in with the default values of the parameters
+///
+/// the first `skip` parameters are already taken by the receiver of a method call
+fn positional_args<'a>(
+    module: &Module,
+    func_ty: Type,
+    skip: usize,
+    args: &[&'a CallArg],
+    span: Span,
+) -> Result<Vec<ArgValue<'a>>> {
+    // function pointers don't have parameter names or default values
+    let (names, defaults) = match func_ty {
+        Type::Func(func_id) => {
+            let func = module.get_function(func_id);
+            (&func.param_names[..], &func.param_defaults[..])
+        }
+        _ => (&[][..], &[][..]),
+    };
+    let names = names.get(skip..).unwrap_or_default();
+    let defaults = defaults.get(skip..).unwrap_or_default();
+    let default = |index: usize| {
+        defaults
+            .get(index)
+            .cloned()
+            .flatten()
+            .map(ArgValue::Default)
+    };
+
+    if args.iter().all(|arg| arg.name.is_none()) {
+        let mut values = args
+            .iter()
+            .map(|arg| ArgValue::Expr(&arg.value))
+            .collect::<Vec<_>>();
+        while let Some(value) = default(values.len()) {
+            values.push(value);
+        }
+        return Ok(values);
+    }
+    if let Some(positional) = args.iter().find(|arg| arg.name.is_none()) {
+        return Err(Error::MixedArguments {
+            span: positional.span(),
+        });
+    }
+
+    let mut ordered = vec![None; names.len()];
+    for (name, value) in args
+        .iter()
+        .filter_map(|arg| Some((&arg.name.as_ref()?.0, &arg.value)))
+    {
+        let Some(index) = names.iter().position(|param| **param == *name.value) else {
+            return Err(Error::UnknownArgument {
+                span: name.span(),
+                name: name.value.clone(),
+            });
+        };
+        if ordered[index].replace(value).is_some() {
+            return Err(Error::DuplicateArgument {
+                span: name.span(),
+                name: name.value.clone(),
+            });
+        }
+    }
+
+    ordered
+        .into_iter()
+        .zip(names)
+        .enumerate()
+        .map(|(index, (value, name))| {
+            value
+                .map(ArgValue::Expr)
+                .or_else(|| default(index))
+                .ok_or_else(|| Error::MissingArgument {
+                    span,
+                    name: name.to_string(),
+                })
+        })
+        .collect()
+}
+
+/// `Name(value)` for an `opaque type Name`,
+/// the value goes through a variable of the opaque type, because both are stored the same way
+fn opaque_from(
+    module: &mut Module,
+    function: &mut Function,
+    call: &Call,
+    opaque: OpaqueId,
+) -> Result<TmpId> {
+    let inner = module.get_opaque(opaque).1;
+    let mut args = call.args();
+    let (Some(arg), None) = (args.next(), args.next()) else {
+        return Err(Error::InvalidType);
+    };
+
+    let src = arg.process(module, function)?;
+    let ty = *module.get_type(function.tmp(src));
+    if ty != inner {
+        return Err(Error::UnexpectedType {
+            span: arg.span(),
+            err: Unexpected::new(
+                "type",
+                format!("{ty:?}"),
+                [format!("{inner:?}")].into(),
+                false,
+            ),
+        });
+    }
+
+    let ty = module.types.create_known(Type::Opaque(opaque));
+    let var = function.new_varid(ty);
+    function.push_stmt(Statement::Let { dst: var, src });
+    let dst = function.new_tmpid(ty);
+    function.push_stmt(Statement::Load { dst, src: var });
+    Ok(dst)
+}
+
+/// registers a `type` or `opaque type` name, a later declaration replaces the earlier one
+fn declare_alias(module: &mut Module, alias: &TypeAlias) -> Result<()> {
+    let name: Rc<str> = alias.name.value.as_str().into();
+    let mut ty = type_hint(module, Some(&alias.ty))?.unwrap();
+    if alias.opaque_kw.is_some() {
+        ty = Type::Opaque(module.types.create_opaque(name.clone(), ty));
+    }
+
+    module
+        .aliases
+        .get_or_insert_with(Default::default)
+        .insert(name, ty);
+    Ok(())
+}
+
+/// warns about locals that are never read,
+/// the first `params` variables and names starting with `_` are skipped
+fn warn_unused_variables(module: &mut Module, func: &Function, params: usize) {
+    let mut used = vec![false; func.variables.len()];
+    for stmt in func.blocks.iter().flat_map(|block| block.stmts.iter()) {
+        if let Statement::Load { src, .. } | Statement::Ref { src, .. } = stmt {
+            used[src.0] = true;
+        }
+    }
+
+    let mut unused: Vec<(VarId, &Rc<str>)> = func
+        .variables_raw
+        .iter()
+        .filter(|(name, var)| var.0 >= params && !used[var.0] && !name.starts_with('_'))
+        .map(|(name, var)| (*var, name))
+        .collect();
+    unused.sort();
+
+    for (_, name) in unused {
+        module
+            .warnings
+            .push(Warning::UnusedVariable(name.to_string()));
+    }
+}
+
+/// registers a `const`, its value has to be known without running any code
+fn declare_const(module: &mut Module, decl: &ConstDecl) -> Result<()> {
+    let ty = match decl.ty.as_ref() {
+        Some((_, ty)) => type_hint(module, Some(ty))?,
+        None => None,
+    };
+    let value = const_eval(module, &decl.value, ty.unwrap_or(Type::I32))?;
+
+    if let Some(ty) = ty.filter(|ty| *ty != value.ty()) {
+        return Err(Error::UnexpectedType {
+            span: decl.value.span(),
+            err: Unexpected::new(
+                "type",
+                format!("{:?}", value.ty()),
+                [format!("{ty:?}")].into(),
+                false,
+            ),
+        });
+    }
+
+    module
+        .consts
+        .get_or_insert_with(Default::default)
+        .insert(decl.name.value.as_str().into(), value);
+    Ok(())
+}
+
+/// creates the global of a `static mut`, its initial value has to be known at compile time
+fn declare_static(module: &mut Module, function: &mut Function, decl: &StaticDecl) -> Result<()> {
+    let ty = match decl.ty.as_ref() {
+        Some((_, ty)) => type_hint(module, Some(ty))?,
+        None => None,
+    };
+    let value = const_eval(module, &decl.value, ty.unwrap_or(Type::I32))?;
+
+    if let Some(ty) = ty.filter(|ty| *ty != value.ty()) {
+        return Err(Error::UnexpectedType {
+            span: decl.value.span(),
+            err: Unexpected::new(
+                "type",
+                format!("{:?}", value.ty()),
+                [format!("{ty:?}")].into(),
+                false,
+            ),
+        });
+    }
+
+    let name: Rc<str> = decl.name.value.as_str().into();
+    let ty = module.types.create_known(value.ty());
+    let global = module.new_global(ty);
+    module
+        .global_inits
+        .get_or_insert_with(Default::default)
+        .insert(global, value);
+    module.insert_static(module.qualified(&name), Static::Global(global));
+    // the global shadows any older local with the same name
+    function.variables_raw.remove(&name);
+    Ok(())
+}
+
+/// folds literals, other `const`s and the binary ops between them,
+/// int literals use `hint` like [`lit_int`]
+fn const_eval(module: &mut Module, expr: &Expr, hint: Type) -> Result<Literal> {
+    let non_const = || Error::NonConstExpr { span: expr.span() };
+
+    Ok(match &expr.expr {
+        AnyExpr::LitInt(int) => int_literal(int, hint),
+        AnyExpr::LitStr(str) => Literal::Str(str.value.as_str().into()),
+        AnyExpr::LitByteStr(str) => Literal::ByteStr(str.value.as_slice().into()),
+        AnyExpr::LitBool(bool) => Literal::Bool(bool.value),
+        AnyExpr::Load(name) => module
+            .get_const(name.value.as_str())
+            .cloned()
+            .ok_or_else(non_const)?,
+        AnyExpr::Binary { op, sides } => {
+            let lhs = const_eval(module, &sides.0, hint)?;
+            let rhs = const_eval(module, &sides.1, lhs.ty())?;
+            binop_result_type(*op, (lhs.ty(), sides.0.span()), (rhs.ty(), sides.1.span()))?;
+
+            fold_binop(*op, lhs, rhs).ok_or_else(non_const)?
+        }
+        AnyExpr::Array(array) => const_array(module, array, hint)?,
+        _ => return Err(non_const()),
+    })
+}
+
+/// `[1, 2, 4, 8]`, the elements all have the type of the first one,
+/// or the element type of a slice `hint`
+fn const_array(module: &mut Module, array: &Array, hint: Type) -> Result<Literal> {
+    let mut elem_ty = match hint {
+        Type::Slice(slice) => Some(module.get_slice(slice)),
+        _ => None,
+    };
+
+    let mut values = Vec::new();
+    for elem in array.elems() {
+        let value = const_eval(module, elem, elem_ty.unwrap_or(Type::I32))?;
+        match elem_ty {
+            Some(ty) if ty != value.ty() => {
+                return Err(Error::UnexpectedType {
+                    span: elem.span(),
+                    err: Unexpected::new(
+                        "type",
+                        format!("{:?}", value.ty()),
+                        [format!("{ty:?}")].into(),
+                        false,
+                    ),
+                });
+            }
+            Some(_) => {}
+            None => elem_ty = Some(value.ty()),
+        }
+        values.push(value);
+    }
+
+    let slice = module.types.create_slice(elem_ty.unwrap_or(Type::I32));
+    Ok(Literal::Slice(slice, values.into()))
+}
+
+/// the value of `lhs op rhs`, if it can be computed at compile time
+fn fold_binop(op: BinaryOp, lhs: Literal, rhs: Literal) -> Option<Literal> {
+    Some(match (op, lhs, rhs) {
+        (BinaryOp::Eq, lhs, rhs) => Literal::Bool(lhs == rhs),
+        (BinaryOp::Neq, lhs, rhs) => Literal::Bool(lhs != rhs),
+        (BinaryOp::And, Literal::Bool(lhs), Literal::Bool(rhs)) => Literal::Bool(lhs && rhs),
+        (BinaryOp::Or, Literal::Bool(lhs), Literal::Bool(rhs)) => Literal::Bool(lhs || rhs),
+        (op, Literal::I32(lhs), Literal::I32(rhs)) => match op {
+            BinaryOp::Mul => Literal::I32(lhs.wrapping_mul(rhs)),
+            BinaryOp::Div => Literal::I32(lhs.checked_div(rhs)?),
+            BinaryOp::Rem => Literal::I32(lhs.checked_rem(rhs)?),
+            BinaryOp::Add => Literal::I32(lhs.wrapping_add(rhs)),
+            BinaryOp::Sub => Literal::I32(lhs.wrapping_sub(rhs)),
+            BinaryOp::Lt => Literal::Bool(lhs < rhs),
+            BinaryOp::Le => Literal::Bool(lhs <= rhs),
+            BinaryOp::Gt => Literal::Bool(lhs > rhs),
+            BinaryOp::Ge => Literal::Bool(lhs >= rhs),
+            _ => return None,
+        },
+        (op, Literal::I64(lhs), Literal::I64(rhs)) => match op {
+            BinaryOp::Mul => Literal::I64(lhs.wrapping_mul(rhs)),
+            BinaryOp::Div => Literal::I64(lhs.checked_div(rhs)?),
+            BinaryOp::Rem => Literal::I64(lhs.checked_rem(rhs)?),
+            BinaryOp::Add => Literal::I64(lhs.wrapping_add(rhs)),
+            BinaryOp::Sub => Literal::I64(lhs.wrapping_sub(rhs)),
+            BinaryOp::Lt => Literal::Bool(lhs < rhs),
+            BinaryOp::Le => Literal::Bool(lhs <= rhs),
+            BinaryOp::Gt => Literal::Bool(lhs > rhs),
+            BinaryOp::Ge => Literal::Bool(lhs >= rhs),
+            _ => return None,
+        },
+        (op, Literal::USize(lhs), Literal::USize(rhs)) => match op {
+            BinaryOp::Mul => Literal::USize(lhs.wrapping_mul(rhs)),
+            BinaryOp::Div => Literal::USize(lhs.checked_div(rhs)?),
+            BinaryOp::Rem => Literal::USize(lhs.checked_rem(rhs)?),
+            BinaryOp::Add => Literal::USize(lhs.wrapping_add(rhs)),
+            BinaryOp::Sub => Literal::USize(lhs.wrapping_sub(rhs)),
+            BinaryOp::Lt => Literal::Bool(lhs < rhs),
+            BinaryOp::Le => Literal::Bool(lhs <= rhs),
+            BinaryOp::Gt => Literal::Bool(lhs > rhs),
+            BinaryOp::Ge => Literal::Bool(lhs >= rhs),
+            _ => return None,
+        },
+        _ => return None,
+    })
+}
+
+/// int literals are `i32` unless the `hint` is another integer type
+fn int_literal(int: &ast::LitInt, hint: Type) -> Literal {
+    match hint {
+        Type::I64 => Literal::I64(int.value as _),
+        Type::USize => Literal::USize(int.value as _),
+        _ => Literal::I32(int.value as _),
+    }
+}
+
+/// a [`Statement::Const`] with the literal from [`int_literal`]
+fn lit_int(module: &mut Module, function: &mut Function, int: &ast::LitInt, hint: Type) -> TmpId {
+    let src = int_literal(int, hint);
+
+    let dst = function.new_tmpid(module.types.create_known(src.ty()));
+    function.push_stmt(Statement::Const { dst, src });
+    dst
+}
+
+/// a [`Statement::Const`] with the slice from [`const_array`]
+fn lit_array(
+    module: &mut Module,
+    function: &mut Function,
+    array: &Array,
+    hint: Type,
+) -> Result<TmpId> {
+    let src = const_array(module, array, hint)?;
+
+    let dst = function.new_tmpid(module.types.create_known(src.ty()));
+    function.push_stmt(Statement::Const { dst, src });
+    Ok(dst)
+}
+
+/// `a, b := f()` splits the tuple returned by `f` into its fields,
+/// otherwise there has to be one expression for each target
+fn destructure(
+    module: &mut Module,
+    function: &mut Function,
+    targets: usize,
+    srcs: Vec<TmpId>,
+) -> Result<Vec<TmpId>> {
+    if srcs.len() == targets {
+        return Ok(srcs);
+    }
+
+    let [src] = srcs[..] else {
+        return Err(Error::InvalidType);
+    };
+    let Type::Tuple(tuple) = *module.get_type(function.tmp(src)) else {
+        return Err(Error::InvalidType);
+    };
+
+    let elems: Box<[Type]> = module.get_tuple(tuple).into();
+    if elems.len() != targets {
+        return Err(Error::InvalidType);
+    }
+
+    Ok(elems
+        .iter()
+        .enumerate()
+        .map(|(index, ty)| {
+            let dst = function.new_tmpid(module.types.create_known(*ty));
+            function.push_stmt(Statement::Field { dst, src, index });
+            dst
+        })
+        .collect())
+}
+
+//
+
+#[derive(Debug, Clone)]
+pub struct Block {
+    pub stmts: Vec<Statement>,
+}
+
+impl Block {
+    /// the blocks the terminator of this block can jump to
+    pub fn successors(&self) -> Vec<BlockId> {
+        match self.stmts.last() {
+            Some(Statement::UnconditionalJump { id }) => vec![*id],
+            Some(Statement::ConditionalJump {
+                then_block,
+                else_block,
+                ..
+            }) => vec![*then_block, *else_block],
+            Some(Statement::SwitchInt { default, cases, .. }) => iter::once(*default)
+                .chain(cases.iter().map(|(_, block)| *block))
+                .collect(),
+            _ => vec![],
+        }
+    }
+}
+
+//
+
+#[derive(Debug, Clone)]
+pub struct Function {
+    /// `None` for anonymous function literals
+    pub name: Option<Rc<str>>,
+    pub returns: LinkedType,
+    pub params: Box<[LinkedType]>,
+    /// for named arguments, empty if the names aren't known, like for externs from Rust
+    pub param_names: Box<[Rc<str>]>,
+    /// the default values of the trailing parameters, empty if no parameter has one
+    pub param_defaults: Box<[Option<Literal>]>,
+    pub blocks: Vec<Block>,
+    pub is_extern: bool,
+    /// a C variadic extern, calls can pass more arguments than there are `params`
+    pub is_variadic: bool,
+    /// an extern from [`Module::add_builtin`], the builtins are recognized by this, not the name
+    pub builtin: bool,
+
+    pub current_block: BlockId,
+    toplevel: bool,
+    predeclared: Vec<(Span, FuncId)>,
+    /// variables from `x: i32;` that some path to the current point assigns nothing to,
+    /// only the `if` branches are told apart, an assignment in a loop or `match` arm counts
+    uninitialized: HashSet<VarId>,
+
+    pub variables: Vec<LinkedType>,
+    variables_raw: HashMap<Rc<str>, VarId>,
+    pub temporaries: Vec<LinkedType>,
+}
+
+impl Function {
+    pub fn new(returns: LinkedType, params: Box<[LinkedType]>) -> Self {
+        let mut func = Self::new_extern(returns, params, false);
+        // the entry block has to be the first one,
+        // even if the first statement creates more blocks
+        func.push_block();
+        func
+    }
+
+    pub fn new_extern(returns: LinkedType, params: Box<[LinkedType]>, is_extern: bool) -> Self {
+        Self {
+            name: None,
+            returns,
+            params,
+            param_names: Box::new([]),
+            param_defaults: Box::new([]),
+            blocks: Vec::new(),
+            is_extern,
+            is_variadic: false,
+            builtin: false,
+
+            current_block: BlockId(0),
+            toplevel: false,
+            predeclared: Vec::new(),
+            uninitialized: HashSet::new(),
+
+            variables: Vec::new(),
+            variables_raw: HashMap::new(),
+            temporaries: Vec::new(),
+        }
+    }
+
+    pub fn current(&self) -> Option<&Statement> {
+        self.blocks
+            .get(self.current_block.0)
+            .and_then(|b| b.stmts.last())
+    }
+
+    pub fn push_stmt(&mut self, stmt: Statement) {
+        if self.blocks.is_empty() {
+            self.push_block();
+        }
+
+        self.blocks[self.current_block.0].stmts.push(stmt);
+    }
+
+    pub fn push_block(&mut self) -> BlockId {
+        let id = BlockId(self.blocks.len());
+        self.blocks.push(Block { stmts: Vec::new() });
+        id
+    }
+
+    pub fn move_to_block(&mut self, id: BlockId) {
+        self.current_block = id;
+    }
+
+    pub fn terminate(&mut self) {
+        self.terminate_with(Statement::ReturnVoid);
+    }
+
+    pub fn terminate_with(&mut self, terminal_stmt: Statement) {
+        // println!("current: {:?}", self.current());
+        if self.current().map_or(false, |stmt| stmt.is_terminal()) {
+            return;
+        }
+
+        self.push_stmt(terminal_stmt);
+    }
+
+    /// returns `true` if `tmp` is a reference to a local variable of this function,
+    /// either directly from `&x` or through variables holding one
+    ///
+    /// locals die when the function returns, so these references can't escape it
+    pub fn points_to_local(&self, tmp: TmpId) -> bool {
+        let mut visited = HashSet::new();
+        let mut tmps = vec![tmp];
+
+        while let Some(tmp) = tmps.pop() {
+            let var = self.statements().find_map(|stmt| match stmt {
+                Statement::Ref { dst, .. } if *dst == tmp => Some(None),
+                Statement::Load { dst, src } if *dst == tmp => Some(Some(*src)),
+                _ => None,
+            });
+
+            match var {
+                Some(None) => return true,
+                // the loaded variable could have been assigned a local reference anywhere
+                Some(Some(var)) if visited.insert(var) => {
+                    tmps.extend(self.statements().filter_map(|stmt| match stmt {
+                        Statement::Let { dst, src } | Statement::Store { dst, src }
+                            if *dst == var =>
+                        {
+                            Some(*src)
+                        }
+                        _ => None,
+                    }));
+                }
+                _ => {}
+            }
+        }
+
+        false
+    }
+
+    fn statements(&self) -> impl Iterator<Item = &Statement> {
+        self.blocks.iter().flat_map(|block| block.stmts.iter())
+    }
+
+    /// appends the single block body of `callee` to `stmts`, as the call `dst = callee(args)`
+    fn inline(
+        &mut self,
+        callee: &Function,
+        dst: TmpId,
+        args: &[TmpId],
+        stmts: &mut Vec<Statement>,
+    ) {
+        let mut tmps = HashMap::new();
+        let mut vars = HashMap::new();
+
+        // params are the args, and the returned value goes directly to `dst`
+        for stmt in callee.blocks[0].stmts.iter() {
+            if let Statement::Param { dst, index } = stmt {
+                tmps.insert(*dst, args[*index]);
+            }
+        }
+        let mut ret = None;
+        if let Some(Statement::Return { src }) = callee.blocks[0].stmts.last() {
+            if tmps.contains_key(src) {
+                ret = Some(*src);
+            } else {
+                tmps.insert(*src, dst);
+            }
+        }
+
+        for stmt in callee.blocks[0].stmts.iter() {
+            match stmt {
+                Statement::Param { .. } | Statement::ReturnVoid => continue,
+                Statement::Return { src } => {
+                    // `return x` where `x` is a param
+                    if ret.is_some() {
+                        let var = self.new_varid(callee.tmp(*src));
+                        stmts.push(Statement::Let {
+                            dst: var,
+                            src: tmps[src],
+                        });
+                        stmts.push(Statement::Load { dst, src: var });
+                    }
+                    continue;
+                }
+                _ => {}
+            }
+
+            let mut stmt = stmt.clone();
+            // the closures borrow the fields separately, so no `self.new_tmpid`
+            let (temporaries, variables) = (&mut self.temporaries, &mut self.variables);
+            stmt.map_ids(
+                |tmp| {
+                    *tmps.entry(tmp).or_insert_with(|| {
+                        temporaries.push(callee.tmp(tmp));
+                        TmpId(temporaries.len() - 1)
+                    })
+                },
+                |var| {
+                    *vars.entry(var).or_insert_with(|| {
+                        variables.push(callee.var(var));
+                        VarId(variables.len() - 1)
+                    })
+                },
+            );
+            stmts.push(stmt);
+        }
+    }
+
+    /// removes the [`Statement::Nop`]s left by passes
+    ///
+    /// passes only replace statements with `Nop`,
+    /// so that the statements are shifted once at the end instead of after every pass
+    pub fn compact(&mut self) {
+        for block in self.blocks.iter_mut() {
+            block.stmts.retain(|stmt| !matches!(stmt, Statement::Nop));
+        }
+    }
+
+    /// replaces loads of variables that are only ever bound to a constant,
+    /// and binary expressions of constants, with the constant values
+    ///
+    /// `a := 2; b := a * 3;` loads `b` as `const 6`
+    fn propagate_consts(&mut self) {
+        // one `Let` and no `Store`s or `Ref`s means the value never changes
+        let mut lets = vec![0usize; self.variables.len()];
+        for stmt in self.statements() {
+            match stmt {
+                Statement::Let { dst, .. } => lets[dst.0] += 1,
+                Statement::Store { dst, .. } | Statement::Ref { src: dst, .. } => {
+                    lets[dst.0] = usize::MAX
+                }
+                _ => {}
+            }
+        }
+
+        let mut consts = HashMap::<TmpId, Literal>::new();
+        let mut vars = HashMap::<VarId, Literal>::new();
+        let mut changed = true;
+        while changed {
+            changed = false;
+            for stmt in self
+                .blocks
+                .iter_mut()
+                .flat_map(|block| block.stmts.iter_mut())
+            {
+                let folded = match stmt {
+                    Statement::Const { dst, src } => {
+                        consts.insert(*dst, src.clone());
+                        continue;
+                    }
+                    Statement::Let { dst, src } => {
+                        if let (1, Some(value)) = (lets[dst.0], consts.get(src)) {
+                            vars.insert(*dst, value.clone());
+                        }
+                        continue;
+                    }
+                    Statement::Load { dst, src } => {
+                        vars.get(src).map(|value| (*dst, value.clone()))
+                    }
+                    Statement::BinExpr { dst, lhs, op, rhs } => consts
+                        .get(lhs)
+                        .zip(consts.get(rhs))
+                        .and_then(|(lhs, rhs)| fold_binop(*op, lhs.clone(), rhs.clone()))
+                        .map(|value| (*dst, value)),
+                    _ => None,
+                };
+
+                if let Some((dst, src)) = folded {
+                    *stmt = Statement::Const { dst, src };
+                    changed = true;
+                }
+            }
+        }
+    }
+
+    /// removes `Func` statements that nothing uses anymore
+    fn remove_unused_funcs(&mut self) {
+        let mut uses = HashMap::<TmpId, usize>::new();
+        for stmt in self.statements() {
+            for tmp in stmt.tmps() {
+                *uses.entry(tmp).or_default() += 1;
+            }
+        }
+
+        for stmt in self
+            .blocks
+            .iter_mut()
+            .flat_map(|block| block.stmts.iter_mut())
+        {
+            if matches!(stmt, Statement::Func { dst, .. } if uses[dst] == 1) {
+                *stmt = Statement::Nop;
+            }
+        }
+    }
+
+    fn eliminate_dead_blocks(&mut self) {
+        let mut consts = HashMap::new();
+        let mut int_consts = HashMap::new();
+        for block in self.blocks.iter_mut() {
+            // anything after the first terminal statement is never executed
+            if let Some(end) = block.stmts.iter().position(Statement::is_terminal) {
+                block.stmts.truncate(end + 1);
+            }
+
+            for stmt in block.stmts.iter_mut() {
+                match stmt {
+                    Statement::Const {
+                        dst,
+                        src: Literal::Bool(value),
+                    } => {
+                        consts.insert(*dst, *value);
+                    }
+                    Statement::Const {
+                        dst,
+                        src: Literal::I32(value),
+                    } => {
+                        int_consts.insert(*dst, *value as i64);
+                    }
+                    Statement::Const {
+                        dst,
+                        src: Literal::I64(value),
+                    } => {
+                        int_consts.insert(*dst, *value);
+                    }
+                    Statement::Const {
+                        dst,
+                        src: Literal::USize(value),
+                    } => {
+                        int_consts.insert(*dst, *value as i64);
+                    }
+                    Statement::ConditionalJump {
+                        bool,
+                        then_block,
+                        else_block,
+                    } => {
+                        if let Some(value) = consts.get(bool) {
+                            let id = if *value { *then_block } else { *else_block };
+                            *stmt = Statement::UnconditionalJump { id };
+                        }
+                    }
+                    Statement::SwitchInt {
+                        val,
+                        default,
+                        cases,
+                    } => {
+                        if let Some(value) = int_consts.get(val) {
+                            let id = cases
+                                .iter()
+                                .find(|(case, _)| case == value)
+                                .map_or(*default, |(_, block)| *block);
+                            *stmt = Statement::UnconditionalJump { id };
+                        }
+                    }
+                    _ => {}
+                }
+            }
+        }
+
+        // more statements could still be added to the current block of the top level
+        let current = self.toplevel.then_some(self.current_block.0);
+        let mut live = vec![false; self.blocks.len()];
+        let mut stack = vec![BlockId(0)];
+        stack.extend(current.map(BlockId));
+        while let Some(block_id) = stack.pop() {
+            if !mem::replace(&mut live[block_id.0], true) {
+                stack.extend(self.blocks[block_id.0].successors());
+            }
+        }
+
+        let mut preds = vec![0usize; self.blocks.len()];
+        for (_, block) in self.blocks().filter(|(id, _)| live[id.0]) {
+            for succ in block.successors() {
+                preds[succ.0] += 1;
+            }
+        }
+
+        for block_id in 0..self.blocks.len() {
+            while live[block_id] {
+                let Some(Statement::UnconditionalJump { id: next }) =
+                    self.blocks[block_id].stmts.last()
+                else {
+                    break;
+                };
+                let next = next.0;
+                if next == 0 || next == block_id || Some(next) == current || preds[next] != 1 {
+                    break;
+                }
+
+                let stmts = mem::take(&mut self.blocks[next].stmts);
+                let block = &mut self.blocks[block_id].stmts;
+                block.pop();
+                block.extend(stmts);
+                live[next] = false;
+            }
+        }
+
+        let mut block_ids = Vec::with_capacity(self.blocks.len());
+        let mut next_id = 0;
+        for live in live.iter() {
+            block_ids.push(BlockId(next_id));
+            next_id += *live as usize;
+        }
+
+        let blocks = mem::take(&mut self.blocks);
+        self.blocks = blocks
+            .into_iter()
+            .zip(live)
+            .filter_map(|(block, live)| live.then_some(block))
+            .collect();
+        self.current_block = current.map_or(BlockId(0), |current| block_ids[current]);
+
+        for stmt in self
+            .blocks
+            .iter_mut()
+            .flat_map(|block| block.stmts.iter_mut())
+        {
+            match stmt {
+                Statement::UnconditionalJump { id } => *id = block_ids[id.0],
+                Statement::ConditionalJump {
+                    then_block,
+                    else_block,
+                    ..
+                } => {
+                    *then_block = block_ids[then_block.0];
+                    *else_block = block_ids[else_block.0];
+                }
+                Statement::SwitchInt { default, cases, .. } => {
+                    *default = block_ids[default.0];
+                    for (_, block) in cases.iter_mut() {
+                        *block = block_ids[block.0];
+                    }
+                }
+                _ => {}
+            }
+        }
+
+        self.release_unused_ids();
+    }
+
+    /// renumbers temporaries and variables so that the ones no statement mentions are gone
+    fn release_unused_ids(&mut self) {
+        let mut tmp_ids = vec![None; self.temporaries.len()];
+        let mut var_ids = vec![None; self.variables.len()];
+        let mut temporaries = Vec::new();
+        let mut variables = Vec::new();
+
+        for stmt in self
+            .blocks
+            .iter_mut()
+            .flat_map(|block| block.stmts.iter_mut())
+        {
+            stmt.map_ids(
+                |tmp| {
+                    *tmp_ids[tmp.0].get_or_insert_with(|| {
+                        temporaries.push(self.temporaries[tmp.0]);
+                        TmpId(temporaries.len() - 1)
+                    })
+                },
+                |var| {
+                    *var_ids[var.0].get_or_insert_with(|| {
+                        variables.push(self.variables[var.0]);
+                        VarId(variables.len() - 1)
+                    })
+                },
+            );
+        }
+
+        self.temporaries = temporaries;
+        self.variables = variables;
+        self.variables_raw
+            .retain(|_, var| var_ids[var.0].map(|new| *var = new).is_some());
+    }
+
+    /// returns `true` if any block reachable from the entry block ends with [`Statement::ReturnVoid`]
+    pub fn may_return_void(&self) -> bool {
+        let mut visited = vec![false; self.blocks.len()];
+        let mut stack = vec![BlockId(0)];
+
+        while let Some(block_id) = stack.pop() {
+            if mem::replace(&mut visited[block_id.0], true) {
+                continue;
+            }
+
+            for stmt in self.blocks[block_id.0].stmts.iter() {
+                match stmt {
+                    Statement::ReturnVoid => return true,
+                    Statement::UnconditionalJump { id } => stack.push(*id),
+                    Statement::ConditionalJump {
+                        then_block,
+                        else_block,
+                        ..
+                    } => {
+                        stack.push(*then_block);
+                        stack.push(*else_block);
+                    }
+                    Statement::SwitchInt { default, cases, .. } => {
+                        stack.push(*default);
+                        stack.extend(cases.iter().map(|(_, block)| *block));
+                    }
+                    _ => {}
+                }
+
+                if stmt.is_terminal() {
+                    break;
+                }
+            }
+        }
+
+        false
+    }
+
+    pub fn blocks(&self) -> impl DoubleEndedIterator<Item = (BlockId, &Block)> + ExactSizeIterator {
+        self.blocks.iter().enumerate().map(|(i, b)| (BlockId(i), b))
+    }
+
+    /// the control-flow graph, `cfg()[i]` are the successors of `BlockId(i)`
+    pub fn cfg(&self) -> Vec<Vec<BlockId>> {
+        self.blocks.iter().map(Block::successors).collect()
+    }
+
+    /// the reversed [`Self::cfg`], `pred_cfg()[i]` are the predecessors of `BlockId(i)`
+    pub fn pred_cfg(&self) -> Vec<Vec<BlockId>> {
+        let mut preds = vec![Vec::new(); self.blocks.len()];
+        for (block_id, block) in self.blocks() {
+            for succ in block.successors() {
+                if let Some(preds) = preds.get_mut(succ.0) {
+                    preds.push(block_id);
+                }
+            }
+        }
+        preds
+    }
+
+    /// the blocks reachable from the entry block in reverse post-order,
+    /// so every block comes after all of its dominators
+    pub fn blocks_in_dominance_order(&self) -> Vec<BlockId> {
+        let mut order = Vec::with_capacity(self.blocks.len());
+        if self.blocks.is_empty() {
+            return order;
+        }
+
+        let mut visited = vec![false; self.blocks.len()];
+        visited[0] = true;
+        // each block with the successors it hasn't visited yet
+        let mut stack = vec![(BlockId(0), self.blocks[0].successors().into_iter())];
+        while let Some((block_id, successors)) = stack.last_mut() {
+            match successors.next() {
+                Some(next) => {
+                    if next.0 < self.blocks.len() && !mem::replace(&mut visited[next.0], true) {
+                        stack.push((next, self.blocks[next.0].successors().into_iter()));
+                    }
+                }
+                None => {
+                    order.push(*block_id);
+                    stack.pop();
+                }
+            }
+        }
+
+        order.reverse();
+        order
+    }
+
+    pub fn var(&self, var: VarId) -> LinkedType {
+        self.variables[var.0]
+    }
+
+    pub fn tmp(&self, tmp: TmpId) -> LinkedType {
+        self.temporaries[tmp.0]
+    }
+
+    pub fn new_varid(&mut self, ty: LinkedType) -> VarId {
+        let id = VarId(self.variables.len());
+        self.variables.push(ty);
+        id
+    }
+
+    pub fn new_tmpid(&mut self, ty: LinkedType) -> TmpId {
+        let id = TmpId(self.temporaries.len());
+        self.temporaries.push(ty);
+        id
+    }
+}
+
+//
+
+/// the memory ordering of an atomic statement, like [`std::sync::atomic::Ordering`]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Ordering {
+    Relaxed,
+    Acquire,
+    Release,
+    SeqCst,
+}
+
+impl Ordering {
+    /// the end of the builtin names like `atomic_load_acquire`, `SeqCst` has none
+    pub fn suffix(self) -> &'static str {
+        match self {
+            Ordering::Relaxed => "_relaxed",
+            Ordering::Acquire => "_acquire",
+            Ordering::Release => "_release",
+            Ordering::SeqCst => "",
+        }
+    }
+
+    /// the ordering of a failed compare exchange, which doesn't store anything
+    pub fn failure(self) -> Self {
+        match self {
+            Ordering::Release => Ordering::Relaxed,
+            ordering => ordering,
+        }
+    }
+}
+
+/// the operation of an [`Statement::AtomicRmw`], `Xchg` just replaces the old value
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum AtomicRmwOp {
+    Add,
+    Sub,
+    And,
+    Or,
+    Xor,
+    Xchg,
+}
+
+//
+
+#[derive(Debug, Clone)]
+pub enum Statement {
+    Let {
+        dst: VarId,
+        src: TmpId,
+    },
+    Store {
+        dst: VarId,
+        src: TmpId,
+    },
+    Load {
+        dst: TmpId,
+        src: VarId,
+    },
+    Param {
+        dst: TmpId,
+        index: usize,
+    },
+    StoreGlobal {
+        dst: GlobalId,
+        src: TmpId,
+    },
+    LoadGlobal {
+        dst: TmpId,
+        src: GlobalId,
+    },
+    /// `&x` of a local variable
+    Ref {
+        dst: TmpId,
+        src: VarId,
+    },
+    /// `&x` of a global variable
+    RefGlobal {
+        dst: TmpId,
+        src: GlobalId,
+    },
+    Extern {
+        dst: TmpId,
+
+        src: FuncId,
+        name: Rc<str>,
+    },
+    Func {
+        dst: TmpId,
+        src: FuncId,
+    },
+    Const {
+        dst: TmpId,
+        src: Literal,
+    },
+    /// an `undef` value, the initial value of a variable declared like `x: i32;`
+    PoisonUndef {
+        dst: TmpId,
+    },
+    BinExpr {
+        dst: TmpId,
+        lhs: TmpId,
+        op: BinaryOp,
+        rhs: TmpId,
+    },
+    /// `if_true` if `cond` is true, otherwise `if_false`, without branching
+    Select {
+        dst: TmpId,
+        cond: TmpId,
+        if_true: TmpId,
+        if_false: TmpId,
+    },
+    Tuple {
+        dst: TmpId,
+        elems: Box<[TmpId]>,
+    },
+    Field {
+        dst: TmpId,
+        src: TmpId,
+        index: usize,
+    },
+    /// the integer `src` converted to the integer type of `dst`, like `n as usize`
+    Cast {
+        dst: TmpId,
+        src: TmpId,
+    },
+    /// the element `index` of the slice `src`, traps if the index is out of bounds
+    Index {
+        dst: TmpId,
+        src: TmpId,
+        index: TmpId,
+    },
+    /// loads the value that the reference `src` points to, like `*r`
+    LoadRef {
+        dst: TmpId,
+        src: TmpId,
+    },
+    /// stores `src` where the reference `dst` points to, like `*r = value`
+    StoreRef {
+        dst: TmpId,
+        src: TmpId,
+    },
+    /// loads the integer that the reference `ptr` points to as a single atomic operation
+    AtomicLoad {
+        dst: TmpId,
+        ptr: TmpId,
+        ordering: Ordering,
+    },
+    /// stores `src` to the integer that the reference `ptr` points to as a single atomic operation
+    AtomicStore {
+        ptr: TmpId,
+        src: TmpId,
+        ordering: Ordering,
+    },
+    /// applies `op` with `val` to the integer that the reference `ptr` points to
+    /// as a single atomic operation, `dst` is the old value
+    AtomicRmw {
+        dst: TmpId,
+        ptr: TmpId,
+        op: AtomicRmwOp,
+        val: TmpId,
+        ordering: Ordering,
+    },
+    /// replaces the integer that the reference `ptr` points to with `desired`,
+    /// if it is still `expected`, as a single atomic operation
+    ///
+    /// `dst` is a tuple of the old value and whether it was replaced
+    CompareExchange {
+        dst: TmpId,
+        ptr: TmpId,
+        expected: TmpId,
+        desired: TmpId,
+        success_order: Ordering,
+        fail_order: Ordering,
+    },
+    /// orders the memory accesses around it, without accessing memory itself
+    Fence {
+        ordering: Ordering,
+    },
+    Call {
+        dst: TmpId,
+        func: TmpId,
+        args: Box<[TmpId]>,
+    },
+    /// does nothing, passes leave it in place of removed statements, see [`Function::compact`]
+    Nop,
+    Return {
+        src: TmpId,
+    },
+    ReturnVoid,
+    /// control never reaches this point, like after a call returning [`Type::Never`]
+    Unreachable,
+    /// aborts the program right away, for code that must never run, like `unreachable()`
+    Trap,
+    UnconditionalJump {
+        id: BlockId,
+    },
+    ConditionalJump {
+        bool: TmpId,
+        then_block: BlockId,
+        else_block: BlockId,
+    },
+    /// jumps to the block of the case equal to `val`, or to `default` if there is none
+    SwitchInt {
+        val: TmpId,
+        default: BlockId,
+        cases: Vec<(i64, BlockId)>,
+    },
+}
+
+impl Statement {
+    pub const fn is_terminal(&self) -> bool {
+        matches!(
+            self,
+            Statement::Return { .. }
+                | Statement::ReturnVoid
+                | Statement::Unreachable
+                | Statement::Trap
+                | Statement::UnconditionalJump { .. }
+                | Statement::ConditionalJump { .. }
+                | Statement::SwitchInt { .. }
+        )
+    }
+
+    /// replaces every temporary and variable this statement defines or uses
+    pub fn map_ids(
+        &mut self,
+        mut tmp: impl FnMut(TmpId) -> TmpId,
+        mut var: impl FnMut(VarId) -> VarId,
+    ) {
+        match self {
+            Statement::Let { dst, src } | Statement::Store { dst, src } => {
+                *dst = var(*dst);
+                *src = tmp(*src);
+            }
+            Statement::Load { dst, src } | Statement::Ref { dst, src } => {
+                *dst = tmp(*dst);
+                *src = var(*src);
+            }
+            Statement::Param { dst, .. }
+            | Statement::LoadGlobal { dst, .. }
+            | Statement::RefGlobal { dst, .. }
+            | Statement::Extern { dst, .. }
+            | Statement::Func { dst, .. }
+            | Statement::Const { dst, .. }
+            | Statement::PoisonUndef { dst } => *dst = tmp(*dst),
+            Statement::StoreGlobal { src, .. } | Statement::Return { src } => *src = tmp(*src),
+            Statement::BinExpr { dst, lhs, rhs, .. } => {
+                *dst = tmp(*dst);
+                *lhs = tmp(*lhs);
+                *rhs = tmp(*rhs);
+            }
+            Statement::Select {
+                dst,
+                cond,
+                if_true,
+                if_false,
+            } => {
+                *dst = tmp(*dst);
+                *cond = tmp(*cond);
+                *if_true = tmp(*if_true);
+                *if_false = tmp(*if_false);
+            }
+            Statement::Tuple { dst, elems } => {
+                *dst = tmp(*dst);
+                elems.iter_mut().for_each(|elem| *elem = tmp(*elem));
+            }
+            Statement::Field { dst, src, .. }
+            | Statement::Cast { dst, src }
+            | Statement::LoadRef { dst, src }
+            | Statement::StoreRef { dst, src } => {
+                *dst = tmp(*dst);
+                *src = tmp(*src);
+            }
+            Statement::Index { dst, src, index } => {
+                *dst = tmp(*dst);
+                *src = tmp(*src);
+                *index = tmp(*index);
+            }
+            Statement::AtomicLoad { dst, ptr, .. } => {
+                *dst = tmp(*dst);
+                *ptr = tmp(*ptr);
+            }
+            Statement::AtomicStore { ptr, src, .. } => {
+                *ptr = tmp(*ptr);
+                *src = tmp(*src);
+            }
+            Statement::AtomicRmw { dst, ptr, val, .. } => {
+                *dst = tmp(*dst);
+                *ptr = tmp(*ptr);
+                *val = tmp(*val);
+            }
+            Statement::CompareExchange {
+                dst,
+                ptr,
+                expected,
+                desired,
+                ..
+            } => {
+                *dst = tmp(*dst);
+                *ptr = tmp(*ptr);
+                *expected = tmp(*expected);
+                *desired = tmp(*desired);
+            }
+            Statement::Call { dst, func, args } => {
+                *dst = tmp(*dst);
+                *func = tmp(*func);
+                args.iter_mut().for_each(|arg| *arg = tmp(*arg));
+            }
+            Statement::ConditionalJump { bool, .. } => *bool = tmp(*bool),
+            Statement::SwitchInt { val, .. } => *val = tmp(*val),
+            Statement::Nop
+            | Statement::ReturnVoid
+            | Statement::Unreachable
+            | Statement::Trap
+            | Statement::Fence { .. }
+            | Statement::UnconditionalJump { .. } => {}
+        }
+    }
+
+    /// the temporary this statement assigns, always the first one in [`Self::tmps`]
+    pub fn defined_tmp(&self) -> Option<TmpId> {
+        match self {
+            Statement::Load { dst, .. }
+            | Statement::Param { dst, .. }
+            | Statement::LoadGlobal { dst, .. }
+            | Statement::Ref { dst, .. }
+            | Statement::RefGlobal { dst, .. }
+            | Statement::Extern { dst, .. }
+            | Statement::Func { dst, .. }
+            | Statement::Const { dst, .. }
+            | Statement::PoisonUndef { dst }
+            | Statement::BinExpr { dst, .. }
+            | Statement::Select { dst, .. }
+            | Statement::Tuple { dst, .. }
+            | Statement::Field { dst, .. }
+            | Statement::Cast { dst, .. }
+            | Statement::Index { dst, .. }
+            | Statement::LoadRef { dst, .. }
+            | Statement::AtomicLoad { dst, .. }
+            | Statement::AtomicRmw { dst, .. }
+            | Statement::CompareExchange { dst, .. }
+            | Statement::Call { dst, .. } => Some(*dst),
+            _ => None,
+        }
+    }
+
+    /// every temporary this statement defines or uses
+    pub fn tmps(&self) -> Vec<TmpId> {
+        let mut tmps = Vec::new();
+        self.clone().map_ids(
+            |tmp| {
+                tmps.push(tmp);
+                tmp
+            },
+            |var| var,
+        );
+        tmps
+    }
+}
+
+//
+
+/// the interning maps aren't serialized, see [`Types::reindex`]
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Types {
+    type_links: Vec<Type>,
+    #[serde(skip)]
+    known_type_links: Option<HashMap<Type, LinkedType>>,
+    tuples: Vec<Box<[Type]>>,
+    #[serde(skip)]
+    known_tuples: Option<HashMap<Box<[Type]>, TupleId>>,
+    signatures: Vec<Signature>,
+    #[serde(skip)]
+    known_signatures: Option<HashMap<Signature, SignatureId>>,
+    slices: Vec<Type>,
+    #[serde(skip)]
+    known_slices: Option<HashMap<Type, SliceId>>,
+    refs: Vec<Type>,
+    #[serde(skip)]
+    known_refs: Option<HashMap<Type, RefId>>,
+    /// not interned, every `opaque type` is a different type
+    opaques: Vec<(Rc<str>, Type)>,
+}
+
+impl Types {
+    pub fn create(&mut self) -> LinkedType {
+        self.create_new(Type::Unknown)
+    }
+
+    pub fn create_known(&mut self, ty: Type) -> LinkedType {
+        if let Some(known) = self.known_type_links.as_ref().and_then(|map| map.get(&ty)) {
+            return *known;
+        }
+
+        self.create_new(ty)
+    }
+
+    /// tuples are interned, so two tuples with the same fields have the same [`TupleId`]
+    pub fn create_tuple(&mut self, elems: Box<[Type]>) -> TupleId {
+        let known_tuples = self.known_tuples.get_or_insert_with(Default::default);
+        if let Some(known) = known_tuples.get(&elems) {
+            return *known;
+        }
+
+        let id = TupleId(self.tuples.len());
+        self.tuples.push(elems.clone());
+        known_tuples.insert(elems, id);
+        id
+    }
+
+    /// signatures are interned the same way as tuples
+    pub fn create_signature(&mut self, sig: Signature) -> SignatureId {
+        let known_signatures = self.known_signatures.get_or_insert_with(Default::default);
+        if let Some(known) = known_signatures.get(&sig) {
+            return *known;
+        }
+
+        let id = SignatureId(self.signatures.len());
+        self.signatures.push(sig.clone());
+        known_signatures.insert(sig, id);
+        id
+    }
+
+    /// slices are interned by their element type
+    pub fn create_slice(&mut self, elem: Type) -> SliceId {
+        let known_slices = self.known_slices.get_or_insert_with(Default::default);
+        if let Some(known) = known_slices.get(&elem) {
+            return *known;
+        }
+
+        let id = SliceId(self.slices.len());
+        self.slices.push(elem);
+        known_slices.insert(elem, id);
+        id
+    }
+
+    /// references are interned like slices
+    pub fn create_ref(&mut self, elem: Type) -> RefId {
+        let known_refs = self.known_refs.get_or_insert_with(Default::default);
+        if let Some(known) = known_refs.get(&elem) {
+            return *known;
+        }
+
+        let id = RefId(self.refs.len());
+        self.refs.push(elem);
+        known_refs.insert(elem, id);
+        id
+    }
+
+    pub fn create_opaque(&mut self, name: Rc<str>, ty: Type) -> OpaqueId {
+        let id = OpaqueId(self.opaques.len());
+        self.opaques.push((name, ty));
+        id
+    }
+
+    /// `ty` with every `from` in it replaced by `to`, also inside tuples, slices, references
+    /// and function pointers
+    pub fn substitute(&mut self, ty: Type, from: Type, to: Type) -> Type {
+        if ty == from {
+            return to;
+        }
+
+        match ty {
+            Type::FnPtr(sig) => {
+                let sig = self.signatures[sig.0].clone();
+                let sig = Signature {
+                    returns: self.substitute(sig.returns, from, to),
+                    params: sig
+                        .params
+                        .iter()
+                        .map(|param| self.substitute(*param, from, to))
+                        .collect(),
+                };
+                Type::FnPtr(self.create_signature(sig))
+            }
+            Type::Tuple(tuple) => {
+                let elems = self.tuples[tuple.0].clone();
+                let elems = elems
+                    .iter()
+                    .map(|elem| self.substitute(*elem, from, to))
+                    .collect();
+                Type::Tuple(self.create_tuple(elems))
+            }
+            Type::Slice(slice) => {
+                let elem = self.substitute(self.slices[slice.0], from, to);
+                Type::Slice(self.create_slice(elem))
+            }
+            Type::Ref(elem) => {
+                let elem = self.substitute(self.refs[elem.0], from, to);
+                Type::Ref(self.create_ref(elem))
+            }
+            ty => ty,
+        }
+    }
+
+    fn len(&self) -> TypesLen {
+        TypesLen {
+            type_links: self.type_links.len(),
+            tuples: self.tuples.len(),
+            signatures: self.signatures.len(),
+            slices: self.slices.len(),
+            refs: self.refs.len(),
+            opaques: self.opaques.len(),
+        }
+    }
+
+    /// forgets the types created after [`Self::len`], also in the interning maps
+    fn truncate(&mut self, len: TypesLen) {
+        self.type_links.truncate(len.type_links);
+        self.tuples.truncate(len.tuples);
+        self.signatures.truncate(len.signatures);
+        self.slices.truncate(len.slices);
+        self.refs.truncate(len.refs);
+        self.opaques.truncate(len.opaques);
+
+        if let Some(map) = self.known_type_links.as_mut() {
+            map.retain(|_, id| id.0 < len.type_links);
+        }
+        if let Some(map) = self.known_tuples.as_mut() {
+            map.retain(|_, id| id.0 < len.tuples);
+        }
+        if let Some(map) = self.known_signatures.as_mut() {
+            map.retain(|_, id| id.0 < len.signatures);
+        }
+        if let Some(map) = self.known_slices.as_mut() {
+            map.retain(|_, id| id.0 < len.slices);
+        }
+        if let Some(map) = self.known_refs.as_mut() {
+            map.retain(|_, id| id.0 < len.refs);
+        }
+    }
+
+    /// rebuilds the interning maps after deserializing,
+    /// so that new tuples, signatures, slices and refs get the existing ids
+    fn reindex(&mut self) {
+        let mut known_type_links = HashMap::new();
+        for (i, ty) in self.type_links.iter().enumerate() {
+            // unresolved links are never shared
+            if *ty != Type::Unknown {
+                known_type_links.entry(*ty).or_insert(LinkedType(i));
+            }
+        }
+        self.known_type_links = Some(known_type_links);
+
+        self.known_tuples = Some(
+            self.tuples
+                .iter()
+                .cloned()
+                .zip((0..).map(TupleId))
+                .collect(),
+        );
+        self.known_signatures = Some(
+            self.signatures
+                .iter()
+                .cloned()
+                .zip((0..).map(SignatureId))
+                .collect(),
+        );
+        self.known_slices = Some(
+            self.slices
+                .iter()
+                .copied()
+                .zip((0..).map(SliceId))
+                .collect(),
+        );
+        self.known_refs = Some(self.refs.iter().copied().zip((0..).map(RefId)).collect());
+    }
+
+    fn create_new(&mut self, ty: Type) -> LinkedType {
+        let id = LinkedType(self.type_links.len());
+        self.type_links.push(ty);
+        match self
+            .known_type_links
+            .get_or_insert_with(Default::default)
+            .entry(ty)
+        {
+            Entry::Vacant(entry) => _ = entry.insert(id),
+            Entry::Occupied(_) => {}
+        }
+        id
+    }
+}
+
+/// the lengths of the tables in [`Types`], see [`Types::truncate`]
+#[derive(Debug, Clone, Copy)]
+struct TypesLen {
+    type_links: usize,
+    tuples: usize,
+    signatures: usize,
+    slices: usize,
+    refs: usize,
+    opaques: usize,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash, Serialize, Deserialize)]
+pub enum Type {
+    Func(FuncId),
+    FnPtr(SignatureId),
+    Tuple(TupleId),
+    /// a length and a pointer to the elements
+    Slice(SliceId),
+    /// a pointer to a variable
+    Ref(RefId),
+    /// from `opaque type`, stored like the underlying type but not interchangeable with it
+    Opaque(OpaqueId),
+    Bool,
+    I32,
+    I64,
+    USize,
+    Str,
+    Never,
+    Void,
+    Unknown,
+}
+
+/// the param and return types of a function pointer
+#[derive(Debug, Clone, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub struct Signature {
+    pub returns: Type,
+    pub params: Box<[Type]>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub enum Literal {
+    Bool(bool),
+    I32(i32),
+    I64(i64),
+    USize(usize),
+    Str(Box<str>),
+    /// `b"..."`, a `str` that might not be UTF-8
+    ByteStr(Box<[u8]>),
+    /// `[1, 2, 4, 8]`, the elements all have the element type of the slice
+    Slice(SliceId, Box<[Literal]>),
+}
+
+impl Literal {
+    pub const fn ty(&self) -> Type {
+        match self {
+            Literal::Bool(_) => Type::Bool,
+            Literal::I32(_) => Type::I32,
+            Literal::I64(_) => Type::I64,
+            Literal::USize(_) => Type::USize,
+            Literal::Str(_) | Literal::ByteStr(_) => Type::Str,
+            Literal::Slice(slice, _) => Type::Slice(*slice),
+        }
+    }
+}
+
+//
+
+#[cfg(test)]
+mod tests {
+    use std::{
+        collections::HashMap,
+        path::{Path, PathBuf},
+    };
+
+    use lexer::Lexer;
+    use parser::{
+        ast::{Ast, BinaryOp, Expr, Root},
+        ParseStream,
+    };
+
+    use crate::{
+        AtomicRmwOp, BlockId, Error, FuncId, Function, GlobalId, LinkedType, Literal, Module,
+        Ordering, Result, Statement, Static, TmpId, Type, VerifyError, Warning,
+    };
+
+    //
+
+    fn process(str: &str) -> Result<FuncId> {
+        process_in(&mut Module::new(), str)
+    }
+
+    fn process_in(module: &mut Module, str: &str) -> Result<FuncId> {
+        let mut stream = ParseStream::from_lexer(Lexer::new(str));
+        let ast: Ast<Root> = stream.parse().unwrap();
+        module.process(&ast)
+    }
+
+    #[test]
+    fn missing_return() {
+        let res = process("f := fn() -> i32 { if 1 == 1 { return 1; } };");
+        assert!(matches!(res, Err(Error::MissingReturn { .. })), "{res:?}");
+
+        let res = process("f := fn() -> i32 { if 1 == 1 { return 1; } else { return 2; } };");
+        assert!(res.is_ok(), "{res:?}");
+
+        let res = process("f := fn() { if 1 == 1 { return; } };");
+        assert!(res.is_ok(), "{res:?}");
+    }
+
+    #[test]
+    fn statics_persist() {
+        let mut module = Module::new();
+
+        process_in(&mut module, "x := 5; f := fn() -> i32 { return x; };").unwrap();
+        process_in(&mut module, "x = f() + 1;").unwrap();
+        assert_eq!(module.globals().count(), 1);
+
+        let res = process_in(&mut module, "f = 4;");
+        assert!(matches!(res, Err(Error::InvalidType)), "{res:?}");
+
+        let res = process_in(&mut module, "y = 4;");
+        assert!(matches!(res, Err(Error::VariableNotFound(_))), "{res:?}");
+    }
+
+    #[test]
+    fn failed_process_rolls_back() {
+        let mut module = Module::new();
+        process_in(&mut module, "x := 5;").unwrap();
+        let functions = module.functions().len();
+        let type_links = module.types.type_links.len();
+
+        let res = process_in(
+            &mut module,
+            "g := fn() -> i32 { return 1; }; y := (6, \"a\"); f := fn() -> i32 { return z; };",
+        );
+        assert!(matches!(res, Err(Error::VariableNotFound(_))), "{res:?}");
+
+        assert_eq!(module.functions().len(), functions);
+        assert_eq!(module.types.type_links.len(), type_links);
+        assert!(module.types.tuples.is_empty());
+        assert_eq!(module.globals().count(), 1);
+        assert!(module.get_static("g").is_none());
+        assert!(module.get_static("y").is_none());
+        assert!(module.get_static("x").is_some());
+
+        // the types forgotten above are created again with new ids
+        process_in(&mut module, "y := (6, \"a\"); z := x;").unwrap();
+        assert_eq!(module.verify(), []);
+    }
+
+    #[test]
+    fn extern_fn() {
+        let mut module = Module::new();
+        process_in(&mut module, "x := abs(5); extern fn abs(n: i32) -> i32;").unwrap();
+
+        let abs = module.get_function_by_name("abs").unwrap();
+        assert!(module.get_function(abs).is_extern);
+
+        let res = process_in(&mut module, "extern fn abs(n: i32) -> i32; y := abs(6);");
+        assert!(res.is_ok(), "{res:?}");
+
+        let res = process_in(&mut module, "extern fn abs(n: str) -> i32;");
+        assert!(matches!(res, Err(Error::InvalidType)), "{res:?}");
+
+        let res = process_in(&mut module, "z := labs(5);");
+        assert!(matches!(res, Err(Error::VariableNotFound(_))), "{res:?}");
+    }
+
+    #[test]
+    fn bool_variable() {
+        let res =
+            process("f := fn() -> i32 { b := true; b = false; if b { return 1; }; return 0; };");
+        assert!(res.is_ok(), "{res:?}");
+
+        let res = process("b := true; b = 4;");
+        assert!(matches!(res, Err(Error::UnexpectedType { .. })), "{res:?}");
+
+        let res = process("a := true; b := a == false; c := a != b;");
+        assert!(res.is_ok(), "{res:?}");
+
+        let res = process("a := true; b := a == 1;");
+        assert!(matches!(res, Err(Error::UnexpectedType { .. })), "{res:?}");
+    }
+
+    #[test]
+    fn immediately_invoked() {
+        let mut module = Module::new();
+        process_in(&mut module, "x := (fn(x: i32) -> i32 { x * 2 })(21);").unwrap();
+
+        assert_eq!(*module.get_type(module.get_global(GlobalId(0))), Type::I32);
+        assert!(module.functions().iter().all(|func| func.name.is_none()));
+
+        process_in(&mut module, "double := fn(x: i32) -> i32 { x * 2 };").unwrap();
+        let double = module.get_function_by_name("double").unwrap();
+        assert_eq!(module.get_function(double).name.as_deref(), Some("double"));
+
+        let res = process("f := fn() -> i32 { \"a\" };");
+        assert!(matches!(res, Err(Error::TypeMismatch { .. })), "{res:?}");
+    }
+
+    #[test]
+    fn tuple_destructure() {
+        let res = process("f := fn() -> (i32, str) { return (1, \"a\"); }; a, b := f();");
+        assert!(res.is_ok(), "{res:?}");
+
+        let res = process("f := fn() -> (i32, str) { return (1, \"a\"); }; a, b, c := f();");
+        assert!(matches!(res, Err(Error::InvalidType)), "{res:?}");
+    }
+
+    #[test]
+    fn str_len() {
+        let mut module = Module::new();
+        module.add_extern("len", Type::USize, &[Type::Str]);
+
+        process_in(&mut module, "small := len(\"abcd\") < 5;").unwrap();
+        process_in(&mut module, "four := len(\"abcd\") == 4;").unwrap();
+
+        for name in ["small", "four"] {
+            let Some(Static::Global(global)) = module.get_static(name) else {
+                panic!("{name} should be a global");
+            };
+            assert_eq!(*module.get_type(module.get_global(global)), Type::Bool);
+        }
+    }
+
+    #[test]
+    fn nested_call() {
+        let mut module = Module::new();
+        let main = process_in(
+            &mut module,
+            "inner := fn(n: i32) -> i32 { n + 1 }; outer := fn(n: i32) -> i32 { n * 2 }; x := outer(inner(3));",
+        )
+        .unwrap();
+
+        let calls: Vec<_> = module
+            .get_function(main)
+            .blocks
+            .iter()
+            .flat_map(|block| block.stmts.iter())
+            .filter_map(|stmt| match stmt {
+                Statement::Call { dst, args, .. } => Some((*dst, args.clone())),
+                _ => None,
+            })
+            .collect();
+
+        // the inner call runs first and its result is the argument of the outer call
+        let [(inner, _), (_, outer_args)] = &calls[..] else {
+            panic!("expected two calls, got {calls:?}");
+        };
+        assert_eq!(&outer_args[..], &[*inner]);
+    }
+
+    #[test]
+    fn panic_never_returns() {
+        let mut module = Module::new();
+        module.add_extern("panic", Type::Never, &[Type::Str]);
+
+        let res = process_in(
+            &mut module,
+            "check := fn(x: i32) -> i32 { if x < 0 { panic(\"negative!\"); }; return x; };",
+        );
+        assert!(res.is_ok(), "{res:?}");
+
+        // no return needed after a call that never returns
+        let res = process_in(&mut module, "fail := fn() -> i32 { panic(\"always\"); };");
+        assert!(res.is_ok(), "{res:?}");
+    }
+
+    #[test]
+    fn functions_order() {
+        let mut module = Module::new();
+        let abs = module.add_extern("abs", Type::I32, &[Type::I32]);
+
+        // `b` is used before it is defined, so it gets predeclared ahead of its body
+        process_in(
+            &mut module,
+            "a := fn() -> i32 { b() }; b := fn() -> i32 { abs(1) }; c := fn() -> i32 { a() };",
+        )
+        .unwrap();
+        let neg = module.add_extern("neg", Type::I32, &[Type::I32]);
+
+        let ids =
+            ["abs", "a", "b", "c", "neg"].map(|name| module.get_function_by_name(name).unwrap());
+        assert_eq!(ids[0], abs);
+        assert_eq!(ids[4], neg);
+
+        for (index, func) in module.functions().iter().enumerate() {
+            if let Some(name) = func.name.as_deref() {
+                assert_eq!(module.get_function_by_name(name), Some(FuncId(index)));
+            }
+        }
+
+        let mut sorted = ids;
+        sorted.sort();
+        assert_eq!(sorted, ids, "named functions should get ascending ids");
+    }
+
+    #[test]
+    fn bool_condition() {
+        let res = process("ready := fn() -> bool { true }; x := 1; if ready() { x = 2; };");
+        assert!(res.is_ok(), "{res:?}");
+
+        let res = process("count := fn() -> i32 { 1 }; x := 1; if count() { x = 2; };");
+        assert!(matches!(res, Err(Error::UnexpectedType { .. })), "{res:?}");
+    }
+
+    #[test]
+    fn binop_operand_span() {
+        let source = "x := 1 + true;";
+        let res = process(source);
+        let Err(Error::UnexpectedType { span, .. }) = res else {
+            panic!("{res:?}");
+        };
+        assert_eq!(span.as_str(source), "true");
+
+        let source = "x := \"a\" * 2;";
+        let res = process(source);
+        let Err(Error::UnexpectedType { span, .. }) = res else {
+            panic!("{res:?}");
+        };
+        assert_eq!(span.as_str(source), "\"a\"");
+
+        let res = process("x := 1 + 2 * 3 < 4 && true;");
+        assert!(res.is_ok(), "{res:?}");
+    }
+
+    #[test]
+    fn assert_eq_helpers() {
+        let mut module = Module::new();
+        module.add_builtin("assert_eq", Type::Void, &[Type::I32, Type::I32]);
+        let bool_helper =
+            module.add_extern("assert_eq_bool", Type::Void, &[Type::Bool, Type::Bool]);
+        let str_helper = module.add_extern("assert_eq_str", Type::Void, &[Type::Str, Type::Str]);
+
+        let main = process_in(
+            &mut module,
+            "assert_eq(1, 2); assert_eq(true, false); assert_eq(\"a\", \"b\");",
+        )
+        .unwrap();
+        let externs: Vec<_> = module
+            .get_function(main)
+            .blocks
+            .iter()
+            .flat_map(|block| block.stmts.iter())
+            .filter_map(|stmt| match stmt {
+                Statement::Extern { src, .. } => Some(*src),
+                _ => None,
+            })
+            .collect();
+        let assert_eq = module.get_function_by_name("assert_eq").unwrap();
+        assert_eq!(
+            externs,
+            [assert_eq, assert_eq, bool_helper, assert_eq, str_helper]
+        );
+
+        let res = process_in(&mut module, "assert_eq(true, 1);");
+        assert!(matches!(res, Err(Error::UnexpectedType { .. })), "{res:?}");
+    }
+
+    #[test]
+    fn returns_local_reference() {
+        for source in [
+            "f := fn() -> &i32 { x := 1; return &x; };",
+            "f := fn() -> &i32 { x := 1; r := &x; return r; };",
+            "f := fn() -> &i32 { x := 1; &x };",
+            "f := fn(x: i32) -> &i32 { return &x; };",
+        ] {
+            let res = process(source);
+            assert!(
+                matches!(res, Err(Error::ReturnsLocalReference { .. })),
+                "{source}: {res:?}"
+            );
+        }
+
+        let mut module = Module::new();
+        process_in(&mut module, "y := 1; g := fn() -> &i32 { &y };").unwrap();
+        process_in(&mut module, "id := fn(r: &i32) -> &i32 { return r; };").unwrap();
+
+        let id = module.get_function_by_name("id").unwrap();
+        let returns = *module.get_type(module.get_function(id).returns);
+        assert_eq!(module.type_name(returns), "&i32");
+    }
+
+    #[test]
+    fn inline_calls() {
+        let mut module = Module::new();
+        let main = process_in(
+            &mut module,
+            r#"
+                helper := fn(n: i32) -> i32 { m := n * 2; m + 1 };
+                fact := fn(n: i32) -> i32 { if n <= 1 { return 1; }; return n * fact(n - 1); };
+                user := fn(x: i32) -> i32 { return helper(x) + fact(x); };
+            "#,
+        )
+        .unwrap();
+        let helper = module.get_function_by_name("helper").unwrap();
+        let fact = module.get_function_by_name("fact").unwrap();
+        let user = module.get_function_by_name("user").unwrap();
+
+        module.inline_calls(16);
+
+        let graph = module.call_graph();
+        assert_eq!(
+            graph[&user],
+            [fact],
+            "only the non-recursive helper is inlined"
+        );
+        assert_eq!(graph[&fact], [fact]);
+        assert!(graph[&main].is_empty());
+
+        let stmts = &module.get_function(user).blocks[0].stmts;
+        assert!(stmts
+            .iter()
+            .all(|stmt| !matches!(stmt, Statement::Func { src, .. } if *src == helper)));
+        let muls = stmts
+            .iter()
+            .filter(|stmt| {
+                matches!(
+                    stmt,
+                    Statement::BinExpr {
+                        op: BinaryOp::Mul,
+                        ..
+                    }
+                )
+            })
+            .count();
+        assert_eq!(muls, 1, "{stmts:#?}");
+
+        // nothing is small enough
+        let mut module = Module::new();
+        process_in(
+            &mut module,
+            "f := fn() -> i32 { 1 }; g := fn() -> i32 { f() };",
+        )
+        .unwrap();
+        let graph = module.call_graph();
+        module.inline_calls(0);
+        assert_eq!(module.call_graph(), graph);
+
+        // callees that never return are left alone
+        let mut module = Module::new();
+        module.add_extern("panic", Type::Never, &[Type::Str]);
+        process_in(
+            &mut module,
+            "fail := fn() -> i32 { panic(\"x\") }; g := fn() -> i32 { y := fail(); return y + 1; };",
+        )
+        .unwrap();
+        let graph = module.call_graph();
+        module.inline_calls(16);
+        assert_eq!(module.call_graph(), graph);
+        assert_eq!(module.verify(), []);
+    }
+
+    #[test]
+    fn eliminate_dead_blocks() {
+        let mut module = Module::new();
+        process_in(
+            &mut module,
+            "f := fn() -> i32 { if false { return 1; }; return 2; };",
+        )
+        .unwrap();
+        let f = module.get_function_by_name("f").unwrap();
+        let temporaries = module.get_function(f).temporaries.len();
+        assert!(module.get_function(f).blocks.len() > 1);
+
+        module.eliminate_dead_blocks();
+
+        let func = module.get_function(f);
+        assert_eq!(func.blocks.len(), 1, "{:#?}", func.blocks);
+        assert!(matches!(
+            func.blocks[0].stmts.last(),
+            Some(Statement::Return { .. })
+        ));
+        assert!(
+            func.temporaries.len() < temporaries,
+            "the `1` only lived in the dead block"
+        );
+        assert!(func.statements().all(|stmt| !matches!(
+            stmt,
+            Statement::Const {
+                src: Literal::I32(1),
+                ..
+            }
+        )));
+    }
+
+    #[test]
+    fn verify() {
+        let mut module = Module::new();
+        process_in(
+            &mut module,
+            r#"
+                helper := fn(n: i32) -> i32 { m := n * 2; m + 1 };
+                f := fn(n: i32) -> i32 {
+                    x := helper(n);
+                    if x < 4 { return 1; } else { x = x + 1; };
+                    for { if x > 10 { return x; }; x = x + 1; };
+                };
+                y := f(2);
+            "#,
+        )
+        .unwrap();
+        assert_eq!(module.verify(), []);
+        module.inline_calls(16);
+        module.eliminate_dead_blocks();
+        assert_eq!(module.verify(), []);
+
+        let f = module.get_function_by_name("f").unwrap();
+        let helper = module.get_function_by_name("helper").unwrap();
+
+        // the returned value is never defined
+        let mut broken = module.clone();
+        let func = &mut broken.functions[helper.0];
+        let Some(Statement::Return { src }) = func.blocks[0].stmts.pop() else {
+            panic!("helper should end with a return");
+        };
+        func.blocks[0]
+            .stmts
+            .retain(|stmt| stmt.defined_tmp() != Some(src));
+        func.blocks[0].stmts.push(Statement::Return { src });
+        assert!(broken.verify().contains(&VerifyError::UndefinedTmp {
+            func: helper,
+            tmp: src
+        }));
+
+        // jump to a block that doesn't exist
+        let mut broken = module.clone();
+        let func = &mut broken.functions[f.0];
+        let block = BlockId(func.blocks.len());
+        func.blocks[0].stmts.pop();
+        func.blocks[0]
+            .stmts
+            .push(Statement::UnconditionalJump { id: block });
+        assert!(broken
+            .verify()
+            .contains(&VerifyError::MissingBlock { func: f, block }));
+
+        // a call with too few arguments
+        let mut broken = module.clone();
+        let main = broken.functions.len() - 1;
+        let call = broken.functions[main]
+            .blocks
+            .iter_mut()
+            .flat_map(|block| block.stmts.iter_mut())
+            .find_map(|stmt| match stmt {
+                Statement::Call { args, .. } => Some(args),
+                _ => None,
+            })
+            .unwrap();
+        *call = [].into();
+        assert!(broken.verify().contains(&VerifyError::ArityMismatch {
+            func: FuncId(main),
+            expected: 1,
+            got: 0,
+        }));
+    }
+
+    #[test]
+    fn validate() {
+        let mut module = Module::new();
+        process_in(&mut module, "is_one := fn(n: i32) -> bool { n == 1 };").unwrap();
+        assert_eq!(module.validate(), Ok(()));
+        let f = module.get_function_by_name("is_one").unwrap();
+
+        // the last block falls off the end
+        let mut broken = module.clone();
+        broken.functions[f.0].blocks[0].stmts.pop();
+        assert_eq!(
+            broken.validate(),
+            Err(VerifyError::MissingTerminator {
+                func: f,
+                block: BlockId(0)
+            })
+        );
+
+        // returns the `i32` param instead of the `bool`
+        let mut broken = module.clone();
+        let stmts = &mut broken.functions[f.0].blocks[0].stmts;
+        let Some(Statement::Return { .. }) = stmts.pop() else {
+            panic!("is_one should end with a return");
+        };
+        stmts.push(Statement::Return { src: TmpId(0) });
+        assert_eq!(
+            broken.validate(),
+            Err(VerifyError::ReturnTypeMismatch {
+                func: f,
+                expected: Type::Bool,
+                got: Type::I32
+            })
+        );
+
+        // a tmp used before anything defines it
+        let mut broken = module.clone();
+        let stmts = &mut broken.functions[f.0].blocks[0].stmts;
+        let param = stmts.remove(0);
+        stmts.insert(1, param);
+        assert_eq!(
+            broken.validate(),
+            Err(VerifyError::UndefinedTmp {
+                func: f,
+                tmp: TmpId(0)
+            })
+        );
+    }
+
+    #[test]
+    fn blocks_in_dominance_order() {
+        let mut module = Module::new();
+        process_in(
+            &mut module,
+            "f := fn(n: i32) -> i32 { x := 0; for { if x > n { return x; }; x = x + 1; }; };",
+        )
+        .unwrap();
+        let f = module.get_function(module.get_function_by_name("f").unwrap());
+
+        let order = f.blocks_in_dominance_order();
+        assert_eq!(order[0], BlockId(0));
+        assert_eq!(order.len(), f.blocks.len());
+
+        let [header] = f.blocks[0].successors()[..] else {
+            panic!("the entry should jump to the loop header");
+        };
+        let position = |id: BlockId| order.iter().position(|block| *block == id).unwrap();
+
+        // every edge goes forwards, except the one back to the loop header
+        for (block_id, block) in f.blocks() {
+            for succ in block.successors() {
+                if succ != header {
+                    assert!(position(block_id) < position(succ), "{order:?}");
+                }
+            }
+        }
+        assert!(f
+            .blocks()
+            .any(|(id, block)| block.successors().contains(&header) && id != BlockId(0)));
+    }
+
+    #[test]
+    fn select() {
+        let mut module = Module::new();
+        process_in(
+            &mut module,
+            r#"
+            pick := fn(b: bool, n: i32) -> i32 { x := 0; if b { x = 1; } else { x = n; }; x };
+            call := fn(b: bool) -> i32 { x := 0; if b { x = pick(b, 1); } else { x = 2; }; x };
+            "#,
+        )
+        .unwrap();
+
+        let has_select = |name: &str| {
+            let func = module.get_function(module.get_function_by_name(name).unwrap());
+            func.statements()
+                .any(|stmt| matches!(stmt, Statement::Select { .. }))
+        };
+        assert!(has_select("pick"));
+        assert!(!has_select("call"), "calls have side effects");
+
+        let pick = module.get_function(module.get_function_by_name("pick").unwrap());
+        assert_eq!(pick.blocks.len(), 1, "no branches are needed");
+        assert_eq!(module.verify(), []);
+
+        let res = process("x := 0; b := true; if b { x = 1; } else { x = b; };");
+        assert!(matches!(res, Err(Error::UnexpectedType { .. })), "{res:?}");
+    }
+
+    #[test]
+    fn match_switch() {
+        let mut module = Module::new();
+        process_in(
+            &mut module,
+            r#"
+            describe := fn(n: i32) -> i32 { match n { 0 => 10, 1 => 20, 1 => 30, _ => 40 } };
+            flip := fn(b: bool) -> bool { match b { true => false, false => true } };
+            "#,
+        )
+        .unwrap();
+
+        let func = |name: &str| module.get_function(module.get_function_by_name(name).unwrap());
+        let cases: Vec<i64> = func("describe")
+            .statements()
+            .find_map(|stmt| match stmt {
+                Statement::SwitchInt { cases, .. } => {
+                    Some(cases.iter().map(|(case, _)| *case).collect())
+                }
+                _ => None,
+            })
+            .unwrap();
+        assert_eq!(cases, [0, 1], "the second `1` arm is never reached");
+
+        let flip = func("flip");
+        assert!(flip
+            .statements()
+            .any(|stmt| matches!(stmt, Statement::ConditionalJump { .. })));
+        assert!(!flip
+            .statements()
+            .any(|stmt| matches!(stmt, Statement::SwitchInt { .. })));
+        assert_eq!(module.verify(), []);
+
+        let res = process("n := 1; x := match n { 0 => 1, 1 => 2 };");
+        assert!(
+            matches!(res, Err(Error::NonExhaustiveMatch { .. })),
+            "{res:?}"
+        );
+
+        let res = process("n := 1; x := match n { true => 1, _ => 2 };");
+        assert!(matches!(res, Err(Error::UnexpectedType { .. })), "{res:?}");
+
+        let res = process("n := 1; x := match n { 0 => 1, _ => true };");
+        assert!(matches!(res, Err(Error::UnexpectedType { .. })), "{res:?}");
+    }
+
+    #[test]
+    fn compact() {
+        let mut func = Function::new(LinkedType(0), [].into());
+        for i in 0..5 {
+            let dst = func.new_tmpid(LinkedType(0));
+            func.push_stmt(Statement::Const {
+                dst,
+                src: Literal::I32(i),
+            });
+        }
+
+        func.blocks[0].stmts[1] = Statement::Nop;
+        func.blocks[0].stmts[3] = Statement::Nop;
+        func.compact();
+
+        let values: Vec<_> = func.blocks[0]
+            .stmts
+            .iter()
+            .map(|stmt| match stmt {
+                Statement::Const {
+                    src: Literal::I32(i),
+                    ..
+                } => *i,
+                _ => panic!("{stmt:?}"),
+            })
+            .collect();
+        assert_eq!(values, [0, 2, 4]);
+    }
+
+    #[test]
+    fn slice_len() {
+        let mut module = Module::new();
+        module.add_builtin("len", Type::USize, &[Type::Str]);
+
+        process_in(
+            &mut module,
+            "count := fn(args: [str]) -> usize { len(args) };",
+        )
+        .unwrap();
+
+        let count = module.get_function_by_name("count").unwrap();
+        let func = module.get_function(count);
+        let Type::Slice(slice) = *module.get_type(func.params[0]) else {
+            panic!("args should be a slice");
+        };
+        assert_eq!(module.get_slice(slice), Type::Str);
+        assert_eq!(module.type_name(Type::Slice(slice)), "[Str]");
+        assert_eq!(*module.get_type(func.returns), Type::USize);
+        assert!(!func
+            .statements()
+            .any(|stmt| matches!(stmt, Statement::Call { .. })));
+
+        // an extern from the source isn't the builtin, even with the same name
+        let mut module = Module::new();
+        process_in(
+            &mut module,
+            "extern fn len(args: [str]) -> usize; count := fn(args: [str]) -> usize { len(args) };",
+        )
+        .unwrap();
+        let count = module.get_function_by_name("count").unwrap();
+        assert!(module
+            .get_function(count)
+            .statements()
+            .any(|stmt| matches!(stmt, Statement::Call { .. })));
+    }
+
+    #[test]
+    fn type_of_expr() {
+        let mut module = Module::new();
+        process_in(&mut module, "x := (1, \"a\");").unwrap();
+        let functions = module.functions().len();
+
+        let type_of = |str: &str| {
+            let mut stream = ParseStream::from_lexer(Lexer::new(str));
+            let ast: Ast<Expr> = stream.parse().unwrap();
+            module.type_name_of_expr(&ast.inner)
+        };
+
+        assert_eq!(type_of("1 + 2").unwrap(), "i32");
+        assert_eq!(type_of("\"hello\"").unwrap(), "Str");
+        assert_eq!(type_of("x").unwrap(), "(i32, Str)");
+        assert_eq!(type_of("fn() -> i32 { return 1; }").unwrap(), "fn() -> i32");
+        assert!(matches!(type_of("y"), Err(Error::VariableNotFound(_))));
+
+        let mut stream = ParseStream::from_lexer(Lexer::new("x"));
+        let ast: Ast<Expr> = stream.parse().unwrap();
+        assert!(matches!(
+            module.type_of_expr(&ast.inner),
+            Ok(Type::Tuple(_))
+        ));
+
+        // nothing was added to the module
+        assert_eq!(module.functions().len(), functions);
+    }
+
+    #[test]
+    fn call_graph() {
+        let mut module = Module::new();
+        let main = process_in(
+            &mut module,
+            "b := fn() {}; a := fn() { b(); b(); }; c := fn() { a(); }; a();",
+        )
+        .unwrap();
+
+        let func = |name| match module.get_static(name) {
+            Some(Static::Func(func)) => func,
+            _ => panic!("{name} should be a function"),
+        };
+        let (a, b, c) = (func("a"), func("b"), func("c"));
+
+        let graph = module.call_graph();
+        assert_eq!(graph.len(), module.functions().len());
+        assert_eq!(graph[&a], [b], "{graph:?}");
+        assert_eq!(graph[&b], [], "{graph:?}");
+        assert_eq!(graph[&c], [a], "{graph:?}");
+        assert_eq!(graph[&main], [a], "{graph:?}");
+
+        assert!(!module.is_recursive(a));
+        assert!(!module.is_recursive(b));
+        assert!(!module.is_recursive(c));
+
+        let mut module = Module::new();
+        process_in(
+            &mut module,
+            r#"
+            fact := fn(n: i32) -> i32 { if n <= 1 { return 1; }; return n * fact(n - 1); };
+            ping := fn(n: i32) { if 0 < n { pong(n - 1); }; };
+            pong := fn(n: i32) { ping(n); };
+            user := fn() -> i32 { pong(3); fact(3) };
+            "#,
+        )
+        .unwrap();
+        let func = |name| module.get_function_by_name(name).unwrap();
+        assert!(module.is_recursive(func("fact")));
+        assert!(module.is_recursive(func("ping")));
+        assert!(module.is_recursive(func("pong")));
+        assert!(!module.is_recursive(func("user")));
+    }
+
+    #[test]
+    fn recursion() {
+        let res = process(
+            "fact := fn(n: i32) -> i32 { if n <= 1 { return 1; }; return n * fact(n - 1); };",
+        );
+        assert!(res.is_ok(), "{res:?}");
+
+        let res = process(
+            r#"
+            is_even := fn(n: i32) -> bool { if n == 0 { return n == 0; }; return is_odd(n - 1); };
+            is_odd := fn(n: i32) -> bool { if n == 0 { return n != 0; }; return is_even(n - 1); };
+            "#,
+        );
+        assert!(res.is_ok(), "{res:?}");
+    }
+
+    #[test]
+    fn mutual_recursion() {
+        let mut module = Module::new();
+        process_in(
+            &mut module,
+            r#"
+            is_even := fn(n: i32) -> bool { if n == 0 { return true; }; return is_odd(n - 1); };
+            is_odd := fn(n: i32) -> bool { if n == 0 { return false; }; return is_even(n - 1); };
+            x := is_even(4) && is_odd(3);
+            "#,
+        )
+        .unwrap();
+
+        for name in ["is_even", "is_odd"] {
+            let func = module.get_function(module.get_function_by_name(name).unwrap());
+            assert_eq!(module.get_type(func.returns), &Type::Bool);
+            assert!(!func.blocks.is_empty(), "{name} should have a body");
+        }
+        assert!(module.verify().is_empty());
+    }
+
+    #[test]
+    fn function_by_name() {
+        let mut module = Module::new();
+        let ext = module.add_extern("ext", Type::Void, &[]);
+        process_in(&mut module, "f := fn() {}; x := 4;").unwrap();
+
+        let Some(Static::Func(f)) = module.get_static("f") else {
+            panic!("f should be a function");
+        };
+        assert_eq!(module.get_function_by_name("f"), Some(f));
+        assert_eq!(module.get_function_by_name("ext"), Some(ext));
+        assert_eq!(module.get_function_by_name("x"), None);
+        assert_eq!(module.get_function_by_name("y"), None);
+    }
+
+    #[test]
+    fn infinite_recursion() {
+        let mut module = Module::new();
+        process_in(&mut module, "f := fn() { return f(); };").unwrap();
+        assert_eq!(
+            module.take_warnings(),
+            [Warning::PossibleInfiniteRecursion("f".to_string())]
+        );
+
+        process_in(
+            &mut module,
+            "g := fn(n: i32) -> i32 { if n == 0 { return g(n); } else { return g(n - 1); } };",
+        )
+        .unwrap();
+        assert_eq!(
+            module.take_warnings(),
+            [Warning::PossibleInfiniteRecursion("g".to_string())]
+        );
+
+        process_in(
+            &mut module,
+            "fact := fn(n: i32) -> i32 { if n <= 1 { return 1; }; return n * fact(n - 1); };",
+        )
+        .unwrap();
+        assert_eq!(module.take_warnings(), []);
+
+        // both branches of the if-else end up in the same block, which calls `h`
+        process_in(
+            &mut module,
+            "g := fn() {}; k := fn() {}; h := fn(c: bool) { if c { g(); } else { k(); }; h(c); };",
+        )
+        .unwrap();
+        assert_eq!(
+            module.take_warnings(),
+            [Warning::PossibleInfiniteRecursion("h".to_string())]
+        );
+
+        // always in definition order
+        process_in(&mut module, "a := fn() { a(); }; b := fn() { b(); };").unwrap();
+        assert_eq!(
+            module.take_warnings(),
+            [
+                Warning::PossibleInfiniteRecursion("a".to_string()),
+                Warning::PossibleInfiniteRecursion("b".to_string())
+            ]
+        );
+    }
+
+    #[test]
+    fn warnings_as_errors() {
+        let src = "f := fn() -> i32 { unused := 4; _ignored := 5; used := 6; used };";
+
+        let mut module = Module::new();
+        process_in(&mut module, src).unwrap();
+        assert_eq!(
+            module.take_warnings(),
+            [Warning::UnusedVariable("unused".to_string())]
+        );
+
+        let mut module = Module::new();
+        module.set_warnings_as_errors(true);
+        let res = process_in(&mut module, src);
+        assert!(
+            matches!(res, Err(Error::Warning(Warning::UnusedVariable(_)))),
+            "{res:?}"
+        );
+        assert_eq!(module.get_static("f"), None);
+        assert_eq!(module.take_warnings(), []);
+
+        process_in(&mut module, "g := fn(n: i32) -> i32 { 4 };").unwrap();
+    }
+
+    #[test]
+    fn rename() {
+        let mut module = Module::new();
+        module.add_extern("ext", Type::I32, &[]);
+        process_in(
+            &mut module,
+            "foo := fn() -> i32 { 1 }; add := fn(a: i32, b: i32) -> i32 { a + b };",
+        )
+        .unwrap();
+
+        let foo = module.get_function_by_name("foo").unwrap();
+        module.rename_function(foo, "bar".into()).unwrap();
+        assert_eq!(module.get_function_by_name("bar"), Some(foo));
+        assert_eq!(module.get_function_by_name("foo"), None);
+        assert_eq!(module.get_function(foo).name.as_deref(), Some("bar"));
+
+        let res = module.rename_function(foo, "ext".into());
+        assert!(matches!(res, Err(Error::StaticRedefined(name)) if name == "ext"));
+        assert_eq!(module.get_function_by_name("bar"), Some(foo));
+
+        let ext = module.get_function_by_name("ext").unwrap();
+        module.rename_function(ext, "ext2".into()).unwrap();
+        assert_eq!(module.get_function_by_name("ext2"), Some(ext));
+        assert!(module.externs().all(|(name, _)| name != "ext"));
+
+        process_in(&mut module, "y := bar() + ext2();").unwrap();
+        let res = process_in(&mut module, "foo();");
+        assert!(matches!(res, Err(Error::VariableNotFound(_))), "{res:?}");
+
+        let add = module.get_function_by_name("add").unwrap();
+        let a = module.functions[add.0].variables_raw["a"];
+        module.rename_variable(add, a, "c".into());
+        let variables = &module.functions[add.0].variables_raw;
+        assert_eq!(variables.get("c"), Some(&a));
+        assert_eq!(variables.get("a"), None);
+    }
+
+    #[test]
+    fn clone_function() {
+        let mut module = Module::new();
+        process_in(
+            &mut module,
+            "count := fn(n: i32) -> i32 { if n == 0 { return 7; }; count(n - 1) };",
+        )
+        .unwrap();
+
+        let count = module.get_function_by_name("count").unwrap();
+        let copy = module.clone_function(count, "copy");
+        assert_ne!(copy, count);
+        assert_eq!(module.get_function_by_name("copy"), Some(copy));
+        assert_eq!(module.get_function(copy).name.as_deref(), Some("copy"));
+
+        let copied = &mut module.functions[copy.0];
+        for stmt in copied.blocks.iter_mut().flat_map(|b| b.stmts.iter_mut()) {
+            if let Statement::Const {
+                src: src @ Literal::I32(7),
+                ..
+            } = stmt
+            {
+                *src = Literal::I32(8);
+            }
+        }
+
+        let consts = |func: FuncId| -> Vec<Literal> {
+            module
+                .get_function(func)
+                .statements()
+                .filter_map(|stmt| match stmt {
+                    Statement::Const { src, .. } => Some(src.clone()),
+                    _ => None,
+                })
+                .collect()
+        };
+        assert!(consts(count).contains(&Literal::I32(7)));
+        assert!(!consts(count).contains(&Literal::I32(8)));
+        assert!(consts(copy).contains(&Literal::I32(8)));
+        assert!(!consts(copy).contains(&Literal::I32(7)));
+
+        // the copy calls itself, not the original
+        let calls_itself = module
+            .get_function(copy)
+            .statements()
+            .any(|stmt| matches!(stmt, Statement::Func { src, .. } if *src == copy));
+        assert!(calls_itself);
+        assert_eq!(module.call_graph()[&copy], [copy]);
+
+        let ty = module.get_function(copy).params[0];
+        assert_ne!(ty, module.get_function(count).params[0]);
+        assert_eq!(module.verify(), []);
+    }
+
+    #[test]
+    fn substitute_type() {
+        let mut module = Module::new();
+        process_in(
+            &mut module,
+            "pair := fn(n: i32) -> (i32, i32) { m := n + n; (n, m) };",
+        )
+        .unwrap();
+
+        let returns = |module: &Module, func: FuncId| {
+            let ty = *module.get_type(module.get_function(func).returns);
+            let Type::Tuple(tuple) = ty else {
+                panic!("{ty:?}")
+            };
+            module.get_tuple(tuple).to_vec()
+        };
+
+        let pair = module.get_function_by_name("pair").unwrap();
+        let template = module.clone_function(pair, "template");
+        module.substitute_type(template, Type::I32, Type::Unknown);
+        assert_eq!(returns(&module, template), [Type::Unknown, Type::Unknown]);
+
+        module.substitute_type(template, Type::Unknown, Type::USize);
+        assert_eq!(returns(&module, template), [Type::USize, Type::USize]);
+        assert_eq!(returns(&module, pair), [Type::I32, Type::I32]);
+        assert_eq!(module.verify(), []);
+
+        let param = module.get_function(template).params[0];
+        assert_eq!(*module.get_type(param), Type::USize);
+    }
+
+    #[test]
+    fn imports() {
+        let mut module = Module::new();
+        process_in(&mut module, r#"use "other.uni"; x := 1;"#).unwrap();
+        assert_eq!(
+            module.imports(),
+            [] as [PathBuf; 0],
+            "codegen adds the files"
+        );
+
+        assert!(module.add_import("a.uni".into()));
+        assert!(!module.add_import("a.uni".into()));
+        assert!(module.add_import("b.uni".into()));
+        assert!(module.is_imported(Path::new("a.uni")));
+        assert_eq!(
+            module.imports(),
+            [PathBuf::from("a.uni"), PathBuf::from("b.uni")]
+        );
+    }
+
+    #[test]
+    fn variadic_extern() {
+        let mut module = Module::new();
+        let printf = module.add_variadic_extern("printf", Type::I32, &[Type::I32]);
+        assert!(module.get_function(printf).is_variadic);
+
+        process_in(&mut module, "n := printf(0, 1, true);").unwrap();
+
+        let sig = module.types.create_signature(module.signature_of(printf));
+        assert!(!module.coerces_to(Type::Func(printf), Type::FnPtr(sig)));
+    }
+
+    #[test]
+    fn propagate_consts() {
+        let mut module = Module::new();
+        process_in(
+            &mut module,
+            r#"
+            folded := fn() -> i32 { a := 2; b := a * 3; b };
+            mutated := fn() -> i32 { a := 2; a = 4; b := a * 3; b };
+            "#,
+        )
+        .unwrap();
+
+        let dump = module.dump_to_string();
+        let (folded, mutated) = dump.split_once("fn mutated").unwrap();
+        assert!(folded.contains("= const 6;"), "{folded}");
+        assert!(!folded.contains(" * "), "{folded}");
+        assert!(!mutated.contains("= const 6;"), "{mutated}");
+        assert!(mutated.contains(" * "), "{mutated}");
+        assert_eq!(module.verify(), []);
+    }
+
+    #[test]
+    fn int_literal_hint() {
+        let mut module = Module::new();
+        module.add_extern("wide", Type::I64, &[Type::I64]);
+        process_in(
+            &mut module,
+            r#"
+            set := fn() -> i64 { x := wide(0); x = 5; return x + 1; };
+            arg := fn() -> i64 { wide(2 * 3) };
+            tail := fn() -> i64 { 7 };
+            "#,
+        )
+        .unwrap();
+
+        for name in ["set", "arg", "tail"] {
+            let func = module.get_function(module.get_function_by_name(name).unwrap());
+            for stmt in func.statements() {
+                if let Statement::Const { src, .. } = stmt {
+                    assert!(matches!(src, Literal::I64(_)), "{name}: {src:?}");
+                }
+            }
+        }
+        assert_eq!(module.verify(), []);
+
+        // nothing else says what the type should be
+        process_in(&mut module, "small := 5;").unwrap();
+        let Some(Static::Global(small)) = module.get_static("small") else {
+            panic!("`small` should be a global");
+        };
+        assert_eq!(*module.get_type(module.get_global(small)), Type::I32);
+
+        let res = process_in(&mut module, "bad := fn() -> i64 { wide(1 < 2) };");
+        assert!(matches!(res, Err(Error::UnexpectedType { .. })), "{res:?}");
+    }
+
+    #[test]
+    fn dump_to_string() {
+        let mut module = Module::new();
+        process_in(&mut module, "add := fn(a: i32, b: i32) -> i32 { a + b };").unwrap();
+
+        let expected = "\
+fn add(a: i32, b: i32) -> i32 {
+block0:
+    %0: i32 = param 0;
+    let $0: i32 = %0;
+    %1: i32 = param 1;
+    let $1: i32 = %1;
+    %2: i32 = $0;
+    %3: i32 = $1;
+    %4: i32 = %2 + %3;
+    ret %4;
+}
+
+fn #1() -> void {
+block0:
+    %0: fn(i32, i32) -> i32 = fn add;
+    ret void;
+}
+";
+        assert_eq!(module.dump_to_string(), expected);
+    }
+
+    #[test]
+    fn fn_ptr_param() {
+        let src = r#"
+            less := fn(a: i32, b: i32) -> bool { return a < b; };
+            pick := fn(a: i32, b: i32, cmp: fn(i32, i32) -> bool) -> i32 {
+                if cmp(a, b) { return a; };
+                return b;
+            };
+            "#;
+
+        let res = process(&format!("{src} x := pick(3, 5, less);"));
+        assert!(res.is_ok(), "{res:?}");
+
+        let res = process(&format!("{src} x := pick(3, 5, pick);"));
+        assert!(matches!(res, Err(Error::UnexpectedType { .. })), "{res:?}");
+    }
+    #[test]
+    fn const_decl() {
+        let mut module = Module::new();
+        process_in(
+            &mut module,
+            "const MAX = 100; const LIMIT: i32 = MAX * 2 + 1; x := 5; if x > MAX { x = LIMIT; };",
+        )
+        .unwrap();
+        assert_eq!(module.get_const("MAX"), Some(&Literal::I32(100)));
+        assert_eq!(module.get_const("LIMIT"), Some(&Literal::I32(201)));
+        // inlined, not stored in a global
+        assert!(module.get_static("MAX").is_none());
+
+        let res = process("f := fn() -> i32 { 4 }; const N = f();");
+        assert!(matches!(res, Err(Error::NonConstExpr { .. })), "{res:?}");
+
+        let res = process("x := 4; const N = x + 1;");
+        assert!(matches!(res, Err(Error::NonConstExpr { .. })), "{res:?}");
+
+        let res = process("const N: bool = 4;");
+        assert!(matches!(res, Err(Error::UnexpectedType { .. })), "{res:?}");
+    }
+
+    #[test]
+    fn type_alias() {
+        let mut module = Module::new();
+        process_in(
+            &mut module,
+            "type Score = i32; f := fn(s: Score) -> Score { s + 1 }; x := f(2);",
+        )
+        .unwrap();
+        let f = module.get_function(module.get_function_by_name("f").unwrap());
+        assert_eq!(module.get_type(f.params[0]), &Type::I32);
+        assert_eq!(module.get_type(f.returns), &Type::I32);
+
+        let src = "opaque type Meters = i32; g := fn(m: Meters) -> Meters { m };";
+        let res = process(src);
+        assert!(res.is_ok(), "{res:?}");
+
+        let res = process(&format!("{src} x := g(5);"));
+        assert!(matches!(res, Err(Error::UnexpectedType { .. })), "{res:?}");
+
+        let res = process(&format!("{src} x := g(Meters(5));"));
+        assert!(res.is_ok(), "{res:?}");
+    }
+
+    #[test]
+    fn methods() {
+        let src = r#"
+            type Counter = (i32, i32);
+            impl Counter {
+                increment := fn(self: Counter) -> Counter { v, step := self; (v + step, step) };
+                value := fn(self: Counter) -> i32 { v, step := self; v };
+                bump := fn(self: &mut Counter) { v, step := *self; *self = (v + step, step); };
+                get := fn(self: &Counter) -> i32 { v, step := *self; v };
+            }
+        "#;
+
+        let mut module = Module::new();
+        let main = process_in(
+            &mut module,
+            &format!(
+                "{src}
+                c := (0, 1);
+                c = c.increment();
+                c = c.increment().increment();
+                n := c.value();
+                c.bump();
+                c.bump();
+                c.bump();
+                r := &c;
+                r.bump();
+                m := r.value() + c.get();
+                "
+            ),
+        )
+        .unwrap();
+
+        let counter = module.get_alias("Counter").unwrap();
+        let increment = module.get_method(counter, "increment").unwrap();
+        assert_eq!(
+            module.get_function(increment).name.as_deref(),
+            Some("Counter::increment")
+        );
+        assert!(module.get_method(Type::I32, "increment").is_none());
+        let main = module.get_function(main);
+        // `c` is passed by reference, `r` is loaded for the method that takes a value
+        assert_eq!(
+            main.statements()
+                .filter(|stmt| matches!(stmt, Statement::RefGlobal { .. }))
+                .count(),
+            5
+        );
+        assert_eq!(
+            main.statements()
+                .filter(|stmt| matches!(stmt, Statement::LoadRef { .. }))
+                .count(),
+            1
+        );
+        assert_eq!(module.verify(), []);
+
+        // a temporary is spilled to a variable for the reference
+        let res = process(&format!("{src} (0, 1).bump();"));
+        assert!(res.is_ok(), "{res:?}");
+
+        let res = process(&format!("{src} c := (0, 1); r := &c; *r = 5;"));
+        assert!(matches!(res, Err(Error::UnexpectedType { .. })), "{res:?}");
+
+        let res = process("n := 5; m := *n;");
+        assert!(matches!(res, Err(Error::UnexpectedType { .. })), "{res:?}");
+
+        let res = process(&format!("{src} n := (0, 1).value();"));
+        assert!(res.is_ok(), "{res:?}");
+
+        let res = process(&format!("{src} n := 5.value();"));
+        assert!(matches!(res, Err(Error::VariableNotFound(_))), "{res:?}");
+
+        let res = process("impl i32 { double := fn(self: bool) -> i32 { 2 }; }");
+        assert!(matches!(res, Err(Error::UnexpectedType { .. })), "{res:?}");
+
+        let res = process("impl i32 { zero := fn() -> i32 { 0 }; }");
+        assert!(matches!(res, Err(Error::UnexpectedType { .. })), "{res:?}");
+    }
+
+    #[test]
+    fn init_annotation() {
+        let mut module = Module::new();
+        process_in(
+            &mut module,
+            "f := fn() -> i64 { x: i64 := 5 + 1; y := x * 2; y };",
+        )
+        .unwrap();
+
+        let f = module.get_function(module.get_function_by_name("f").unwrap());
+        assert!(f
+            .statements()
+            .filter_map(|stmt| match stmt {
+                Statement::Const { src, .. } => Some(src),
+                _ => None,
+            })
+            .all(|src| matches!(src, Literal::I64(_))));
+        assert_eq!(module.verify(), []);
+
+        let res = process("x: bool := 5;");
+        assert!(matches!(res, Err(Error::UnexpectedType { .. })), "{res:?}");
+
+        let res = process("x: i32 := \"five\";");
+        assert!(matches!(res, Err(Error::UnexpectedType { .. })), "{res:?}");
+
+        let res = process("x: usize := 5; y: (usize, bool) := (x, true);");
+        assert!(res.is_ok(), "{res:?}");
+    }
+
+    #[test]
+    fn void_value() {
+        let src = "extern fn print(s: str); extern fn take(n: i32) -> i32;";
+
+        for code in [
+            "x := 1 + print(\"x\");",
+            "x := print(\"x\") == print(\"y\");",
+            "x := take(print(\"x\"));",
+            "x := (print(\"x\"), 1);",
+        ] {
+            let res = process(&format!("{src} {code}"));
+            assert!(
+                matches!(res, Err(Error::VoidValue { .. })),
+                "{code}: {res:?}"
+            );
+        }
+
+        let res = process(&format!(
+            "{src} f := fn() {{ print(\"x\") }}; print(\"y\");"
+        ));
+        assert!(res.is_ok(), "{res:?}");
+    }
+
+    #[test]
+    fn casts() {
+        let mut module = Module::new();
+        process_in(
+            &mut module,
+            r#"
+            count := fn(n: usize) -> i32 {
+                i := 0;
+                for {
+                    if n <= i as usize { return i; };
+                    i = i + 1;
+                };
+            };
+            size := 8 as usize;
+            wide := count(size) as i64 * 2;
+            "#,
+        )
+        .unwrap();
+
+        let func = |name| module.get_function(module.get_function_by_name(name).unwrap());
+        let casts = func("count")
+            .statements()
+            .filter(|stmt| matches!(stmt, Statement::Cast { .. }))
+            .count();
+        assert_eq!(casts, 1);
+        let Some(Static::Global(size)) = module.get_static("size") else {
+            panic!("`size` should be a global");
+        };
+        assert_eq!(*module.get_type(module.get_global(size)), Type::USize);
+        assert_eq!(module.verify(), []);
+
+        for code in [
+            "x := true as i32;",
+            "x := 1 as bool;",
+            "x := \"1\" as usize;",
+        ] {
+            let res = process(code);
+            assert!(
+                matches!(res, Err(Error::UnexpectedType { .. })),
+                "{code}: {res:?}"
+            );
+        }
+    }
+
+    #[test]
+    fn named_args() {
+        let src = r#"
+            draw := fn(x: i32, y: i32) -> i32 { x * 10 + y };
+            impl i32 {
+                plus := fn(self: i32, other: i32) -> i32 { self + other };
+            }
+        "#;
+
+        let mut module = Module::new();
+        let main = process_in(
+            &mut module,
+            &format!("{src} a := draw(y: 2, x: 1); b := a.plus(other: 3);"),
+        )
+        .unwrap();
+
+        let main = module.get_function(main);
+        let consts: HashMap<TmpId, &Literal> = main
+            .statements()
+            .filter_map(|stmt| match stmt {
+                Statement::Const { dst, src } => Some((*dst, src)),
+                _ => None,
+            })
+            .collect();
+        let args: Vec<Vec<Option<&Literal>>> = main
+            .statements()
+            .filter_map(|stmt| match stmt {
+                Statement::Call { args, .. } => {
+                    Some(args.iter().map(|arg| consts.get(arg).copied()).collect())
+                }
+                _ => None,
+            })
+            .collect();
+        assert_eq!(
+            args,
+            [
+                vec![Some(&Literal::I32(1)), Some(&Literal::I32(2))],
+                vec![None, Some(&Literal::I32(3))],
+            ]
+        );
+        assert_eq!(module.verify(), []);
+
+        let res = process(&format!("{src} a := draw(1, y: 2);"));
+        assert!(matches!(res, Err(Error::MixedArguments { .. })), "{res:?}");
+
+        let res = process(&format!("{src} a := draw(x: 1, z: 2);"));
+        assert!(
+            matches!(&res, Err(Error::UnknownArgument { name, .. }) if name == "z"),
+            "{res:?}"
+        );
+
+        let res = process(&format!("{src} a := draw(x: 1, x: 2);"));
+        assert!(
+            matches!(res, Err(Error::DuplicateArgument { .. })),
+            "{res:?}"
+        );
+
+        let res = process(&format!("{src} a := draw(x: 1);"));
+        assert!(
+            matches!(&res, Err(Error::MissingArgument { name, .. }) if name == "y"),
+            "{res:?}"
+        );
+
+        let res = process(&format!("{src} a := 1; b := a.plus(self: 1, other: 2);"));
+        assert!(matches!(res, Err(Error::UnknownArgument { .. })), "{res:?}");
+    }
+
+    #[test]
+    fn default_params() {
+        let src = r#"
+            greet := fn(name: str, loud: bool = false, times: i64 = 2 * 3) -> bool { loud };
+        "#;
+
+        let mut module = Module::new();
+        let main = process_in(
+            &mut module,
+            &format!(
+                r#"{src} a := greet("hi"); b := greet("hi", true); c := greet(times: 1, name: "hi");"#
+            ),
+        )
+        .unwrap();
+
+        let main = module.get_function(main);
+        let consts: HashMap<TmpId, &Literal> = main
+            .statements()
+            .filter_map(|stmt| match stmt {
+                Statement::Const { dst, src } => Some((*dst, src)),
+                _ => None,
+            })
+            .collect();
+        let args: Vec<Vec<Option<&Literal>>> = main
+            .statements()
+            .filter_map(|stmt| match stmt {
+                Statement::Call { args, .. } => {
+                    Some(args.iter().map(|arg| consts.get(arg).copied()).collect())
+                }
+                _ => None,
+            })
+            .collect();
+        let hi = Literal::Str("hi".into());
+        assert_eq!(
+            args,
+            [
+                vec![
+                    Some(&hi),
+                    Some(&Literal::Bool(false)),
+                    Some(&Literal::I64(6))
+                ],
+                vec![
+                    Some(&hi),
+                    Some(&Literal::Bool(true)),
+                    Some(&Literal::I64(6))
+                ],
+                vec![
+                    Some(&hi),
+                    Some(&Literal::Bool(false)),
+                    Some(&Literal::I64(1))
+                ],
+            ]
+        );
+        assert_eq!(module.verify(), []);
+
+        let res = process("f := fn(x: i32 = 1, y: i32) -> i32 { x + y };");
+        assert!(
+            matches!(res, Err(Error::DefaultNotTrailing { .. })),
+            "{res:?}"
+        );
+
+        let res = process("f := fn(x: i32 = true) -> i32 { x };");
+        assert!(matches!(res, Err(Error::UnexpectedType { .. })), "{res:?}");
+
+        let res = process("f := fn(x: i32 = g()) -> i32 { x };");
+        assert!(matches!(res, Err(Error::NonConstExpr { .. })), "{res:?}");
+    }
+
+    #[test]
+    fn dead_functions() {
+        let mut module = Module::new();
+        let main = process_in(
+            &mut module,
+            r#"
+            helper := fn(x: i32) -> i32 { x * 2 };
+            ping := fn(n: i32) -> i32 { pong(n) };
+            pong := fn(n: i32) -> i32 { ping(n) };
+            square := fn(x: i32) -> i32 { x * x };
+            apply := fn(f: fn(i32) -> i32, x: i32) -> i32 { f(x) };
+            y := apply(square, 3);
+            "#,
+        )
+        .unwrap();
+
+        let helper = module.get_function_by_name("helper").unwrap();
+        let ping = module.get_function_by_name("ping").unwrap();
+        let pong = module.get_function_by_name("pong").unwrap();
+        let square = module.get_function_by_name("square").unwrap();
+        let apply = module.get_function_by_name("apply").unwrap();
+
+        let res = module.remove_function(apply);
+        assert!(
+            matches!(res, Err(Error::FunctionStillReferenced { func, by }) if func == apply && by == main),
+            "{res:?}"
+        );
+        // only used as a function pointer
+        let res = module.remove_function(square);
+        assert!(
+            matches!(res, Err(Error::FunctionStillReferenced { by, .. }) if by == main),
+            "{res:?}"
+        );
+
+        module.remove_function(helper).unwrap();
+        // `ping` and `pong` only call each other
+        let res = module.remove_function(ping);
+        assert!(
+            matches!(res, Err(Error::FunctionStillReferenced { by, .. }) if by == pong),
+            "{res:?}"
+        );
+
+        module.eliminate_dead_functions(main);
+        for removed in [helper, ping, pong] {
+            assert!(module.is_removed(removed));
+        }
+        for kept in [main, square, apply] {
+            assert!(!module.is_removed(kept));
+        }
+        assert_eq!(module.get_function_by_name("helper"), None);
+        assert_eq!(module.verify(), []);
+
+        let res = module.remove_function(helper);
+        assert!(matches!(res, Err(Error::FunctionRemoved(_))), "{res:?}");
+    }
+
+    #[test]
+    #[should_panic(expected = "no function 100")]
+    fn remove_invalid_function() {
+        let mut module = Module::new();
+        process_in(&mut module, "f := fn() {};").unwrap();
+        _ = module.remove_function(FuncId(100));
+    }
+
+    #[test]
+    fn static_mut() {
+        let mut module = Module::new();
+        process_in(
+            &mut module,
+            r#"
+            const START: i64 = 40;
+            static mut counter: i64 := START + 2;
+            static mut name := "counter";
+            bump := fn() -> i64 { counter = counter + 1; counter };
+            a := bump();
+            "#,
+        )
+        .unwrap();
+
+        let Some(Static::Global(counter)) = module.get_static("counter") else {
+            panic!("counter should be a global");
+        };
+        assert_eq!(module.get_global_init(counter), Some(&Literal::I64(42)));
+        let Some(Static::Global(name)) = module.get_static("name") else {
+            panic!("name should be a global");
+        };
+        assert_eq!(
+            module.get_global_init(name),
+            Some(&Literal::Str("counter".into()))
+        );
+        let Some(Static::Global(a)) = module.get_static("a") else {
+            panic!("a should be a global");
+        };
+        assert_eq!(module.get_global_init(a), None);
+
+        let bump = module.get_function_by_name("bump").unwrap();
+        let bump = module.get_function(bump);
+        assert!(bump
+            .statements()
+            .any(|stmt| matches!(stmt, Statement::LoadGlobal { src, .. } if *src == counter)));
+        assert!(bump
+            .statements()
+            .any(|stmt| matches!(stmt, Statement::StoreGlobal { dst, .. } if *dst == counter)));
+        assert_eq!(module.verify(), []);
+
+        let res = process("static mut x: bool := 1;");
+        assert!(matches!(res, Err(Error::UnexpectedType { .. })), "{res:?}");
+
+        let res = process("f := fn() -> i32 { 1 }; static mut x := f();");
+        assert!(matches!(res, Err(Error::NonConstExpr { .. })), "{res:?}");
+    }
+
+    #[test]
+    fn trap() {
+        let mut module = Module::new();
+        module.add_builtin("unreachable", Type::Never, &[]);
+        process_in(
+            &mut module,
+            "parity := fn(n: usize) -> i32 { match n % 2 { 0 => 10, 1 => 20, _ => unreachable() } };",
+        )
+        .unwrap();
+
+        let parity = module.get_function_by_name("parity").unwrap();
+        let parity = module.get_function(parity);
+        assert_eq!(
+            parity
+                .statements()
+                .filter(|stmt| matches!(stmt, Statement::Trap))
+                .count(),
+            1
+        );
+        assert!(!parity
+            .statements()
+            .any(|stmt| matches!(stmt, Statement::Call { .. })));
+        assert_eq!(module.verify(), []);
+    }
+
+    #[test]
+    fn const_arrays() {
+        let mut module = Module::new();
+        process_in(
+            &mut module,
+            r#"
+            const TABLE = [1, 2, 4, 8];
+            const WIDE: [i64] = [1, 2 * 3];
+            lookup := fn(i: usize) -> i32 { TABLE[i] };
+            wide := fn() -> i64 { WIDE[1] };
+            "#,
+        )
+        .unwrap();
+
+        let Some(Literal::Slice(slice, values)) = module.get_const("TABLE") else {
+            panic!("TABLE should be a slice");
+        };
+        assert_eq!(module.get_slice(*slice), Type::I32);
+        assert_eq!(values[..], [1, 2, 4, 8].map(Literal::I32),);
+        let Some(Literal::Slice(_, values)) = module.get_const("WIDE") else {
+            panic!("WIDE should be a slice");
+        };
+        assert_eq!(values[..], [Literal::I64(1), Literal::I64(6)]);
+
+        let lookup = module.get_function_by_name("lookup").unwrap();
+        assert!(module
+            .get_function(lookup)
+            .statements()
+            .any(|stmt| matches!(stmt, Statement::Index { .. })));
+        assert_eq!(module.verify(), []);
+
+        let res = process("const T = [1, true];");
+        assert!(matches!(res, Err(Error::UnexpectedType { .. })), "{res:?}");
+
+        let mut module = Module::new();
+        process_in(
+            &mut module,
+            "const T = [1, 2]; f := fn(i: i32) -> i32 { T[i] };",
+        )
+        .unwrap();
+        let f = module.get_function_by_name("f").unwrap();
+        assert!(module
+            .get_function(f)
+            .statements()
+            .any(|stmt| matches!(stmt, Statement::Cast { .. })));
+        assert_eq!(module.verify(), []);
+
+        let res = process("const T = [1, 2]; f := fn(i: i64) -> i32 { T[i] };");
+        assert!(matches!(res, Err(Error::UnexpectedType { .. })), "{res:?}");
+
+        let res = process("f := fn(i: usize) -> i32 { i[0] };");
+        assert!(matches!(res, Err(Error::UnexpectedType { .. })), "{res:?}");
+
+        let res = process("f := fn(i: i32) -> i32 { x := [i, 2]; 0 };");
+        assert!(matches!(res, Err(Error::NonConstExpr { .. })), "{res:?}");
+    }
+
+    #[test]
+    fn atomics() {
+        let mut module = Module::new();
+        let ref_i32 = module.create_ref_type(Type::I32);
+        module.add_builtin("atomic_load", Type::I32, &[ref_i32]);
+        module.add_builtin("atomic_store", Type::Void, &[ref_i32, Type::I32]);
+        process_in(
+            &mut module,
+            r#"
+            flag := 0;
+            ticks: i64 := 0;
+            wait := fn() -> i32 { for { if atomic_load(&flag) != 0 { return atomic_load(&flag); }; }; };
+            tick := fn() { atomic_store(&ticks, atomic_load(&ticks) + 1); };
+            "#,
+        )
+        .unwrap();
+
+        let wait = module.get_function_by_name("wait").unwrap();
+        let wait = module.get_function(wait);
+        assert_eq!(
+            wait.statements()
+                .filter(|stmt| matches!(
+                    stmt,
+                    Statement::AtomicLoad {
+                        ordering: Ordering::SeqCst,
+                        ..
+                    }
+                ))
+                .count(),
+            2
+        );
+        assert!(!wait
+            .statements()
+            .any(|stmt| matches!(stmt, Statement::Call { .. })));
+
+        let tick = module.get_function_by_name("tick").unwrap();
+        let tick = module.get_function(tick);
+        assert!(tick
+            .statements()
+            .any(|stmt| matches!(stmt, Statement::AtomicStore { .. })));
+        assert_eq!(module.verify(), []);
+
+        for src in [
+            "bad := fn(x: i32) -> i32 { atomic_load(x) };",
+            "b := true; bad := fn() -> bool { atomic_load(&b) };",
+            "x := 0; bad := fn() { atomic_store(&x, true); };",
+        ] {
+            let res = process_in(&mut module, src);
+            assert!(matches!(res, Err(Error::UnexpectedType { .. })), "{res:?}");
+        }
+
+        // the suffix picks a weaker ordering
+        module.add_builtin("atomic_load_acquire", Type::I32, &[ref_i32]);
+        module.add_builtin("atomic_store_release", Type::Void, &[ref_i32, Type::I32]);
+        module.add_builtin("atomic_load_release", Type::I32, &[ref_i32]);
+        process_in(
+            &mut module,
+            r#"
+            get := fn() -> i32 { atomic_load_acquire(&flag) };
+            set := fn() { atomic_store_release(&flag, 1); };
+            odd := fn() -> i32 { atomic_load_release(&flag) };
+            "#,
+        )
+        .unwrap();
+        let orderings = |name: &str| -> Vec<_> {
+            let func = module.get_function_by_name(name).unwrap();
+            module
+                .get_function(func)
+                .statements()
+                .filter_map(|stmt| match stmt {
+                    Statement::AtomicLoad { ordering, .. }
+                    | Statement::AtomicStore { ordering, .. } => Some(*ordering),
+                    _ => None,
+                })
+                .collect()
+        };
+        assert_eq!(orderings("get"), [Ordering::Acquire]);
+        assert_eq!(orderings("set"), [Ordering::Release]);
+        assert_eq!(orderings("odd"), [], "a release load is just a call");
+    }
+
+    #[test]
+    fn print_format() {
+        let mut module = Module::new();
+        module.add_builtin("print", Type::Void, &[Type::Str]);
+        module.add_extern("i32_to_str", Type::Str, &[Type::I32]);
+        process_in(
+            &mut module,
+            r#"
+            x := 5;
+            show := fn() { print("x = {}, {}!\n", x, "hi"); };
+            plain := fn(s: str) { print(s); };
+            "#,
+        )
+        .unwrap();
+
+        let calls = |module: &Module, name: &str| {
+            let func = module.get_function_by_name(name).unwrap();
+            module
+                .get_function(func)
+                .statements()
+                .filter(|stmt| matches!(stmt, Statement::Call { .. }))
+                .count()
+        };
+        // "x = ", x, ", ", "hi", "!\n" and `i32_to_str(x)`
+        assert_eq!(calls(&module, "show"), 6);
+        assert_eq!(calls(&module, "plain"), 1);
+        assert_eq!(module.verify(), []);
+
+        let res = process_in(&mut module, r#"print("{} {}", 1);"#);
+        assert!(
+            matches!(
+                res,
+                Err(Error::FormatArgCount {
+                    placeholders: 2,
+                    args: 1,
+                    ..
+                })
+            ),
+            "{res:?}"
+        );
+
+        let res = process_in(&mut module, r#"print("{x}", 1);"#);
+        assert!(matches!(res, Err(Error::InvalidFormat { .. })), "{res:?}");
+
+        let res = process_in(&mut module, r#"print("{}", true);"#);
+        assert!(matches!(res, Err(Error::UnexpectedType { .. })), "{res:?}");
+
+        let res = process_in(&mut module, r#"s := "{}"; print(s, 1);"#);
+        assert!(matches!(res, Err(Error::NonConstExpr { .. })), "{res:?}");
+    }
+
+    #[test]
+    fn atomic_rmw() {
+        let mut module = Module::new();
+        let ref_i32 = module.create_ref_type(Type::I32);
+        module.add_builtin("atomic_add", Type::I32, &[ref_i32, Type::I32]);
+        module.add_builtin("atomic_xchg", Type::I32, &[ref_i32, Type::I32]);
+        process_in(
+            &mut module,
+            r#"
+            counter: i64 := 0;
+            next := fn() -> i64 { atomic_add(&counter, 1) };
+            reset := fn() -> i64 { atomic_xchg(&counter, 0) };
+            "#,
+        )
+        .unwrap();
+
+        let op_of = |name: &str| {
+            let func = module.get_function_by_name(name).unwrap();
+            let func = module.get_function(func);
+            assert!(!func
+                .statements()
+                .any(|stmt| matches!(stmt, Statement::Call { .. })));
+            func.statements().find_map(|stmt| match stmt {
+                Statement::AtomicRmw { dst, op, .. } => {
+                    assert_eq!(*module.get_type(func.tmp(*dst)), Type::I64);
+                    Some(*op)
+                }
+                _ => None,
+            })
+        };
+        assert_eq!(op_of("next"), Some(AtomicRmwOp::Add));
+        assert_eq!(op_of("reset"), Some(AtomicRmwOp::Xchg));
+        assert_eq!(module.verify(), []);
+
+        module.add_builtin("atomic_add_relaxed", Type::I32, &[ref_i32, Type::I32]);
+        process_in(
+            &mut module,
+            "count := fn() -> i64 { atomic_add_relaxed(&counter, 1) };",
+        )
+        .unwrap();
+        let count = module.get_function_by_name("count").unwrap();
+        assert!(module.get_function(count).statements().any(|stmt| matches!(
+            stmt,
+            Statement::AtomicRmw {
+                ordering: Ordering::Relaxed,
+                ..
+            }
+        )));
+
+        let res = process_in(
+            &mut module,
+            "b := true; bad := fn() { atomic_add(&b, true); };",
+        );
+        assert!(matches!(res, Err(Error::UnexpectedType { .. })), "{res:?}");
+    }
+
+    #[test]
+    fn compare_exchange() {
+        let mut module = Module::new();
+        let ref_i32 = module.create_ref_type(Type::I32);
+        let old_ok = module.create_tuple_type([Type::I32, Type::Bool].into());
+        module.add_builtin("cmpxchg", old_ok, &[ref_i32, Type::I32, Type::I32]);
+        process_in(
+            &mut module,
+            r#"
+            lock := 0;
+            acquire := fn() -> i32 {
+                for {
+                    old, ok := cmpxchg(&lock, 0, 1);
+                    if ok { return old; };
+                };
+            };
+            "#,
+        )
+        .unwrap();
+
+        let acquire = module.get_function_by_name("acquire").unwrap();
+        let acquire = module.get_function(acquire);
+        let dst = acquire
+            .statements()
+            .find_map(|stmt| match stmt {
+                Statement::CompareExchange { dst, .. } => Some(*dst),
+                _ => None,
+            })
+            .unwrap();
+        let Type::Tuple(tuple) = *module.get_type(acquire.tmp(dst)) else {
+            panic!("cmpxchg should return a tuple");
+        };
+        assert_eq!(module.get_tuple(tuple), [Type::I32, Type::Bool]);
+        assert_eq!(module.verify(), []);
+
+        // a failed exchange stores nothing, so it can't be `Release`
+        module.add_builtin("cmpxchg_release", old_ok, &[ref_i32, Type::I32, Type::I32]);
+        process_in(
+            &mut module,
+            "release := fn() { old, ok := cmpxchg_release(&lock, 1, 0); };",
+        )
+        .unwrap();
+        let release = module.get_function_by_name("release").unwrap();
+        assert!(module
+            .get_function(release)
+            .statements()
+            .any(|stmt| matches!(
+                stmt,
+                Statement::CompareExchange {
+                    success_order: Ordering::Release,
+                    fail_order: Ordering::Relaxed,
+                    ..
+                }
+            )));
+
+        let res = process_in(
+            &mut module,
+            "x := 0; bad := fn() { cmpxchg(&x, 0, true); };",
+        );
+        assert!(matches!(res, Err(Error::UnexpectedType { .. })), "{res:?}");
+    }
+
+    #[test]
+    fn exit_diverges() {
+        let mut module = Module::new();
+        module.add_extern("exit", Type::Never, &[Type::I32]);
+        module.add_extern("result", Type::Void, &[Type::I32]);
+        process_in(
+            &mut module,
+            "f := fn() -> i32 { exit(3); result(1); 5 }; g := fn() -> i32 { exit(3) };",
+        )
+        .unwrap();
+        assert_eq!(module.verify(), []);
+
+        // the code after `exit` is dead, only the call itself is left
+        module.eliminate_dead_blocks();
+        let f = module.get_function_by_name("f").unwrap();
+        let f = module.get_function(f);
+        assert_eq!(
+            f.statements()
+                .filter(|stmt| matches!(stmt, Statement::Call { .. }))
+                .count(),
+            1
+        );
+        assert!(matches!(
+            f.blocks.last().unwrap().stmts.last(),
+            Some(Statement::Unreachable)
+        ));
+    }
+
+    #[test]
+    fn fences() {
+        let mut module = Module::new();
+        module.add_builtin("memory_fence_acquire", Type::Void, &[]);
+        module.add_builtin("memory_fence_release", Type::Void, &[]);
+        process_in(
+            &mut module,
+            "f := fn() { memory_fence_release(); memory_fence_acquire(); };",
+        )
+        .unwrap();
+
+        let f = module.get_function_by_name("f").unwrap();
+        let orderings: Vec<_> = module
+            .get_function(f)
+            .statements()
+            .filter_map(|stmt| match stmt {
+                Statement::Fence { ordering } => Some(*ordering),
+                Statement::Call { .. } => panic!("fences aren't calls"),
+                _ => None,
+            })
+            .collect();
+        assert_eq!(orderings, [Ordering::Release, Ordering::Acquire]);
+        assert_eq!(module.verify(), []);
+    }
+
+    #[test]
+    fn never_propagation() {
+        // both branches diverge, so the `if` is `Never` and the rest of the function is dead
+        let mut module = Module::new();
+        process_in(
+            &mut module,
+            "f := fn(c: bool) -> i32 { x: i32 := if c { return 1; } else { return 2 }; x };",
+        )
+        .unwrap();
+        let f = module.get_function_by_name("f").unwrap();
+        let f = module.get_function(f);
+        assert!(f
+            .statements()
+            .any(|stmt| matches!(stmt, Statement::Unreachable)));
+        assert_eq!(module.verify(), []);
+
+        // only one branch diverges, the `if` has the type of the other one
+        for src in [
+            "g := fn(c: bool) -> i64 { x: i64 := if c { return 1; } else { 2 }; x };",
+            "g := fn(c: bool, d: bool) -> i64 { x: i64 := if c { 2 } else if d { return 1; } else { 3 }; x };",
+            "g := fn(n: i64) -> i64 { match n { 0 => { return 5; }, _ => n } };",
+        ] {
+            let mut module = Module::new();
+            let res = process_in(&mut module, src);
+            assert!(res.is_ok(), "{src}: {res:?}");
+            assert_eq!(module.verify(), []);
+        }
+
+        let res = process("f := fn(c: bool) { x := if c { 1 } else { true }; };");
+        assert!(matches!(res, Err(Error::UnexpectedType { .. })), "{res:?}");
+
+        let res = process("f := fn(c: bool) -> i32 { x := if c { 1 }; x };");
+        assert!(matches!(res, Err(Error::UnexpectedType { .. })), "{res:?}");
+    }
+
+    #[test]
+    fn tail_type_mismatch() {
+        let source = "g := fn() {}; f := fn() -> i32 { x := 1; g() };";
+        let res = process(source);
+        let Err(Error::TypeMismatch {
+            span,
+            expected: Type::I32,
+            got: Type::Void,
+        }) = res
+        else {
+            panic!("{res:?}");
+        };
+        assert_eq!(span.as_str(source), "g()");
+
+        let source = "f := fn() -> i32 { x := 1; x == 1 };";
+        let res = process(source);
+        let Err(Error::TypeMismatch {
+            span,
+            expected: Type::I32,
+            got: Type::Bool,
+        }) = res
+        else {
+            panic!("{res:?}");
+        };
+        assert_eq!(span.as_str(source), "x == 1");
+
+        let res = process("f := fn() -> i32 { x := 1; x + 1 };");
+        assert!(res.is_ok(), "{res:?}");
+    }
+
+    #[test]
+    fn mod_paths() {
+        let mut module = Module::new();
+        let res = process_in(
+            &mut module,
+            r#"
+            add := fn(a: i32, b: i32) -> i32 { a - b };
+            mod math {
+                add := fn(a: i32, b: i32) -> i32 { id(a) + b };
+                id := fn(a: i32) -> i32 { a };
+                mod inner {
+                    twice := fn(a: i32) -> i32 { add(a, a) };
+                }
+                limit := 10;
+            }
+            x := math::add(1, 2);
+            y := add(1, 2);
+            z := math::inner::twice(math::limit);
+            "#,
+        );
+        assert!(res.is_ok(), "{res:?}");
+
+        let add = module.get_function_by_name("add").unwrap();
+        let math_add = module.get_function_by_name("math::add").unwrap();
+        let twice = module.get_function_by_name("math::inner::twice").unwrap();
+        assert_ne!(add, math_add);
+        assert_eq!(
+            module.get_function(math_add).name.as_deref(),
+            Some("math::add")
+        );
+        // `add` inside `math::inner` is the closest one, `math::add`
+        assert_eq!(module.call_graph()[&twice], [math_add]);
+        assert!(matches!(
+            module.get_static("math::limit"),
+            Some(Static::Global(_))
+        ));
+
+        let res = process_in(&mut module, "w := id(1);");
+        assert!(
+            matches!(&res, Err(Error::VariableNotFound(name)) if name == "id"),
+            "{res:?}"
+        );
+        let res = process_in(&mut module, "w := math::sub(1, 2);");
+        assert!(
+            matches!(&res, Err(Error::VariableNotFound(name)) if name == "math::sub"),
+            "{res:?}"
+        );
+    }
+
+    #[test]
+    fn uninitialized_variable() {
+        let mut module = Module::new();
+        let res = process_in(
+            &mut module,
+            "f := fn() -> i32 { x: i32; y := x; x = 5; x + y };",
+        );
+        assert!(res.is_ok(), "{res:?}");
+        assert_eq!(
+            module.take_warnings(),
+            [Warning::UninitializedVariable("x".to_string())]
+        );
+
+        let f = module.get_function_by_name("f").unwrap();
+        let undef = module
+            .get_function(f)
+            .blocks
+            .iter()
+            .flat_map(|block| block.stmts.iter())
+            .find_map(|stmt| match stmt {
+                Statement::PoisonUndef { dst } => Some(*dst),
+                _ => None,
+            })
+            .unwrap();
+        assert_eq!(
+            *module.get_type(module.get_function(f).tmp(undef)),
+            Type::I32
+        );
+        module.validate().unwrap();
+
+        // assigned before the first read, also through a reference
+        let res = process_in(
+            &mut module,
+            "g := fn() -> i32 { x: i32; x = 1; x }; h := fn() -> i32 { x: i32; _r := &x; x };",
+        );
+        assert!(res.is_ok(), "{res:?}");
+        assert_eq!(module.take_warnings(), []);
+
+        // only one of the branches assigns it
+        let res = process_in(
+            &mut module,
+            "m := fn(c: bool) -> i32 { x: i32; if c { x = 1; }; y := x; y };",
+        );
+        assert!(res.is_ok(), "{res:?}");
+        assert_eq!(
+            module.take_warnings(),
+            [Warning::UninitializedVariable("x".to_string())]
+        );
+
+        // every branch that reaches the read assigns it
+        let res = process_in(
+            &mut module,
+            "n := fn(c: bool, d: bool) -> i32 {
+                x: i32;
+                if c { x = 1; } else if d { return 0; } else { x = 2; };
+                x
+            };",
+        );
+        assert!(res.is_ok(), "{res:?}");
+        assert_eq!(module.take_warnings(), []);
+
+        let res = process_in(&mut module, "k := fn() { x: i32; x = true; };");
+        assert!(matches!(res, Err(Error::UnexpectedType { .. })), "{res:?}");
+    }
+
+    #[test]
+    fn use_path() {
+        let mut module = Module::new();
+        let res = process_in(
+            &mut module,
+            r#"
+            mod math {
+                add := fn(a: i32, b: i32) -> i32 { a + b };
+            }
+            mod other {
+                add := fn(a: i32, b: i32) -> i32 { a - b };
+                mod inner {
+                    use other::add;
+                    three := fn() -> i32 { add(2, 1) };
+                }
+            }
+            use math::add;
+            x := add(1, 2);
+            "#,
+        );
+        assert!(res.is_ok(), "{res:?}");
+
+        let math_add = module.get_function_by_name("math::add").unwrap();
+        let other_add = module.get_function_by_name("other::add").unwrap();
+        let three = module.get_function_by_name("other::inner::three").unwrap();
+        assert_eq!(module.get_static("add"), Some(Static::Func(math_add)));
+        assert_eq!(module.call_graph()[&three], [other_add]);
+
+        // the same import again is fine, a different one with the same name isn't
+        let res = process_in(&mut module, "use math::add;");
+        assert!(res.is_ok(), "{res:?}");
+        let res = process_in(&mut module, "use other::add;");
+        assert!(
+            matches!(&res, Err(Error::AmbiguousImport { name, .. }) if name == "add"),
+            "{res:?}"
+        );
+        let res = process_in(&mut module, "use math::sub;");
+        assert!(
+            matches!(&res, Err(Error::VariableNotFound(name)) if name == "math::sub"),
+            "{res:?}"
+        );
+    }
+
+    #[test]
+    fn fmt_ir() {
+        let mut module = Module::new();
+        process_in(
+            &mut module,
+            r#"
+            add := fn(a: i32, b: i32) -> i32 { a + b };
+            greet := fn(s: str) -> str { s };
+            main := fn() -> i32 {
+                if add(1, 2) == 3 { greet("hi"); };
+                return 0;
+            };
+            "#,
+        )
+        .unwrap();
+
+        let expected = r#"
+fn add(a: i32, b: i32) -> i32 {
+block0:
+    %0: i32 = param 0;
+    let $0: i32 = %0;
+    %1: i32 = param 1;
+    let $1: i32 = %1;
+    %2: i32 = $0;
+    %3: i32 = $1;
+    %4: i32 = %2 + %3;
+    ret %4;
+}
+
+fn greet(s: Str) -> Str {
+block0:
+    %0: Str = param 0;
+    let $0: Str = %0;
+    %1: Str = $0;
+    ret %1;
+}
+
+fn main() -> i32 {
+block0:
+    %0: fn(i32, i32) -> i32 = fn add;
+    %1: i32 = const 1;
+    %2: i32 = const 2;
+    %3: i32 = call %0(%1, %2);
+    %4: i32 = const 3;
+    %5: bool = %3 == %4;
+    br %5, block2, block3;
+block1:
+    %10: i32 = const 0;
+    ret %10;
+block2:
+    %6: fn(Str) -> Str = fn greet;
+    %7: Str = const "hi";
+    %8: Str = call %6(%7);
+    jump block1;
+block3:
+    jump block1;
+}
+
+fn #3() -> void {
+block0:
+    %0: fn(i32, i32) -> i32 = fn add;
+    %1: fn(Str) -> Str = fn greet;
+    %2: fn() -> i32 = fn main;
+    ret void;
+}
+"#;
+        assert_eq!(module.to_string(), &expected[1..]);
+    }
+
+    #[test]
+    fn serialize_interface() {
+        let mut module = Module::new();
+        process_in(
+            &mut module,
+            r#"
+            type Pair = (i32, str);
+            impl Pair {
+                len := fn(self: Pair) -> i32 { n, _s := self; n };
+            }
+            add := fn(a: i32, b: i32) -> i32 { a + b };
+            pair := fn(n: i32, s: str) -> Pair { (n, s) };
+            first := fn(xs: [i32], cmp: fn(i32, i32) -> bool) -> i32 { xs[0] };
+            "#,
+        )
+        .unwrap();
+
+        let json = serde_json::to_string(&module).unwrap();
+        let mut loaded: Module = serde_json::from_str(&json).unwrap();
+
+        for name in ["add", "pair", "first"] {
+            let before = module.get_function_by_name(name).unwrap();
+            let after = loaded.get_function_by_name(name).unwrap();
+            assert_eq!(before, after);
+            assert_eq!(
+                module.type_name(Type::Func(before)),
+                loaded.type_name(Type::Func(after))
+            );
+            assert!(loaded.get_function(after).is_extern);
+            assert!(loaded.get_function(after).blocks.is_empty());
+        }
+
+        // the loaded module can be used like the original, and the interned types still match
+        process_in(
+            &mut loaded,
+            r#"
+            main := fn() -> i32 { pair(add(1, 2), "three").len() };
+            "#,
+        )
+        .unwrap();
+        let main = loaded.get_function_by_name("main").unwrap();
+        assert!(!loaded.get_function(main).is_extern);
+    }
+
+    #[test]
+    fn cfg() {
+        let mut module = Module::new();
+        process_in(
+            &mut module,
+            r#"
+            g := fn(x: i32) -> i32 { x + 1 };
+            f := fn(x: i32) -> i32 {
+                y := 0;
+                if x == 1 { y = g(1); } else { y = g(2); };
+                return y;
+            };
+            "#,
+        )
+        .unwrap();
+
+        let f = module.get_function(module.get_function_by_name("f").unwrap());
+        // block0 branches to block2 and block3, which both jump to the merge block1
+        let (entry, merge, then, els) = (BlockId(0), BlockId(1), BlockId(2), BlockId(3));
+        assert_eq!(f.cfg(), [vec![then, els], vec![], vec![merge], vec![merge]]);
+        assert_eq!(
+            f.pred_cfg(),
+            [vec![], vec![then, els], vec![entry], vec![entry]]
+        );
+    }
 }